@@ -0,0 +1,16 @@
+-- Reverts the initial schema, tables are dropped in reverse order of creation.
+DROP TABLE IF EXISTS refresh_tokens;
+DROP TABLE IF EXISTS sessions;
+DROP TABLE IF EXISTS service_keys;
+DROP TABLE IF EXISTS password_reset_tokens;
+DROP TABLE IF EXISTS player_effects;
+DROP TABLE IF EXISTS mailbox;
+DROP TABLE IF EXISTS mail;
+DROP TABLE IF EXISTS inventory_item;
+DROP TABLE IF EXISTS fish_caught_bait;
+DROP TABLE IF EXISTS fish_caught_area;
+DROP TABLE IF EXISTS fish_caught;
+DROP TABLE IF EXISTS stats;
+DROP TABLE IF EXISTS friend_requests;
+DROP TABLE IF EXISTS friends;
+DROP TABLE IF EXISTS users;
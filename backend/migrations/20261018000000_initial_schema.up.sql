@@ -4,7 +4,8 @@ CREATE TABLE users (
     email TEXT UNIQUE NOT NULL,
     password TEXT NOT NULL,
     salt TEXT NOT NULL,
-    created TIMESTAMPTZ NOT NULL
+    created TIMESTAMPTZ NOT NULL,
+    password_changed TIMESTAMPTZ -- Tokens issued before this moment are rejected
 );
 
 CREATE TABLE friends (
@@ -96,3 +97,44 @@ CREATE TABLE player_effects (
 CREATE INDEX idx_player_effects_expiry ON player_effects(expiry_time);
 CREATE INDEX idx_player_effects_user_id ON player_effects(user_id);
 
+
+CREATE TABLE password_reset_tokens (
+    token_hash TEXT PRIMARY KEY, -- sha256 of the token, the token itself is only send by mail
+    user_id UUID NOT NULL REFERENCES users(user_id),
+    created TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_password_reset_tokens_user_id ON password_reset_tokens(user_id);
+
+CREATE TABLE service_keys (
+    key_id UUID PRIMARY KEY,
+    name TEXT NOT NULL,
+    key_hash TEXT NOT NULL, -- sha256 of the secret part of the key
+    scopes TEXT[] NOT NULL,
+    created TIMESTAMPTZ NOT NULL,
+    revoked TIMESTAMPTZ
+);
+
+CREATE TABLE sessions (
+    session_id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(user_id),
+    device TEXT NOT NULL,
+    created TIMESTAMPTZ NOT NULL,
+    last_used TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked TIMESTAMPTZ
+);
+
+CREATE INDEX idx_sessions_user_id ON sessions(user_id);
+
+-- Every refresh token ever handed out, so reuse of a rotated token can be detected.
+CREATE TABLE refresh_tokens (
+    token_hash TEXT PRIMARY KEY, -- sha256 of the token
+    session_id UUID NOT NULL REFERENCES sessions(session_id),
+    created TIMESTAMPTZ NOT NULL,
+    used TIMESTAMPTZ
+);
+
+CREATE INDEX idx_refresh_tokens_session_id ON refresh_tokens(session_id);
@@ -0,0 +1,2 @@
+-- Reverts the currency ledger, the balances in stats are kept.
+DROP TABLE IF EXISTS currency_ledger;
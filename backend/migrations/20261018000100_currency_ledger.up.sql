@@ -0,0 +1,28 @@
+-- Every change of coins or bucks, written in the same transaction as the change to stats.
+-- The sum of the deltas of a user and currency always equals the balance in stats.
+CREATE TABLE currency_ledger (
+    entry_id BIGSERIAL PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES stats(user_id),
+    currency TEXT NOT NULL,
+    delta INTEGER NOT NULL,
+    balance_after INTEGER NOT NULL,
+    reason TEXT NOT NULL, -- Why the balance changed, for example 'shop_purchase'
+    source_type TEXT NOT NULL,
+    source_id UUID, -- The service key or player that caused the change, NULL for the system
+    reference_id UUID, -- For example the purchase or mail the change belongs to
+    created TIMESTAMPTZ NOT NULL,
+    CONSTRAINT valid_currency CHECK (currency IN ('coins', 'bucks')),
+    CONSTRAINT valid_source CHECK (
+        (source_type IN ('player', 'service') AND source_id IS NOT NULL)
+        OR (source_type = 'system' AND source_id IS NULL)
+    )
+);
+
+CREATE INDEX idx_currency_ledger_user_id ON currency_ledger(user_id, entry_id DESC);
+
+-- Existing balances become the opening entries, so the ledger sums match stats from the start.
+INSERT INTO currency_ledger (user_id, currency, delta, balance_after, reason, source_type, created)
+SELECT user_id, 'coins', coins, coins, 'opening_balance', 'system', NOW() FROM stats;
+
+INSERT INTO currency_ledger (user_id, currency, delta, balance_after, reason, source_type, created)
+SELECT user_id, 'bucks', bucks, bucks, 'opening_balance', 'system', NOW() FROM stats;
@@ -0,0 +1,3 @@
+-- Reverts the shop, bought items stay in the inventories.
+DROP TABLE IF EXISTS shop_purchases;
+DROP TABLE IF EXISTS shop_offers;
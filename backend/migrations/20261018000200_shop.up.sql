@@ -0,0 +1,28 @@
+-- Items that players can buy with coins or bucks.
+CREATE TABLE shop_offers (
+    offer_id UUID PRIMARY KEY,
+    definition_id INTEGER NOT NULL,
+    state_template TEXT NOT NULL, -- The state_blob every bought item starts with
+    currency TEXT NOT NULL,
+    price INTEGER NOT NULL,
+    available_from TIMESTAMPTZ, -- NULL means since always
+    available_until TIMESTAMPTZ, -- NULL means forever
+    purchase_limit INTEGER, -- How often one player can buy it, NULL means unlimited
+    created TIMESTAMPTZ NOT NULL,
+    CONSTRAINT valid_currency CHECK (currency IN ('coins', 'bucks')),
+    CONSTRAINT positive_price CHECK (price > 0),
+    CONSTRAINT valid_window CHECK (available_from IS NULL OR available_until IS NULL OR available_from < available_until),
+    CONSTRAINT positive_purchase_limit CHECK (purchase_limit IS NULL OR purchase_limit > 0)
+);
+
+CREATE TABLE shop_purchases (
+    purchase_id UUID PRIMARY KEY,
+    offer_id UUID NOT NULL REFERENCES shop_offers(offer_id),
+    user_id UUID NOT NULL REFERENCES stats(user_id),
+    item_uuid UUID NOT NULL, -- Not a reference, the item may be destroyed later
+    currency TEXT NOT NULL,
+    price INTEGER NOT NULL,
+    purchased TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_shop_purchases_user_offer ON shop_purchases(user_id, offer_id);
@@ -0,0 +1,2 @@
+-- Reverts the idempotency keys, retries are applied again afterwards.
+DROP TABLE IF EXISTS idempotency_keys;
@@ -0,0 +1,13 @@
+-- Results of requests sent with an Idempotency-Key header, returned again when the request is retried.
+CREATE TABLE idempotency_keys (
+    caller_type TEXT NOT NULL, -- 'player' or 'service'
+    caller_id UUID NOT NULL, -- The user or service key that sent the request
+    idempotency_key TEXT NOT NULL,
+    request_hash TEXT NOT NULL, -- sha256 of the route and body, a retry has to match it
+    response TEXT, -- The JSON body of the first result, NULL while the request is still running
+    created TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (caller_type, caller_id, idempotency_key)
+);
+
+CREATE INDEX idx_idempotency_keys_expires_at ON idempotency_keys(expires_at);
@@ -0,0 +1,4 @@
+-- Reverts trading, items stay with whoever owns them now.
+ALTER TABLE inventory_item DROP COLUMN IF EXISTS escrow_trade_id;
+DROP TABLE IF EXISTS trade_items;
+DROP TABLE IF EXISTS trades;
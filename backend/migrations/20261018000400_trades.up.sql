@@ -0,0 +1,34 @@
+-- Trades between two players. The proposer offers items and coins and asks for items and coins in return.
+-- Resolved trades are kept, so support can look up who traded what.
+CREATE TABLE trades (
+    trade_id UUID PRIMARY KEY,
+    proposer_id UUID NOT NULL REFERENCES stats(user_id),
+    recipient_id UUID NOT NULL REFERENCES stats(user_id),
+    offered_coins INTEGER NOT NULL,
+    requested_coins INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    parent_trade_id UUID REFERENCES trades(trade_id), -- The trade this one counters
+    created TIMESTAMPTZ NOT NULL,
+    resolved TIMESTAMPTZ, -- NULL while the trade is open
+    CONSTRAINT different_players CHECK (proposer_id <> recipient_id),
+    CONSTRAINT non_negative_coins CHECK (offered_coins >= 0 AND requested_coins >= 0),
+    CONSTRAINT valid_status CHECK (status IN ('open', 'accepted', 'declined', 'countered', 'cancelled')),
+    CONSTRAINT resolved_when_closed CHECK ((status = 'open') = (resolved IS NULL))
+);
+
+CREATE INDEX idx_trades_proposer ON trades(proposer_id, created DESC);
+CREATE INDEX idx_trades_recipient ON trades(recipient_id, created DESC);
+
+-- The items of a trade as they were when it was proposed, and again when it was accepted.
+CREATE TABLE trade_items (
+    trade_id UUID NOT NULL REFERENCES trades(trade_id),
+    item_uuid UUID NOT NULL, -- Not a reference, the item may be destroyed later
+    side TEXT NOT NULL,
+    definition_id INTEGER NOT NULL,
+    state_blob TEXT NOT NULL,
+    PRIMARY KEY (trade_id, item_uuid),
+    CONSTRAINT valid_side CHECK (side IN ('offered', 'requested'))
+);
+
+-- Items offered in an open trade are locked in escrow until it is resolved.
+ALTER TABLE inventory_item ADD COLUMN escrow_trade_id UUID REFERENCES trades(trade_id);
@@ -0,0 +1,3 @@
+-- Reverts mail attachments, claimed items and currency stay with the players.
+ALTER TABLE mailbox DROP COLUMN IF EXISTS claimed;
+DROP TABLE IF EXISTS mail_attachments;
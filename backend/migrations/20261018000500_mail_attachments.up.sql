@@ -0,0 +1,19 @@
+-- Items and currency sent with a mail. Every recipient can claim them once.
+CREATE TABLE mail_attachments (
+    attachment_id BIGSERIAL PRIMARY KEY,
+    mail_id UUID NOT NULL REFERENCES mail(mail_id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    definition_id INTEGER, -- Set for items
+    state_blob TEXT, -- Set for items
+    currency TEXT, -- Set for currency
+    amount INTEGER, -- Set for currency
+    CONSTRAINT valid_attachment CHECK (
+        (kind = 'item' AND definition_id IS NOT NULL AND state_blob IS NOT NULL AND currency IS NULL AND amount IS NULL)
+        OR (kind = 'currency' AND currency IN ('coins', 'bucks') AND amount > 0 AND definition_id IS NULL AND state_blob IS NULL)
+    )
+);
+
+CREATE INDEX idx_mail_attachments_mail_id ON mail_attachments(mail_id);
+
+-- When the recipient claimed the attachments, NULL while they are unclaimed.
+ALTER TABLE mailbox ADD COLUMN claimed TIMESTAMPTZ;
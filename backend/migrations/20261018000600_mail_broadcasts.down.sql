@@ -0,0 +1,7 @@
+-- Reverts broadcasts, delivered broadcasts stay in the mailboxes.
+DROP TABLE IF EXISTS mail_broadcast_deliveries;
+DROP TABLE IF EXISTS mail_broadcasts;
+ALTER TABLE mail DROP COLUMN IF EXISTS expires_at;
+DELETE FROM mailbox WHERE mail_id IN (SELECT mail_id FROM mail WHERE sender_id IS NULL);
+DELETE FROM mail WHERE sender_id IS NULL;
+ALTER TABLE mail ALTER COLUMN sender_id SET NOT NULL;
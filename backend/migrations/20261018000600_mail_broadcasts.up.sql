@@ -0,0 +1,20 @@
+-- Mail from the game itself has no sender.
+ALTER TABLE mail ALTER COLUMN sender_id DROP NOT NULL;
+-- Expired mail is hidden from the mailbox, NULL means it never expires.
+ALTER TABLE mail ADD COLUMN expires_at TIMESTAMPTZ;
+
+-- Mail for all players, or those matching the filters. It is put into a mailbox when the player fetches their data.
+CREATE TABLE mail_broadcasts (
+    mail_id UUID PRIMARY KEY REFERENCES mail(mail_id) ON DELETE CASCADE,
+    registered_before TIMESTAMPTZ, -- Only players whose account was created before this
+    min_xp INTEGER, -- Only players with at least this much xp
+    created TIMESTAMPTZ NOT NULL
+);
+
+-- Which players got a broadcast already, so a deleted broadcast does not come back.
+CREATE TABLE mail_broadcast_deliveries (
+    mail_id UUID NOT NULL REFERENCES mail_broadcasts(mail_id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(user_id),
+    delivered TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (user_id, mail_id)
+);
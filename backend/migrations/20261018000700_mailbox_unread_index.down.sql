@@ -0,0 +1 @@
+DROP INDEX idx_mailbox_unread;
@@ -0,0 +1,2 @@
+-- Counting unread mail for the badge in the client only reads these rows.
+CREATE INDEX idx_mailbox_unread ON mailbox (user_id) WHERE NOT read;
@@ -0,0 +1,2 @@
+DROP FUNCTION create_catch_log_partitions;
+DROP TABLE catch_log;
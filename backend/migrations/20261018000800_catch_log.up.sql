@@ -0,0 +1,45 @@
+-- Every catch, appended by /stats/add_fish next to the fish_caught aggregates.
+-- Partitioned by month, so old months can be detached or dropped without touching the rest.
+CREATE TABLE catch_log (
+    catch_id BIGSERIAL NOT NULL,
+    user_id UUID NOT NULL REFERENCES users(user_id),
+    caught TIMESTAMPTZ NOT NULL,
+    fish_id INTEGER NOT NULL,
+    length INTEGER NOT NULL,
+    area_id INTEGER NOT NULL,
+    bait_id INTEGER NOT NULL,
+    rod_uuid UUID, -- The rod selected at the time
+    rod_definition_id INTEGER, -- Kept, the rod itself may be gone later
+    effects INTEGER[] NOT NULL DEFAULT '{}', -- Item ids of the effects that were active
+    PRIMARY KEY (user_id, caught, catch_id)
+) PARTITION BY RANGE (caught);
+
+-- Catches outside of the created months, so an insert never fails for lack of a partition.
+CREATE TABLE catch_log_default PARTITION OF catch_log DEFAULT;
+
+-- Creates the partitions for this month and the next months_ahead months when they are missing.
+-- The backend runs it daily, returns how many partitions it created.
+CREATE FUNCTION create_catch_log_partitions(months_ahead INTEGER) RETURNS INTEGER AS $$
+DECLARE
+    month TIMESTAMP := date_trunc('month', NOW() AT TIME ZONE 'UTC');
+    partition TEXT;
+    created INTEGER := 0;
+BEGIN
+    FOR i IN 0..months_ahead LOOP
+        partition := 'catch_log_' || to_char(month, 'YYYY_MM');
+        IF to_regclass(partition) IS NULL THEN
+            EXECUTE format(
+                'CREATE TABLE %I PARTITION OF catch_log FOR VALUES FROM (%L) TO (%L)',
+                partition,
+                month AT TIME ZONE 'UTC',
+                (month + INTERVAL '1 month') AT TIME ZONE 'UTC'
+            );
+            created := created + 1;
+        END IF;
+        month := month + INTERVAL '1 month';
+    END LOOP;
+    RETURN created;
+END;
+$$ LANGUAGE plpgsql;
+
+SELECT create_catch_log_partitions(2);
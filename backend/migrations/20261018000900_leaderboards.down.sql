@@ -0,0 +1,2 @@
+DROP MATERIALIZED VIEW fish_leaderboards;
+DROP MATERIALIZED VIEW player_leaderboards;
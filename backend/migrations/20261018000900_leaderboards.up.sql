@@ -0,0 +1,53 @@
+-- Leaderboards are read from these views instead of ranking every player on each request.
+-- The backend refreshes them every few minutes, `refreshed` tells clients how old they are.
+-- `rank` is shared by players with the same score, `position` is unique and used for paging.
+-- A player shows up on a board once they have a score above zero on it.
+CREATE MATERIALIZED VIEW player_leaderboards AS
+WITH totals AS (
+    SELECT
+        u.user_id,
+        u.name,
+        s.xp::BIGINT AS xp,
+        COALESCE(SUM(fc.amount), 0)::BIGINT AS catches,
+        COUNT(fc.fish_id)::BIGINT AS species
+    FROM users u
+    JOIN stats s ON s.user_id = u.user_id
+    LEFT JOIN fish_caught fc ON fc.user_id = u.user_id
+    GROUP BY u.user_id, u.name, s.xp
+), scores AS (
+    SELECT 'xp' AS board, user_id, name, xp AS score FROM totals WHERE xp > 0
+    UNION ALL
+    SELECT 'catches', user_id, name, catches FROM totals WHERE catches > 0
+    UNION ALL
+    SELECT 'species', user_id, name, species FROM totals WHERE species > 0
+)
+SELECT
+    board,
+    user_id,
+    name,
+    score,
+    RANK() OVER (PARTITION BY board ORDER BY score DESC) AS rank,
+    ROW_NUMBER() OVER (PARTITION BY board ORDER BY score DESC, user_id) AS position,
+    NOW() AS refreshed
+FROM scores;
+
+-- A unique index is needed to refresh without blocking readers.
+CREATE UNIQUE INDEX idx_player_leaderboards_user ON player_leaderboards (board, user_id);
+CREATE UNIQUE INDEX idx_player_leaderboards_position ON player_leaderboards (board, position);
+
+-- The longest fish of every player, per species.
+CREATE MATERIALIZED VIEW fish_leaderboards AS
+SELECT
+    fc.fish_id,
+    fc.user_id,
+    u.name,
+    fc.max_length::BIGINT AS score,
+    RANK() OVER (PARTITION BY fc.fish_id ORDER BY fc.max_length DESC) AS rank,
+    ROW_NUMBER() OVER (PARTITION BY fc.fish_id ORDER BY fc.max_length DESC, fc.user_id) AS position,
+    NOW() AS refreshed
+FROM fish_caught fc
+JOIN users u ON u.user_id = fc.user_id
+WHERE fc.max_length > 0;
+
+CREATE UNIQUE INDEX idx_fish_leaderboards_user ON fish_leaderboards (fish_id, user_id);
+CREATE UNIQUE INDEX idx_fish_leaderboards_position ON fish_leaderboards (fish_id, position);
@@ -0,0 +1,4 @@
+DROP INDEX idx_friends_user_two;
+DROP TABLE leaderboard_resets;
+DROP TABLE fish_length_snapshots;
+DROP TABLE player_score_snapshots;
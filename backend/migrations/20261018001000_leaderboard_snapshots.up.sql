@@ -0,0 +1,24 @@
+-- The scores at the start of the week, friends leaderboards compare against them to show rank changes.
+-- Replaced at each weekly reset, a player without a row registered after it.
+CREATE TABLE player_score_snapshots (
+    user_id UUID PRIMARY KEY REFERENCES users(user_id) ON DELETE CASCADE,
+    xp BIGINT NOT NULL,
+    catches BIGINT NOT NULL,
+    species BIGINT NOT NULL
+);
+
+CREATE TABLE fish_length_snapshots (
+    user_id UUID NOT NULL REFERENCES player_score_snapshots(user_id) ON DELETE CASCADE,
+    fish_id INTEGER NOT NULL,
+    max_length BIGINT NOT NULL,
+    PRIMARY KEY (user_id, fish_id)
+);
+
+-- One row per weekly reset, so only one backend takes the snapshot.
+CREATE TABLE leaderboard_resets (
+    week_start TIMESTAMPTZ PRIMARY KEY,
+    taken TIMESTAMPTZ NOT NULL
+);
+
+-- The friends of a player are looked up from both sides.
+CREATE INDEX idx_friends_user_two ON friends (user_two_id);
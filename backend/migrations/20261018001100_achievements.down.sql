@@ -0,0 +1,2 @@
+DROP TABLE achievement_unlocks;
+DROP TABLE achievement_progress;
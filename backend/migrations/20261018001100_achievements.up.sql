@@ -0,0 +1,17 @@
+-- How far a player got towards each achievement, written whenever an event can change it.
+-- Achievements are defined in backend/achievements.toml, achievement_id is their id there.
+CREATE TABLE achievement_progress (
+    user_id UUID NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    achievement_id TEXT NOT NULL,
+    progress INTEGER NOT NULL,
+    updated TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (user_id, achievement_id)
+);
+
+-- One row per unlocked achievement, so its reward is only granted once.
+CREATE TABLE achievement_unlocks (
+    user_id UUID NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    achievement_id TEXT NOT NULL,
+    unlocked TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (user_id, achievement_id)
+);
@@ -0,0 +1,2 @@
+DROP TABLE player_quests;
+DROP TABLE quest_assignments;
@@ -0,0 +1,23 @@
+-- The quests of a player are picked the first time they are needed in a day or week.
+-- One row per player and period, so the quests are only picked once.
+CREATE TABLE quest_assignments (
+    user_id UUID NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    period TEXT NOT NULL,
+    period_start TIMESTAMPTZ NOT NULL,
+    assigned TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (user_id, period, period_start)
+);
+
+-- Quests are defined in backend/quests.toml, quest_id is their id there.
+CREATE TABLE player_quests (
+    user_id UUID NOT NULL,
+    period TEXT NOT NULL,
+    period_start TIMESTAMPTZ NOT NULL,
+    quest_id TEXT NOT NULL,
+    progress INTEGER NOT NULL,
+    target INTEGER NOT NULL,
+    completed TIMESTAMPTZ,
+    claimed TIMESTAMPTZ,
+    PRIMARY KEY (user_id, period, period_start, quest_id),
+    FOREIGN KEY (user_id, period, period_start) REFERENCES quest_assignments (user_id, period, period_start) ON DELETE CASCADE
+);
@@ -0,0 +1,3 @@
+-- Reverts storing failed requests, their retries run again afterwards.
+DELETE FROM idempotency_keys WHERE status >= 400;
+ALTER TABLE idempotency_keys DROP COLUMN IF EXISTS status;
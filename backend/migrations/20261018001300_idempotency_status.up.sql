@@ -0,0 +1,3 @@
+-- Failed requests are stored too, so a retry fails the same way. The status tells a stored error from a result.
+ALTER TABLE idempotency_keys ADD COLUMN status SMALLINT; -- NULL while the first request is still running
+UPDATE idempotency_keys SET status = 200 WHERE response IS NOT NULL;
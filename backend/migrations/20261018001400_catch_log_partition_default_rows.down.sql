@@ -0,0 +1,23 @@
+-- Reverts to creating partitions without moving rows out of the default partition.
+CREATE OR REPLACE FUNCTION create_catch_log_partitions(months_ahead INTEGER) RETURNS INTEGER AS $$
+DECLARE
+    month TIMESTAMP := date_trunc('month', NOW() AT TIME ZONE 'UTC');
+    partition TEXT;
+    created INTEGER := 0;
+BEGIN
+    FOR i IN 0..months_ahead LOOP
+        partition := 'catch_log_' || to_char(month, 'YYYY_MM');
+        IF to_regclass(partition) IS NULL THEN
+            EXECUTE format(
+                'CREATE TABLE %I PARTITION OF catch_log FOR VALUES FROM (%L) TO (%L)',
+                partition,
+                month AT TIME ZONE 'UTC',
+                (month + INTERVAL '1 month') AT TIME ZONE 'UTC'
+            );
+            created := created + 1;
+        END IF;
+        month := month + INTERVAL '1 month';
+    END LOOP;
+    RETURN created;
+END;
+$$ LANGUAGE plpgsql;
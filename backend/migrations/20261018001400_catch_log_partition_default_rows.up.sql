@@ -0,0 +1,46 @@
+-- Creating a partition fails when the default partition has rows of its month, for example when the daily job
+-- did not run before the month started. Such rows are now moved to the new partition.
+CREATE OR REPLACE FUNCTION create_catch_log_partitions(months_ahead INTEGER) RETURNS INTEGER AS $$
+DECLARE
+    month TIMESTAMP := date_trunc('month', NOW() AT TIME ZONE 'UTC');
+    month_start TIMESTAMPTZ;
+    month_end TIMESTAMPTZ;
+    partition TEXT;
+    created INTEGER := 0;
+BEGIN
+    FOR i IN 0..months_ahead LOOP
+        partition := 'catch_log_' || to_char(month, 'YYYY_MM');
+        month_start := month AT TIME ZONE 'UTC';
+        month_end := (month + INTERVAL '1 month') AT TIME ZONE 'UTC';
+        IF to_regclass(partition) IS NULL THEN
+            IF EXISTS (SELECT 1 FROM catch_log_default WHERE caught >= month_start AND caught < month_end) THEN
+                -- Detached, the default partition does not conflict with the new one.
+                -- The function runs in one transaction, so no catch is lost or seen twice in between.
+                ALTER TABLE catch_log DETACH PARTITION catch_log_default;
+                EXECUTE format(
+                    'CREATE TABLE %I PARTITION OF catch_log FOR VALUES FROM (%L) TO (%L)',
+                    partition,
+                    month_start,
+                    month_end
+                );
+                EXECUTE format(
+                    'INSERT INTO %I SELECT * FROM catch_log_default WHERE caught >= $1 AND caught < $2',
+                    partition
+                ) USING month_start, month_end;
+                DELETE FROM catch_log_default WHERE caught >= month_start AND caught < month_end;
+                ALTER TABLE catch_log ATTACH PARTITION catch_log_default DEFAULT;
+            ELSE
+                EXECUTE format(
+                    'CREATE TABLE %I PARTITION OF catch_log FOR VALUES FROM (%L) TO (%L)',
+                    partition,
+                    month_start,
+                    month_end
+                );
+            END IF;
+            created := created + 1;
+        END IF;
+        month := month + INTERVAL '1 month';
+    END LOOP;
+    RETURN created;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,3 @@
+-- Reverts the read and archive times, retention is measured from the send time again afterwards.
+ALTER TABLE mailbox DROP COLUMN IF EXISTS archived_at;
+ALTER TABLE mailbox DROP COLUMN IF EXISTS read_at;
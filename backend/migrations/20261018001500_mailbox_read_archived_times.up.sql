@@ -0,0 +1,6 @@
+-- When the entry was marked read and archived, read and archived mail is removed a while after both.
+ALTER TABLE mailbox ADD COLUMN read_at TIMESTAMPTZ;
+ALTER TABLE mailbox ADD COLUMN archived_at TIMESTAMPTZ;
+-- The times of earlier entries are unknown, their retention starts now.
+UPDATE mailbox SET read_at = NOW() WHERE read;
+UPDATE mailbox SET archived_at = NOW() WHERE archived;
@@ -0,0 +1,183 @@
+use sqlx::migrate::{Migrate, Migrator};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::domain::Scope;
+use crate::repository::ledger::LedgerRepositoryImpl;
+use crate::repository::service_keys::ServiceKeyRepositoryImpl;
+use crate::service::ledger::{LedgerService, LedgerServiceImpl};
+use crate::service::service_keys::{ServiceKeyService, ServiceKeyServiceImpl};
+
+/// The migrations in backend/migrations, embedded in the binary.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+const USAGE: &str = "Usage:
+    backend                                      Start the server
+    backend migrate up                           Apply all pending migrations
+    backend migrate status                       Show which migrations are applied
+    backend migrate down                         Revert the last applied migration
+    backend ledger reconcile                     Check that the ledger sums match the balances
+    backend service-key create <name> <scope>... Create a service key
+    backend service-key revoke <key_id>          Revoke a service key
+    backend service-key list                     List all service keys";
+
+// Subcommands for administrating the backend from the command line.
+//
+// Returns false when no subcommand was given, then the server should be started.
+pub async fn run(args: &[String], pool: PgPool) -> bool {
+    match args.get(1).map(String::as_str) {
+        None => false,
+        Some("migrate") => {
+            migrate(&args[2..], pool).await;
+            true
+        }
+        Some("ledger") => {
+            ledger(&args[2..], pool).await;
+            true
+        }
+        Some("service-key") => {
+            service_key(&args[2..], pool).await;
+            true
+        }
+        Some(_) => fail(USAGE),
+    }
+}
+
+async fn migrate(args: &[String], pool: PgPool) {
+    match args.first().map(String::as_str) {
+        Some("up") => match MIGRATOR.run(&pool).await {
+            Ok(()) => println!("All migrations are applied"),
+            Err(e) => fail(format!("Could not apply migrations: {}", e)),
+        },
+        Some("status") => {
+            let applied = match applied_migrations(&pool).await {
+                Ok(applied) => applied,
+                Err(e) => fail(format!("Could not read applied migrations: {}", e)),
+            };
+            for migration in MIGRATOR.iter().filter(|m| !m.migration_type.is_down_migration()) {
+                let state = match applied.get(&migration.version) {
+                    None => "pending",
+                    Some(checksum) if *checksum == *migration.checksum => "applied",
+                    // The file was edited after it was applied, add a new migration instead.
+                    Some(_) => "applied, but changed since",
+                };
+                println!("{} {} [{}]", migration.version, migration.description, state);
+            }
+        }
+        Some("down") => {
+            let applied = match applied_migrations(&pool).await {
+                Ok(applied) => applied,
+                Err(e) => fail(format!("Could not read applied migrations: {}", e)),
+            };
+            let mut versions: Vec<i64> = applied.into_keys().collect();
+            versions.sort();
+            let last = match versions.pop() {
+                Some(last) => last,
+                None => {
+                    println!("No migrations are applied");
+                    return;
+                }
+            };
+            // Everything newer than the target is reverted, so target the one before the last.
+            let target = versions.pop().unwrap_or(0);
+            match MIGRATOR.undo(&pool, target).await {
+                Ok(()) => println!("Reverted migration {}", last),
+                Err(e) => fail(format!("Could not revert migration {}: {}", last, e)),
+            }
+        }
+        _ => fail(USAGE),
+    }
+}
+
+/// Prints the error and exits with status 1, so scripts and deploy steps notice that the command failed.
+fn fail(message: impl std::fmt::Display) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+/// Returns the checksum of every applied migration by version.
+async fn applied_migrations(pool: &PgPool) -> Result<HashMap<i64, Vec<u8>>, sqlx::Error> {
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    let applied = conn.list_applied_migrations().await?;
+    Ok(applied
+        .into_iter()
+        .map(|migration| (migration.version, migration.checksum.into_owned()))
+        .collect())
+}
+
+async fn ledger(args: &[String], pool: PgPool) {
+    let service = LedgerServiceImpl::new(LedgerRepositoryImpl::new(pool));
+
+    match args.first().map(String::as_str) {
+        Some("reconcile") => match service.reconcile().await {
+            Ok(mismatches) if mismatches.is_empty() => println!("All balances match the ledger"),
+            Ok(mismatches) => {
+                for mismatch in &mismatches {
+                    println!(
+                        "{} {}: balance {}, ledger total {}",
+                        mismatch.user_id,
+                        mismatch.currency.as_str(),
+                        mismatch.balance,
+                        mismatch.ledger_total
+                    );
+                }
+                fail(format!("{} balances do not match the ledger", mismatches.len()));
+            }
+            Err(e) => fail(format!("Could not reconcile the ledger: {}", e)),
+        },
+        _ => fail(USAGE),
+    }
+}
+
+async fn service_key(args: &[String], pool: PgPool) {
+    let service = ServiceKeyServiceImpl::new(ServiceKeyRepositoryImpl::new(pool));
+
+    match args.first().map(String::as_str) {
+        Some("create") if args.len() >= 3 => {
+            let mut scopes = vec![];
+            for scope in &args[2..] {
+                match Scope::parse(scope) {
+                    Some(scope) => scopes.push(scope),
+                    None => {
+                        let known: Vec<&str> = Scope::ALL.iter().map(Scope::as_str).collect();
+                        fail(format!("Unknown scope {:?}, known scopes are: {}", scope, known.join(", ")));
+                    }
+                }
+            }
+            match service.create(args[1].clone(), scopes).await {
+                Ok((key, secret)) => {
+                    println!("Created service key {} ({})", key.name, key.key_id);
+                    println!("{}", secret);
+                    println!("Store this key safely, it can not be shown again.");
+                }
+                Err(e) => fail(format!("Could not create service key: {}", e)),
+            }
+        }
+        Some("revoke") if args.len() == 2 => {
+            let key_id = match Uuid::parse_str(&args[1]) {
+                Ok(key_id) => key_id,
+                Err(_) => fail(format!("Invalid key id: {:?}", args[1])),
+            };
+            match service.revoke(key_id).await {
+                Ok(()) => println!("Revoked service key {}", key_id),
+                Err(e) => fail(format!("Could not revoke service key: {}", e)),
+            }
+        }
+        Some("list") => match service.list().await {
+            Ok(keys) => {
+                for key in keys {
+                    let scopes: Vec<&str> = key.scopes.iter().map(Scope::as_str).collect();
+                    let state = match key.revoked {
+                        Some(revoked) => format!("revoked {}", revoked),
+                        None => "active".to_string(),
+                    };
+                    println!("{} {} [{}] {}", key.key_id, key.name, scopes.join(", "), state);
+                }
+            }
+            Err(e) => fail(format!("Could not list service keys: {}", e)),
+        },
+        _ => fail(USAGE),
+    }
+}
@@ -0,0 +1,80 @@
+use rocket::{get, routes, serde::json::Json, State};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::controller::guards::{Caller, ReadPlayerData};
+use crate::domain::AchievementList;
+use crate::error::{AppError, ErrorResponse};
+use crate::service::achievements::AchievementService;
+
+// Utoipa is the crate that generates swagger documentation for your endpoints.
+// The documentation for each endpoint is combined in docs.rs
+// Make sure to add your endpoint in docs.rs when you write new endpoints.
+#[utoipa::path(
+    get,
+    path = "/achievements",
+    params(
+        ("user_id" = Option<Uuid>, Query, description = "The user, required for services"),
+    ),
+    responses(
+        (status = 200, description = "Every achievement with the progress of the user", body = AchievementList),
+        (status = 400, description = "A service did not name the user", body = ErrorResponse),
+        (status = 401, description = "Not logged in and no valid service key", body = ErrorResponse),
+        (status = 403, description = "The request names another user, or the service key lacks the data:read scope", body = ErrorResponse),
+        (status = 404, description = "The user has no stats", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Lists all achievements with how far the user got, progress is updated shortly after the game server reports it",
+    operation_id = "achievements",
+    tag = "Achievements",
+    security(
+        ("jwt_auth" = []),
+        ("service_key" = ["data:read"])
+    )
+)]
+#[get("/?<user_id>")]
+async fn achievements(
+    caller: Caller<ReadPlayerData>,
+    user_id: Option<Uuid>,
+    achievement_service: &State<Arc<dyn AchievementService>>,
+) -> Result<Json<AchievementList>, AppError> {
+    let user_id = caller.acting_user(user_id)?;
+    Ok(Json(achievement_service.list(user_id).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/achievements/unlocked",
+    params(
+        ("user_id" = Option<Uuid>, Query, description = "The user, required for services"),
+    ),
+    responses(
+        (status = 200, description = "The unlocked achievements of the user, the latest first", body = AchievementList),
+        (status = 400, description = "A service did not name the user", body = ErrorResponse),
+        (status = 401, description = "Not logged in and no valid service key", body = ErrorResponse),
+        (status = 403, description = "The request names another user, or the service key lacks the data:read scope", body = ErrorResponse),
+        (status = 404, description = "The user has no stats", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Lists the achievements the user unlocked",
+    operation_id = "unlockedAchievements",
+    tag = "Achievements",
+    security(
+        ("jwt_auth" = []),
+        ("service_key" = ["data:read"])
+    )
+)]
+#[get("/unlocked?<user_id>")]
+async fn unlocked_achievements(
+    caller: Caller<ReadPlayerData>,
+    user_id: Option<Uuid>,
+    achievement_service: &State<Arc<dyn AchievementService>>,
+) -> Result<Json<AchievementList>, AppError> {
+    let user_id = caller.acting_user(user_id)?;
+    Ok(Json(achievement_service.unlocked(user_id).await?))
+}
+
+// Combine all the achievement routes.
+pub fn achievement_routes() -> Vec<rocket::Route> {
+    routes![achievements, unlocked_achievements]
+}
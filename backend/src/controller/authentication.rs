@@ -1,8 +1,10 @@
-use crate::domain::LoginResponse;
+use crate::controller::idempotency::Idempotency;
+use crate::domain::{AuthenticatedUser, LoginResponse, Session, User};
+use crate::error::{AppError, ErrorResponse};
 use crate::service::authentication::AuthenticationService;
-use rocket::http::Status;
+use jsonwebtoken::jwk::JwkSet;
+use rocket::get;
 use rocket::post;
-use rocket::response::status;
 use rocket::routes;
 use rocket::serde::json::Json;
 use rocket::State;
@@ -10,14 +12,26 @@ use serde::Deserialize;
 use serde::Serialize;
 use std::sync::Arc;
 use utoipa::ToSchema;
-
-#[derive(Debug, Serialize, Deserialize, ToSchema)]
-struct GetPlacesRequest;
+use uuid::Uuid;
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct LoginRequest {
     username: String,
     password: String,
+    /// Name of the device, shown in the session list.
+    device: Option<String>,
+}
+
+/// Request body for exchanging a refresh token.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct RefreshRequest {
+    refresh_token: String,
+}
+
+/// Request body for revoking a session.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct RevokeSessionRequest {
+    session_id: Uuid,
 }
 
 // Return type should later be CreateUserRepsonse
@@ -27,10 +41,11 @@ struct LoginRequest {
     request_body = LoginRequest,
     responses(
         (status = 201, description = "Login successful", body = LoginResponse),
-        (status = 400, description = "Invalid input data"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Username or password is wrong", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
-    description = "Recieve a jwt when creditials are valid.",
+    description = "Recieve a jwt and refresh token when creditials are valid. Every login starts a new session.",
     operation_id = "Login",
     tag = "Authentication"
 )]
@@ -38,25 +53,141 @@ struct LoginRequest {
 async fn login(
     payload: Json<LoginRequest>,
     authentication_service: &State<Arc<dyn AuthenticationService>>,
-) -> Result<Json<LoginResponse>, status::Custom<String>> {
-    match authentication_service
-        .login(payload.username.clone(), payload.password.clone())
-        .await
-    {
-        Ok(jwt) => match jwt {
-            Some(res) => Ok(Json(res)),
-            None => Err(status::Custom(
-                Status::InternalServerError,
-                "Access denied".to_string(),
-            )),
-        },
-        Err(_) => Err(status::Custom(
-            Status::InternalServerError,
-            "Internal server error".to_string(),
-        )),
-    }
+) -> Result<Json<LoginResponse>, AppError> {
+    let res = authentication_service
+        .login(payload.username.clone(), payload.password.clone(), payload.device.clone())
+        .await?;
+    Ok(Json(res))
+}
+
+#[utoipa::path(
+    post,
+    path = "/login/refresh",
+    request_body = RefreshRequest,
+    responses(
+        (status = 201, description = "Tokens refreshed", body = LoginResponse),
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Refresh token is invalid, expired or already used", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Exchanges a refresh token for a new jwt and refresh token. A refresh token can be used once, using it again logs out the session.",
+    operation_id = "refreshToken",
+    tag = "Authentication"
+)]
+#[post("/refresh", data = "<payload>")]
+async fn refresh(
+    payload: Json<RefreshRequest>,
+    authentication_service: &State<Arc<dyn AuthenticationService>>,
+) -> Result<Json<LoginResponse>, AppError> {
+    let res = authentication_service.refresh(payload.refresh_token.clone()).await?;
+    Ok(Json(res))
+}
+
+#[utoipa::path(
+    post,
+    path = "/login/logout",
+    responses(
+        (status = 201, description = "Logged out", body = bool),
+        (status = 401, description = "Not logged in", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Logs out the session of the jwt. Its jwt and refresh token stop working.",
+    operation_id = "logout",
+    tag = "Authentication",
+    security(
+        ("jwt_auth" = [])
+    )
+)]
+#[post("/logout")]
+async fn logout(
+    authenticated: AuthenticatedUser,
+    idempotency: Idempotency,
+    authentication_service: &State<Arc<dyn AuthenticationService>>,
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&(), || async {
+        authentication_service
+            .logout(authenticated.user.user_id, authenticated.session_id)
+            .await?;
+        Ok(Json(true))
+    }).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/login/sessions",
+    responses(
+        (status = 201, description = "Active sessions", body = Vec<Session>),
+        (status = 401, description = "Not logged in", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Lists the active sessions of the logged in user, one for every logged in device.",
+    operation_id = "listSessions",
+    tag = "Authentication",
+    security(
+        ("jwt_auth" = [])
+    )
+)]
+#[get("/sessions")]
+async fn list_sessions(
+    user: User,
+    authentication_service: &State<Arc<dyn AuthenticationService>>,
+) -> Result<Json<Vec<Session>>, AppError> {
+    Ok(Json(authentication_service.list_sessions(user.user_id).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/login/sessions/revoke",
+    request_body = RevokeSessionRequest,
+    responses(
+        (status = 201, description = "Session revoked", body = bool),
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in", body = ErrorResponse),
+        (status = 404, description = "No active session with this id", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Logs out one of the sessions of the logged in user, for example a lost device.",
+    operation_id = "revokeSession",
+    tag = "Authentication",
+    security(
+        ("jwt_auth" = [])
+    )
+)]
+#[post("/sessions/revoke", data = "<payload>")]
+async fn revoke_session(
+    user: User,
+    idempotency: Idempotency,
+    payload: Json<RevokeSessionRequest>,
+    authentication_service: &State<Arc<dyn AuthenticationService>>,
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&*payload, || async {
+        authentication_service
+            .logout(user.user_id, payload.session_id)
+            .await?;
+        Ok(Json(true))
+    }).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/.well-known/jwks.json",
+    responses(
+        (status = 200, description = "The public keys as JSON Web Key Set"),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "The public keys player JWT's are signed with, identified by the kid in the JWT header. Other services can use these to verify player tokens. Keys signed with a shared secret are not listed.",
+    operation_id = "jwks",
+    tag = "Authentication"
+)]
+#[get("/jwks.json")]
+async fn jwks(authentication_service: &State<Arc<dyn AuthenticationService>>) -> Json<JwkSet> {
+    Json(authentication_service.jwks())
 }
 
 pub fn authentication_routes() -> Vec<rocket::Route> {
-    routes![login]
+    routes![login, refresh, logout, list_sessions, revoke_session]
+}
+
+pub fn well_known_routes() -> Vec<rocket::Route> {
+    routes![jwks]
 }
@@ -5,12 +5,18 @@ use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::{domain::UserData, service::data::DataService};
+use crate::{
+    controller::guards::{Caller, ReadPlayerData},
+    domain::UserData,
+    error::{AppError, ErrorResponse},
+    service::data::DataService,
+};
 
-/// Request body for adding an item.
+/// Request body for retreiving all data of a player.
+/// `user_id` is optional for players, only their own data can be retreived.
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct RetreiveDataRequest {
-    pub user_id: Uuid
+    pub user_id: Option<Uuid>
 }
 
 // Utoipa is the crate that generates swagger documentation for your endpoints.
@@ -21,28 +27,34 @@ struct RetreiveDataRequest {
     path = "/data/retreive_all_playerdata",
     request_body = RetreiveDataRequest,
     responses(
-        (status = 201, description = "Retreived successfully", body = bool),
-        (status = 400, description = "Invalid input data"),
-        (status = 500, description = "Internal server error")
+        (status = 201, description = "Retreived successfully"),
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in and no valid service key", body = ErrorResponse),
+        (status = 403, description = "The request names another user, or the service key lacks the data:read scope", body = ErrorResponse),
+        (status = 404, description = "The user does not exist", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     description = "Retreives user data from the database",
     operation_id = "retreiveItem",
-    tag = "userData"
+    tag = "userData",
+    security(
+        ("jwt_auth" = []),
+        ("service_key" = ["data:read"])
+    )
 )]
 #[post("/retreive_all_playerdata", data = "<payload>")]
 async fn retreive_player_data(
+    caller: Caller<ReadPlayerData>,
     payload: Json<RetreiveDataRequest>,
     inventory_service: &State<Arc<dyn DataService>>,
-) -> Json<Option<UserData>> {
-    match inventory_service
+) -> Result<Json<UserData>, AppError> {
+    let user_id = caller.acting_user(payload.user_id)?;
+    let data = inventory_service
         .retreive_all(
-            payload.user_id
+            user_id
         )
-        .await
-    {
-        Ok(o) => Json(Some(o)),
-        Err(_) => Json(None),
-    }
+        .await?;
+    Ok(Json(data))
 }
 
 // Combine all the data routes.
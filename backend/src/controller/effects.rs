@@ -1,4 +1,10 @@
-use crate::{domain::AddActiveEffectRequest, service::effects::EffectsService};
+use crate::{
+    controller::guards::{Caller, GrantEffects, ManageEffects, Service},
+    controller::idempotency::Idempotency,
+    domain::AddActiveEffectRequest,
+    error::{AppError, ErrorResponse},
+    service::effects::EffectsService,
+};
 use rocket::{post, routes, serde::json::Json, State};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
@@ -8,7 +14,7 @@ use uuid::Uuid;
 /// Request body for removing expired effects for a specific user
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct RemoveExpiredEffectRequest {
-    pub user_id: Uuid,
+    pub user_id: Option<Uuid>,
     pub item_id: i32,
 }
 
@@ -18,22 +24,27 @@ pub struct RemoveExpiredEffectRequest {
     request_body = AddActiveEffectRequest,
     responses(
         (status = 200, description = "Effect added successfully", body = bool),
-        (status = 400, description = "Invalid request data"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid request data", body = ErrorResponse),
+        (status = 401, description = "No valid service key", body = ErrorResponse),
+        (status = 403, description = "Not called with a service key with the effects:grant scope", body = ErrorResponse),
+        (status = 422, description = "The expiry time is not in the future", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    security(
+        ("service_key" = ["effects:grant"])
     )
 )]
 #[post("/add_effect", data = "<add_request>")]
 pub async fn add_effect(
+    _service: Service<GrantEffects>,
+    idempotency: Idempotency,
     add_request: Json<AddActiveEffectRequest>,
     effects_service: &State<Arc<dyn EffectsService>>,
-) -> Json<bool>{
-    match effects_service.add_effect(add_request.into_inner()).await {
-        Ok(_) => Json(true),
-        Err(e) => {
-            eprintln!("Error adding effect: {:?}", e);
-            Json(false)
-        }
-    }
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&*add_request, || async {
+        effects_service.add_effect(add_request.clone().into_inner()).await?;
+        Ok(Json(true))
+    }).await
 }
 
 #[utoipa::path(
@@ -42,21 +53,28 @@ pub async fn add_effect(
     request_body = RemoveExpiredEffectRequest,
     responses(
         (status = 200, description = "Expired effects removed", body = bool),
-        (status = 500, description = "Internal server error")
+        (status = 401, description = "Not logged in and no valid service key", body = ErrorResponse),
+        (status = 403, description = "The request names another user, or the service key lacks the effects:manage scope", body = ErrorResponse),
+        (status = 404, description = "The user does not have this effect", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    security(
+        ("jwt_auth" = []),
+        ("service_key" = ["effects:manage"])
     )
 )]
 #[post("/remove_expired", data = "<request>")]
 pub async fn remove_expired_effects(
+    caller: Caller<ManageEffects>,
+    idempotency: Idempotency,
     request: Json<RemoveExpiredEffectRequest>,
     effects_service: &State<Arc<dyn EffectsService>>,
-) -> Json<bool> {
-    match effects_service.remove_effect(request.user_id, request.item_id).await {
-        Ok(_) => Json(true),
-        Err(e) => {
-            eprintln!("Error removing expired effects: {:?}", e);
-            Json(false)
-        }
-    }
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&*request, || async {
+        let user_id = caller.acting_user(request.user_id)?;
+        effects_service.remove_effect(user_id, request.item_id).await?;
+        Ok(Json(true))
+    }).await
 }
 
 #[utoipa::path(
@@ -64,20 +82,24 @@ pub async fn remove_expired_effects(
     path = "/effects/cleanup_all_expired",
     responses(
         (status = 200, description = "All expired effects cleaned up", body = bool),
-        (status = 500, description = "Internal server error")
+        (status = 401, description = "No valid service key", body = ErrorResponse),
+        (status = 403, description = "Not called with a service key with the effects:manage scope", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    security(
+        ("service_key" = ["effects:manage"])
     )
 )]
 #[post("/cleanup_all_expired")]
 pub async fn cleanup_all_expired_effects(
+    _service: Service<ManageEffects>,
+    idempotency: Idempotency,
     effects_service: &State<Arc<dyn EffectsService>>,
-) -> Json<bool> {
-    match effects_service.cleanup_all_expired_effects().await {
-        Ok(_) => Json(true),
-        Err(e) => {
-            eprintln!("Error cleaning up all expired effects: {:?}", e);
-            Json(false)
-        }
-    }
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&(), || async {
+        effects_service.cleanup_all_expired_effects().await?;
+        Ok(Json(true))
+    }).await
 }
 
 pub fn routes() -> Vec<rocket::Route> {
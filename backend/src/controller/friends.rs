@@ -5,14 +5,19 @@ use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 use uuid::Uuid;
 
+use crate::controller::acting_user;
+use crate::controller::idempotency::Idempotency;
+use crate::domain::User;
+use crate::error::{AppError, ErrorResponse};
 use crate::service::friends::FriendService;
 
 /// Request body for adding a friend.
+/// `sender_id` is optional, requests are always send by the logged in user.
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct FriendRequests {
     pub user_one: Uuid,
     pub user_two: Uuid,
-    pub sender_id: Uuid,
+    pub sender_id: Option<Uuid>,
 }
 
 /// Request body for removing a friend.
@@ -29,6 +34,15 @@ struct HandleFriendRequest {
     pub request_accepted: bool,
 }
 
+/// Players can only change friendships they are part of.
+fn ensure_in_pair(user_id: Uuid, user_one: Uuid, user_two: Uuid) -> Result<(), AppError> {
+    if user_id == user_one || user_id == user_two {
+        Ok(())
+    } else {
+        Err(AppError::Forbidden("not_in_friendship"))
+    }
+}
+
 
 #[utoipa::path(
     post,
@@ -36,28 +50,36 @@ struct HandleFriendRequest {
     request_body = RemoveFriendRequests,
     responses(
         (status = 201, description = "Successfully removed friend", body = bool),
-        (status = 400, description = "Invalid input data"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in", body = ErrorResponse),
+        (status = 403, description = "The logged in user is not part of this friendship", body = ErrorResponse),
+        (status = 404, description = "The users are not friends", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     description = "Removes a friend from the database",
     operation_id = "removeFriend",
-    tag = "Friends"
+    tag = "Friends",
+    security(
+        ("jwt_auth" = [])
+    )
 )]
 #[post("/remove_friend", data = "<payload>")]
 async fn remove_friend(
+    user: User,
+    idempotency: Idempotency,
     payload: Json<RemoveFriendRequests>,
     friends_service: &State<Arc<dyn FriendService>>,
-) -> Json<bool> {
-    match friends_service
-        .remove_friend(
-            payload.user_one,
-            payload.user_two,
-        )
-        .await
-    {
-        Ok(()) => Json(true),
-        Err(_) => Json(false),
-    }
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&*payload, || async {
+        ensure_in_pair(user.user_id, payload.user_one, payload.user_two)?;
+        friends_service
+            .remove_friend(
+                payload.user_one,
+                payload.user_two,
+            )
+            .await?;
+        Ok(Json(true))
+    }).await
 }
 
 #[utoipa::path(
@@ -66,29 +88,38 @@ async fn remove_friend(
     request_body = FriendRequests,
     responses(
         (status = 201, description = "Successfully added a friend request", body = bool),
-        (status = 400, description = "Invalid input data"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in", body = ErrorResponse),
+        (status = 403, description = "The logged in user is not part of this friendship", body = ErrorResponse),
+        (status = 409, description = "There already is a request between these users", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     description = "Adds a friend request to the database",
     operation_id = "addFriendRequest",
-    tag = "Friends"
+    tag = "Friends",
+    security(
+        ("jwt_auth" = [])
+    )
 )]
 #[post("/add_friend_request", data = "<payload>")]
 async fn add_friend_request(
+    user: User,
+    idempotency: Idempotency,
     payload: Json<FriendRequests>,
     friends_service: &State<Arc<dyn FriendService>>,
-) -> Json<bool> {
-    match friends_service
-        .add_friend_request(
-            payload.user_one,
-            payload.user_two,
-            payload.sender_id,
-        )
-        .await
-    {
-        Ok(()) => Json(true),
-        Err(_) => Json(false),
-    }
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&*payload, || async {
+        let sender_id = acting_user(&user, payload.sender_id)?;
+        ensure_in_pair(sender_id, payload.user_one, payload.user_two)?;
+        friends_service
+            .add_friend_request(
+                payload.user_one,
+                payload.user_two,
+                sender_id,
+            )
+            .await?;
+        Ok(Json(true))
+    }).await
 }
 
 
@@ -98,38 +129,53 @@ async fn add_friend_request(
     request_body = FriendRequests,
     responses(
         (status = 201, description = "Successfully handled a friend request", body = bool),
-        (status = 400, description = "Invalid input data"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in", body = ErrorResponse),
+        (status = 403, description = "The logged in user is not part of this friendship", body = ErrorResponse),
+        (status = 404, description = "There is no request between these users", body = ErrorResponse),
+        (status = 409, description = "The users are already friends", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     description = "Handles a pending friend request",
     operation_id = "handleFriendRequest",
-    tag = "Friends"
+    tag = "Friends",
+    security(
+        ("jwt_auth" = [])
+    )
 )]
 #[post("/handle_request", data = "<payload>")]
 async fn handle_friend_request(
+    user: User,
+    idempotency: Idempotency,
     payload: Json<HandleFriendRequest>,
     friends_service: &State<Arc<dyn FriendService>>,
-) -> Json<bool> {
-    if payload.request_accepted == true {
-        if friends_service
-            .add_friend(
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&*payload, || async {
+        ensure_in_pair(user.user_id, payload.user_one, payload.user_two)?;
+
+        // Only the receiver can accept a request, the sender can only withdraw it.
+        if payload.request_accepted {
+            match friends_service.get_request_sender(payload.user_one, payload.user_two).await? {
+                Some(sender) if sender == user.user_id => return Err(AppError::Forbidden("own_friend_request")),
+                Some(_) => {}
+                None => return Err(AppError::NotFound("friend_request_not_found")),
+            }
+
+            friends_service
+                .add_friend(
+                    payload.user_one,
+                    payload.user_two,
+                )
+                .await?;
+        }
+        friends_service
+            .remove_friend_request(
                 payload.user_one,
                 payload.user_two,
             )
-            .await.is_err() {
-            return Json(false)
-        }
-    }
-    match friends_service
-        .remove_friend_request(
-            payload.user_one,
-            payload.user_two,
-        )
-        .await
-    {
-        Ok(()) => Json(true),
-        Err(_) => Json(false),
-    }
+            .await?;
+        Ok(Json(true))
+    }).await
 }
 
 // Combine all the friend routes.
@@ -0,0 +1,146 @@
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Request, State};
+use uuid::Uuid;
+
+use crate::controller::acting_user;
+use crate::domain::{AuthenticatedUser, Scope, ServiceKey, TransactionSource, User};
+use crate::error::AppError;
+use crate::service::service_keys::{ServiceKeyService, SERVICE_KEY_PREFIX};
+
+// Routes declare who may call them by the guard they take:
+// - `User` for players only, see main.rs.
+// - `Service<S>` for service keys with scope S only.
+// - `Caller<S>` for both players and service keys with scope S.
+
+/// The scope a route requires from a service key.
+pub trait RequiredScope: Send + Sync + 'static {
+    const SCOPE: Scope;
+}
+
+macro_rules! required_scopes {
+    ($($name:ident),* $(,)?) => {
+        $(
+            pub struct $name;
+
+            impl RequiredScope for $name {
+                const SCOPE: Scope = Scope::$name;
+            }
+        )*
+    };
+}
+
+required_scopes!(
+    GrantXp,
+    GrantCurrency,
+    GrantFish,
+    AddPlaytime,
+    GrantItems,
+    ManageInventory,
+    GrantEffects,
+    ManageEffects,
+    ReadPlayerData,
+    SendMail,
+    ManageKeys,
+    ManageShop,
+);
+
+fn bearer_token<'r>(request: &'r Request<'_>) -> Option<&'r str> {
+    request
+        .headers()
+        .get_one("Authorization")
+        .and_then(|header| header.strip_prefix("Bearer "))
+}
+
+/// A trusted service, such as the game server, calling with a key that has scope S.
+pub struct Service<S: RequiredScope> {
+    pub key: ServiceKey,
+    scope: PhantomData<S>,
+}
+
+#[rocket::async_trait]
+impl<'r, S: RequiredScope> FromRequest<'r> for Service<S> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = match bearer_token(request) {
+            Some(token) => token,
+            None => return Outcome::Error((Status::Unauthorized, ())),
+        };
+        // Players are not allowed on service only routes.
+        if !token.starts_with(SERVICE_KEY_PREFIX) {
+            return Outcome::Error((Status::Forbidden, ()));
+        }
+
+        match verified_service_key(request, token).await {
+            Some(key) if key.scopes.contains(&S::SCOPE) => Outcome::Success(Service {
+                key,
+                scope: PhantomData,
+            }),
+            Some(_) => Outcome::Error((Status::Forbidden, ())),
+            None => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+/// Verifies the service key of a request once, later guards of the same request reuse the result.
+async fn verified_service_key(request: &Request<'_>, token: &str) -> Option<ServiceKey> {
+    request
+        .local_cache_async(async {
+            let service_key_service = request
+                .guard::<&State<Arc<dyn ServiceKeyService>>>()
+                .await
+                .unwrap();
+            service_key_service.verify(token).await.ok().flatten()
+        })
+        .await
+        .clone()
+}
+
+/// Returns who sent the request, a player or a service key, when the credentials are valid.
+pub async fn request_caller(request: &Request<'_>) -> Option<TransactionSource> {
+    match bearer_token(request) {
+        Some(token) if token.starts_with(SERVICE_KEY_PREFIX) => verified_service_key(request, token)
+            .await
+            .map(|key| TransactionSource::Service(key.key_id)),
+        _ => request
+            .guard::<AuthenticatedUser>()
+            .await
+            .succeeded()
+            .map(|authenticated| TransactionSource::Player(authenticated.user.user_id)),
+    }
+}
+
+/// Either a logged in player or a service with scope S.
+pub enum Caller<S: RequiredScope> {
+    Player(User),
+    Service(Service<S>),
+}
+
+impl<S: RequiredScope> Caller<S> {
+    /// Returns the user the request acts on.
+    /// Players can only act on themselves, services have to name the user.
+    pub fn acting_user(&self, claimed: Option<Uuid>) -> Result<Uuid, AppError> {
+        match self {
+            Caller::Player(user) => acting_user(user, claimed),
+            Caller::Service(_) => claimed.ok_or(AppError::BadRequest("user_id_required")),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, S: RequiredScope> FromRequest<'r> for Caller<S> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match bearer_token(request) {
+            Some(token) if token.starts_with(SERVICE_KEY_PREFIX) => {
+                request.guard::<Service<S>>().await.map(Caller::Service)
+            }
+            _ => request.guard::<User>().await.map(Caller::Player),
+        }
+    }
+}
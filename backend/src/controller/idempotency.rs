@@ -0,0 +1,137 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::serde::json::Json;
+use rocket::{Request, State};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::controller::guards::request_caller;
+use crate::domain::TransactionSource;
+use crate::error::{AppError, ErrorResponse};
+use crate::service::idempotency::{IdempotencyService, StoredResponse};
+
+/// The header a client sets to make a POST request safe to retry.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// POST routes that ignore the header. They either have no caller to keep the keys apart,
+/// send credentials, return secrets that should not be stored, or change nothing.
+pub const NOT_IDEMPOTENT_ROUTES: [&str; 9] = [
+    "/account/register",
+    "/account/retreive_username",
+    "/account/request_password_reset",
+    "/account/reset_password",
+    "/account/change_password",
+    "/login",
+    "/login/refresh",
+    "/service_keys/create",
+    "/data/retreive_all_playerdata",
+];
+
+/// The `Idempotency-Key` of a request, if it has one.
+///
+/// Take it after the guard that authenticates the caller and wrap the route body in `run`:
+///
+/// ```ignore
+/// idempotency.run(&*payload, || async {
+///     stats_service.add_xp(payload.user_id, payload.amount).await?;
+///     Ok(Json(true))
+/// }).await
+/// ```
+pub struct Idempotency {
+    request: Option<IdempotentRequest>,
+}
+
+struct IdempotentRequest {
+    key: String,
+    /// Keys are per caller, so two callers can not see each others results.
+    caller: Option<TransactionSource>,
+    route: String,
+    idempotency_service: Arc<dyn IdempotencyService>,
+}
+
+impl Idempotency {
+    /// Runs the route, unless the same request was done before with this key, then its result is returned again.
+    ///
+    /// Errors are stored too, so a retry fails the same way even when it would succeed now.
+    /// Only errors that `AppError::is_retryable` allows to turn out differently are not stored, a retry of those runs again.
+    pub async fn run<P, T, F, Fut>(self, payload: &P, route: F) -> Result<Json<T>, AppError>
+    where
+        P: Serialize + ?Sized,
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Json<T>, AppError>>,
+    {
+        let request = match self.request {
+            Some(request) => request,
+            None => return route().await,
+        };
+        let caller = request.caller.ok_or(AppError::Unauthorized("unauthorized"))?;
+
+        // The body is hashed as parsed, so a retry with other formatting or field order is still the same request.
+        let body = serde_json::to_string(payload).map_err(internal_error)?;
+        let request_hash = format!("{:x}", Sha256::digest(format!("{}\n{}", request.route, body)));
+
+        let service = &request.idempotency_service;
+        if let Some(stored) = service.begin(caller, &request.key, &request_hash).await? {
+            if stored.status == Status::Ok.code {
+                return serde_json::from_str(&stored.body).map(Json).map_err(internal_error);
+            }
+            let error: ErrorResponse = serde_json::from_str(&stored.body).map_err(internal_error)?;
+            let status = Status::from_code(stored.status).unwrap_or(Status::InternalServerError);
+            return Err(AppError::Replayed(status, error.code));
+        }
+
+        let result = route().await;
+        let response = match &result {
+            Ok(Json(value)) => Some(StoredResponse {
+                status: Status::Ok.code,
+                body: serde_json::to_string(value).map_err(internal_error)?,
+            }),
+            Err(e) if !e.is_retryable() => Some(StoredResponse {
+                status: e.status().code,
+                body: serde_json::to_string(&ErrorResponse { code: e.code().to_string() }).map_err(internal_error)?,
+            }),
+            Err(_) => None,
+        };
+        // The route is done at this point, so its result is returned even when it could not be stored.
+        // Retries then get request_in_progress until the key expires, they never run the route again.
+        if let Err(e) = service.finish(caller, &request.key, response).await {
+            dbg!(&e);
+        }
+        result
+    }
+}
+
+fn internal_error(e: serde_json::Error) -> AppError {
+    AppError::Database(sqlx::Error::Decode(e.into()))
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Idempotency {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let key = match request.headers().get_one(IDEMPOTENCY_KEY_HEADER) {
+            Some(key) => key.to_string(),
+            None => return Outcome::Success(Idempotency { request: None }),
+        };
+
+        let idempotency_service = request
+            .guard::<&State<Arc<dyn IdempotencyService>>>()
+            .await
+            .unwrap();
+
+        Outcome::Success(Idempotency {
+            request: Some(IdempotentRequest {
+                key,
+                caller: request_caller(request).await,
+                route: request.uri().path().to_string(),
+                idempotency_service: idempotency_service.inner().clone(),
+            }),
+        })
+    }
+}
@@ -5,29 +5,47 @@ use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 use uuid::Uuid;
 
+use crate::controller::guards::{Caller, GrantItems, ManageInventory, Service};
+use crate::controller::idempotency::Idempotency;
+use crate::domain::ItemStateUpdate;
+use crate::error::{AppError, ErrorResponse};
 use crate::service::inventory::InventoryService;
+use crate::utils::item_state::ItemState;
 
 /// Request body for adding an item.
+/// The state is given either encoded as `state_blob`, or as JSON in `state`.
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct AddOrUpdateItemRequest {
     pub user_id: Uuid,
     pub item_uuid: Uuid,
     pub definition_id: i32,
-    pub state_blob: String,
+    pub state_blob: Option<String>,
+    pub state: Option<ItemState>,
 }
 
-/// Request body for adding an item.
+/// Returns the state of a request that has either a blob or a decoded state.
+fn requested_state(state_blob: &Option<String>, state: &Option<ItemState>) -> Result<ItemState, AppError> {
+    let state = match (state_blob, state) {
+        (Some(blob), None) => ItemState::decode(blob),
+        (None, Some(state)) => state.validate().map(|_| *state),
+        (Some(_), Some(_)) => return Err(AppError::BadRequest("state_blob_and_state")),
+        (None, None) => return Err(AppError::BadRequest("state_required")),
+    };
+    state.map_err(|_| AppError::UnprocessableEntity("invalid_state_blob"))
+}
+
+/// Request body for lowering the durability of an item.
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct DegradeItemRequest {
-    pub user_id: Uuid,
+    pub user_id: Option<Uuid>,
     pub item_uid: Uuid,
     pub amount: i32,
 }
 
-/// Request body for adding an item.
+/// Request body for changing the quantity of an item, a negative amount uses some up.
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct IncreaseItemRequest {
-    pub user_id: Uuid,
+    pub user_id: Option<Uuid>,
     pub item_uid: Uuid,
     pub amount: i32,
 }
@@ -35,7 +53,7 @@ struct IncreaseItemRequest {
 /// Request body for adding an item.
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct DestroyItemRequest {
-    pub user_id: Uuid,
+    pub user_id: Option<Uuid>,
     pub item_uid: Uuid,
 }
 
@@ -49,30 +67,40 @@ struct DestroyItemRequest {
     request_body = AddOrUpdateItemRequest,
     responses(
         (status = 201, description = "Item added/updated successfully", body = bool),
-        (status = 400, description = "Invalid input data"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "No valid service key", body = ErrorResponse),
+        (status = 403, description = "Not called with a service key with the inventory:grant scope", body = ErrorResponse),
+        (status = 409, description = "The item belongs to another user (item_owned_by_other_user), has another definition or is locked in escrow", body = ErrorResponse),
+        (status = 422, description = "The definition is not in the item catalog, the state is malformed, or its quantity does not fit the stack", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     description = "Inserts an item in the database or updates it if it did already exist",
     operation_id = "addOrUpdateItem",
-    tag = "Inventory"
+    tag = "Inventory",
+    security(
+        ("service_key" = ["inventory:grant"])
+    )
 )]
 #[post("/add", data = "<payload>")]
 async fn add_or_update_item(
+    _service: Service<GrantItems>,
+    idempotency: Idempotency,
     payload: Json<AddOrUpdateItemRequest>,
     inventory_service: &State<Arc<dyn InventoryService>>,
-) -> Json<bool> {
-    match inventory_service
-        .add_or_update(
-            payload.user_id,
-            payload.item_uuid,
-            payload.definition_id,
-            payload.state_blob.clone(),
-        )
-        .await
-    {
-        Ok(()) => Json(true),
-        Err(_) => Json(false),
-    }
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&*payload, || async {
+        let user_id = payload.user_id;
+        let state = requested_state(&payload.state_blob, &payload.state)?;
+        inventory_service
+            .add_or_update(
+                user_id,
+                payload.item_uuid,
+                payload.definition_id,
+                state,
+            )
+            .await?;
+        Ok(Json(true))
+    }).await
 }
 
 // Utoipa is the crate that generates swagger documentation for your endpoints.
@@ -84,31 +112,121 @@ async fn add_or_update_item(
     request_body = DestroyItemRequest,
     responses(
         (status = 201, description = "Item removed successfully", body = bool),
-        (status = 400, description = "Invalid input data"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in and no valid service key", body = ErrorResponse),
+        (status = 403, description = "The request names another user, or the service key lacks the inventory:manage scope", body = ErrorResponse),
+        (status = 404, description = "The user does not have this item", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     description = "Removes an item from the database",
     operation_id = "destroyItem",
-    tag = "Inventory"
+    tag = "Inventory",
+    security(
+        ("jwt_auth" = []),
+        ("service_key" = ["inventory:manage"])
+    )
 )]
 #[post("/destroy", data = "<payload>")]
 async fn destroy_item(
+    caller: Caller<ManageInventory>,
+    idempotency: Idempotency,
     payload: Json<DestroyItemRequest>,
     inventory_service: &State<Arc<dyn InventoryService>>,
-) -> Json<bool> {
-    match inventory_service
-        .destroy(
-            payload.user_id,
-            payload.item_uid,
-        )
-        .await
-    {
-        Ok(()) => Json(true),
-        Err(_) => Json(false),
-    }
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&*payload, || async {
+        let user_id = caller.acting_user(payload.user_id)?;
+        inventory_service
+            .destroy(
+                user_id,
+                payload.item_uid,
+            )
+            .await?;
+        Ok(Json(true))
+    }).await
+}
+
+// Utoipa is the crate that generates swagger documentation for your endpoints.
+// The documentation for each endpoint is combined in docs.rs
+// Make sure to add your endpoint in docs.rs when you write new endpoints.
+#[utoipa::path(
+    post,
+    path = "/inventory/degrade",
+    request_body = DegradeItemRequest,
+    responses(
+        (status = 201, description = "Durability lowered, returns the new state", body = ItemStateUpdate),
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in and no valid service key", body = ErrorResponse),
+        (status = 403, description = "The request names another user, or the service key lacks the inventory:manage scope", body = ErrorResponse),
+        (status = 404, description = "The user does not have this item", body = ErrorResponse),
+        (status = 409, description = "The item kept changing concurrently", body = ErrorResponse),
+        (status = 422, description = "The amount is not positive, or the stored state is malformed", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Lowers the durability of an item. An item that reaches zero is destroyed and unselected. Unbreakable items stay as they are",
+    operation_id = "degradeItem",
+    tag = "Inventory",
+    security(
+        ("jwt_auth" = []),
+        ("service_key" = ["inventory:manage"])
+    )
+)]
+#[post("/degrade", data = "<payload>")]
+async fn degrade_item(
+    caller: Caller<ManageInventory>,
+    idempotency: Idempotency,
+    payload: Json<DegradeItemRequest>,
+    inventory_service: &State<Arc<dyn InventoryService>>,
+) -> Result<Json<ItemStateUpdate>, AppError> {
+    idempotency.run(&*payload, || async {
+        let user_id = caller.acting_user(payload.user_id)?;
+        let update = inventory_service
+            .degrade(user_id, payload.item_uid, payload.amount)
+            .await?;
+        Ok(Json(update))
+    }).await
+}
+
+// Utoipa is the crate that generates swagger documentation for your endpoints.
+// The documentation for each endpoint is combined in docs.rs
+// Make sure to add your endpoint in docs.rs when you write new endpoints.
+#[utoipa::path(
+    post,
+    path = "/inventory/increase",
+    request_body = IncreaseItemRequest,
+    responses(
+        (status = 201, description = "Quantity changed, returns the new state", body = ItemStateUpdate),
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "No valid service key", body = ErrorResponse),
+        (status = 403, description = "Not called with a service key with the inventory:grant scope", body = ErrorResponse),
+        (status = 404, description = "The user does not have this item", body = ErrorResponse),
+        (status = 409, description = "The item kept changing concurrently", body = ErrorResponse),
+        (status = 422, description = "The amount is zero, the stack would be negative or exceed its limit, or the stored state is malformed", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Changes the quantity of an item. An item that reaches zero is destroyed and unselected",
+    operation_id = "increaseItem",
+    tag = "Inventory",
+    security(
+        ("service_key" = ["inventory:grant"])
+    )
+)]
+#[post("/increase", data = "<payload>")]
+async fn increase_item(
+    _service: Service<GrantItems>,
+    idempotency: Idempotency,
+    payload: Json<IncreaseItemRequest>,
+    inventory_service: &State<Arc<dyn InventoryService>>,
+) -> Result<Json<ItemStateUpdate>, AppError> {
+    idempotency.run(&*payload, || async {
+        let user_id = payload.user_id.ok_or(AppError::BadRequest("user_id_required"))?;
+        let update = inventory_service
+            .increase(user_id, payload.item_uid, payload.amount)
+            .await?;
+        Ok(Json(update))
+    }).await
 }
 
 // Combine all the inventory routes.
 pub fn inventory_routes() -> Vec<rocket::Route> {
-    routes![add_or_update_item, destroy_item]
+    routes![add_or_update_item, destroy_item, degrade_item, increase_item]
 }
@@ -0,0 +1,61 @@
+use std::sync::Arc;
+
+use rocket::http::{Header, Status};
+use rocket::response::{self, Responder, Response};
+use rocket::{get, routes, serde::json::Json, Request, State};
+
+use crate::domain::ItemCatalogResponse;
+use crate::error::ErrorResponse;
+use crate::utils::catalog::ItemCatalog;
+
+/// The catalog with its hash as ETag.
+/// Clients that send the hash they have in If-None-Match get a 304 without body when it did not change.
+struct CachedCatalog(ItemCatalogResponse);
+
+impl<'r> Responder<'r, 'static> for CachedCatalog {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let etag = format!("\"{}\"", self.0.hash);
+        let unchanged = request
+            .headers()
+            .get_one("If-None-Match")
+            .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+
+        let mut response = if unchanged {
+            Response::build().status(Status::NotModified).finalize()
+        } else {
+            Json(self.0).respond_to(request)?
+        };
+        response.set_header(Header::new("ETag", etag));
+        // The client may keep it, but has to ask if it is still current before using it.
+        response.set_header(Header::new("Cache-Control", "no-cache"));
+        Ok(response)
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/items/catalog",
+    params(
+        ("If-None-Match" = Option<String>, Header, description = "The ETag of the catalog the client has"),
+    ),
+    responses(
+        (status = 200, description = "All item definitions, the hash is also send as ETag", body = ItemCatalogResponse),
+        (status = 304, description = "The catalog did not change since the given ETag"),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Returns the item definitions of the game",
+    operation_id = "itemCatalog",
+    tag = "Items",
+)]
+#[get("/catalog")]
+async fn item_catalog(catalog: &State<Arc<ItemCatalog>>) -> CachedCatalog {
+    CachedCatalog(ItemCatalogResponse {
+        hash: catalog.hash().to_string(),
+        items: catalog.definitions(),
+    })
+}
+
+// Combine all the item routes.
+pub fn item_routes() -> Vec<rocket::Route> {
+    routes![item_catalog]
+}
@@ -0,0 +1,243 @@
+use rocket::{get, routes, serde::json::Json, State};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::controller::guards::{Caller, ReadPlayerData};
+use crate::domain::{FriendLeaderboard, Leaderboard, LeaderboardPage};
+use crate::error::{AppError, ErrorResponse};
+use crate::service::leaderboards::LeaderboardService;
+
+fn player_board(board: &str) -> Result<Leaderboard, AppError> {
+    Leaderboard::parse(board).ok_or(AppError::NotFound("unknown_leaderboard"))
+}
+
+// Utoipa is the crate that generates swagger documentation for your endpoints.
+// The documentation for each endpoint is combined in docs.rs
+// Make sure to add your endpoint in docs.rs when you write new endpoints.
+#[utoipa::path(
+    get,
+    path = "/leaderboards/{board}",
+    params(
+        ("board" = String, Path, description = "xp, catches or species"),
+        ("start" = Option<i64>, Query, description = "The first position, 1 by default"),
+        ("limit" = Option<i64>, Query, description = "Entries per page, 50 by default and at most 200"),
+    ),
+    responses(
+        (status = 200, description = "A page of the leaderboard, best first", body = LeaderboardPage),
+        (status = 401, description = "Not logged in and no valid service key", body = ErrorResponse),
+        (status = 403, description = "The service key lacks the data:read scope", body = ErrorResponse),
+        (status = 404, description = "The board does not exist", body = ErrorResponse),
+        (status = 422, description = "The start or limit is out of range", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Lists the players with the most xp, fish caught or species caught. The boards are refreshed every few minutes",
+    operation_id = "leaderboard",
+    tag = "Leaderboards",
+    security(
+        ("jwt_auth" = []),
+        ("service_key" = ["data:read"])
+    )
+)]
+#[get("/<board>?<start>&<limit>")]
+async fn leaderboard(
+    _caller: Caller<ReadPlayerData>,
+    board: &str,
+    start: Option<i64>,
+    limit: Option<i64>,
+    leaderboard_service: &State<Arc<dyn LeaderboardService>>,
+) -> Result<Json<LeaderboardPage>, AppError> {
+    let board = player_board(board)?;
+    Ok(Json(leaderboard_service.page(board, start, limit).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/leaderboards/{board}/around",
+    params(
+        ("board" = String, Path, description = "xp, catches or species"),
+        ("user_id" = Option<Uuid>, Query, description = "The user, required for services"),
+        ("neighbours" = Option<i64>, Query, description = "How many players above and below, 5 by default and at most 50"),
+    ),
+    responses(
+        (status = 200, description = "The user and the players around them", body = LeaderboardPage),
+        (status = 400, description = "A service did not name the user", body = ErrorResponse),
+        (status = 401, description = "Not logged in and no valid service key", body = ErrorResponse),
+        (status = 403, description = "The request names another user, or the service key lacks the data:read scope", body = ErrorResponse),
+        (status = 404, description = "The board does not exist, or the user is not on it yet", body = ErrorResponse),
+        (status = 422, description = "The number of neighbours is out of range", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Finds the rank of a user on a leaderboard, with the players just above and below",
+    operation_id = "leaderboardAround",
+    tag = "Leaderboards",
+    security(
+        ("jwt_auth" = []),
+        ("service_key" = ["data:read"])
+    )
+)]
+// Ranked below the fish routes, which also match `/fish/around`.
+#[get("/<board>/around?<user_id>&<neighbours>", rank = 2)]
+async fn leaderboard_around(
+    caller: Caller<ReadPlayerData>,
+    board: &str,
+    user_id: Option<Uuid>,
+    neighbours: Option<i64>,
+    leaderboard_service: &State<Arc<dyn LeaderboardService>>,
+) -> Result<Json<LeaderboardPage>, AppError> {
+    let user_id = caller.acting_user(user_id)?;
+    let board = player_board(board)?;
+    Ok(Json(leaderboard_service.around(board, user_id, neighbours).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/leaderboards/fish/{fish_id}",
+    params(
+        ("fish_id" = i32, Path, description = "The species"),
+        ("start" = Option<i64>, Query, description = "The first position, 1 by default"),
+        ("limit" = Option<i64>, Query, description = "Entries per page, 50 by default and at most 200"),
+    ),
+    responses(
+        (status = 200, description = "A page of the leaderboard, longest fish first", body = LeaderboardPage),
+        (status = 401, description = "Not logged in and no valid service key", body = ErrorResponse),
+        (status = 403, description = "The service key lacks the data:read scope", body = ErrorResponse),
+        (status = 422, description = "The start or limit is out of range", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Lists the players with the longest fish of a species. The boards are refreshed every few minutes",
+    operation_id = "fishLeaderboard",
+    tag = "Leaderboards",
+    security(
+        ("jwt_auth" = []),
+        ("service_key" = ["data:read"])
+    )
+)]
+#[get("/fish/<fish_id>?<start>&<limit>")]
+async fn fish_leaderboard(
+    _caller: Caller<ReadPlayerData>,
+    fish_id: i32,
+    start: Option<i64>,
+    limit: Option<i64>,
+    leaderboard_service: &State<Arc<dyn LeaderboardService>>,
+) -> Result<Json<LeaderboardPage>, AppError> {
+    Ok(Json(leaderboard_service.page(Leaderboard::FishLength(fish_id), start, limit).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/leaderboards/fish/{fish_id}/around",
+    params(
+        ("fish_id" = i32, Path, description = "The species"),
+        ("user_id" = Option<Uuid>, Query, description = "The user, required for services"),
+        ("neighbours" = Option<i64>, Query, description = "How many players above and below, 5 by default and at most 50"),
+    ),
+    responses(
+        (status = 200, description = "The user and the players around them", body = LeaderboardPage),
+        (status = 400, description = "A service did not name the user", body = ErrorResponse),
+        (status = 401, description = "Not logged in and no valid service key", body = ErrorResponse),
+        (status = 403, description = "The request names another user, or the service key lacks the data:read scope", body = ErrorResponse),
+        (status = 404, description = "The user is not on the board yet", body = ErrorResponse),
+        (status = 422, description = "The number of neighbours is out of range", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Finds the rank of a user on the leaderboard of a species, with the players just above and below",
+    operation_id = "fishLeaderboardAround",
+    tag = "Leaderboards",
+    security(
+        ("jwt_auth" = []),
+        ("service_key" = ["data:read"])
+    )
+)]
+#[get("/fish/<fish_id>/around?<user_id>&<neighbours>")]
+async fn fish_leaderboard_around(
+    caller: Caller<ReadPlayerData>,
+    fish_id: i32,
+    user_id: Option<Uuid>,
+    neighbours: Option<i64>,
+    leaderboard_service: &State<Arc<dyn LeaderboardService>>,
+) -> Result<Json<LeaderboardPage>, AppError> {
+    let user_id = caller.acting_user(user_id)?;
+    Ok(Json(leaderboard_service.around(Leaderboard::FishLength(fish_id), user_id, neighbours).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/leaderboards/{board}/friends",
+    params(
+        ("board" = String, Path, description = "xp, catches or species"),
+        ("user_id" = Option<Uuid>, Query, description = "The user, required for services"),
+    ),
+    responses(
+        (status = 200, description = "The user and their friends, best first", body = FriendLeaderboard),
+        (status = 400, description = "A service did not name the user", body = ErrorResponse),
+        (status = 401, description = "Not logged in and no valid service key", body = ErrorResponse),
+        (status = 403, description = "The request names another user, or the service key lacks the data:read scope", body = ErrorResponse),
+        (status = 404, description = "The board or the user does not exist", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Ranks the user among their friends by current scores, with how each rank changed since the weekly reset on Monday",
+    operation_id = "friendsLeaderboard",
+    tag = "Leaderboards",
+    security(
+        ("jwt_auth" = []),
+        ("service_key" = ["data:read"])
+    )
+)]
+// Ranked below the fish routes, which also match `/fish/friends`.
+#[get("/<board>/friends?<user_id>", rank = 2)]
+async fn friends_leaderboard(
+    caller: Caller<ReadPlayerData>,
+    board: &str,
+    user_id: Option<Uuid>,
+    leaderboard_service: &State<Arc<dyn LeaderboardService>>,
+) -> Result<Json<FriendLeaderboard>, AppError> {
+    let user_id = caller.acting_user(user_id)?;
+    let board = player_board(board)?;
+    Ok(Json(leaderboard_service.friends(board, user_id).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/leaderboards/fish/{fish_id}/friends",
+    params(
+        ("fish_id" = i32, Path, description = "The species"),
+        ("user_id" = Option<Uuid>, Query, description = "The user, required for services"),
+    ),
+    responses(
+        (status = 200, description = "The user and their friends, longest fish first", body = FriendLeaderboard),
+        (status = 400, description = "A service did not name the user", body = ErrorResponse),
+        (status = 401, description = "Not logged in and no valid service key", body = ErrorResponse),
+        (status = 403, description = "The request names another user, or the service key lacks the data:read scope", body = ErrorResponse),
+        (status = 404, description = "The user does not exist", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Ranks the user among their friends by their longest fish of a species, with how each rank changed since the weekly reset on Monday",
+    operation_id = "fishFriendsLeaderboard",
+    tag = "Leaderboards",
+    security(
+        ("jwt_auth" = []),
+        ("service_key" = ["data:read"])
+    )
+)]
+#[get("/fish/<fish_id>/friends?<user_id>")]
+async fn fish_friends_leaderboard(
+    caller: Caller<ReadPlayerData>,
+    fish_id: i32,
+    user_id: Option<Uuid>,
+    leaderboard_service: &State<Arc<dyn LeaderboardService>>,
+) -> Result<Json<FriendLeaderboard>, AppError> {
+    let user_id = caller.acting_user(user_id)?;
+    Ok(Json(leaderboard_service.friends(Leaderboard::FishLength(fish_id), user_id).await?))
+}
+
+// Combine all the leaderboard routes.
+pub fn leaderboard_routes() -> Vec<rocket::Route> {
+    routes![
+        leaderboard,
+        leaderboard_around,
+        friends_leaderboard,
+        fish_leaderboard,
+        fish_leaderboard_around,
+        fish_friends_leaderboard,
+    ]
+}
@@ -1,32 +1,80 @@
-use rocket::{post, routes, serde::json::Json, State};
+use chrono::{DateTime, Utc};
+use rocket::{get, post, routes, serde::json::Json, FromForm, State};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use utoipa::ToSchema;
 use uuid::Uuid;
 
+use crate::controller::acting_user;
+use crate::controller::guards::{Caller, ReadPlayerData, SendMail, Service};
+use crate::controller::idempotency::Idempotency;
+use crate::domain::{
+    BroadcastFilter, ClaimedAttachments, MailAttachment, MailFilter, MailPage, NewMail, UnreadCount, User,
+};
+use crate::error::{AppError, ErrorResponse};
 use crate::service::mail::MailService;
 
+/// Query parameters for listing a mailbox.
+#[derive(Debug, FromForm)]
+struct ListMailQuery {
+    user_id: Option<Uuid>,
+    read: Option<bool>,
+    archived: Option<bool>,
+    headers_only: Option<bool>,
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
 /// Request body for creating a mail.
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct CreateMailRequest {
     pub mail_id: Uuid,
-    pub sender_id: Uuid,
+    pub sender_id: Option<Uuid>,
     pub receiver_ids: Vec<Uuid>,
     pub title: String,
     pub message: String,
+    /// Items and currency every receiver can claim once. Only services can attach something.
+    #[serde(default)]
+    pub attachments: Vec<MailAttachment>,
+    /// After this the mail is no longer shown and is removed by the retention job.
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Request body for broadcasting a system mail.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct BroadcastMailRequest {
+    pub mail_id: Uuid,
+    pub title: String,
+    pub message: String,
+    /// Items and currency every receiver can claim once.
+    #[serde(default)]
+    pub attachments: Vec<MailAttachment>,
+    #[serde(flatten)]
+    pub filter: BroadcastFilter,
+    /// After this the mail is no longer shown or delivered.
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Request body for claiming the attachments of a mail.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct ClaimMailRequest {
+    pub user_id: Option<Uuid>,
+    pub mail_id: Uuid,
 }
 
 /// Request body for deleting a mail.
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct DeleteMailRequest {
-    pub user_id: Uuid,
+    pub user_id: Option<Uuid>,
     pub mail_id: Uuid,
 }
 
 /// Request body for reading a mail.
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct ReadMailRequest {
-    pub user_id: Uuid,
+    pub user_id: Option<Uuid>,
     pub mail_id: Uuid,
     pub read: bool,
 }
@@ -34,42 +82,181 @@ struct ReadMailRequest {
 /// Request body for archiving a mail.
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct ArchiveMailRequest {
-    pub user_id: Uuid,
+    pub user_id: Option<Uuid>,
     pub mail_id: Uuid,
     pub archived: bool,
 }
 
+#[utoipa::path(
+    get,
+    path = "/mail",
+    params(
+        ("user_id" = Option<Uuid>, Query, description = "The user, required for services"),
+        ("read" = Option<bool>, Query, description = "Only read mail when true, only unread mail when false"),
+        ("archived" = Option<bool>, Query, description = "Only archived mail when true, only mail that is not archived when false"),
+        ("headers_only" = Option<bool>, Query, description = "Leave out the messages"),
+        ("cursor" = Option<String>, Query, description = "The next_cursor of the previous page"),
+        ("limit" = Option<i64>, Query, description = "How many mails, 50 by default and at most 200"),
+    ),
+    responses(
+        (status = 200, description = "A page of the mailbox, the newest mail first", body = MailPage),
+        (status = 400, description = "A service did not name the user, or the cursor is invalid", body = ErrorResponse),
+        (status = 401, description = "Not logged in and no valid service key", body = ErrorResponse),
+        (status = 403, description = "The request names another user, or the service key lacks the data:read scope", body = ErrorResponse),
+        (status = 422, description = "The limit is out of range", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Lists the mailbox of a user page by page. Expired mail is left out",
+    operation_id = "listMail",
+    tag = "Mails",
+    security(
+        ("jwt_auth" = []),
+        ("service_key" = ["data:read"])
+    )
+)]
+#[get("/?<query..>")]
+async fn list_mail(
+    caller: Caller<ReadPlayerData>,
+    query: ListMailQuery,
+    mail_service: &State<Arc<dyn MailService>>,
+) -> Result<Json<MailPage>, AppError> {
+    let user_id = caller.acting_user(query.user_id)?;
+    let filter = MailFilter {
+        read: query.read,
+        archived: query.archived,
+    };
+    let page = mail_service
+        .list(user_id, filter, query.cursor, query.limit, query.headers_only.unwrap_or(false))
+        .await?;
+    Ok(Json(page))
+}
+
+#[utoipa::path(
+    get,
+    path = "/mail/unread_count",
+    params(
+        ("user_id" = Option<Uuid>, Query, description = "The user, required for services"),
+    ),
+    responses(
+        (status = 200, description = "The number of unread mails", body = UnreadCount),
+        (status = 400, description = "A service did not name the user", body = ErrorResponse),
+        (status = 401, description = "Not logged in and no valid service key", body = ErrorResponse),
+        (status = 403, description = "The request names another user, or the service key lacks the data:read scope", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Counts the unread mail of a user, cheap enough to poll for a badge",
+    operation_id = "countUnreadMail",
+    tag = "Mails",
+    security(
+        ("jwt_auth" = []),
+        ("service_key" = ["data:read"])
+    )
+)]
+#[get("/unread_count?<user_id>")]
+async fn unread_count(
+    caller: Caller<ReadPlayerData>,
+    user_id: Option<Uuid>,
+    mail_service: &State<Arc<dyn MailService>>,
+) -> Result<Json<UnreadCount>, AppError> {
+    let user_id = caller.acting_user(user_id)?;
+    Ok(Json(mail_service.unread_count(user_id).await?))
+}
+
 #[utoipa::path(
     post,
     path = "/mail/create",
     request_body = CreateMailRequest,
     responses(
         (status = 201, description = "Mail created successfully", body = bool),
-        (status = 400, description = "Invalid input data"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in and no valid service key", body = ErrorResponse),
+        (status = 403, description = "The request names another user, a player tried to attach something, or the service key lacks the mail:send scope", body = ErrorResponse),
+        (status = 409, description = "A mail with this id already exists", body = ErrorResponse),
+        (status = 422, description = "A receiver does not exist, the expiry is in the past, or an attachment is invalid", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     description = "Creates a mail",
     operation_id = "createMail",
-    tag = "Mails"
+    tag = "Mails",
+    security(
+        ("jwt_auth" = []),
+        ("service_key" = ["mail:send"])
+    )
 )]
 #[post("/create", data = "<payload>")]
 async fn create_mail(
+    caller: Caller<SendMail>,
+    idempotency: Idempotency,
     payload: Json<CreateMailRequest>,
     mail_service: &State<Arc<dyn MailService>>,
-) -> Json<bool> {
-    match mail_service
-        .create(
-            payload.mail_id,
-            payload.sender_id,
-            payload.receiver_ids.clone(),
-            payload.title.clone(),
-            payload.message.clone()
-        )
-        .await
-    {
-        Ok(()) => Json(true),
-        Err(_) => Json(false),
-    }
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&*payload, || async {
+        let user_id = caller.acting_user(payload.sender_id)?;
+        // Players could otherwise create items and currency out of nothing.
+        if matches!(caller, Caller::Player(_)) && !payload.attachments.is_empty() {
+            return Err(AppError::Forbidden("attachments_require_service"));
+        }
+        mail_service
+            .create(
+                NewMail {
+                    mail_id: payload.mail_id,
+                    sender_id: Some(user_id),
+                    title: payload.title.clone(),
+                    message: payload.message.clone(),
+                    attachments: payload.attachments.clone(),
+                    expires_at: payload.expires_at,
+                },
+                payload.receiver_ids.clone(),
+            )
+            .await?;
+        Ok(Json(true))
+    }).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/mail/broadcast",
+    request_body = BroadcastMailRequest,
+    responses(
+        (status = 201, description = "Mail broadcast successfully", body = bool),
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "No valid service key", body = ErrorResponse),
+        (status = 403, description = "Not called with a service key with the mail:send scope", body = ErrorResponse),
+        (status = 409, description = "A mail with this id already exists", body = ErrorResponse),
+        (status = 422, description = "The expiry is in the past, min_xp is negative, or an attachment is invalid", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Sends a mail without sender to all players, or to those registered before a date or with at least some xp. \
+        Players get it in their mailbox the next time their data is fetched, also when they register after the broadcast",
+    operation_id = "broadcastMail",
+    tag = "Mails",
+    security(
+        ("service_key" = ["mail:send"])
+    )
+)]
+#[post("/broadcast", data = "<payload>")]
+async fn broadcast_mail(
+    _service: Service<SendMail>,
+    idempotency: Idempotency,
+    payload: Json<BroadcastMailRequest>,
+    mail_service: &State<Arc<dyn MailService>>,
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&*payload, || async {
+        mail_service
+            .broadcast(
+                NewMail {
+                    mail_id: payload.mail_id,
+                    sender_id: None,
+                    title: payload.title.clone(),
+                    message: payload.message.clone(),
+                    attachments: payload.attachments.clone(),
+                    expires_at: payload.expires_at,
+                },
+                payload.filter.clone(),
+            )
+            .await?;
+        Ok(Json(true))
+    }).await
 }
 
 #[utoipa::path(
@@ -78,22 +265,31 @@ async fn create_mail(
     request_body = DeleteMailRequest,
     responses(
         (status = 201, description = "Mail successfully deleted", body = bool),
-        (status = 400, description = "Invalid input data"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in", body = ErrorResponse),
+        (status = 403, description = "The request names another user", body = ErrorResponse),
+        (status = 404, description = "The mail is not in the mailbox of the user", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     description = "Deletes a mail",
     operation_id = "deleteMail",
-    tag = "Mails"
+    tag = "Mails",
+    security(
+        ("jwt_auth" = [])
+    )
 )]
 #[post("/delete", data = "<payload>")]
 async fn delete_mail(
+    user: User,
+    idempotency: Idempotency,
     payload: Json<DeleteMailRequest>,
     mail_service: &State<Arc<dyn MailService>>,
-) -> Json<bool> {
-    match mail_service.delete(payload.user_id, payload.mail_id).await {
-        Ok(()) => Json(true),
-        Err(_) => Json(false),
-    }
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&*payload, || async {
+        let user_id = acting_user(&user, payload.user_id)?;
+        mail_service.delete(user_id, payload.mail_id).await?;
+        Ok(Json(true))
+    }).await
 }
 
 #[utoipa::path(
@@ -102,25 +298,33 @@ async fn delete_mail(
     request_body = ReadMailRequest,
     responses(
         (status = 201, description = "Mail read state changed successfully", body = bool),
-        (status = 400, description = "Invalid input data"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in", body = ErrorResponse),
+        (status = 403, description = "The request names another user", body = ErrorResponse),
+        (status = 404, description = "The mail is not in the mailbox of the user", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     description = "Changes the read state",
     operation_id = "readStateMail",
-    tag = "Mails"
+    tag = "Mails",
+    security(
+        ("jwt_auth" = [])
+    )
 )]
 #[post("/change_read_state", data = "<payload>")]
 async fn change_read_state(
+    user: User,
+    idempotency: Idempotency,
     payload: Json<ReadMailRequest>,
     mail_service: &State<Arc<dyn MailService>>,
-) -> Json<bool> {
-    match mail_service
-        .change_read_state(payload.user_id, payload.mail_id, payload.read)
-        .await
-    {
-        Ok(()) => Json(true),
-        Err(_) => Json(false),
-    }
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&*payload, || async {
+        let user_id = acting_user(&user, payload.user_id)?;
+        mail_service
+            .change_read_state(user_id, payload.mail_id, payload.read)
+            .await?;
+        Ok(Json(true))
+    }).await
 }
 
 #[utoipa::path(
@@ -129,28 +333,70 @@ async fn change_read_state(
     request_body = ArchiveMailRequest,
     responses(
         (status = 201, description = "Mail archive state changed successfully", body = bool),
-        (status = 400, description = "Invalid input data"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in", body = ErrorResponse),
+        (status = 403, description = "The request names another user", body = ErrorResponse),
+        (status = 404, description = "The mail is not in the mailbox of the user", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     description = "Changes the archived state",
     operation_id = "archiveStateMail",
-    tag = "Mails"
+    tag = "Mails",
+    security(
+        ("jwt_auth" = [])
+    )
 )]
 #[post("/archive_state", data = "<payload>")]
 async fn change_archive_state(
+    user: User,
+    idempotency: Idempotency,
     payload: Json<ArchiveMailRequest>,
     mail_service: &State<Arc<dyn MailService>>,
-) -> Json<bool> {
-    match mail_service
-        .change_archive_state(payload.user_id, payload.mail_id, payload.archived)
-        .await
-    {
-        Ok(()) => Json(true),
-        Err(_) => Json(false),
-    }
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&*payload, || async {
+        let user_id = acting_user(&user, payload.user_id)?;
+        mail_service
+            .change_archive_state(user_id, payload.mail_id, payload.archived)
+            .await?;
+        Ok(Json(true))
+    }).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/mail/claim",
+    request_body = ClaimMailRequest,
+    responses(
+        (status = 201, description = "Attachments claimed, returns what was granted", body = ClaimedAttachments),
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in", body = ErrorResponse),
+        (status = 403, description = "The request names another user", body = ErrorResponse),
+        (status = 404, description = "The mail is not in the mailbox of the user", body = ErrorResponse),
+        (status = 409, description = "The attachments were claimed already", body = ErrorResponse),
+        (status = 422, description = "The mail has no attachments", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Grants the items and currency attached to a mail. Every recipient can claim them once",
+    operation_id = "claimMail",
+    tag = "Mails",
+    security(
+        ("jwt_auth" = [])
+    )
+)]
+#[post("/claim", data = "<payload>")]
+async fn claim_mail(
+    user: User,
+    idempotency: Idempotency,
+    payload: Json<ClaimMailRequest>,
+    mail_service: &State<Arc<dyn MailService>>,
+) -> Result<Json<ClaimedAttachments>, AppError> {
+    idempotency.run(&*payload, || async {
+        let user_id = acting_user(&user, payload.user_id)?;
+        Ok(Json(mail_service.claim(user_id, payload.mail_id).await?))
+    }).await
 }
 
 // Combine all the user routes.
 pub fn mail_routes() -> Vec<rocket::Route> {
-    routes![create_mail, delete_mail, change_read_state, change_archive_state]
+    routes![list_mail, unread_count, create_mail, broadcast_mail, delete_mail, change_read_state, change_archive_state, claim_mail]
 }
@@ -1,9 +1,33 @@
 /// The controller builds from http requests concrete types and validates if the request is correct.
+pub mod achievements;
 pub mod authentication;
 pub mod data;
 pub mod effects;
 pub mod friends;
+pub mod guards;
+pub mod idempotency;
 pub mod inventory;
+pub mod items;
+pub mod leaderboards;
 pub mod mail;
+pub mod quests;
+pub mod service_keys;
+pub mod shop;
 pub mod stats;
+pub mod trade;
 pub mod user;
+
+use crate::domain::User;
+use crate::error::AppError;
+use uuid::Uuid;
+
+/// Returns the user a player facing request acts on, which is always the logged in user.
+///
+/// Request bodies may still name a user, for example older clients send `user_id`.
+/// When that is someone else the request is rejected with 403.
+pub fn acting_user(user: &User, claimed: Option<Uuid>) -> Result<Uuid, AppError> {
+    match claimed {
+        Some(claimed) if claimed != user.user_id => Err(AppError::Forbidden("other_user")),
+        _ => Ok(user.user_id),
+    }
+}
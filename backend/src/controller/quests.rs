@@ -0,0 +1,93 @@
+use rocket::{get, post, routes, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::controller::acting_user;
+use crate::controller::guards::{Caller, ReadPlayerData};
+use crate::controller::idempotency::Idempotency;
+use crate::domain::{GrantedReward, QuestList, User};
+use crate::error::{AppError, ErrorResponse};
+use crate::service::quests::QuestService;
+
+/// Request body for claiming the reward of a quest.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct ClaimQuestRequest {
+    pub user_id: Option<Uuid>,
+    pub quest_id: String,
+}
+
+// Utoipa is the crate that generates swagger documentation for your endpoints.
+// The documentation for each endpoint is combined in docs.rs
+// Make sure to add your endpoint in docs.rs when you write new endpoints.
+#[utoipa::path(
+    get,
+    path = "/quests",
+    params(
+        ("user_id" = Option<Uuid>, Query, description = "The user, required for services"),
+    ),
+    responses(
+        (status = 200, description = "The daily and weekly quests of the user, with when they reset", body = QuestList),
+        (status = 400, description = "A service did not name the user", body = ErrorResponse),
+        (status = 401, description = "Not logged in and no valid service key", body = ErrorResponse),
+        (status = 403, description = "The request names another user, or the service key lacks the data:read scope", body = ErrorResponse),
+        (status = 404, description = "The user does not exist", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Lists the quests of the user for the current day and week, they are picked on the first request of a period",
+    operation_id = "quests",
+    tag = "Quests",
+    security(
+        ("jwt_auth" = []),
+        ("service_key" = ["data:read"])
+    )
+)]
+#[get("/?<user_id>")]
+async fn quests(
+    caller: Caller<ReadPlayerData>,
+    user_id: Option<Uuid>,
+    quest_service: &State<Arc<dyn QuestService>>,
+) -> Result<Json<QuestList>, AppError> {
+    let user_id = caller.acting_user(user_id)?;
+    Ok(Json(quest_service.list(user_id).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/quests/claim",
+    request_body = ClaimQuestRequest,
+    responses(
+        (status = 201, description = "The reward was granted, or sent as a mail", body = GrantedReward),
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in", body = ErrorResponse),
+        (status = 403, description = "The request names another user", body = ErrorResponse),
+        (status = 404, description = "The user does not have the quest this day or week", body = ErrorResponse),
+        (status = 409, description = "The reward was claimed already", body = ErrorResponse),
+        (status = 422, description = "The quest is not completed", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Grants the reward of a completed quest of the current day or week, once",
+    operation_id = "claimQuest",
+    tag = "Quests",
+    security(
+        ("jwt_auth" = [])
+    )
+)]
+#[post("/claim", data = "<payload>")]
+async fn claim_quest(
+    user: User,
+    idempotency: Idempotency,
+    payload: Json<ClaimQuestRequest>,
+    quest_service: &State<Arc<dyn QuestService>>,
+) -> Result<Json<GrantedReward>, AppError> {
+    idempotency.run(&*payload, || async {
+        let user_id = acting_user(&user, payload.user_id)?;
+        Ok(Json(quest_service.claim(user_id, &payload.quest_id).await?))
+    }).await
+}
+
+// Combine all the quest routes.
+pub fn quest_routes() -> Vec<rocket::Route> {
+    routes![quests, claim_quest]
+}
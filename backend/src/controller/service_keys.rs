@@ -0,0 +1,123 @@
+use std::sync::Arc;
+
+use rocket::{get, post, routes, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::controller::guards::{ManageKeys, Service};
+use crate::controller::idempotency::Idempotency;
+use crate::domain::{Scope, ServiceKey};
+use crate::error::{AppError, ErrorResponse};
+use crate::service::service_keys::ServiceKeyService;
+
+/// Request body for creating a service key.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct CreateServiceKeyRequest {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+}
+
+/// Response with a newly created service key.
+/// The `secret` is the value to send as bearer token, it can not be retreived again.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct CreateServiceKeyResponse {
+    pub key: ServiceKey,
+    pub secret: String,
+}
+
+/// Request body for revoking a service key.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct RevokeServiceKeyRequest {
+    pub key_id: Uuid,
+}
+
+#[utoipa::path(
+    post,
+    path = "/service_keys/create",
+    request_body = CreateServiceKeyRequest,
+    responses(
+        (status = 201, description = "Service key created", body = CreateServiceKeyResponse),
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "No valid service key", body = ErrorResponse),
+        (status = 403, description = "The service key lacks the keys:manage scope", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Creates a service key with the given scopes",
+    operation_id = "createServiceKey",
+    tag = "Service keys",
+    security(
+        ("service_key" = ["keys:manage"])
+    )
+)]
+#[post("/create", data = "<payload>")]
+async fn create_service_key(
+    _service: Service<ManageKeys>,
+    payload: Json<CreateServiceKeyRequest>,
+    service_key_service: &State<Arc<dyn ServiceKeyService>>,
+) -> Result<Json<CreateServiceKeyResponse>, AppError> {
+    let payload = payload.into_inner();
+    let (key, secret) = service_key_service.create(payload.name, payload.scopes).await?;
+    Ok(Json(CreateServiceKeyResponse { key, secret }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/service_keys/revoke",
+    request_body = RevokeServiceKeyRequest,
+    responses(
+        (status = 201, description = "Service key revoked", body = bool),
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "No valid service key", body = ErrorResponse),
+        (status = 403, description = "The service key lacks the keys:manage scope", body = ErrorResponse),
+        (status = 404, description = "There is no active key with this id", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Revokes a service key, it can not be used anymore afterwards",
+    operation_id = "revokeServiceKey",
+    tag = "Service keys",
+    security(
+        ("service_key" = ["keys:manage"])
+    )
+)]
+#[post("/revoke", data = "<payload>")]
+async fn revoke_service_key(
+    _service: Service<ManageKeys>,
+    idempotency: Idempotency,
+    payload: Json<RevokeServiceKeyRequest>,
+    service_key_service: &State<Arc<dyn ServiceKeyService>>,
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&*payload, || async {
+        service_key_service.revoke(payload.key_id).await?;
+        Ok(Json(true))
+    }).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/service_keys",
+    responses(
+        (status = 201, description = "All service keys", body = Vec<ServiceKey>),
+        (status = 401, description = "No valid service key", body = ErrorResponse),
+        (status = 403, description = "The service key lacks the keys:manage scope", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Lists all service keys, including revoked ones",
+    operation_id = "listServiceKeys",
+    tag = "Service keys",
+    security(
+        ("service_key" = ["keys:manage"])
+    )
+)]
+#[get("/")]
+async fn list_service_keys(
+    _service: Service<ManageKeys>,
+    service_key_service: &State<Arc<dyn ServiceKeyService>>,
+) -> Result<Json<Vec<ServiceKey>>, AppError> {
+    Ok(Json(service_key_service.list().await?))
+}
+
+// Combine all the service key routes.
+pub fn service_key_routes() -> Vec<rocket::Route> {
+    routes![create_service_key, revoke_service_key, list_service_keys]
+}
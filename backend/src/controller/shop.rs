@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use rocket::{get, post, routes, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::controller::guards::{ManageShop, Service};
+use crate::controller::idempotency::Idempotency;
+use crate::domain::{InventoryItem, ShopOffer, User};
+use crate::error::{AppError, ErrorResponse};
+use crate::service::shop::ShopService;
+
+/// Request body for buying an offer.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct PurchaseRequest {
+    pub offer_id: Uuid,
+}
+
+#[utoipa::path(
+    get,
+    path = "/shop/offers",
+    responses(
+        (status = 200, description = "The offers that can be bought right now", body = Vec<ShopOffer>),
+        (status = 401, description = "Not logged in", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Lists the offers in the shop",
+    operation_id = "listShopOffers",
+    tag = "Shop",
+    security(
+        ("jwt_auth" = [])
+    )
+)]
+#[get("/offers")]
+async fn list_offers(
+    _user: User,
+    shop_service: &State<Arc<dyn ShopService>>,
+) -> Result<Json<Vec<ShopOffer>>, AppError> {
+    Ok(Json(shop_service.list_available().await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/shop/offers/add_or_update",
+    request_body = ShopOffer,
+    responses(
+        (status = 201, description = "Offer saved", body = bool),
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "No valid service key", body = ErrorResponse),
+        (status = 403, description = "The service key lacks the shop:manage scope", body = ErrorResponse),
+        (status = 422, description = "The definition is not in the item catalog, the state template is malformed or does not fit the stack, or the price, purchase limit or availability window is invalid", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Adds an offer to the shop, or updates it if it did already exist. Earlier purchases keep the price they were bought for",
+    operation_id = "addOrUpdateShopOffer",
+    tag = "Shop",
+    security(
+        ("service_key" = ["shop:manage"])
+    )
+)]
+#[post("/offers/add_or_update", data = "<payload>")]
+async fn add_or_update_offer(
+    _service: Service<ManageShop>,
+    idempotency: Idempotency,
+    payload: Json<ShopOffer>,
+    shop_service: &State<Arc<dyn ShopService>>,
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&*payload, || async {
+        shop_service.add_or_update_offer(payload.clone().into_inner()).await?;
+        Ok(Json(true))
+    }).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/shop/purchase",
+    request_body = PurchaseRequest,
+    responses(
+        (status = 201, description = "Offer bought, returns the new item", body = InventoryItem),
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in", body = ErrorResponse),
+        (status = 404, description = "The offer does not exist", body = ErrorResponse),
+        (status = 422, description = "The offer is not available, its purchase limit is reached, or the player can not pay it", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Buys an offer. The price is paid and the item is given in one transaction",
+    operation_id = "purchaseShopOffer",
+    tag = "Shop",
+    security(
+        ("jwt_auth" = [])
+    )
+)]
+#[post("/purchase", data = "<payload>")]
+async fn purchase(
+    user: User,
+    idempotency: Idempotency,
+    payload: Json<PurchaseRequest>,
+    shop_service: &State<Arc<dyn ShopService>>,
+) -> Result<Json<InventoryItem>, AppError> {
+    idempotency.run(&*payload, || async {
+        let item = shop_service.purchase(user.user_id, payload.offer_id).await?;
+        Ok(Json(item))
+    }).await
+}
+
+// Combine all the shop routes.
+pub fn shop_routes() -> Vec<rocket::Route> {
+    routes![list_offers, add_or_update_offer, purchase]
+}
@@ -1,5 +1,18 @@
-use crate::{domain::{SelectItemRequest, StatFish}, service::stats::StatsService};
-use rocket::{post, routes, serde::json::Json, State};
+use crate::{
+    controller::{
+        acting_user,
+        idempotency::Idempotency,
+        guards::{AddPlaytime, Caller, GrantCurrency, GrantFish, GrantXp, ReadPlayerData, Service},
+    },
+    domain::{
+        CatchFilter, CatchPage, Currency, CurrencyChange, CurrencyHistory, LevelProgress, SelectItemRequest, StatFish,
+        TransactionSource, User, XpGain,
+    },
+    error::{AppError, ErrorResponse},
+    service::{ledger::LedgerService, stats::StatsService},
+};
+use chrono::{DateTime, Utc};
+use rocket::{get, post, routes, serde::json::Json, FromForm, State};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use utoipa::ToSchema;
@@ -12,24 +25,35 @@ struct AddXPRequest {
     pub amount: i32,
 }
 
-/// Request body for changing the coins amount of a player
+/// Request body for changing the bucks amount of a player
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct ChangeBucksRequest {
     pub user_id: Uuid,
     pub amount: i32,
+    /// Why the balance changes, stored in the ledger. Defaults to `unspecified`.
+    pub reason: Option<String>,
+    /// For example the match or mail the change belongs to.
+    pub reference_id: Option<Uuid>,
 }
 
-/// Request body for changing the bucks amount of a player
+/// Request body for changing the coins amount of a player
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct ChangeCoinsRequest {
     pub user_id: Uuid,
     pub amount: i32,
+    /// Why the balance changes, stored in the ledger. Defaults to `unspecified`.
+    pub reason: Option<String>,
+    /// For example the match or mail the change belongs to.
+    pub reference_id: Option<Uuid>,
 }
 
+/// The reason stored in the ledger when a service does not give one.
+const UNSPECIFIED_REASON: &str = "unspecified";
+
 /// Request body for adding playtime of a player
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct AddPlayTimeRequest {
-    pub user_id: Uuid,
+    pub user_id: Option<Uuid>,
     pub amount: i32,
 }
 
@@ -43,31 +67,84 @@ struct AddFishRequest {
     pub area_id: i32,
 }
 
+/// Query parameters for listing the catch log.
+#[derive(Debug, FromForm)]
+struct ListCatchesQuery<'r> {
+    user_id: Option<Uuid>,
+    fish_id: Option<i32>,
+    area_id: Option<i32>,
+    bait_id: Option<i32>,
+    since: Option<&'r str>,
+    until: Option<&'r str>,
+    cursor: Option<&'r str>,
+    limit: Option<i64>,
+}
+
 #[utoipa::path(
     post,
     path = "/stats/add_xp",
     request_body = AddXPRequest,
     responses(
-        (status = 201, description = "xp added successfully", body = bool),
-        (status = 400, description = "Invalid input data"),
-        (status = 500, description = "Internal server error")
+        (status = 201, description = "The xp was added, with the new level and the rewards of every level reached", body = XpGain),
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "No valid service key", body = ErrorResponse),
+        (status = 403, description = "Not called with a service key with the stats:xp scope", body = ErrorResponse),
+        (status = 404, description = "The user has no stats", body = ErrorResponse),
+        (status = 422, description = "The amount is negative", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
-    description = "Adds xp to a given user account",
+    description = "Adds xp to a given user account and grants the rewards of the levels reached, \
+        rewards with a mail arrive in the mailbox",
     operation_id = "addXP",
-    tag = "Stats"
+    tag = "Stats",
+    security(
+        ("service_key" = ["stats:xp"])
+    )
 )]
 #[post("/add_xp", data = "<payload>")]
 async fn add_xp(
+    _service: Service<GrantXp>,
+    idempotency: Idempotency,
     payload: Json<AddXPRequest>,
     stats_service: &State<Arc<dyn StatsService>>,
-) -> Json<bool> {
-    if payload.amount < 0 {
-        return Json(false);
-    }
-    match stats_service.add_xp(payload.user_id, payload.amount).await {
-        Ok(()) => Json(true),
-        Err(_) => Json(false),
-    }
+) -> Result<Json<XpGain>, AppError> {
+    idempotency.run(&*payload, || async {
+        let user_id = payload.user_id;
+        let gain = stats_service.add_xp(user_id, payload.amount).await?;
+        Ok(Json(gain))
+    }).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats/level",
+    params(
+        ("user_id" = Option<Uuid>, Query, description = "The user, required for services"),
+    ),
+    responses(
+        (status = 200, description = "The level of the user and the xp needed for the next one", body = LevelProgress),
+        (status = 400, description = "A service did not name the user", body = ErrorResponse),
+        (status = 401, description = "Not logged in and no valid service key", body = ErrorResponse),
+        (status = 403, description = "The request names another user, or the service key lacks the data:read scope", body = ErrorResponse),
+        (status = 404, description = "The user has no stats", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Returns the level of a user, computed from their xp by the level curve",
+    operation_id = "getLevel",
+    tag = "Stats",
+    security(
+        ("jwt_auth" = []),
+        ("service_key" = ["data:read"])
+    )
+)]
+#[get("/level?<user_id>")]
+async fn level(
+    caller: Caller<ReadPlayerData>,
+    user_id: Option<Uuid>,
+    stats_service: &State<Arc<dyn StatsService>>,
+) -> Result<Json<LevelProgress>, AppError> {
+    let user_id = caller.acting_user(user_id)?;
+    Ok(Json(stats_service.level(user_id).await?))
 }
 
 #[utoipa::path(
@@ -76,25 +153,40 @@ async fn add_xp(
     request_body = ChangeBucksRequest,
     responses(
         (status = 201, description = "bucks changed successfully", body = bool),
-        (status = 400, description = "Invalid input data"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "No valid service key", body = ErrorResponse),
+        (status = 403, description = "Not called with a service key with the stats:currency scope", body = ErrorResponse),
+        (status = 404, description = "The user has no stats", body = ErrorResponse),
+        (status = 422, description = "The user has not enough bucks, or the reason is not a snake case code", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
-    description = "Changes the amount of bucks of a given user account",
+    description = "Changes the amount of bucks of a given user account and records it in the currency ledger",
     operation_id = "changeBucks",
-    tag = "Stats"
+    tag = "Stats",
+    security(
+        ("service_key" = ["stats:currency"])
+    )
 )]
 #[post("/change_bucks", data = "<payload>")]
 async fn change_bucks(
+    service: Service<GrantCurrency>,
+    idempotency: Idempotency,
     payload: Json<ChangeBucksRequest>,
     stats_service: &State<Arc<dyn StatsService>>,
-) -> Json<bool> {
-    match stats_service
-        .change_bucks(payload.user_id, payload.amount)
-        .await
-    {
-        Ok(()) => Json(true),
-        Err(_) => Json(false),
-    }
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&*payload, || async {
+        stats_service
+            .change_currency(CurrencyChange {
+                user_id: payload.user_id,
+                currency: Currency::Bucks,
+                delta: payload.amount,
+                reason: payload.reason.clone().unwrap_or_else(|| UNSPECIFIED_REASON.to_string()),
+                source: TransactionSource::Service(service.key.key_id),
+                reference_id: payload.reference_id,
+            })
+            .await?;
+        Ok(Json(true))
+    }).await
 }
 
 #[utoipa::path(
@@ -103,25 +195,40 @@ async fn change_bucks(
     request_body = ChangeCoinsRequest,
     responses(
         (status = 201, description = "coins changed successfully", body = bool),
-        (status = 400, description = "Invalid input data"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "No valid service key", body = ErrorResponse),
+        (status = 403, description = "Not called with a service key with the stats:currency scope", body = ErrorResponse),
+        (status = 404, description = "The user has no stats", body = ErrorResponse),
+        (status = 422, description = "The user has not enough coins, or the reason is not a snake case code", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
-    description = "Changes the amount of coins of a given user account",
+    description = "Changes the amount of coins of a given user account and records it in the currency ledger",
     operation_id = "changeCoins",
-    tag = "Stats"
+    tag = "Stats",
+    security(
+        ("service_key" = ["stats:currency"])
+    )
 )]
 #[post("/change_coins", data = "<payload>")]
 async fn change_coins(
+    service: Service<GrantCurrency>,
+    idempotency: Idempotency,
     payload: Json<ChangeCoinsRequest>,
     stats_service: &State<Arc<dyn StatsService>>,
-) -> Json<bool> {
-    match stats_service
-        .change_coins(payload.user_id, payload.amount)
-        .await
-    {
-        Ok(()) => Json(true),
-        Err(_) => Json(false),
-    }
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&*payload, || async {
+        stats_service
+            .change_currency(CurrencyChange {
+                user_id: payload.user_id,
+                currency: Currency::Coins,
+                delta: payload.amount,
+                reason: payload.reason.clone().unwrap_or_else(|| UNSPECIFIED_REASON.to_string()),
+                source: TransactionSource::Service(service.key.key_id),
+                reference_id: payload.reference_id,
+            })
+            .await?;
+        Ok(Json(true))
+    }).await
 }
 
 #[utoipa::path(
@@ -130,28 +237,35 @@ async fn change_coins(
     request_body = AddPlayTimeRequest,
     responses(
         (status = 201, description = "playtime changed successfully", body = bool),
-        (status = 400, description = "Invalid input data"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in and no valid service key", body = ErrorResponse),
+        (status = 403, description = "The request names another user, or the service key lacks the stats:playtime scope", body = ErrorResponse),
+        (status = 404, description = "The user has no stats", body = ErrorResponse),
+        (status = 422, description = "The amount is negative", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     description = "Adds more playtime to a given user account",
     operation_id = "changePlayetime",
-    tag = "Stats"
+    tag = "Stats",
+    security(
+        ("jwt_auth" = []),
+        ("service_key" = ["stats:playtime"])
+    )
 )]
 #[post("/add_playtime", data = "<payload>")]
 async fn add_playtime(
+    caller: Caller<AddPlaytime>,
+    idempotency: Idempotency,
     payload: Json<AddPlayTimeRequest>,
     stats_service: &State<Arc<dyn StatsService>>,
-) -> Json<bool> {
-    if payload.amount < 0 {
-        return Json(false);
-    }
-    match stats_service
-        .add_playtime(payload.user_id, payload.amount)
-        .await
-    {
-        Ok(()) => Json(true),
-        Err(_) => Json(false),
-    }
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&*payload, || async {
+        let user_id = caller.acting_user(payload.user_id)?;
+        stats_service
+            .add_playtime(user_id, payload.amount)
+            .await?;
+        Ok(Json(true))
+    }).await
 }
 
 #[utoipa::path(
@@ -160,31 +274,94 @@ async fn add_playtime(
     request_body = AddFishRequest,
     responses(
         (status = 201, description = "stat fish added successfully", body = bool),
-        (status = 400, description = "Invalid input data"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "No valid service key", body = ErrorResponse),
+        (status = 403, description = "Not called with a service key with the stats:fish scope", body = ErrorResponse),
+        (status = 422, description = "The user does not exist", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     description = "Adds a stat fish to a given user account",
     operation_id = "changePlayetime",
-    tag = "Stats"
+    tag = "Stats",
+    security(
+        ("service_key" = ["stats:fish"])
+    )
 )]
 #[post("/add_fish", data = "<payload>")]
 async fn add_fish(
+    _service: Service<GrantFish>,
+    idempotency: Idempotency,
     payload: Json<AddFishRequest>,
     stats_service: &State<Arc<dyn StatsService>>,
-) -> Json<bool> {
-    match stats_service
-        .add_fish(StatFish {
-            user_id: payload.user_id,
-            fish_id: payload.fish_id,
-            length: payload.length,
-            bait_id: payload.bait_id,
-            area_id: payload.area_id,
-        })
-        .await
-    {
-        Ok(()) => Json(true),
-        Err(_) => Json(false),
-    }
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&*payload, || async {
+        let user_id = payload.user_id;
+        stats_service
+            .add_fish(StatFish {
+                user_id,
+                fish_id: payload.fish_id,
+                length: payload.length,
+                bait_id: payload.bait_id,
+                area_id: payload.area_id,
+            })
+            .await?;
+        Ok(Json(true))
+    }).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats/catches",
+    params(
+        ("user_id" = Option<Uuid>, Query, description = "The user, required for services"),
+        ("fish_id" = Option<i32>, Query, description = "Only catches of this fish"),
+        ("area_id" = Option<i32>, Query, description = "Only catches in this area"),
+        ("bait_id" = Option<i32>, Query, description = "Only catches with this bait"),
+        ("since" = Option<String>, Query, description = "Only catches at or after this RFC 3339 time"),
+        ("until" = Option<String>, Query, description = "Only catches before this RFC 3339 time"),
+        ("cursor" = Option<String>, Query, description = "The next_cursor of the previous page"),
+        ("limit" = Option<i64>, Query, description = "Catches per page, 50 by default and at most 200"),
+    ),
+    responses(
+        (status = 200, description = "A page of the catch log, the latest catch first", body = CatchPage),
+        (status = 400, description = "A service did not name the user, or a time or the cursor is invalid", body = ErrorResponse),
+        (status = 401, description = "Not logged in and no valid service key", body = ErrorResponse),
+        (status = 403, description = "The request names another user, or the service key lacks the data:read scope", body = ErrorResponse),
+        (status = 422, description = "The limit is out of range, or since is not before until", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Lists the catches of a user, with when, where and with what each fish was caught",
+    operation_id = "listCatches",
+    tag = "Stats",
+    security(
+        ("jwt_auth" = []),
+        ("service_key" = ["data:read"])
+    )
+)]
+#[get("/catches?<query..>")]
+async fn list_catches(
+    caller: Caller<ReadPlayerData>,
+    query: ListCatchesQuery<'_>,
+    stats_service: &State<Arc<dyn StatsService>>,
+) -> Result<Json<CatchPage>, AppError> {
+    let user_id = caller.acting_user(query.user_id)?;
+    let filter = CatchFilter {
+        fish_id: query.fish_id,
+        area_id: query.area_id,
+        bait_id: query.bait_id,
+        since: query.since.map(parse_time).transpose()?,
+        until: query.until.map(parse_time).transpose()?,
+    };
+    let page = stats_service
+        .list_catches(user_id, filter, query.cursor.map(str::to_string), query.limit)
+        .await?;
+    Ok(Json(page))
+}
+
+fn parse_time(time: &str) -> Result<DateTime<Utc>, AppError> {
+    DateTime::parse_from_rfc3339(time)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| AppError::BadRequest("invalid_time"))
 }
 
 #[utoipa::path(
@@ -193,34 +370,82 @@ async fn add_fish(
     request_body = SelectItemRequest,
     responses(
         (status = 201, description = "Successfully selected an item", body = bool),
-        (status = 400, description = "Invalid input data"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in", body = ErrorResponse),
+        (status = 403, description = "The request names another user", body = ErrorResponse),
+        (status = 404, description = "The user does not have this item", body = ErrorResponse),
+        (status = 422, description = "Items of this type can not be selected, or the item is of another kind than the type", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     description = "Select an item",
     operation_id = "selectItem",
-    tag = "Stats"
+    tag = "Stats",
+    security(
+        ("jwt_auth" = [])
+    )
 )]
 #[post("/select_item", data = "<payload>")]
 async fn select_item(
+    user: User,
+    idempotency: Idempotency,
     payload: Json<SelectItemRequest>,
     stats_service: &State<Arc<dyn StatsService>>,
-) -> Json<bool> {
-    match stats_service
-        .select_item(
-            SelectItemRequest {
-                user_id: payload.user_id,
-                item_uid: payload.item_uid,
-                item_type: payload.item_type,
-            }
-        )
-        .await
-    {
-        Ok(()) => Json(true),
-        Err(_) => Json(false),
-    }
+) -> Result<Json<bool>, AppError> {
+    idempotency.run(&*payload, || async {
+        let user_id = acting_user(&user, payload.user_id)?;
+        stats_service
+            .select_item(user_id, payload.item_uid, payload.item_type)
+            .await?;
+        Ok(Json(true))
+    }).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats/currency_history",
+    params(
+        ("user_id" = Option<Uuid>, Query, description = "The user, required for services"),
+        ("currency" = Option<Currency>, Query, description = "Only entries of this currency"),
+        ("before" = Option<i64>, Query, description = "Only entries older than this entry, use next_before of the previous page"),
+        ("limit" = Option<i64>, Query, description = "Entries per page, 50 by default and at most 200"),
+    ),
+    responses(
+        (status = 200, description = "A page of the currency ledger, newest first", body = CurrencyHistory),
+        (status = 400, description = "A service did not name the user, or the currency is unknown", body = ErrorResponse),
+        (status = 401, description = "Not logged in and no valid service key", body = ErrorResponse),
+        (status = 403, description = "The request names another user, or the service key lacks the data:read scope", body = ErrorResponse),
+        (status = 422, description = "The limit is out of range", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Lists the coin and bucks changes of a user",
+    operation_id = "currencyHistory",
+    tag = "Stats",
+    security(
+        ("jwt_auth" = []),
+        ("service_key" = ["data:read"])
+    )
+)]
+#[get("/currency_history?<user_id>&<currency>&<before>&<limit>")]
+async fn currency_history(
+    caller: Caller<ReadPlayerData>,
+    user_id: Option<Uuid>,
+    currency: Option<&str>,
+    before: Option<i64>,
+    limit: Option<i64>,
+    ledger_service: &State<Arc<dyn LedgerService>>,
+) -> Result<Json<CurrencyHistory>, AppError> {
+    let user_id = caller.acting_user(user_id)?;
+    let currency = match currency {
+        Some(currency) => Some(Currency::parse(currency).ok_or(AppError::BadRequest("unknown_currency"))?),
+        None => None,
+    };
+    let history = ledger_service
+        .history(user_id, currency, before, limit)
+        .await?;
+    Ok(Json(history))
 }
 
 // Combine all the user routes.
 pub fn stats_routes() -> Vec<rocket::Route> {
-    routes![add_xp, change_bucks, change_coins, add_playtime, add_fish, select_item]
+    routes![add_xp, level, change_bucks, change_coins, add_playtime, add_fish, list_catches, select_item, currency_history]
 }
@@ -0,0 +1,241 @@
+use std::sync::Arc;
+
+use rocket::{get, post, routes, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::controller::guards::{Caller, ReadPlayerData};
+use crate::controller::idempotency::Idempotency;
+use crate::domain::{Trade, TradeProposal, TradeTerms, User};
+use crate::error::{AppError, ErrorResponse};
+use crate::service::trade::TradeService;
+
+/// Request body for proposing a trade to another player.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct ProposeTradeRequest {
+    pub recipient_id: Uuid,
+    #[serde(flatten)]
+    pub terms: TradeTerms,
+}
+
+/// Request body for answering a trade with another one.
+/// The offered items are the caller's, the requested ones those of the original proposer.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct CounterTradeRequest {
+    pub trade_id: Uuid,
+    #[serde(flatten)]
+    pub terms: TradeTerms,
+}
+
+/// Request body for accepting, declining or cancelling a trade.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct TradeIdRequest {
+    pub trade_id: Uuid,
+}
+
+#[utoipa::path(
+    get,
+    path = "/trades",
+    params(
+        ("user_id" = Option<Uuid>, Query, description = "The user, required for services"),
+        ("limit" = Option<i64>, Query, description = "How many trades, 50 by default and at most 200"),
+    ),
+    responses(
+        (status = 200, description = "The trades the user proposed or received, newest first", body = Vec<Trade>),
+        (status = 400, description = "A service did not name the user", body = ErrorResponse),
+        (status = 401, description = "Not logged in and no valid service key", body = ErrorResponse),
+        (status = 403, description = "The request names another user, or the service key lacks the data:read scope", body = ErrorResponse),
+        (status = 422, description = "The limit is out of range", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Lists the open and resolved trades of a user. Accepted trades show the items as they were handed over",
+    operation_id = "listTrades",
+    tag = "Trades",
+    security(
+        ("jwt_auth" = []),
+        ("service_key" = ["data:read"])
+    )
+)]
+#[get("/?<user_id>&<limit>")]
+async fn list_trades(
+    caller: Caller<ReadPlayerData>,
+    user_id: Option<Uuid>,
+    limit: Option<i64>,
+    trade_service: &State<Arc<dyn TradeService>>,
+) -> Result<Json<Vec<Trade>>, AppError> {
+    let user_id = caller.acting_user(user_id)?;
+    Ok(Json(trade_service.list(user_id, limit).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/trades/propose",
+    request_body = ProposeTradeRequest,
+    responses(
+        (status = 201, description = "Trade proposed, returns the open trade", body = Trade),
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in", body = ErrorResponse),
+        (status = 404, description = "The recipient does not exist", body = ErrorResponse),
+        (status = 409, description = "An item does not belong to the player who should give it, or is locked in another trade", body = ErrorResponse),
+        (status = 422, description = "The trade is empty, has negative coins, too many or duplicate items, or is with the caller themselves", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Proposes a trade to another player. The offered items are locked in escrow, so they can not be changed, destroyed or selected until the trade is resolved",
+    operation_id = "proposeTrade",
+    tag = "Trades",
+    security(
+        ("jwt_auth" = [])
+    )
+)]
+#[post("/propose", data = "<payload>")]
+async fn propose_trade(
+    user: User,
+    idempotency: Idempotency,
+    payload: Json<ProposeTradeRequest>,
+    trade_service: &State<Arc<dyn TradeService>>,
+) -> Result<Json<Trade>, AppError> {
+    idempotency.run(&*payload, || async {
+        let proposal = TradeProposal {
+            proposer_id: user.user_id,
+            recipient_id: payload.recipient_id,
+            terms: payload.terms.clone(),
+        };
+        let trade = trade_service.propose(proposal).await?;
+        Ok(Json(trade))
+    }).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/trades/counter",
+    request_body = CounterTradeRequest,
+    responses(
+        (status = 201, description = "Trade countered, returns the new open trade", body = Trade),
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in", body = ErrorResponse),
+        (status = 404, description = "The trade does not exist or was not proposed to the caller", body = ErrorResponse),
+        (status = 409, description = "The trade is not open anymore, or an item is unavailable", body = ErrorResponse),
+        (status = 422, description = "The new trade is empty, has negative coins, or too many or duplicate items", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Answers a trade the caller received with a trade of their own. The original trade is closed and its escrow released",
+    operation_id = "counterTrade",
+    tag = "Trades",
+    security(
+        ("jwt_auth" = [])
+    )
+)]
+#[post("/counter", data = "<payload>")]
+async fn counter_trade(
+    user: User,
+    idempotency: Idempotency,
+    payload: Json<CounterTradeRequest>,
+    trade_service: &State<Arc<dyn TradeService>>,
+) -> Result<Json<Trade>, AppError> {
+    idempotency.run(&*payload, || async {
+        let trade = trade_service
+            .counter(payload.trade_id, user.user_id, payload.terms.clone())
+            .await?;
+        Ok(Json(trade))
+    }).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/trades/accept",
+    request_body = TradeIdRequest,
+    responses(
+        (status = 201, description = "Trade done, returns the accepted trade", body = Trade),
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in", body = ErrorResponse),
+        (status = 404, description = "The trade does not exist or was not proposed to the caller", body = ErrorResponse),
+        (status = 409, description = "The trade is not open anymore, or a requested item is no longer available", body = ErrorResponse),
+        (status = 422, description = "One of the players can not pay their coins", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Accepts a trade the caller received. The items change owner and the coins are paid in one transaction",
+    operation_id = "acceptTrade",
+    tag = "Trades",
+    security(
+        ("jwt_auth" = [])
+    )
+)]
+#[post("/accept", data = "<payload>")]
+async fn accept_trade(
+    user: User,
+    idempotency: Idempotency,
+    payload: Json<TradeIdRequest>,
+    trade_service: &State<Arc<dyn TradeService>>,
+) -> Result<Json<Trade>, AppError> {
+    idempotency.run(&*payload, || async {
+        Ok(Json(trade_service.accept(payload.trade_id, user.user_id).await?))
+    }).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/trades/decline",
+    request_body = TradeIdRequest,
+    responses(
+        (status = 201, description = "Trade declined", body = Trade),
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in", body = ErrorResponse),
+        (status = 404, description = "The trade does not exist or was not proposed to the caller", body = ErrorResponse),
+        (status = 409, description = "The trade is not open anymore", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Declines a trade the caller received and releases its escrow",
+    operation_id = "declineTrade",
+    tag = "Trades",
+    security(
+        ("jwt_auth" = [])
+    )
+)]
+#[post("/decline", data = "<payload>")]
+async fn decline_trade(
+    user: User,
+    idempotency: Idempotency,
+    payload: Json<TradeIdRequest>,
+    trade_service: &State<Arc<dyn TradeService>>,
+) -> Result<Json<Trade>, AppError> {
+    idempotency.run(&*payload, || async {
+        Ok(Json(trade_service.decline(payload.trade_id, user.user_id).await?))
+    }).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/trades/cancel",
+    request_body = TradeIdRequest,
+    responses(
+        (status = 201, description = "Trade cancelled", body = Trade),
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in", body = ErrorResponse),
+        (status = 404, description = "The trade does not exist or was not proposed by the caller", body = ErrorResponse),
+        (status = 409, description = "The trade is not open anymore", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Cancels a trade the caller proposed and releases its escrow",
+    operation_id = "cancelTrade",
+    tag = "Trades",
+    security(
+        ("jwt_auth" = [])
+    )
+)]
+#[post("/cancel", data = "<payload>")]
+async fn cancel_trade(
+    user: User,
+    idempotency: Idempotency,
+    payload: Json<TradeIdRequest>,
+    trade_service: &State<Arc<dyn TradeService>>,
+) -> Result<Json<Trade>, AppError> {
+    idempotency.run(&*payload, || async {
+        Ok(Json(trade_service.cancel(payload.trade_id, user.user_id).await?))
+    }).await
+}
+
+// Combine all the trade routes.
+pub fn trade_routes() -> Vec<rocket::Route> {
+    routes![list_trades, propose_trade, counter_trade, accept_trade, decline_trade, cancel_trade]
+}
@@ -1,9 +1,10 @@
 use crate::domain::LoginResponse;
 use crate::domain::User;
+use crate::error::{AppError, ErrorResponse};
+use crate::service::authentication::AuthenticationService;
 use crate::service::user::UserService;
 use rocket::get;
 use rocket::post;
-use rocket::response::status;
 use rocket::routes;
 use rocket::serde::json::Json;
 use rocket::State;
@@ -18,13 +19,15 @@ struct CreateUserRequest {
     pub email: String,
     pub username: String,
     pub password: String,
+    pub device: Option<String>,
 }
 
 /// Request body for changing a password.
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct ChangePasswordRequest {
-    pub username: String,
+    pub current_password: String,
     pub new_password: String,
+    pub device: Option<String>,
 }
 
 
@@ -34,6 +37,19 @@ struct RetreiveUsernameRequest {
     pub email: String,
 }
 
+/// Request body for requesting a password reset code.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct RequestPasswordResetRequest {
+    pub email: String,
+}
+
+/// Request body for setting a new password with a reset code.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
 // Utoipa is the crate that generates swagger documentation for your endpoints.
 // The documentation for each endpoint is combined in docs.rs
 // Make sure to add your endpoint in docs.rs when you write new endpoints.
@@ -42,11 +58,12 @@ struct RetreiveUsernameRequest {
     path = "/account/register",
     request_body = CreateUserRequest,
     responses(
-        (status = 201, description = "User created successfully", body = bool),
-        (status = 400, description = "Invalid input data"),
-        (status = 500, description = "Internal server error")
+        (status = 201, description = "User created successfully", body = LoginResponse),
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 409, description = "The username or email is already used", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
-    description = "Creates a user. The email and username should be unique.",
+    description = "Creates a user and logs it in. The email and username should be unique.",
     operation_id = "createUser",
     tag = "Users"
 )]
@@ -54,21 +71,18 @@ struct RetreiveUsernameRequest {
 async fn create_user(
     payload: Json<CreateUserRequest>,
     user_service: &State<Arc<dyn UserService>>,
-) -> Json<LoginResponse> {
-    match user_service
+    authentication_service: &State<Arc<dyn AuthenticationService>>,
+) -> Result<Json<LoginResponse>, AppError> {
+    let user_id = user_service
         .create(
             payload.username.clone(),
             payload.email.clone(),
             payload.password.clone(),
         )
-        .await
-    {
-        Ok(res) => Json(res),
-        Err(_) => Json(LoginResponse{
-            code: 401,
-            jwt: String::from(""),
-        })
-    }
+        .await?;
+
+    let res = authentication_service.create_session(user_id, payload.device.clone()).await?;
+    Ok(Json(res))
 }
 
 #[utoipa::path(
@@ -77,10 +91,10 @@ async fn create_user(
     request_body = RetreiveUsernameRequest,
     responses(
         (status = 201, description = "Username send successfull", body = bool),
-        (status = 400, description = "Invalid input data"),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
-    description = "Sends the username of the account the email belongs to to the mail address",
+    description = "Sends the username of the account the email belongs to to the mail address. Always returns true, so it does not reveal if an account exists.",
     operation_id = "retreiveUsername",
     tag = "Users"
 )]
@@ -88,16 +102,67 @@ async fn create_user(
 async fn retreive_username(
     payload: Json<RetreiveUsernameRequest>,
     user_service: &State<Arc<dyn UserService>>,
-) -> Json<bool> {
-    match user_service
+) -> Result<Json<bool>, AppError> {
+    let res = user_service
         .retreive_username(
             payload.email.clone(),
         )
-        .await
-    {
-        Ok(res) => Json(res),
-        Err(_) => Json(false)
-    }
+        .await?;
+    Ok(Json(res))
+}
+
+#[utoipa::path(
+    post,
+    path = "/account/request_password_reset",
+    request_body = RequestPasswordResetRequest,
+    responses(
+        (status = 201, description = "Reset code send if the account exists", body = bool),
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Sends a single use password reset code to the mail address. Always returns true, so it does not reveal if an account exists.",
+    operation_id = "requestPasswordReset",
+    tag = "Users"
+)]
+#[post("/request_password_reset", data = "<payload>")]
+async fn request_password_reset(
+    payload: Json<RequestPasswordResetRequest>,
+    user_service: &State<Arc<dyn UserService>>,
+) -> Result<Json<bool>, AppError> {
+    let res = user_service
+        .request_password_reset(
+            payload.email.clone(),
+        )
+        .await?;
+    Ok(Json(res))
+}
+
+#[utoipa::path(
+    post,
+    path = "/account/reset_password",
+    request_body = ResetPasswordRequest,
+    responses(
+        (status = 201, description = "Password reset", body = bool),
+        (status = 400, description = "Invalid input data, or the code is invalid, expired or used", body = ErrorResponse),
+        (status = 422, description = "The new password is empty", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    description = "Sets a new password with a reset code. Tokens issued before the reset stop working.",
+    operation_id = "resetPassword",
+    tag = "Users"
+)]
+#[post("/reset_password", data = "<payload>")]
+async fn reset_password(
+    payload: Json<ResetPasswordRequest>,
+    user_service: &State<Arc<dyn UserService>>,
+) -> Result<Json<bool>, AppError> {
+    user_service
+        .reset_password(
+            payload.token.clone(),
+            payload.new_password.clone(),
+        )
+        .await?;
+    Ok(Json(true))
 }
 
 #[utoipa::path(
@@ -105,30 +170,38 @@ async fn retreive_username(
     path = "/account/change_password",
     request_body = ChangePasswordRequest,
     responses(
-        (status = 201, description = "Changed password", body = bool),
-        (status = 400, description = "Invalid input data"),
-        (status = 500, description = "Internal server error")
+        (status = 201, description = "Changed password", body = LoginResponse),
+        (status = 400, description = "Invalid input data", body = ErrorResponse),
+        (status = 401, description = "Not logged in", body = ErrorResponse),
+        (status = 403, description = "The current password is wrong", body = ErrorResponse),
+        (status = 422, description = "The new password is empty", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
-    description = "Changes a users password",
+    description = "Changes the password of the logged in user. All sessions are logged out, a new session is returned.",
     operation_id = "changePassword",
-    tag = "Users"
+    tag = "Users",
+    security(
+        ("jwt_auth" = [])
+    )
 )]
 #[post("/change_password", data = "<payload>")]
 async fn change_password(
+    user: User,
     payload: Json<ChangePasswordRequest>,
     user_service: &State<Arc<dyn UserService>>,
-) -> Json<bool> {
-    // TODO: We need a way to verify a user is actually changing the password of ut;s own account
-    match user_service
+    authentication_service: &State<Arc<dyn AuthenticationService>>,
+) -> Result<Json<LoginResponse>, AppError> {
+    let user_id = user.user_id;
+    user_service
         .change_password(
-            payload.username.clone(),
+            user,
+            payload.current_password.clone(),
             payload.new_password.clone(),
         )
-        .await
-    {
-        Ok(res) => Json(res),
-        Err(_) => Json(false),
-    }
+        .await?;
+
+    let res = authentication_service.create_session(user_id, payload.device.clone()).await?;
+    Ok(Json(res))
 }
 
 /// Response for recieving user information.
@@ -143,8 +216,8 @@ struct GetUserResponse {
     path = "/users",
     responses(
         (status = 201, description = "User recieved successfully", body = GetUserResponse),
-        (status = 400, description = "Invalid input data"),
-        (status = 500, description = "Internal server error")
+        (status = 401, description = "Not logged in", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     description = "Recieve user details.",
     operation_id = "getUser",
@@ -154,7 +227,7 @@ struct GetUserResponse {
     )
 )]
 #[get("/")]
-async fn get_user(user: User) -> Result<Json<GetUserResponse>, status::Custom<String>> {
+async fn get_user(user: User) -> Result<Json<GetUserResponse>, AppError> {
     Ok(Json(GetUserResponse {
         email: user.email,
         name: user.name,
@@ -163,5 +236,12 @@ async fn get_user(user: User) -> Result<Json<GetUserResponse>, status::Custom<St
 
 // Combine all the user routes.
 pub fn user_routes() -> Vec<rocket::Route> {
-    routes![create_user, retreive_username, change_password, get_user]
+    routes![
+        create_user,
+        retreive_username,
+        request_password_reset,
+        reset_password,
+        change_password,
+        get_user
+    ]
 }
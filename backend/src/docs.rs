@@ -1,18 +1,39 @@
+use crate::controller::achievements::*;
 use crate::controller::authentication::*;
 use crate::controller::data::*;
 use crate::controller::effects::*;
 use crate::controller::friends::*;
 use crate::controller::inventory::*;
+use crate::controller::items::*;
+use crate::controller::leaderboards::*;
 use crate::controller::mail::*;
+use crate::controller::quests::*;
+use crate::controller::service_keys::*;
+use crate::controller::shop::*;
+use crate::controller::trade::*;
 use crate::controller::stats::*;
 use crate::controller::user::*;
-use utoipa::OpenApi;
+use crate::controller::idempotency::{IDEMPOTENCY_KEY_HEADER, NOT_IDEMPOTENT_ROUTES};
+use crate::error::ErrorResponse;
+use utoipa::openapi::path::{ParameterBuilder, ParameterIn};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::{ContentBuilder, ObjectBuilder, Required, ResponseBuilder, Type};
+use utoipa::{Modify, OpenApi, PartialSchema};
 
 #[derive(OpenApi)]
-#[openapi(paths(
+#[openapi(modifiers(&SecurityAddon, &IdempotencyAddon), paths(
     create_user,
+    retreive_username,
+    request_password_reset,
+    reset_password,
+    change_password,
     get_user,
     login,
+    refresh,
+    logout,
+    list_sessions,
+    revoke_session,
+    jwks,
 
     remove_friend,
     add_friend_request,
@@ -20,23 +41,116 @@ use utoipa::OpenApi;
 
     select_item,
     add_xp,
+    level,
     change_bucks,
     change_coins,
     add_playtime,
     add_fish,
+    list_catches,
+    currency_history,
 
+    list_mail,
+    unread_count,
     create_mail,
+    broadcast_mail,
     delete_mail,
     change_read_state,
     change_archive_state,
+    claim_mail,
 
     add_or_update_item,
     destroy_item,
+    degrade_item,
+    increase_item,
+
+    item_catalog,
 
     add_effect,
     remove_expired_effects,
     cleanup_all_expired_effects,
 
     retreive_player_data,
+
+    create_service_key,
+    revoke_service_key,
+    list_service_keys,
+
+    list_offers,
+    add_or_update_offer,
+    purchase,
+
+    list_trades,
+    propose_trade,
+    counter_trade,
+    accept_trade,
+    decline_trade,
+    cancel_trade,
+
+    leaderboard,
+    leaderboard_around,
+    friends_leaderboard,
+    fish_leaderboard,
+    fish_leaderboard_around,
+    fish_friends_leaderboard,
+
+    achievements,
+    unlocked_achievements,
+
+    quests,
+    claim_quest,
 ))]
 pub struct ApiDoc;
+
+/// Players send their JWT and services their service key, both as bearer token.
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "jwt_auth",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "service_key",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+/// Documents the Idempotency-Key header on every POST route that honors it.
+struct IdempotencyAddon;
+
+impl Modify for IdempotencyAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            if NOT_IDEMPOTENT_ROUTES.contains(&path.as_str()) {
+                continue;
+            }
+            let Some(operation) = item.post.as_mut() else {
+                continue;
+            };
+            operation.parameters.get_or_insert_with(Vec::new).push(
+                ParameterBuilder::new()
+                    .name(IDEMPOTENCY_KEY_HEADER)
+                    .parameter_in(ParameterIn::Header)
+                    .required(Required::False)
+                    .description(Some("Retries with the same key get the first result again instead of applying the request twice"))
+                    .schema(Some(ObjectBuilder::new().schema_type(Type::String).max_length(Some(255))))
+                    .build(),
+            );
+            operation.responses.responses.entry("409".to_string()).or_insert_with(|| {
+                ResponseBuilder::new()
+                    .description("The idempotency key was used for another request, or its first request is still running")
+                    .content("application/json", ContentBuilder::new().schema(Some(ErrorResponse::schema())).build())
+                    .build()
+                    .into()
+            });
+        }
+    }
+}
@@ -4,11 +4,13 @@ use serde::Serialize;
 use sqlx::FromRow;
 use utoipa::ToSchema;
 use uuid::Uuid;
+use crate::utils::item_state::ItemState;
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct LoginResponse {
     pub code: i16,
     pub jwt: String,
+    pub refresh_token: String,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, FromRow)]
@@ -19,6 +21,30 @@ pub struct User {
     pub password: String,
     pub salt: String,
     pub created: DateTime<Utc>,
+    pub password_changed: Option<DateTime<Utc>>,
+}
+
+/// A logged in device of a user. Every session has its own refresh token.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct Session {
+    pub session_id: Uuid,
+    pub user_id: Uuid,
+    pub device: String,
+    #[schema(value_type = String, format = DateTime)]
+    pub created: DateTime<Utc>,
+    #[schema(value_type = String, format = DateTime)]
+    pub last_used: DateTime<Utc>,
+    #[schema(value_type = String, format = DateTime)]
+    pub expires_at: DateTime<Utc>,
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub revoked: Option<DateTime<Utc>>,
+}
+
+/// A user together with the session their JWT belongs to.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AuthenticatedUser {
+    pub user: User,
+    pub session_id: Uuid,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, FromRow)]
@@ -30,10 +56,48 @@ pub struct StatFish {
     pub area_id: i32,
 }
 
-/// Request body for adding playtime of a player
+/// One catch from the catch log.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CatchRecord {
+    pub catch_id: i64,
+    #[schema(value_type = String, format = DateTime)]
+    pub caught: DateTime<Utc>,
+    pub fish_id: i32,
+    pub length: i32,
+    pub area_id: i32,
+    pub bait_id: i32,
+    /// The rod that was selected, None when there was none.
+    pub rod_uuid: Option<Uuid>,
+    pub rod_definition_id: Option<i32>,
+    /// Item ids of the effects that were active.
+    pub effects: Vec<i32>,
+}
+
+/// Which catches to list, everything that is not set matches.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct CatchFilter {
+    pub fish_id: Option<i32>,
+    pub area_id: Option<i32>,
+    pub bait_id: Option<i32>,
+    /// Caught at or after this.
+    pub since: Option<DateTime<Utc>>,
+    /// Caught before this.
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// One page of the catch log, the latest catch first.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CatchPage {
+    pub catches: Vec<CatchRecord>,
+    /// Pass this as `cursor` to get the next page, None on the last page.
+    pub next_cursor: Option<String>,
+}
+
+/// Request body for selecting an item.
+/// `user_id` is optional, the item is always selected for the logged in user.
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct SelectItemRequest {
-    pub user_id: Uuid,
+    pub user_id: Option<Uuid>,
     pub item_uid: Uuid,
     pub item_type: ItemType,
 }
@@ -73,21 +137,230 @@ pub struct FishData {
     pub baits: Vec<i32>,
 }
 
-#[derive(Serialize, Debug, Deserialize)]
+#[derive(Serialize, Debug, Deserialize, ToSchema)]
 pub struct InventoryItem {
     pub item_uuid: Uuid,
     pub definition_id: i32,
     pub state_blob: String,
+    /// The decoded `state_blob`, missing when the blob is malformed.
+    #[serde(default)]
+    pub state: Option<ItemState>,
+    /// The open trade that offers this item. It can not be changed, destroyed or selected meanwhile.
+    #[serde(default)]
+    pub escrow_trade_id: Option<Uuid>,
 }
 
-#[derive(Serialize, Debug, Deserialize)]
+impl InventoryItem {
+    pub fn new(item_uuid: Uuid, definition_id: i32, state_blob: String, escrow_trade_id: Option<Uuid>) -> Self {
+        let state = ItemState::decode(&state_blob).ok();
+        Self { item_uuid, definition_id, state_blob, state, escrow_trade_id }
+    }
+}
+
+/// The state of an item after its durability or quantity was changed.
+#[derive(Serialize, Debug, Deserialize, ToSchema, Clone)]
+pub struct ItemStateUpdate {
+    pub item_uuid: Uuid,
+    pub state: ItemState,
+    /// The item reached zero and was removed from the inventory.
+    pub destroyed: bool,
+}
+
+#[derive(Serialize, Debug, Deserialize, ToSchema)]
 pub struct MailEntry {
     pub mail_id: Uuid,
     pub title: String,
-    pub message: String,
+    /// Left out when only the headers were requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[schema(value_type = String, format = DateTime)]
     pub send_time: DateTime<Utc>,
     pub read: bool,
     pub archived: bool,
+    #[serde(default)]
+    pub attachments: Vec<MailAttachment>,
+    /// Whether the attachments were claimed already.
+    #[serde(default)]
+    pub claimed: bool,
+    #[serde(default)]
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Which mail of a mailbox to list, everything that is not set matches.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct MailFilter {
+    pub read: Option<bool>,
+    pub archived: Option<bool>,
+}
+
+/// One page of a mailbox, the newest mail first.
+#[derive(Serialize, Debug, Deserialize, ToSchema)]
+pub struct MailPage {
+    pub mail: Vec<MailEntry>,
+    /// Pass this as `cursor` to get the next page, None on the last page.
+    pub next_cursor: Option<String>,
+}
+
+/// How many unread mails are in a mailbox.
+#[derive(Serialize, Debug, Deserialize, ToSchema)]
+pub struct UnreadCount {
+    pub unread: i64,
+}
+
+/// A mail to send to players.
+#[derive(Debug, Clone)]
+pub struct NewMail {
+    pub mail_id: Uuid,
+    /// None for mail from the game itself.
+    pub sender_id: Option<Uuid>,
+    pub title: String,
+    pub message: String,
+    pub attachments: Vec<MailAttachment>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// How many rows a run of the mail retention job removed.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize)]
+pub struct MailRetentionReport {
+    /// Mail past its `expires_at`, removed from every mailbox.
+    pub expired_mail: u64,
+    /// Mailbox entries that were read and archived before the retention period.
+    pub old_entries: u64,
+    /// Mail no mailbox refers to anymore.
+    pub orphaned_mail: u64,
+}
+
+/// Which players get a broadcast, all of them when no filter is set.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct BroadcastFilter {
+    /// Only players whose account was created before this.
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub registered_before: Option<DateTime<Utc>>,
+    /// Only players with at least this much xp.
+    pub min_xp: Option<i32>,
+}
+
+/// Something sent with a mail, granted when the recipient claims it.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum MailAttachment {
+    Item { definition_id: i32, state_blob: String },
+    Currency { currency: Currency, amount: i32 },
+}
+
+/// What a player got by claiming the attachments of a mail.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ClaimedAttachments {
+    pub items: Vec<InventoryItem>,
+    pub coins: i32,
+    pub bucks: i32,
+}
+
+/// Items and currency a player gets for reaching a goal, such as a level.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Reward {
+    pub attachments: Vec<MailAttachment>,
+    /// Send the reward as a mail the player claims, instead of granting it right away.
+    pub mail: Option<RewardMail>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+pub struct RewardMail {
+    pub title: String,
+    pub message: String,
+}
+
+/// How a reward reached the player.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "delivery", rename_all = "lowercase")]
+pub enum GrantedReward {
+    /// Added to the inventory and balances.
+    Granted(ClaimedAttachments),
+    /// Sent as a mail with attachments.
+    Mailed { mail_id: Uuid },
+}
+
+/// The level of a player as computed from their xp by the level curve.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LevelProgress {
+    pub level: i32,
+    pub xp: i32,
+    /// None at the highest level.
+    pub xp_to_next_level: Option<i32>,
+}
+
+/// What adding xp did, with the rewards of every level that was reached.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct XpGain {
+    pub old_level: i32,
+    pub new_level: i32,
+    pub xp: i32,
+    /// None at the highest level.
+    pub xp_to_next_level: Option<i32>,
+    pub rewards: Vec<GrantedReward>,
+}
+
+/// An achievement with the progress of a player towards it.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Achievement {
+    pub achievement_id: String,
+    pub name: String,
+    pub description: String,
+    pub progress: i32,
+    /// The progress at which the achievement unlocks.
+    pub target: i32,
+    /// None while the achievement is locked.
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub unlocked: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AchievementList {
+    pub achievements: Vec<Achievement>,
+}
+
+/// How often quests rotate.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum QuestPeriod {
+    Daily,
+    Weekly,
+}
+
+impl QuestPeriod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuestPeriod::Daily => "daily",
+            QuestPeriod::Weekly => "weekly",
+        }
+    }
+}
+
+/// A quest of a player for the current day or week.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Quest {
+    pub quest_id: String,
+    pub period: QuestPeriod,
+    pub description: String,
+    pub progress: i32,
+    pub target: i32,
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub completed: Option<DateTime<Utc>>,
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub claimed: Option<DateTime<Utc>>,
+    /// What claiming the quest grants.
+    pub reward: Vec<MailAttachment>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct QuestList {
+    pub quests: Vec<Quest>,
+    /// When the daily quests are replaced, unclaimed rewards are lost then.
+    #[schema(value_type = String, format = DateTime)]
+    pub next_daily_reset: DateTime<Utc>,
+    #[schema(value_type = String, format = DateTime)]
+    pub next_weekly_reset: DateTime<Utc>,
 }
 
 #[derive(Serialize, Debug, Deserialize)]
@@ -110,7 +383,7 @@ pub struct ActiveEffect {
 }
 
 /// Request body for adding an active effect
-#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct AddActiveEffectRequest {
     pub user_id: Uuid,
     pub item_id: i32,
@@ -124,3 +397,387 @@ pub struct RemoveActiveEffectRequest {
     pub user_id: Uuid,
     pub item_id: i32,
 }
+
+/// What a service key is allowed to do.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, ToSchema)]
+pub enum Scope {
+    #[serde(rename = "stats:xp")]
+    GrantXp,
+    #[serde(rename = "stats:currency")]
+    GrantCurrency,
+    #[serde(rename = "stats:fish")]
+    GrantFish,
+    #[serde(rename = "stats:playtime")]
+    AddPlaytime,
+    #[serde(rename = "inventory:grant")]
+    GrantItems,
+    #[serde(rename = "inventory:manage")]
+    ManageInventory,
+    #[serde(rename = "effects:grant")]
+    GrantEffects,
+    #[serde(rename = "effects:manage")]
+    ManageEffects,
+    #[serde(rename = "data:read")]
+    ReadPlayerData,
+    #[serde(rename = "mail:send")]
+    SendMail,
+    #[serde(rename = "keys:manage")]
+    ManageKeys,
+    #[serde(rename = "shop:manage")]
+    ManageShop,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 12] = [
+        Scope::GrantXp,
+        Scope::GrantCurrency,
+        Scope::GrantFish,
+        Scope::AddPlaytime,
+        Scope::GrantItems,
+        Scope::ManageInventory,
+        Scope::GrantEffects,
+        Scope::ManageEffects,
+        Scope::ReadPlayerData,
+        Scope::SendMail,
+        Scope::ManageKeys,
+        Scope::ManageShop,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::GrantXp => "stats:xp",
+            Scope::GrantCurrency => "stats:currency",
+            Scope::GrantFish => "stats:fish",
+            Scope::AddPlaytime => "stats:playtime",
+            Scope::GrantItems => "inventory:grant",
+            Scope::ManageInventory => "inventory:manage",
+            Scope::GrantEffects => "effects:grant",
+            Scope::ManageEffects => "effects:manage",
+            Scope::ReadPlayerData => "data:read",
+            Scope::SendMail => "mail:send",
+            Scope::ManageKeys => "keys:manage",
+            Scope::ManageShop => "shop:manage",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<Scope> {
+        Scope::ALL.into_iter().find(|s| s.as_str() == scope)
+    }
+}
+
+/// A credential for a trusted service such as the game server.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ServiceKey {
+    pub key_id: Uuid,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    #[schema(value_type = String, format = DateTime)]
+    pub created: DateTime<Utc>,
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub revoked: Option<DateTime<Utc>>,
+}
+
+/// The two currencies a player has.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Currency {
+    Coins,
+    Bucks,
+}
+
+impl Currency {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Currency::Coins => "coins",
+            Currency::Bucks => "bucks",
+        }
+    }
+
+    pub fn parse(currency: &str) -> Option<Currency> {
+        match currency {
+            "coins" => Some(Currency::Coins),
+            "bucks" => Some(Currency::Bucks),
+            _ => None,
+        }
+    }
+}
+
+/// Who caused a change of a balance.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TransactionSource {
+    Player(Uuid),
+    Service(Uuid),
+    /// The backend itself, for example the starting balance of a new account.
+    System,
+}
+
+impl TransactionSource {
+    /// Returns the source type and id as stored in the ledger.
+    pub fn parts(&self) -> (&'static str, Option<Uuid>) {
+        match self {
+            TransactionSource::Player(user_id) => ("player", Some(*user_id)),
+            TransactionSource::Service(key_id) => ("service", Some(*key_id)),
+            TransactionSource::System => ("system", None),
+        }
+    }
+}
+
+/// A change of a balance that is written to the ledger.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CurrencyChange {
+    pub user_id: Uuid,
+    pub currency: Currency,
+    pub delta: i32,
+    /// A short code such as `shop_purchase`, see the README for the codes in use.
+    pub reason: String,
+    pub source: TransactionSource,
+    pub reference_id: Option<Uuid>,
+}
+
+/// One change of a balance as recorded in the ledger.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LedgerEntry {
+    pub entry_id: i64,
+    pub currency: Currency,
+    pub delta: i32,
+    pub balance_after: i32,
+    pub reason: String,
+    /// `player`, `service` or `system`.
+    pub source_type: String,
+    pub source_id: Option<Uuid>,
+    pub reference_id: Option<Uuid>,
+    #[schema(value_type = String, format = DateTime)]
+    pub created: DateTime<Utc>,
+}
+
+/// A page of the ledger of a user, newest first.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CurrencyHistory {
+    pub entries: Vec<LedgerEntry>,
+    /// Pass this as `before` to get the next page, absent on the last page.
+    pub next_before: Option<i64>,
+}
+
+/// A balance in stats that does not match the sum of its ledger entries.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LedgerMismatch {
+    pub user_id: Uuid,
+    pub currency: Currency,
+    pub balance: i64,
+    pub ledger_total: i64,
+}
+
+/// Something players can buy in the shop.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ShopOffer {
+    pub offer_id: Uuid,
+    /// The item that is bought.
+    pub definition_id: i32,
+    /// The state_blob every bought item starts with.
+    pub state_template: String,
+    pub currency: Currency,
+    pub price: i32,
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub available_from: Option<DateTime<Utc>>,
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub available_until: Option<DateTime<Utc>>,
+    /// How often one player can buy this offer, unlimited when absent.
+    pub purchase_limit: Option<i32>,
+}
+
+impl ShopOffer {
+    pub fn is_available(&self, now: DateTime<Utc>) -> bool {
+        self.available_from.is_none_or(|from| from <= now)
+            && self.available_until.is_none_or(|until| now < until)
+    }
+}
+
+/// What kind of item a definition is.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemKind {
+    Rod,
+    Bait,
+    Extra,
+    Consumable,
+}
+
+/// An item of the game, as defined in the item catalog.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ItemDefinition {
+    pub id: i32,
+    pub name: String,
+    pub kind: ItemKind,
+    /// How many of the item fit on one inventory item.
+    pub max_stack: u16,
+    /// The state_blob a new item of this definition starts with.
+    pub default_state: String,
+    /// Every new account gets one of these.
+    #[serde(default)]
+    pub starter: bool,
+}
+
+/// All item definitions together with a hash of them, so clients only download the catalog when it changed.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ItemCatalogResponse {
+    pub hash: String,
+    pub items: Vec<ItemDefinition>,
+}
+
+/// Where a trade is at. Only open trades can be accepted, countered, declined or cancelled.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TradeStatus {
+    Open,
+    Accepted,
+    Declined,
+    /// The recipient answered with a trade of their own.
+    Countered,
+    Cancelled,
+}
+
+impl TradeStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TradeStatus::Open => "open",
+            TradeStatus::Accepted => "accepted",
+            TradeStatus::Declined => "declined",
+            TradeStatus::Countered => "countered",
+            TradeStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<TradeStatus> {
+        match status {
+            "open" => Some(TradeStatus::Open),
+            "accepted" => Some(TradeStatus::Accepted),
+            "declined" => Some(TradeStatus::Declined),
+            "countered" => Some(TradeStatus::Countered),
+            "cancelled" => Some(TradeStatus::Cancelled),
+            _ => None,
+        }
+    }
+}
+
+/// What the proposer of a trade gives and wants in return.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TradeTerms {
+    /// Items of the proposer, they are locked in escrow while the trade is open.
+    #[serde(default)]
+    pub offered_items: Vec<Uuid>,
+    #[serde(default)]
+    pub offered_coins: i32,
+    /// Items of the recipient.
+    #[serde(default)]
+    pub requested_items: Vec<Uuid>,
+    #[serde(default)]
+    pub requested_coins: i32,
+}
+
+/// A trade one player proposes to another.
+#[derive(Debug, Clone)]
+pub struct TradeProposal {
+    pub proposer_id: Uuid,
+    pub recipient_id: Uuid,
+    pub terms: TradeTerms,
+}
+
+/// An item in a trade, with its state when the trade was proposed or accepted.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TradeItem {
+    pub item_uuid: Uuid,
+    pub definition_id: i32,
+    pub state_blob: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Trade {
+    pub trade_id: Uuid,
+    pub proposer_id: Uuid,
+    pub recipient_id: Uuid,
+    pub offered_items: Vec<TradeItem>,
+    pub offered_coins: i32,
+    pub requested_items: Vec<TradeItem>,
+    pub requested_coins: i32,
+    pub status: TradeStatus,
+    /// The trade this one counters.
+    pub parent_trade_id: Option<Uuid>,
+    #[schema(value_type = String, format = DateTime)]
+    pub created: DateTime<Utc>,
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub resolved: Option<DateTime<Utc>>,
+}
+
+/// A ranking of players. The boards are refreshed periodically, not on every change.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Leaderboard {
+    Xp,
+    /// All fish caught.
+    Catches,
+    /// Distinct species caught.
+    Species,
+    /// The longest fish of one species.
+    FishLength(i32),
+}
+
+impl Leaderboard {
+    /// The name of a board in the `player_leaderboards` view, None for per species boards.
+    pub fn as_str(&self) -> Option<&'static str> {
+        match self {
+            Leaderboard::Xp => Some("xp"),
+            Leaderboard::Catches => Some("catches"),
+            Leaderboard::Species => Some("species"),
+            Leaderboard::FishLength(_) => None,
+        }
+    }
+
+    pub fn parse(board: &str) -> Option<Leaderboard> {
+        match board {
+            "xp" => Some(Leaderboard::Xp),
+            "catches" => Some(Leaderboard::Catches),
+            "species" => Some(Leaderboard::Species),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LeaderboardEntry {
+    /// Unique place on the board, 1 is the top.
+    pub position: i64,
+    /// Shared by players with the same score.
+    pub rank: i64,
+    pub user_id: Uuid,
+    pub name: String,
+    pub score: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LeaderboardPage {
+    pub entries: Vec<LeaderboardEntry>,
+    /// When the board was last refreshed, None when it is empty.
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub refreshed: Option<DateTime<Utc>>,
+}
+
+/// A player on the leaderboard of the caller and their friends, with their rank at the weekly reset.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FriendLeaderboardEntry {
+    pub position: i64,
+    pub rank: i64,
+    pub user_id: Uuid,
+    pub name: String,
+    pub score: i64,
+    /// The rank among the same players at the last weekly reset, None when the player registered after it.
+    pub previous_rank: Option<i64>,
+    /// How many places the player moved up since the reset, negative when they dropped.
+    pub rank_change: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FriendLeaderboard {
+    pub entries: Vec<FriendLeaderboardEntry>,
+    /// When the week the rank changes are counted from started, None before the first reset.
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub week_start: Option<DateTime<Utc>>,
+}
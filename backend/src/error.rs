@@ -0,0 +1,153 @@
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::serde::json::Json;
+use rocket::{catch, catchers, Catcher};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use utoipa::ToSchema;
+
+/// Error body of every failed request.
+/// `code` is meant for clients to act on, for example `insufficient_coins` or `mail_not_found`.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ErrorResponse {
+    pub code: String,
+}
+
+/// Everything that can go wrong in a service.
+/// Each variant is answered with its own status code and carries the error code for the body.
+#[derive(Debug)]
+pub enum AppError {
+    /// 400, the request itself is wrong.
+    BadRequest(&'static str),
+    /// 401, not logged in or the credentials are invalid.
+    Unauthorized(&'static str),
+    /// 403, logged in but not allowed to do this.
+    Forbidden(&'static str),
+    /// 404
+    NotFound(&'static str),
+    /// 409, the request conflicts with the current state, for example a name that is already taken.
+    Conflict(&'static str),
+    /// 409, other requests kept changing the same data in between, a retry may succeed.
+    Contention(&'static str),
+    /// 422, the request is well formed but breaks a game rule, for example not enough coins.
+    UnprocessableEntity(&'static str),
+    /// 500, the details are logged but not send to the client.
+    Database(sqlx::Error),
+    /// A failed result stored for an idempotency key, answered again with the same status and code.
+    Replayed(Status, String),
+}
+
+impl AppError {
+    pub fn status(&self) -> Status {
+        match self {
+            AppError::BadRequest(_) => Status::BadRequest,
+            AppError::Unauthorized(_) => Status::Unauthorized,
+            AppError::Forbidden(_) => Status::Forbidden,
+            AppError::NotFound(_) => Status::NotFound,
+            AppError::Conflict(_) | AppError::Contention(_) => Status::Conflict,
+            AppError::UnprocessableEntity(_) => Status::UnprocessableEntity,
+            AppError::Database(_) => Status::InternalServerError,
+            AppError::Replayed(status, _) => *status,
+        }
+    }
+
+    pub fn code(&self) -> &str {
+        match self {
+            AppError::BadRequest(code)
+            | AppError::Unauthorized(code)
+            | AppError::Forbidden(code)
+            | AppError::NotFound(code)
+            | AppError::Conflict(code)
+            | AppError::Contention(code)
+            | AppError::UnprocessableEntity(code) => code,
+            AppError::Database(_) => "internal_error",
+            AppError::Replayed(_, code) => code,
+        }
+    }
+
+    /// Whether a retry of the same request may turn out differently.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, AppError::Database(_) | AppError::Contention(_))
+    }
+
+    /// Converts a repository error, naming what was not found when the row is missing.
+    ///
+    /// ```ignore
+    /// self.mail_repository.delete(user_id, mail_id).await.map_err(AppError::not_found("mail_not_found"))
+    /// ```
+    pub fn not_found(code: &'static str) -> impl Fn(sqlx::Error) -> AppError {
+        move |e| match e {
+            sqlx::Error::RowNotFound => AppError::NotFound(code),
+            e => AppError::from(e),
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Database(e) => write!(f, "{}", e),
+            e => write!(f, "{}", e.code()),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+// Postgres error codes, see https://www.postgresql.org/docs/current/errcodes-appendix.html
+const FOREIGN_KEY_VIOLATION: &str = "23503";
+const UNIQUE_VIOLATION: &str = "23505";
+const CHECK_VIOLATION: &str = "23514";
+
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        match &e {
+            sqlx::Error::RowNotFound => AppError::NotFound("not_found"),
+            sqlx::Error::Database(db) => match db.code().as_deref() {
+                Some(UNIQUE_VIOLATION) => AppError::Conflict("already_exists"),
+                Some(FOREIGN_KEY_VIOLATION) => AppError::UnprocessableEntity("unknown_reference"),
+                Some(CHECK_VIOLATION) => AppError::UnprocessableEntity("invalid_value"),
+                _ => AppError::Database(e),
+            },
+            _ => AppError::Database(e),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for AppError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        if let AppError::Database(e) = &self {
+            dbg!(e);
+        }
+        let body = Json(ErrorResponse {
+            code: self.code().to_string(),
+        });
+        (self.status(), body).respond_to(request)
+    }
+}
+
+/// Errors raised before a route runs, for example by a guard or an invalid body,
+/// get the same body as an `AppError`.
+#[catch(default)]
+fn default_catcher(status: Status, _request: &Request) -> (Status, Json<ErrorResponse>) {
+    let code = match status.code {
+        400 => "bad_request",
+        401 => "unauthorized",
+        403 => "forbidden",
+        404 => "not_found",
+        409 => "conflict",
+        422 => "unprocessable_entity",
+        _ => "internal_error",
+    };
+    (
+        status,
+        Json(ErrorResponse {
+            code: code.to_string(),
+        }),
+    )
+}
+
+pub fn error_catchers() -> Vec<Catcher> {
+    catchers![default_catcher]
+}
@@ -1,20 +1,37 @@
-use crate::controller::authentication::authentication_routes;
+use crate::controller::authentication::{authentication_routes, well_known_routes};
+use crate::controller::idempotency::IDEMPOTENCY_KEY_HEADER;
 use crate::controller::stats::stats_routes;
-use crate::domain::User;
+use crate::domain::{AuthenticatedUser, User};
 use crate::repository::friends::FriendRepositoryImpl;
 use crate::service::authentication::*;
 use crate::service::user::UserService;
 use crate::AuthenticationService;
+use controller::achievements::achievement_routes;
 use controller::data::data_routes;
 use controller::effects::routes as effects_routes;
 use controller::inventory::inventory_routes;
+use controller::items::item_routes;
+use controller::leaderboards::leaderboard_routes;
 use controller::mail::mail_routes;
+use controller::quests::quest_routes;
+use controller::service_keys::service_key_routes;
+use controller::shop::shop_routes;
+use controller::trade::trade_routes;
 use controller::friends::friend_routes;
 use dotenv::dotenv;
+use repository::achievements::AchievementRepositoryImpl;
 use repository::data::DataRepositoryImpl;
 use repository::effects::EffectsRepositoryImpl;
+use repository::idempotency::IdempotencyRepositoryImpl;
 use repository::inventory::InventoryRepositoryImpl;
+use repository::leaderboards::LeaderboardRepositoryImpl;
+use repository::ledger::LedgerRepositoryImpl;
 use repository::mail::MailRepositoryImpl;
+use repository::quests::QuestRepositoryImpl;
+use repository::service_keys::ServiceKeyRepositoryImpl;
+use repository::sessions::SessionRepositoryImpl;
+use repository::shop::ShopRepositoryImpl;
+use repository::trade::TradeRepositoryImpl;
 use repository::stats::StatsRepositoryImpl;
 use rocket::http::Status;
 use rocket::request;
@@ -25,18 +42,34 @@ use rocket::Request;
 use rocket::State;
 use rocket_cors::AllowedOrigins;
 use rocket_cors::{AllowedHeaders, CorsOptions};
+use service::achievements::{spawn_achievement_worker, AchievementService, AchievementServiceImpl};
 use service::data::DataService;
 use service::data::DataServiceImpl;
 use service::effects::EffectsService;
 use service::effects::EffectsServiceImpl;
 use service::friends::FriendService;
 use service::friends::FriendServiceImpl;
+use service::idempotency::{IdempotencyService, IdempotencyServiceImpl, DEFAULT_IDEMPOTENCY_TTL_HOURS};
 use service::inventory::InventoryService;
 use service::inventory::InventoryServiceImpl;
+use service::leaderboards::{
+    spawn_refresh_job, LeaderboardService, LeaderboardServiceImpl, DEFAULT_LEADERBOARD_REFRESH_MINUTES,
+};
+use service::ledger::LedgerService;
+use service::ledger::LedgerServiceImpl;
 use service::mail::MailService;
-use service::mail::MailServiceImpl;
+use service::mail::{
+    spawn_retention_job, MailServiceImpl, DEFAULT_MAIL_RETENTION_DAYS, DEFAULT_MAIL_RETENTION_INTERVAL_MINUTES,
+};
+use service::quests::{QuestService, QuestServiceImpl, DEFAULT_QUEST_RESET_UTC_OFFSET};
+use service::service_keys::ServiceKeyService;
+use service::service_keys::ServiceKeyServiceImpl;
+use service::shop::ShopService;
+use service::shop::ShopServiceImpl;
+use service::trade::TradeService;
+use service::trade::TradeServiceImpl;
 use service::stats::StatsService;
-use service::stats::StatsServiceImpl;
+use service::stats::{spawn_catch_log_partition_job, StatsServiceImpl};
 use std::env;
 use std::sync::Arc;
 use sqlx::PgPool;
@@ -44,15 +77,25 @@ use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 use crate::controller::user::*;
 use crate::docs::ApiDoc;
+use crate::error::error_catchers;
 use crate::repository::user::UserRepositoryImpl;
 use crate::service::user::UserServiceImpl;
+use crate::utils::achievements::achievements_from_env;
+use crate::utils::catalog::catalog_from_env;
+use crate::utils::events::EventBus;
+use crate::utils::keys::keys_from_env;
+use crate::utils::levels::levels_from_env;
+use crate::utils::quests::quests_from_env;
+use crate::utils::mailer::mailer_from_env;
 
 extern crate rocket;
 
 // Import all the different layers that make up the backend.
+pub mod cli;
 pub mod controller;
 pub mod docs;
 pub mod domain;
+pub mod error;
 pub mod repository;
 pub mod service;
 pub mod utils;
@@ -64,33 +107,52 @@ pub mod utils;
 // it reades the user_id from the jwt and recieves the user from the database.
 // Then that user is being parsed into the endpoint.
 //
-// If the JWT is not in the header, it returns access denied.
+// If the JWT is not in the header, or its session is revoked, it returns access denied.
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for User {
     type Error = ();
 
     async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
-        // Recieve the authentication service from rocket for recieving and validating the jwt.
-        let authentication_service = request
-            .guard::<&State<Arc<dyn AuthenticationService>>>()
+        request
+            .guard::<AuthenticatedUser>()
             .await
-            .unwrap();
+            .map(|authenticated| authenticated.user)
+    }
+}
+
+// Same as the User guard, but also gives the session the JWT belongs to.
+// Use this for endpoints that act on the current session, such as logout.
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthenticatedUser {
+    type Error = ();
 
-        match request.headers().get_one("Authorization") {
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        // The JWT is verified once per request, other guards that need the user reuse the result.
+        let authenticated: &Option<AuthenticatedUser> = request
+            .local_cache_async(async {
+                // Recieve the authentication service from rocket for recieving and validating the jwt.
+                let authentication_service = request
+                    .guard::<&State<Arc<dyn AuthenticationService>>>()
+                    .await
+                    .unwrap();
+
+                let jwt = request
+                    .headers()
+                    .get_one("Authorization")
+                    .and_then(|autherisation_header| autherisation_header.strip_prefix("Bearer "))?;
+                authentication_service.verify_jwt(jwt).await.ok().flatten()
+            })
+            .await;
+
+        match authenticated {
+            Some(authenticated) => request::Outcome::Success(authenticated.clone()),
             None => Outcome::Error((Status::Unauthorized, ())),
-            Some(autherisation_header) => match autherisation_header.strip_prefix("Bearer ") {
-                None => Outcome::Error((Status::Unauthorized, ())),
-                Some(jwt) => match authentication_service.verify_jwt(jwt).await {
-                    Ok(Some(user)) => request::Outcome::Success(user.clone()),
-                    Ok(None) => Outcome::Error((Status::Unauthorized, ())),
-                    Err(_) => Outcome::Error((Status::Unauthorized, ())),
-                },
-            },
         }
     }
 }
 
 #[rocket::main]
+#[allow(clippy::result_large_err)]
 async fn main() -> Result<(), rocket::Error> {
     // Read the database path from enviousment variables.
     // You can set them in bash like so:
@@ -104,21 +166,53 @@ async fn main() -> Result<(), rocket::Error> {
     // export SECRET_KEY="My secret key :)"
     // ```
     // the program will panic if these are not set.
-    println!("Starting backend...");
+    //
+    // Instead of SECRET_KEY you can point JWT_KEYS to a key set file, see `keys_from_env`.
+    // That allows rotating keys and signing with EdDSA or RS256.
+    //
+    // Service keys for trusted services such as the game server are managed with
+    // `backend service-key`, run it without arguments to see the usage.
     dotenv().ok();
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let secret_key = env::var("SECRET_KEY").expect("SECRET_KEY must be set for generating JWT");
-    let port = env::var("PORT").expect("Connection port must be provided in the ENV");
-    let listening_ip = env::var("IP_ADDR").expect("Listening ip must be provided in the ENV");
 
     // Connect to postgres database.
     let pool = PgPool::connect_lazy(&database_url).expect("Failed to connect to the database");
 
+    // Run a subcommand instead of the server when one is given, see cli.rs.
+    let args: Vec<String> = env::args().collect();
+    if cli::run(&args, pool.clone()).await {
+        return Ok(());
+    }
+
+    // Apply pending migrations before serving when RUN_MIGRATIONS=true.
+    // Otherwise run `backend migrate up` yourself, for example as a deploy step.
+    if env::var("RUN_MIGRATIONS").is_ok_and(|run| run == "true") {
+        cli::MIGRATOR
+            .run(&pool)
+            .await
+            .expect("Failed to apply database migrations");
+    }
+
+    println!("Starting backend...");
+    let keys = Arc::new(keys_from_env());
+    // The item definitions, see backend/items.toml.
+    let catalog = Arc::new(catalog_from_env());
+    // The xp needed for every level and the level rewards, see backend/levels.toml.
+    let levels = Arc::new(levels_from_env(&catalog));
+    // Gameplay events, for the parts of the backend that react to what players do.
+    let events = Arc::new(EventBus::new());
+    // The achievements and their goals, see backend/achievements.toml.
+    let achievements = Arc::new(achievements_from_env(&catalog, &levels));
+    // The daily and weekly quest pool, see backend/quests.toml.
+    let quests = Arc::new(quests_from_env(&catalog));
+    let port = env::var("PORT").expect("Connection port must be provided in the ENV");
+    let listening_ip = env::var("IP_ADDR").expect("Listening ip must be provided in the ENV");
+
     // Alow request from any origin.
     // You should customize this if you want to make your backend more secure.
     let cors = CorsOptions {
         allowed_origins: AllowedOrigins::all(), // Allow all origins
-        allowed_headers: AllowedHeaders::some(&["Authorization", "Content-Type"]),
+        allowed_headers: AllowedHeaders::some(&["Authorization", "Content-Type", IDEMPOTENCY_KEY_HEADER]),
         allow_credentials: true,
         ..Default::default()
     }
@@ -133,12 +227,31 @@ async fn main() -> Result<(), rocket::Error> {
     let stats_repository = StatsRepositoryImpl::new(pool.clone());
     let mail_repository = MailRepositoryImpl::new(pool.clone());
     let inventory_repository = InventoryRepositoryImpl::new(pool.clone());
+    let service_key_repository = ServiceKeyRepositoryImpl::new(pool.clone());
+    let session_repository = SessionRepositoryImpl::new(pool.clone());
+    let ledger_repository = LedgerRepositoryImpl::new(pool.clone());
+    let shop_repository = ShopRepositoryImpl::new(pool.clone());
+    let trade_repository = TradeRepositoryImpl::new(pool.clone());
+    let leaderboard_repository = LeaderboardRepositoryImpl::new(pool.clone());
+    let idempotency_repository = IdempotencyRepositoryImpl::new(pool.clone());
+    let achievement_repository = AchievementRepositoryImpl::new(pool.clone());
+    let quest_repository = QuestRepositoryImpl::new(pool.clone());
+
+    // Mails are written to a log unless an SMTP server is configured, see `mailer_from_env`.
+    let mailer = mailer_from_env();
 
-    let user_service: Arc<dyn UserService> =
-        Arc::new(UserServiceImpl::new(user_repository.clone(), secret_key.clone()));
+    let user_service: Arc<dyn UserService> = Arc::new(UserServiceImpl::new(
+        user_repository.clone(),
+        mailer.clone(),
+        catalog.clone(),
+    ));
 
     let authentication_service: Arc<dyn AuthenticationService> = Arc::new(
-        AuthenticationServiceImpl::new(user_repository.clone(), secret_key.clone()),
+        AuthenticationServiceImpl::new(
+            user_repository.clone(),
+            session_repository.clone(),
+            keys.clone(),
+        ),
     );
 
     let data_service: Arc<dyn DataService> = Arc::new(
@@ -149,27 +262,107 @@ async fn main() -> Result<(), rocket::Error> {
         FriendServiceImpl::new(friends_repository.clone())
     );
 
-    let stats_service: Arc<dyn StatsService> =
-        Arc::new(StatsServiceImpl::new(stats_repository.clone()));
+    // Quests reset at midnight at this offset from UTC, for example "+02:00". UTC by default.
+    let quest_reset_offset = env::var("QUEST_RESET_UTC_OFFSET")
+        .map(|offset| offset.parse().unwrap_or_else(|_| panic!("Could not parse QUEST_RESET_UTC_OFFSET: {:?}", offset)))
+        .unwrap_or(DEFAULT_QUEST_RESET_UTC_OFFSET);
+    let stats_service: Arc<dyn StatsService> = Arc::new(StatsServiceImpl::new(
+        stats_repository.clone(),
+        catalog.clone(),
+        levels.clone(),
+        quests.clone(),
+        quest_reset_offset,
+        events.clone(),
+    ));
+    spawn_catch_log_partition_job(stats_service.clone());
 
-    let mail_service: Arc<dyn MailService> = Arc::new(
-        MailServiceImpl::new(mail_repository.clone())
+    let ledger_service: Arc<dyn LedgerService> = Arc::new(
+        LedgerServiceImpl::new(ledger_repository.clone())
     );
 
+    // Read and archived mail is removed after this many days, 30 by default.
+    let mail_retention_days = env::var("MAIL_RETENTION_DAYS")
+        .map(|days| days.parse().unwrap_or_else(|_| panic!("Could not parse MAIL_RETENTION_DAYS: {:?}", days)))
+        .unwrap_or(DEFAULT_MAIL_RETENTION_DAYS);
+    let mail_service: Arc<dyn MailService> = Arc::new(MailServiceImpl::new(
+        mail_repository.clone(),
+        catalog.clone(),
+        chrono::Duration::days(mail_retention_days),
+        events.clone(),
+    ));
+
+    // Clean up the mail in the background, every hour by default. 0 turns the job off.
+    let mail_retention_interval = env::var("MAIL_RETENTION_INTERVAL_MINUTES")
+        .map(|minutes| minutes.parse().unwrap_or_else(|_| panic!("Could not parse MAIL_RETENTION_INTERVAL_MINUTES: {:?}", minutes)))
+        .unwrap_or(DEFAULT_MAIL_RETENTION_INTERVAL_MINUTES);
+    if mail_retention_interval > 0 {
+        spawn_retention_job(mail_service.clone(), std::time::Duration::from_secs(mail_retention_interval * 60));
+    }
+
     let inventory_service: Arc<dyn InventoryService> = Arc::new(
-        InventoryServiceImpl::new(inventory_repository.clone())
+        InventoryServiceImpl::new(inventory_repository.clone(), catalog.clone(), events.clone())
     );
 
     let effects_service: Arc<dyn EffectsService> = Arc::new(
         EffectsServiceImpl::new(effects_repository.clone())
     );
 
+    let service_key_service: Arc<dyn ServiceKeyService> = Arc::new(
+        ServiceKeyServiceImpl::new(service_key_repository.clone())
+    );
+
+    let shop_service: Arc<dyn ShopService> = Arc::new(
+        ShopServiceImpl::new(shop_repository.clone(), catalog.clone(), events.clone())
+    );
+
+    let trade_service: Arc<dyn TradeService> = Arc::new(
+        TradeServiceImpl::new(trade_repository.clone(), events.clone())
+    );
+
+    // Results of requests with an Idempotency-Key are kept this long, 24 hours by default.
+    let idempotency_ttl_hours = env::var("IDEMPOTENCY_TTL_HOURS")
+        .map(|hours| hours.parse().unwrap_or_else(|_| panic!("Could not parse IDEMPOTENCY_TTL_HOURS: {:?}", hours)))
+        .unwrap_or(DEFAULT_IDEMPOTENCY_TTL_HOURS);
+    let idempotency_service: Arc<dyn IdempotencyService> = Arc::new(IdempotencyServiceImpl::new(
+        idempotency_repository.clone(),
+        chrono::Duration::hours(idempotency_ttl_hours),
+    ));
+
+    let leaderboard_service: Arc<dyn LeaderboardService> = Arc::new(
+        LeaderboardServiceImpl::new(leaderboard_repository.clone())
+    );
+
+    // Refresh the leaderboards in the background, every 5 minutes by default. 0 turns the job off.
+    let leaderboard_refresh_interval = env::var("LEADERBOARD_REFRESH_MINUTES")
+        .map(|minutes| minutes.parse().unwrap_or_else(|_| panic!("Could not parse LEADERBOARD_REFRESH_MINUTES: {:?}", minutes)))
+        .unwrap_or(DEFAULT_LEADERBOARD_REFRESH_MINUTES);
+    if leaderboard_refresh_interval > 0 {
+        spawn_refresh_job(leaderboard_service.clone(), std::time::Duration::from_secs(leaderboard_refresh_interval * 60));
+    }
+
+    // Achievements follow the gameplay events in the background.
+    let achievement_service: Arc<dyn AchievementService> = Arc::new(AchievementServiceImpl::new(
+        achievement_repository.clone(),
+        achievements.clone(),
+        catalog.clone(),
+        levels.clone(),
+        events.clone(),
+    ));
+    spawn_achievement_worker(achievement_service.clone(), events.subscribe());
+
+    let quest_service: Arc<dyn QuestService> = Arc::new(QuestServiceImpl::new(
+        quest_repository.clone(),
+        quests.clone(),
+        quest_reset_offset,
+        events.clone(),
+    ));
+
     // Add here more repositories and services when your backend grows.
 
     // Set rocket configuration.
     let config = Config {
-        port: port.parse().expect(&format!("could not parse port: {:?}", port)),
-        address: listening_ip.parse().expect(&format!("Could not parse listening ip: {:?}", listening_ip)),
+        port: port.parse().unwrap_or_else(|_| panic!("could not parse port: {:?}", port)),
+        address: listening_ip.parse().unwrap_or_else(|_| panic!("Could not parse listening ip: {:?}", listening_ip)),
         ..Config::debug_default()
     };
 
@@ -180,11 +373,20 @@ async fn main() -> Result<(), rocket::Error> {
         .manage(user_service)
         .manage(authentication_service)
         .manage(stats_service)
+        .manage(ledger_service)
         .manage(mail_service)
         .manage(inventory_service)
         .manage(data_service)
         .manage(friend_service)
         .manage(effects_service)
+        .manage(service_key_service)
+        .manage(shop_service)
+        .manage(trade_service)
+        .manage(leaderboard_service)
+        .manage(achievement_service)
+        .manage(quest_service)
+        .manage(idempotency_service)
+        .manage(catalog)
         // expose swagger ui.
         // Go to http://localhost:8000/docs to view your endpoint documentation.
         .mount(
@@ -194,12 +396,22 @@ async fn main() -> Result<(), rocket::Error> {
         // Mount all your routes here.
         .mount("/account", user_routes())
         .mount("/login", authentication_routes())
+        .mount("/.well-known", well_known_routes())
         .mount("/stats", stats_routes())
         .mount("/mail", mail_routes())
         .mount("/inventory", inventory_routes())
+        .mount("/items", item_routes())
         .mount("/data", data_routes())
         .mount("/friend", friend_routes())
         .mount("/effects", effects_routes())
+        .mount("/service_keys", service_key_routes())
+        .mount("/shop", shop_routes())
+        .mount("/trades", trade_routes())
+        .mount("/leaderboards", leaderboard_routes())
+        .mount("/achievements", achievement_routes())
+        .mount("/quests", quest_routes())
+        // Failed guards and invalid bodies answer with the same error body as the routes.
+        .register("/", error_catchers())
         .attach(cors)
         .launch()
         .await?;
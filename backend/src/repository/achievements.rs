@@ -0,0 +1,178 @@
+use crate::domain::{GrantedReward, Reward};
+use crate::repository::rewards::grant_reward;
+use chrono::{DateTime, Utc};
+use rocket::async_trait;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// The stored progress of a player towards one achievement.
+#[derive(Debug)]
+pub struct AchievementRecord {
+    pub achievement_id: String,
+    pub progress: i32,
+    pub unlocked: Option<DateTime<Utc>>,
+}
+
+/// The numbers most achievement goals are measured by.
+#[derive(Debug)]
+pub struct PlayerTotals {
+    pub xp: i32,
+    pub playtime: i32,
+    pub fish_caught: i64,
+    pub species_caught: i64,
+}
+
+#[async_trait]
+pub trait AchievementRepository: Send + Sync {
+    /// None when the user has no stats.
+    async fn totals(&self, user_id: Uuid) -> Result<Option<PlayerTotals>, sqlx::Error>;
+
+    /// In how many of `areas` the user caught the fish.
+    async fn areas_with_species(&self, user_id: Uuid, fish_id: i32, areas: &[i32]) -> Result<i64, sqlx::Error>;
+
+    /// How many items of the definitions the user owns.
+    async fn items_owned(&self, user_id: Uuid, definition_ids: &[i32]) -> Result<i64, sqlx::Error>;
+
+    /// The progress and unlock time of every achievement the user made progress on.
+    async fn list(&self, user_id: Uuid) -> Result<Vec<AchievementRecord>, sqlx::Error>;
+
+    async fn save_progress(
+        &self,
+        user_id: Uuid,
+        progress: Vec<(String, i32)>,
+        now: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error>;
+
+    /// Records the unlock and grants the reward in one transaction.
+    /// Returns None when the achievement was unlocked already, then nothing is granted.
+    async fn unlock(
+        &self,
+        user_id: Uuid,
+        achievement_id: &str,
+        reward: &Reward,
+        now: DateTime<Utc>,
+    ) -> Result<Option<GrantedReward>, sqlx::Error>;
+}
+
+#[derive(Debug, Clone)]
+pub struct AchievementRepositoryImpl {
+    pool: PgPool,
+}
+
+impl AchievementRepositoryImpl {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl AchievementRepository for AchievementRepositoryImpl {
+    async fn totals(&self, user_id: Uuid) -> Result<Option<PlayerTotals>, sqlx::Error> {
+        sqlx::query_as!(
+            PlayerTotals,
+            r#"SELECT s.xp, s.total_playtime AS playtime,
+                COALESCE(SUM(f.amount), 0) AS "fish_caught!",
+                COUNT(f.fish_id) AS "species_caught!"
+            FROM stats s
+            LEFT JOIN fish_caught f ON f.user_id = s.user_id
+            WHERE s.user_id = $1
+            GROUP BY s.user_id"#,
+            user_id,
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn areas_with_species(&self, user_id: Uuid, fish_id: i32, areas: &[i32]) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!"
+            FROM fish_caught_area
+            WHERE user_id = $1 AND fish_id = $2 AND area_id = ANY($3)"#,
+            user_id,
+            fish_id,
+            areas,
+        )
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn items_owned(&self, user_id: Uuid, definition_ids: &[i32]) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!"
+            FROM inventory_item
+            WHERE user_id = $1 AND definition_id = ANY($2)"#,
+            user_id,
+            definition_ids,
+        )
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn list(&self, user_id: Uuid) -> Result<Vec<AchievementRecord>, sqlx::Error> {
+        // Unlocking saves the progress first, so every unlock has a progress row.
+        sqlx::query_as!(
+            AchievementRecord,
+            r#"SELECT p.achievement_id, p.progress, u.unlocked AS "unlocked?"
+            FROM achievement_progress p
+            LEFT JOIN achievement_unlocks u ON u.user_id = p.user_id AND u.achievement_id = p.achievement_id
+            WHERE p.user_id = $1"#,
+            user_id,
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn save_progress(
+        &self,
+        user_id: Uuid,
+        progress: Vec<(String, i32)>,
+        now: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        let (achievement_ids, values): (Vec<String>, Vec<i32>) = progress.into_iter().unzip();
+        if let Err(e) = sqlx::query!(
+            "INSERT INTO achievement_progress (user_id, achievement_id, progress, updated)
+            SELECT $1, achievement_id, progress, $4
+            FROM UNNEST($2::text[], $3::int[]) AS p (achievement_id, progress)
+            ON CONFLICT (user_id, achievement_id)
+            DO UPDATE SET progress = EXCLUDED.progress, updated = EXCLUDED.updated",
+            user_id,
+            &achievement_ids,
+            &values,
+            now,
+        )
+        .execute(&self.pool)
+        .await {
+            dbg!(&e);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn unlock(
+        &self,
+        user_id: Uuid,
+        achievement_id: &str,
+        reward: &Reward,
+        now: DateTime<Utc>,
+    ) -> Result<Option<GrantedReward>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let inserted = sqlx::query!(
+            "INSERT INTO achievement_unlocks (user_id, achievement_id, unlocked)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, achievement_id) DO NOTHING",
+            user_id,
+            achievement_id,
+            now,
+        )
+        .execute(&mut *tx)
+        .await?;
+        if inserted.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let granted = grant_reward(&mut tx, user_id, reward, "achievement", now).await?;
+        tx.commit().await?;
+        Ok(Some(granted))
+    }
+}
@@ -1,8 +1,10 @@
+use chrono::Utc;
 use rocket::async_trait;
 use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::domain::{ActiveEffect, FishData, Friend, FriendRequest, InventoryItem, MailEntry, UserData};
+use crate::repository::mail::deliver_broadcasts;
 
 
 #[async_trait]
@@ -30,6 +32,9 @@ impl DataRepository for DataRepositoryImpl {
         &self,
         user_id: Uuid,
     ) -> Result<Option<UserData>, sqlx::Error> {
+        let mut conn = self.pool.acquire().await?;
+        deliver_broadcasts(&mut conn, user_id, Utc::now()).await?;
+
         let rows = match sqlx::query!(
             "SELECT 
             u.name,
@@ -56,7 +61,8 @@ impl DataRepository for DataRepositoryImpl {
                     DISTINCT jsonb_build_object(
                         'definition_id', i.definition_id,
                         'item_uuid', i.item_uuid,
-                        'state_blob', i.state_blob
+                        'state_blob', i.state_blob,
+                        'escrow_trade_id', i.escrow_trade_id
                     )
                 ) FILTER (WHERE i.definition_id IS NOT NULL), '[]'
             ) AS inventory_item,
@@ -68,7 +74,22 @@ impl DataRepository for DataRepositoryImpl {
                         'message', m.message,
                         'send_time', m.send_time,
                         'read', mb.read,
-                        'archived', mb.archived
+                        'archived', mb.archived,
+                        'claimed', mb.claimed IS NOT NULL,
+                        'expires_at', m.expires_at,
+                        'attachments', COALESCE(
+                            (
+                                SELECT jsonb_agg(
+                                    CASE WHEN a.kind = 'item'
+                                        THEN jsonb_build_object('type', 'item', 'definition_id', a.definition_id, 'state_blob', a.state_blob)
+                                        ELSE jsonb_build_object('type', 'currency', 'currency', a.currency, 'amount', a.amount)
+                                    END
+                                    ORDER BY a.attachment_id
+                                )
+                                FROM mail_attachments a
+                                WHERE a.mail_id = m.mail_id
+                            ), '[]'
+                        )
                     )
                 ) FILTER (WHERE m.mail_id IS NOT NULL), '[]'
             ) AS mailbox,
@@ -111,13 +132,13 @@ impl DataRepository for DataRepositoryImpl {
             ) fcb ON fc.user_id = fcb.user_id AND fc.fish_id = fcb.fish_id
             LEFT JOIN inventory_item i ON u.user_id = i.user_id
             LEFT JOIN mailbox mb ON u.user_id = mb.user_id
-            LEFT JOIN mail m ON mb.mail_id = m.mail_id
+            LEFT JOIN mail m ON mb.mail_id = m.mail_id AND (m.expires_at IS NULL OR m.expires_at > NOW())
             WHERE u.user_id = $1
             GROUP BY u.user_id, u.name, u.email, u.created, s.xp, s.coins, s.bucks, s.total_playtime, s.selected_rod, s.selected_bait;
             ",
             user_id
         )
-        .fetch_optional(&self.pool)
+        .fetch_optional(&mut *conn)
         .await {
             Ok(o) => o,
             Err(e) => {
@@ -135,8 +156,11 @@ impl DataRepository for DataRepositoryImpl {
                 }
             };
 
-            let inventory_items: Vec<InventoryItem> = match serde_json::from_value(data.inventory_item.unwrap_or_default()) {
-                Ok(o) => o,
+            let inventory_items: Vec<InventoryItem> = match serde_json::from_value::<Vec<InventoryItem>>(data.inventory_item.unwrap_or_default()) {
+                Ok(o) => o
+                    .into_iter()
+                    .map(|item| InventoryItem::new(item.item_uuid, item.definition_id, item.state_blob, item.escrow_trade_id))
+                    .collect(),
                 Err(e) => {
                     dbg!(&e);
                     return Err(sqlx::Error::WorkerCrashed);
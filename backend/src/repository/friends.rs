@@ -12,6 +12,8 @@ pub trait FriendRepository: Send + Sync {
     async fn remove_friend_request(&self, original_sender: Uuid, original_receiver: Uuid) -> Result<(), sqlx::Error>;
 
     async fn add_friend_request(&self, sender: Uuid, receiver: Uuid, sender_id: Uuid, request_created_time: DateTime<Utc>) -> Result<(), sqlx::Error>;
+
+    async fn get_request_sender(&self, user_one_id: Uuid, user_two_id: Uuid) -> Result<Option<Uuid>, sqlx::Error>;
 }
 
 #[derive(Debug, Clone)]
@@ -120,4 +122,22 @@ impl FriendRepository for FriendRepositoryImpl {
 
         Ok(())
     }
+
+    async fn get_request_sender(&self, user_one_id: Uuid, user_two_id: Uuid) -> Result<Option<Uuid>, sqlx::Error> {
+        match sqlx::query_scalar!(
+            "SELECT request_sender_id FROM friend_requests
+                WHERE (user_one_id = $1 AND user_two_id = $2)
+                OR (user_one_id = $2 AND user_two_id = $1)",
+            user_one_id,
+            user_two_id,
+        )
+        .fetch_optional(&self.pool)
+        .await {
+            Ok(o) => Ok(o),
+            Err(e) => {
+                dbg!(&e);
+                Err(e)
+            }
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,146 @@
+use crate::domain::TransactionSource;
+use chrono::{DateTime, Utc};
+use rocket::async_trait;
+use sqlx::{Error, PgPool};
+
+/// A request that was sent with the same idempotency key before.
+#[derive(Debug, PartialEq, Eq)]
+pub struct StoredRequest {
+    pub request_hash: String,
+    /// The http status of the result, `None` while the first request is still running.
+    pub status: Option<i16>,
+    /// The JSON body of the result, `None` while the first request is still running.
+    pub response: Option<String>,
+}
+
+#[async_trait]
+pub trait IdempotencyRepository: Send + Sync {
+    /// Reserves the key for a request, or returns what is stored for it when it is already taken.
+    ///
+    /// Expired keys are removed first. A reservation that never got a result is kept until it expires,
+    /// its request may have changed something before the result could be stored.
+    async fn claim(
+        &self,
+        caller: TransactionSource,
+        key: &str,
+        request_hash: &str,
+        now: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Option<StoredRequest>, sqlx::Error>;
+
+    async fn complete(&self, caller: TransactionSource, key: &str, status: i16, response: String) -> Result<(), sqlx::Error>;
+
+    /// Frees the key again, for example because the request failed.
+    async fn release(&self, caller: TransactionSource, key: &str) -> Result<(), sqlx::Error>;
+}
+
+#[derive(Debug, Clone)]
+pub struct IdempotencyRepositoryImpl {
+    pool: PgPool,
+}
+
+impl IdempotencyRepositoryImpl {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl IdempotencyRepository for IdempotencyRepositoryImpl {
+    async fn claim(
+        &self,
+        caller: TransactionSource,
+        key: &str,
+        request_hash: &str,
+        now: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Option<StoredRequest>, sqlx::Error> {
+        let (caller_type, caller_id) = caller.parts();
+        // Requests of the system do not go through http, so there is always an id.
+        let caller_id = caller_id.ok_or(Error::RowNotFound)?;
+
+        sqlx::query!(
+            "DELETE FROM idempotency_keys
+            WHERE expires_at <= $1",
+            now,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        let claimed = sqlx::query!(
+            "INSERT INTO idempotency_keys (caller_type, caller_id, idempotency_key, request_hash, created, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT DO NOTHING",
+            caller_type,
+            caller_id,
+            key,
+            request_hash,
+            now,
+            expires_at,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        if claimed.rows_affected() == 1 {
+            return Ok(None);
+        }
+
+        // Taken by an earlier request.
+        let stored = sqlx::query_as!(
+            StoredRequest,
+            "SELECT request_hash, status, response
+            FROM idempotency_keys
+            WHERE caller_type = $1 AND caller_id = $2 AND idempotency_key = $3",
+            caller_type,
+            caller_id,
+            key,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        // It can only be gone when it was released in the meantime, answer as if it is still running
+        // so the client tries again.
+        Ok(Some(stored.unwrap_or(StoredRequest {
+            request_hash: request_hash.to_string(),
+            status: None,
+            response: None,
+        })))
+    }
+
+    async fn complete(&self, caller: TransactionSource, key: &str, status: i16, response: String) -> Result<(), sqlx::Error> {
+        let (caller_type, caller_id) = caller.parts();
+        let result = sqlx::query!(
+            "UPDATE idempotency_keys
+            SET status = $4, response = $5
+            WHERE caller_type = $1 AND caller_id = $2 AND idempotency_key = $3",
+            caller_type,
+            caller_id,
+            key,
+            status,
+            response,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(Error::RowNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn release(&self, caller: TransactionSource, key: &str) -> Result<(), sqlx::Error> {
+        let (caller_type, caller_id) = caller.parts();
+        sqlx::query!(
+            "DELETE FROM idempotency_keys
+            WHERE caller_type = $1 AND caller_id = $2 AND idempotency_key = $3 AND response IS NULL",
+            caller_type,
+            caller_id,
+            key,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -1,22 +1,55 @@
+use crate::domain::InventoryItem;
 use rocket::async_trait;
 use sqlx::{Error, PgPool};
 use uuid::Uuid;
 
+/// What happened when an item was added or its state replaced.
+#[derive(Debug, PartialEq, Eq)]
+pub enum UpsertOutcome {
+    Written,
+    /// The item uuid is taken by an item of another user.
+    OwnedByOtherUser,
+    /// The stored item has another definition, which is never changed.
+    DefinitionMismatch,
+    InEscrow,
+    /// The item was removed between the write and looking up why it failed.
+    Removed,
+}
+
 #[async_trait]
 pub trait InventoryRepository: Send + Sync {
+    /// Adds the item, or replaces the state of the user's existing item with the same definition.
     async fn add_or_update(
         &self,
         user_id: Uuid,
         item_uuid: Uuid,
         definition_id: i32,
         state_blob: String,
-    ) -> Result<(), sqlx::Error>;
+    ) -> Result<UpsertOutcome, sqlx::Error>;
 
+    /// Returns `RowNotFound` when the user does not have the item or it is locked in escrow.
     async fn destroy(
         &self,
         user_id: Uuid,
         item_uid: Uuid
     ) -> Result<(), sqlx::Error>;
+
+    async fn get(&self, user_id: Uuid, item_uid: Uuid) -> Result<Option<InventoryItem>, sqlx::Error>;
+
+    /// Sets a new state, but only while the item still has the `expected` state and is not in escrow.
+    /// Returns false when the item was changed, locked or removed in the meantime.
+    async fn replace_state(
+        &self,
+        user_id: Uuid,
+        item_uid: Uuid,
+        expected: &str,
+        state_blob: String,
+    ) -> Result<bool, sqlx::Error>;
+
+    /// Removes the item and clears it from the selected rod and bait,
+    /// but only while it still has the `expected` state and is not in escrow.
+    /// Returns false when the item was changed, locked or removed in the meantime.
+    async fn destroy_if_state(&self, user_id: Uuid, item_uid: Uuid, expected: &str) -> Result<bool, sqlx::Error>;
 }
 
 #[derive(Debug, Clone)]
@@ -38,19 +71,22 @@ impl InventoryRepository for InventoryRepositoryImpl {
         item_uuid: Uuid,
         definition_id: i32,
         state_blob: String,
-    ) -> Result<(), sqlx::Error> {
-        match sqlx::query!(
+    ) -> Result<UpsertOutcome, sqlx::Error> {
+        let result = match sqlx::query!(
             "INSERT INTO inventory_item (user_id, item_uuid, definition_id, state_blob)
             VALUES ($1, $2, $3, $4)
             ON CONFLICT (item_uuid)
             DO UPDATE SET
-                state_blob = EXCLUDED.state_blob",
+                state_blob = EXCLUDED.state_blob
+            WHERE inventory_item.escrow_trade_id IS NULL
+                AND inventory_item.user_id = EXCLUDED.user_id
+                AND inventory_item.definition_id = EXCLUDED.definition_id",
             user_id,
             item_uuid,
             definition_id,
             state_blob,
         )
-        .fetch_optional(&self.pool)
+        .execute(&self.pool)
         .await {
             Ok(o) => o,
             Err(e) => {
@@ -58,7 +94,26 @@ impl InventoryRepository for InventoryRepositoryImpl {
                 return Err(e);
             }
         };
-        Ok(())
+
+        if result.rows_affected() == 1 {
+            return Ok(UpsertOutcome::Written);
+        }
+
+        // Nothing was written, the stored item tells why.
+        let existing = sqlx::query!(
+            "SELECT user_id, definition_id, escrow_trade_id
+            FROM inventory_item
+            WHERE item_uuid = $1",
+            item_uuid,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(match existing {
+            None => UpsertOutcome::Removed,
+            Some(existing) if existing.user_id != user_id => UpsertOutcome::OwnedByOtherUser,
+            Some(existing) if existing.definition_id != definition_id => UpsertOutcome::DefinitionMismatch,
+            Some(_) => UpsertOutcome::InEscrow,
+        })
     }
 
     async fn destroy(
@@ -68,7 +123,7 @@ impl InventoryRepository for InventoryRepositoryImpl {
     ) -> Result<(), sqlx::Error> {
         let result = match sqlx::query!(
             "DELETE FROM inventory_item WHERE
-            user_id = $1 AND item_uuid = $2",
+            user_id = $1 AND item_uuid = $2 AND escrow_trade_id IS NULL",
             user_id,
             item_uid,
         )
@@ -87,5 +142,80 @@ impl InventoryRepository for InventoryRepositoryImpl {
 
         Ok(())
     }
+
+    async fn get(&self, user_id: Uuid, item_uid: Uuid) -> Result<Option<InventoryItem>, sqlx::Error> {
+        let row = sqlx::query!(
+            "SELECT item_uuid, definition_id, state_blob, escrow_trade_id FROM inventory_item
+            WHERE user_id = $1 AND item_uuid = $2",
+            user_id,
+            item_uid,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| InventoryItem::new(row.item_uuid, row.definition_id, row.state_blob, row.escrow_trade_id)))
+    }
+
+    async fn replace_state(
+        &self,
+        user_id: Uuid,
+        item_uid: Uuid,
+        expected: &str,
+        state_blob: String,
+    ) -> Result<bool, sqlx::Error> {
+        let result = match sqlx::query!(
+            "UPDATE inventory_item
+            SET state_blob = $4
+            WHERE user_id = $1 AND item_uuid = $2 AND state_blob = $3 AND escrow_trade_id IS NULL",
+            user_id,
+            item_uid,
+            expected,
+            state_blob,
+        )
+        .execute(&self.pool)
+        .await {
+            Ok(o) => o,
+            Err(e) => {
+                dbg!(&e);
+                return Err(e);
+            }
+        };
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn destroy_if_state(&self, user_id: Uuid, item_uid: Uuid, expected: &str) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query!(
+            "DELETE FROM inventory_item
+            WHERE user_id = $1 AND item_uuid = $2 AND state_blob = $3 AND escrow_trade_id IS NULL",
+            user_id,
+            item_uid,
+            expected,
+        )
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        if let Err(e) = sqlx::query!(
+            "UPDATE stats
+            SET selected_rod = CASE WHEN selected_rod = $2 THEN NULL ELSE selected_rod END,
+                selected_bait = CASE WHEN selected_bait = $2 THEN NULL ELSE selected_bait END
+            WHERE user_id = $1 AND (selected_rod = $2 OR selected_bait = $2)",
+            user_id,
+            item_uid,
+        )
+        .execute(&mut *tx)
+        .await {
+            dbg!(&e);
+            return Err(e);
+        }
+
+        tx.commit().await?;
+        Ok(true)
+    }
 }
 
@@ -0,0 +1,298 @@
+use crate::domain::{Leaderboard, LeaderboardEntry};
+use chrono::{DateTime, Utc};
+use rocket::async_trait;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// A player of the circle on a friends leaderboard.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FriendScore {
+    pub user_id: Uuid,
+    pub name: String,
+    pub score: i64,
+    /// The score at the last weekly reset, None when the player registered after it.
+    pub previous_score: Option<i64>,
+}
+
+#[async_trait]
+pub trait LeaderboardRepository: Send + Sync {
+    /// Returns the entries from `start` to `end` position, both included, and when the board was refreshed.
+    async fn entries(
+        &self,
+        board: Leaderboard,
+        start: i64,
+        end: i64,
+    ) -> Result<(Vec<LeaderboardEntry>, Option<DateTime<Utc>>), sqlx::Error>;
+
+    /// Returns the position of the user, None when they are not on the board.
+    async fn position(&self, board: Leaderboard, user_id: Uuid) -> Result<Option<i64>, sqlx::Error>;
+
+    /// Recomputes all boards without blocking readers.
+    async fn refresh(&self) -> Result<(), sqlx::Error>;
+
+    /// Returns the current scores of the user and their friends, and their scores at the last weekly reset.
+    /// Read from the live tables, the circle is small.
+    async fn friends(&self, board: Leaderboard, user_id: Uuid) -> Result<Vec<FriendScore>, sqlx::Error>;
+
+    /// The start of the week of the last reset, None before the first one.
+    async fn week_start(&self) -> Result<Option<DateTime<Utc>>, sqlx::Error>;
+
+    /// Replaces the score snapshots with the current scores, unless the reset of `week_start` was done already.
+    /// Returns whether this call did the reset.
+    async fn reset_week(&self, week_start: DateTime<Utc>, now: DateTime<Utc>) -> Result<bool, sqlx::Error>;
+}
+
+#[derive(Debug, Clone)]
+pub struct LeaderboardRepositoryImpl {
+    pool: PgPool,
+}
+
+impl LeaderboardRepositoryImpl {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+struct EntryRow {
+    position: i64,
+    rank: i64,
+    user_id: Uuid,
+    name: String,
+    score: i64,
+    refreshed: DateTime<Utc>,
+}
+
+#[async_trait]
+impl LeaderboardRepository for LeaderboardRepositoryImpl {
+    async fn entries(
+        &self,
+        board: Leaderboard,
+        start: i64,
+        end: i64,
+    ) -> Result<(Vec<LeaderboardEntry>, Option<DateTime<Utc>>), sqlx::Error> {
+        let rows = match board {
+            Leaderboard::FishLength(fish_id) => sqlx::query_as!(
+                EntryRow,
+                r#"SELECT position AS "position!", rank AS "rank!", user_id AS "user_id!", name AS "name!",
+                    score AS "score!", refreshed AS "refreshed!"
+                FROM fish_leaderboards
+                WHERE fish_id = $1 AND position BETWEEN $2 AND $3
+                ORDER BY position"#,
+                fish_id,
+                start,
+                end,
+            )
+            .fetch_all(&self.pool)
+            .await,
+            _ => sqlx::query_as!(
+                EntryRow,
+                r#"SELECT position AS "position!", rank AS "rank!", user_id AS "user_id!", name AS "name!",
+                    score AS "score!", refreshed AS "refreshed!"
+                FROM player_leaderboards
+                WHERE board = $1 AND position BETWEEN $2 AND $3
+                ORDER BY position"#,
+                board.as_str(),
+                start,
+                end,
+            )
+            .fetch_all(&self.pool)
+            .await,
+        };
+        let rows = match rows {
+            Ok(rows) => rows,
+            Err(e) => {
+                dbg!(&e);
+                return Err(e);
+            }
+        };
+
+        // Every row of a view has the same refresh time.
+        let refreshed = rows.first().map(|row| row.refreshed);
+        let entries = rows
+            .into_iter()
+            .map(|row| LeaderboardEntry {
+                position: row.position,
+                rank: row.rank,
+                user_id: row.user_id,
+                name: row.name,
+                score: row.score,
+            })
+            .collect();
+        Ok((entries, refreshed))
+    }
+
+    async fn position(&self, board: Leaderboard, user_id: Uuid) -> Result<Option<i64>, sqlx::Error> {
+        let position = match board {
+            Leaderboard::FishLength(fish_id) => sqlx::query_scalar!(
+                r#"SELECT position AS "position!" FROM fish_leaderboards WHERE fish_id = $1 AND user_id = $2"#,
+                fish_id,
+                user_id,
+            )
+            .fetch_optional(&self.pool)
+            .await?,
+            _ => sqlx::query_scalar!(
+                r#"SELECT position AS "position!" FROM player_leaderboards WHERE board = $1 AND user_id = $2"#,
+                board.as_str(),
+                user_id,
+            )
+            .fetch_optional(&self.pool)
+            .await?,
+        };
+        Ok(position)
+    }
+
+    async fn refresh(&self) -> Result<(), sqlx::Error> {
+        if let Err(e) = sqlx::query!("REFRESH MATERIALIZED VIEW CONCURRENTLY player_leaderboards")
+            .execute(&self.pool)
+            .await {
+            dbg!(&e);
+            return Err(e);
+        }
+        if let Err(e) = sqlx::query!("REFRESH MATERIALIZED VIEW CONCURRENTLY fish_leaderboards")
+            .execute(&self.pool)
+            .await {
+            dbg!(&e);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn friends(&self, board: Leaderboard, user_id: Uuid) -> Result<Vec<FriendScore>, sqlx::Error> {
+        let rows = match board {
+            Leaderboard::FishLength(fish_id) => sqlx::query_as!(
+                FriendScore,
+                r#"WITH circle AS (
+                    SELECT $1::UUID AS user_id
+                    UNION
+                    SELECT CASE WHEN f.user_one_id = $1 THEN f.user_two_id ELSE f.user_one_id END
+                    FROM friends f
+                    WHERE f.user_one_id = $1 OR f.user_two_id = $1
+                ), scores AS (
+                    SELECT
+                        c.user_id,
+                        u.name,
+                        COALESCE(fc.max_length, 0)::BIGINT AS score,
+                        -- Players without a snapshot were not there at the reset
+                        CASE WHEN ps.user_id IS NOT NULL THEN COALESCE(fs.max_length, 0) END AS previous_score
+                    FROM circle c
+                    JOIN users u ON u.user_id = c.user_id
+                    LEFT JOIN fish_caught fc ON fc.user_id = c.user_id AND fc.fish_id = $2
+                    LEFT JOIN player_score_snapshots ps ON ps.user_id = c.user_id
+                    LEFT JOIN fish_length_snapshots fs ON fs.user_id = c.user_id AND fs.fish_id = $2
+                )
+                SELECT
+                    user_id AS "user_id!",
+                    name AS "name!",
+                    score AS "score!",
+                    previous_score
+                FROM scores"#,
+                user_id,
+                fish_id,
+            )
+            .fetch_all(&self.pool)
+            .await,
+            _ => sqlx::query_as!(
+                FriendScore,
+                r#"WITH circle AS (
+                    SELECT $1::UUID AS user_id
+                    UNION
+                    SELECT CASE WHEN f.user_one_id = $1 THEN f.user_two_id ELSE f.user_one_id END
+                    FROM friends f
+                    WHERE f.user_one_id = $1 OR f.user_two_id = $1
+                ), totals AS (
+                    SELECT
+                        c.user_id,
+                        u.name,
+                        s.xp::BIGINT AS xp,
+                        COALESCE(SUM(fc.amount), 0)::BIGINT AS catches,
+                        COUNT(fc.fish_id)::BIGINT AS species
+                    FROM circle c
+                    JOIN users u ON u.user_id = c.user_id
+                    JOIN stats s ON s.user_id = c.user_id
+                    LEFT JOIN fish_caught fc ON fc.user_id = c.user_id
+                    GROUP BY c.user_id, u.name, s.xp
+                ), scores AS (
+                    SELECT
+                        t.user_id,
+                        t.name,
+                        CASE $2 WHEN 'xp' THEN t.xp WHEN 'catches' THEN t.catches ELSE t.species END AS score,
+                        CASE $2 WHEN 'xp' THEN ps.xp WHEN 'catches' THEN ps.catches ELSE ps.species END AS previous_score
+                    FROM totals t
+                    LEFT JOIN player_score_snapshots ps ON ps.user_id = t.user_id
+                )
+                SELECT
+                    user_id AS "user_id!",
+                    name AS "name!",
+                    score AS "score!",
+                    previous_score
+                FROM scores"#,
+                user_id,
+                board.as_str(),
+            )
+            .fetch_all(&self.pool)
+            .await,
+        };
+        match rows {
+            Ok(rows) => Ok(rows),
+            Err(e) => {
+                dbg!(&e);
+                Err(e)
+            }
+        }
+    }
+
+    async fn week_start(&self) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+        let week_start = sqlx::query_scalar!("SELECT MAX(week_start) FROM leaderboard_resets")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(week_start)
+    }
+
+    async fn reset_week(&self, week_start: DateTime<Utc>, now: DateTime<Utc>) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // A second backend waits here until the first commits, then finds the row.
+        let claimed = sqlx::query!(
+            "INSERT INTO leaderboard_resets (week_start, taken) VALUES ($1, $2)
+            ON CONFLICT DO NOTHING",
+            week_start,
+            now,
+        )
+        .execute(&mut *tx)
+        .await?;
+        if claimed.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        // Removes the fish snapshots too.
+        sqlx::query!("DELETE FROM player_score_snapshots")
+            .execute(&mut *tx)
+            .await?;
+
+        if let Err(e) = sqlx::query!(
+            "INSERT INTO player_score_snapshots (user_id, xp, catches, species)
+            SELECT s.user_id, s.xp, COALESCE(SUM(fc.amount), 0), COUNT(fc.fish_id)
+            FROM stats s
+            LEFT JOIN fish_caught fc ON fc.user_id = s.user_id
+            GROUP BY s.user_id, s.xp"
+        )
+        .execute(&mut *tx)
+        .await {
+            dbg!(&e);
+            return Err(e);
+        }
+
+        if let Err(e) = sqlx::query!(
+            "INSERT INTO fish_length_snapshots (user_id, fish_id, max_length)
+            SELECT user_id, fish_id, max_length FROM fish_caught"
+        )
+        .execute(&mut *tx)
+        .await {
+            dbg!(&e);
+            return Err(e);
+        }
+
+        tx.commit().await?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,185 @@
+use crate::domain::{Currency, CurrencyChange, LedgerEntry, LedgerMismatch};
+use chrono::Utc;
+use rocket::async_trait;
+use sqlx::{Error, PgConnection, PgPool};
+use uuid::Uuid;
+
+/// What happened when a balance was changed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BalanceChange {
+    Applied { balance_after: i32 },
+    /// The balance would become negative, nothing was changed.
+    Insufficient,
+}
+
+/// Changes a balance in stats and writes the ledger entry for it.
+///
+/// Every change of coins or bucks has to go through here, so the ledger stays complete.
+/// Run it inside the transaction of the rest of the work, for example a purchase,
+/// and roll back when the outcome is `Insufficient`.
+/// Returns `RowNotFound` when the user has no stats.
+pub async fn apply_change(conn: &mut PgConnection, change: &CurrencyChange) -> Result<BalanceChange, sqlx::Error> {
+    let balance_after = match change.currency {
+        Currency::Coins => sqlx::query_scalar!(
+            "UPDATE stats
+            SET coins = coins + $1
+            WHERE user_id = $2 AND coins + $1 >= 0
+            RETURNING coins",
+            change.delta,
+            change.user_id,
+        )
+        .fetch_optional(&mut *conn)
+        .await?,
+        Currency::Bucks => sqlx::query_scalar!(
+            "UPDATE stats
+            SET bucks = bucks + $1
+            WHERE user_id = $2 AND bucks + $1 >= 0
+            RETURNING bucks",
+            change.delta,
+            change.user_id,
+        )
+        .fetch_optional(&mut *conn)
+        .await?,
+    };
+
+    let balance_after = match balance_after {
+        Some(balance_after) => balance_after,
+        None => {
+            // Either the user has no stats or the balance is too low.
+            let exists = sqlx::query_scalar!(
+                "SELECT EXISTS(SELECT 1 FROM stats WHERE user_id = $1) AS \"exists!\"",
+                change.user_id,
+            )
+            .fetch_one(&mut *conn)
+            .await?;
+            if !exists {
+                return Err(Error::RowNotFound);
+            }
+            return Ok(BalanceChange::Insufficient);
+        }
+    };
+
+    let (source_type, source_id) = change.source.parts();
+    if let Err(e) = sqlx::query!(
+        "INSERT INTO currency_ledger
+            (user_id, currency, delta, balance_after, reason, source_type, source_id, reference_id, created)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        change.user_id,
+        change.currency.as_str(),
+        change.delta,
+        balance_after,
+        change.reason,
+        source_type,
+        source_id,
+        change.reference_id,
+        Utc::now(),
+    )
+    .execute(&mut *conn)
+    .await {
+        dbg!(&e);
+        return Err(e);
+    }
+
+    Ok(BalanceChange::Applied { balance_after })
+}
+
+#[async_trait]
+pub trait LedgerRepository: Send + Sync {
+    /// Returns the newest entries of a user first, only those older than `before` when given.
+    async fn history(
+        &self,
+        user_id: Uuid,
+        currency: Option<Currency>,
+        before: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<LedgerEntry>, sqlx::Error>;
+
+    /// Returns every balance in stats that differs from the sum of its ledger entries.
+    async fn reconcile(&self) -> Result<Vec<LedgerMismatch>, sqlx::Error>;
+}
+
+#[derive(Debug, Clone)]
+pub struct LedgerRepositoryImpl {
+    pool: PgPool,
+}
+
+impl LedgerRepositoryImpl {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl LedgerRepository for LedgerRepositoryImpl {
+    async fn history(
+        &self,
+        user_id: Uuid,
+        currency: Option<Currency>,
+        before: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<LedgerEntry>, sqlx::Error> {
+        let rows = sqlx::query!(
+            "SELECT entry_id, currency, delta, balance_after, reason, source_type, source_id, reference_id, created
+            FROM currency_ledger
+            WHERE user_id = $1
+                AND ($2::TEXT IS NULL OR currency = $2)
+                AND ($3::BIGINT IS NULL OR entry_id < $3)
+            ORDER BY entry_id DESC
+            LIMIT $4",
+            user_id,
+            currency.map(|currency| currency.as_str()),
+            before,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(LedgerEntry {
+                    entry_id: row.entry_id,
+                    currency: Currency::parse(&row.currency)
+                        .ok_or_else(|| Error::Decode(format!("unknown currency {:?}", row.currency).into()))?,
+                    delta: row.delta,
+                    balance_after: row.balance_after,
+                    reason: row.reason,
+                    source_type: row.source_type,
+                    source_id: row.source_id,
+                    reference_id: row.reference_id,
+                    created: row.created,
+                })
+            })
+            .collect()
+    }
+
+    async fn reconcile(&self) -> Result<Vec<LedgerMismatch>, sqlx::Error> {
+        let rows = sqlx::query!(
+            "SELECT s.user_id AS \"user_id!\", 'coins' AS \"currency!\", s.coins::BIGINT AS \"balance!\",
+                COALESCE(SUM(l.delta), 0)::BIGINT AS \"ledger_total!\"
+            FROM stats s
+            LEFT JOIN currency_ledger l ON l.user_id = s.user_id AND l.currency = 'coins'
+            GROUP BY s.user_id
+            HAVING s.coins <> COALESCE(SUM(l.delta), 0)
+            UNION ALL
+            SELECT s.user_id, 'bucks', s.bucks::BIGINT, COALESCE(SUM(l.delta), 0)::BIGINT
+            FROM stats s
+            LEFT JOIN currency_ledger l ON l.user_id = s.user_id AND l.currency = 'bucks'
+            GROUP BY s.user_id
+            HAVING s.bucks <> COALESCE(SUM(l.delta), 0)",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(LedgerMismatch {
+                    user_id: row.user_id,
+                    currency: Currency::parse(&row.currency)
+                        .ok_or_else(|| Error::Decode(format!("unknown currency {:?}", row.currency).into()))?,
+                    balance: row.balance,
+                    ledger_total: row.ledger_total,
+                })
+            })
+            .collect()
+    }
+}
@@ -1,25 +1,57 @@
+use crate::domain::{
+    BroadcastFilter, ClaimedAttachments, Currency, MailAttachment, MailEntry, MailFilter,
+    MailRetentionReport, NewMail, TransactionSource,
+};
+use crate::repository::rewards::grant_attachments;
 use chrono::{DateTime, Utc};
 use rocket::async_trait;
-use sqlx::{Error, PgPool};
+use sqlx::{Error, PgConnection, PgPool};
 use uuid::Uuid;
 
+/// What happened when a player tried to claim the attachments of a mail.
+#[derive(Debug)]
+pub enum ClaimOutcome {
+    Claimed(ClaimedAttachments),
+    /// The mail is not in the mailbox of the player.
+    NotInMailbox,
+    AlreadyClaimed,
+    /// The mail has no attachments.
+    NothingToClaim,
+}
+
 #[async_trait]
 pub trait MailRepository: Send + Sync {
-    async fn create(
+    async fn create(&self, mail: NewMail, receiver_ids: Vec<Uuid>, send_time: DateTime<Utc>) -> Result<(), sqlx::Error>;
+
+    /// Stores a mail for every player matching the filter, it reaches their mailbox with `deliver_broadcasts`.
+    async fn broadcast(&self, mail: NewMail, filter: BroadcastFilter, send_time: DateTime<Utc>) -> Result<(), sqlx::Error>;
+
+    /// Grants the attachments of a mail to one of its recipients, all in one transaction.
+    async fn claim(&self, user_id: Uuid, mail_id: Uuid, now: DateTime<Utc>) -> Result<ClaimOutcome, sqlx::Error>;
+
+    /// Returns up to `limit` mails of the mailbox, the newest first, starting after the `after` send time and mail id.
+    /// Delivers pending broadcasts first and leaves out expired mail.
+    async fn list(
         &self,
-        mail_id: Uuid,
-        sender_id: Uuid,
-        receiver_ids: Vec<Uuid>,
-        title: String,
-        message: String,
-        send_time: DateTime<Utc>,
-    ) -> Result<(), sqlx::Error>;
+        user_id: Uuid,
+        filter: MailFilter,
+        after: Option<(DateTime<Utc>, Uuid)>,
+        limit: i64,
+        with_message: bool,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<MailEntry>, sqlx::Error>;
+
+    /// Counts the unread mail in the mailbox, after delivering pending broadcasts.
+    async fn count_unread(&self, user_id: Uuid, now: DateTime<Utc>) -> Result<i64, sqlx::Error>;
 
     async fn delete(&self, user_id: Uuid, mail_id: Uuid) -> Result<(), sqlx::Error>;
 
-    async fn read(&self, user_id: Uuid, mail_id: Uuid, read: bool) -> Result<(), sqlx::Error>;
+    async fn read(&self, user_id: Uuid, mail_id: Uuid, read: bool, now: DateTime<Utc>) -> Result<(), sqlx::Error>;
+
+    async fn archive(&self, user_id: Uuid, mail_id: Uuid, archived: bool, now: DateTime<Utc>) -> Result<(), sqlx::Error>;
 
-    async fn archive(&self, user_id: Uuid, mail_id: Uuid, archived: bool) -> Result<(), sqlx::Error>;
+    /// Removes expired mail, entries that were read and archived before `read_before` and mail nobody has anymore.
+    async fn purge(&self, now: DateTime<Utc>, read_before: DateTime<Utc>) -> Result<MailRetentionReport, sqlx::Error>;
 }
 
 #[derive(Debug, Clone)]
@@ -33,25 +65,136 @@ impl MailRepositoryImpl {
     }
 }
 
+/// Inserts a mail and its attachments, but puts it in no mailbox.
+async fn insert_mail(conn: &mut PgConnection, mail: &NewMail, send_time: DateTime<Utc>) -> Result<(), sqlx::Error> {
+    if let Err(e) = sqlx::query!(
+        "INSERT INTO mail (mail_id, sender_id, title, message, send_time, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6);",
+        mail.mail_id,
+        mail.sender_id,
+        mail.title,
+        mail.message,
+        send_time,
+        mail.expires_at,
+    )
+    .execute(&mut *conn)
+    .await {
+        dbg!(&e);
+        return Err(e);
+    }
+
+    for attachment in &mail.attachments {
+        let (kind, definition_id, state_blob, currency, amount) = match attachment {
+            MailAttachment::Item { definition_id, state_blob } => {
+                ("item", Some(*definition_id), Some(state_blob.as_str()), None, None)
+            }
+            MailAttachment::Currency { currency, amount } => {
+                ("currency", None, None, Some(currency.as_str()), Some(*amount))
+            }
+        };
+        if let Err(e) = sqlx::query!(
+            "INSERT INTO mail_attachments (mail_id, kind, definition_id, state_blob, currency, amount)
+            VALUES ($1, $2, $3, $4, $5, $6);",
+            mail.mail_id,
+            kind,
+            definition_id,
+            state_blob,
+            currency,
+            amount,
+        )
+        .execute(&mut *conn)
+        .await {
+            dbg!(&e);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Inserts a mail and puts it into the mailbox of every receiver, in the transaction of the caller.
+pub async fn send_mail(
+    conn: &mut PgConnection,
+    mail: &NewMail,
+    receiver_ids: &[Uuid],
+    send_time: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    insert_mail(&mut *conn, mail, send_time).await?;
+
+    for receiver in receiver_ids {
+        if let Err(e) = sqlx::query!(
+            "INSERT INTO mailbox (user_id, mail_id, read, archived)
+            VALUES ($1, $2, $3, $4);",
+            receiver,
+            mail.mail_id,
+            false,
+            false,
+        )
+        .execute(&mut *conn)
+        .await {
+            dbg!(&e);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Puts the broadcasts a player should get, and did not get yet, into their mailbox.
+///
+/// Broadcasts are delivered lazily like this, so players who register after a broadcast get it too.
+/// Run it before reading a mailbox.
+pub async fn deliver_broadcasts(conn: &mut PgConnection, user_id: Uuid, now: DateTime<Utc>) -> Result<(), sqlx::Error> {
+    if let Err(e) = sqlx::query!(
+        "WITH delivered AS (
+            INSERT INTO mail_broadcast_deliveries (mail_id, user_id, delivered)
+            SELECT b.mail_id, u.user_id, $2
+            FROM mail_broadcasts b
+            JOIN mail m ON m.mail_id = b.mail_id
+            JOIN users u ON u.user_id = $1
+            JOIN stats s ON s.user_id = u.user_id
+            WHERE (m.expires_at IS NULL OR m.expires_at > $2)
+                AND (b.registered_before IS NULL OR u.created < b.registered_before)
+                AND (b.min_xp IS NULL OR s.xp >= b.min_xp)
+            ON CONFLICT DO NOTHING
+            RETURNING mail_id
+        )
+        INSERT INTO mailbox (user_id, mail_id, read, archived)
+        SELECT $1, mail_id, FALSE, FALSE FROM delivered",
+        user_id,
+        now,
+    )
+    .execute(&mut *conn)
+    .await {
+        dbg!(&e);
+        return Err(e);
+    }
+    Ok(())
+}
+
 #[async_trait]
 impl MailRepository for MailRepositoryImpl {
-    async fn create(
-        &self,
-        mail_id: Uuid,
-        sender_id: Uuid,
-        receiver_ids: Vec<Uuid>,
-        title: String,
-        message: String,
-        send_time: DateTime<Utc>,
-    ) -> Result<(), sqlx::Error> {
+    async fn create(&self, mail: NewMail, receiver_ids: Vec<Uuid>, send_time: DateTime<Utc>) -> Result<(), sqlx::Error> {
         let mut tx = self.pool.begin().await?;
+        send_mail(&mut tx, &mail, &receiver_ids, send_time).await?;
+
+        match tx.commit().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                dbg!(&e);
+                Err(e)
+            }
+        }
+    }
+
+    async fn broadcast(&self, mail: NewMail, filter: BroadcastFilter, send_time: DateTime<Utc>) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        insert_mail(&mut tx, &mail, send_time).await?;
+
         if let Err(e) = sqlx::query!(
-            "INSERT INTO mail (mail_id, sender_id, title, message, send_time)
-            VALUES ($1, $2, $3, $4, $5);",
-            mail_id,
-            sender_id,
-            title,
-            message,
+            "INSERT INTO mail_broadcasts (mail_id, registered_before, min_xp, created)
+            VALUES ($1, $2, $3, $4)",
+            mail.mail_id,
+            filter.registered_before,
+            filter.min_xp,
             send_time,
         )
         .execute(&mut *tx)
@@ -60,24 +203,109 @@ impl MailRepository for MailRepositoryImpl {
             return Err(e);
         }
 
-        for receiver in receiver_ids {
-            if let Err(e) = sqlx::query!(
-                "INSERT INTO mailbox (user_id, mail_id, read, archived)
-                VALUES ($1, $2, $3, $4);",
-                receiver,
-                mail_id,
-                false,
-                false,
-            )
-            .execute(&mut *tx)
-            .await {
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn list(
+        &self,
+        user_id: Uuid,
+        filter: MailFilter,
+        after: Option<(DateTime<Utc>, Uuid)>,
+        limit: i64,
+        with_message: bool,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<MailEntry>, sqlx::Error> {
+        let mut conn = self.pool.acquire().await?;
+        deliver_broadcasts(&mut conn, user_id, now).await?;
+
+        let (after_time, after_id) = after.unzip();
+        let rows = match sqlx::query!(
+            r#"SELECT
+                m.mail_id,
+                m.title,
+                CASE WHEN $7 THEN m.message END AS message,
+                m.send_time,
+                m.expires_at,
+                mb.read,
+                mb.archived,
+                mb.claimed IS NOT NULL AS "claimed!",
+                COALESCE(
+                    (
+                        SELECT jsonb_agg(
+                            CASE WHEN a.kind = 'item'
+                                THEN jsonb_build_object('type', 'item', 'definition_id', a.definition_id, 'state_blob', a.state_blob)
+                                ELSE jsonb_build_object('type', 'currency', 'currency', a.currency, 'amount', a.amount)
+                            END
+                            ORDER BY a.attachment_id
+                        )
+                        FROM mail_attachments a
+                        WHERE a.mail_id = m.mail_id
+                    ), '[]'
+                ) AS "attachments!"
+            FROM mailbox mb
+            JOIN mail m ON m.mail_id = mb.mail_id
+            WHERE mb.user_id = $1
+                AND (m.expires_at IS NULL OR m.expires_at > $8)
+                AND ($2::BOOLEAN IS NULL OR mb.read = $2)
+                AND ($3::BOOLEAN IS NULL OR mb.archived = $3)
+                AND ($4::TIMESTAMPTZ IS NULL OR (m.send_time, m.mail_id) < ($4, $5::UUID))
+            ORDER BY m.send_time DESC, m.mail_id DESC
+            LIMIT $6"#,
+            user_id,
+            filter.read,
+            filter.archived,
+            after_time,
+            after_id,
+            limit,
+            with_message,
+            now,
+        )
+        .fetch_all(&mut *conn)
+        .await {
+            Ok(rows) => rows,
+            Err(e) => {
                 dbg!(&e);
                 return Err(e);
             }
-        }
+        };
 
-        match tx.commit().await {
-            Ok(_) => Ok(()),
+        rows.into_iter()
+            .map(|row| {
+                let attachments = serde_json::from_value(row.attachments).map_err(|e| {
+                    dbg!(&e);
+                    sqlx::Error::WorkerCrashed
+                })?;
+                Ok(MailEntry {
+                    mail_id: row.mail_id,
+                    title: row.title,
+                    message: row.message,
+                    send_time: row.send_time,
+                    read: row.read,
+                    archived: row.archived,
+                    attachments,
+                    claimed: row.claimed,
+                    expires_at: row.expires_at,
+                })
+            })
+            .collect()
+    }
+
+    async fn count_unread(&self, user_id: Uuid, now: DateTime<Utc>) -> Result<i64, sqlx::Error> {
+        let mut conn = self.pool.acquire().await?;
+        deliver_broadcasts(&mut conn, user_id, now).await?;
+
+        match sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "unread!"
+            FROM mailbox mb
+            JOIN mail m ON m.mail_id = mb.mail_id
+            WHERE mb.user_id = $1 AND NOT mb.read AND (m.expires_at IS NULL OR m.expires_at > $2)"#,
+            user_id,
+            now,
+        )
+        .fetch_one(&mut *conn)
+        .await {
+            Ok(unread) => Ok(unread),
             Err(e) => {
                 dbg!(&e);
                 Err(e)
@@ -85,11 +313,80 @@ impl MailRepository for MailRepositoryImpl {
         }
     }
 
+    async fn claim(&self, user_id: Uuid, mail_id: Uuid, now: DateTime<Utc>) -> Result<ClaimOutcome, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Setting the claim time first makes a second claim of the same recipient wait for this one and then find it set.
+        let claimed = sqlx::query_scalar!(
+            "UPDATE mailbox SET claimed = $3
+            WHERE user_id = $1 AND mail_id = $2 AND claimed IS NULL
+                AND NOT EXISTS (SELECT 1 FROM mail WHERE mail_id = $2 AND expires_at <= $3)
+            RETURNING mail_id",
+            user_id,
+            mail_id,
+            now,
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if claimed.is_none() {
+            let in_mailbox = sqlx::query_scalar!(
+                "SELECT EXISTS(
+                    SELECT 1 FROM mailbox mb JOIN mail m ON m.mail_id = mb.mail_id
+                    WHERE mb.user_id = $1 AND mb.mail_id = $2 AND (m.expires_at IS NULL OR m.expires_at > $3)
+                ) AS \"exists!\"",
+                user_id,
+                mail_id,
+                now,
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            return Ok(if in_mailbox { ClaimOutcome::AlreadyClaimed } else { ClaimOutcome::NotInMailbox });
+        }
+
+        let attachments = sqlx::query!(
+            "SELECT kind, definition_id, state_blob, currency, amount
+            FROM mail_attachments
+            WHERE mail_id = $1
+            ORDER BY attachment_id",
+            mail_id,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        if attachments.is_empty() {
+            return Ok(ClaimOutcome::NothingToClaim);
+        }
+
+        let attachments = attachments
+            .into_iter()
+            .map(|attachment| match (attachment.definition_id, attachment.state_blob, attachment.currency, attachment.amount) {
+                (Some(definition_id), Some(state_blob), None, None) => Ok(MailAttachment::Item { definition_id, state_blob }),
+                (None, None, Some(currency), Some(amount)) => {
+                    let currency = Currency::parse(&currency)
+                        .ok_or_else(|| Error::Decode(format!("unknown currency {:?}", currency).into()))?;
+                    Ok(MailAttachment::Currency { currency, amount })
+                }
+                _ => Err(Error::Decode(format!("malformed {} attachment of mail {}", attachment.kind, mail_id).into())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let granted = grant_attachments(
+            &mut tx,
+            user_id,
+            &attachments,
+            "mail_attachment",
+            TransactionSource::Player(user_id),
+            Some(mail_id),
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(ClaimOutcome::Claimed(granted))
+    }
+
     async fn delete(&self, user_id: Uuid, mail_id: Uuid) -> Result<(), sqlx::Error> {
         let mut tx = self.pool.begin().await?;
 
         // Delete the mailbox entry first
-        if let Err(e) = sqlx::query!(
+        let result = match sqlx::query!(
             "DELETE FROM mailbox
             WHERE user_id = $1 AND mail_id = $2;",
             user_id,
@@ -97,16 +394,27 @@ impl MailRepository for MailRepositoryImpl {
         )
         .execute(&mut *tx)
         .await {
-            dbg!(&e);
-            return Err(e);
+            Ok(o) => o,
+            Err(e) => {
+                dbg!(&e);
+                return Err(e);
+            }
+        };
+
+        if result.rows_affected() == 0 {
+            return Err(Error::RowNotFound);
         }
 
-        // Remove the mail itself if nobody has a reference to it anymore
+        // Remove the mail itself if nobody has a reference to it anymore.
+        // Broadcasts stay, players who did not fetch their data yet still get them.
         if let Err(e) = sqlx::query!(
             "DELETE FROM mail
                 WHERE mail_id = $1
                     AND NOT EXISTS (
                         SELECT 1 FROM mailbox WHERE mail_id = $1
+                    )
+                    AND NOT EXISTS (
+                        SELECT 1 FROM mail_broadcasts WHERE mail_id = $1
                     );",
             mail_id,
         )
@@ -125,12 +433,16 @@ impl MailRepository for MailRepositoryImpl {
         }
     }
 
-    async fn read(&self, user_id: Uuid, mail_id: Uuid, read: bool) -> Result<(), sqlx::Error> {
+    async fn read(&self, user_id: Uuid, mail_id: Uuid, read: bool, now: DateTime<Utc>) -> Result<(), sqlx::Error> {
+        // Marking it read again keeps the time it was first read.
         let result = match sqlx::query!(
-            "UPDATE mailbox SET read = $3 WHERE user_id = $1 AND mail_id = $2",
+            "UPDATE mailbox
+            SET read = $3, read_at = CASE WHEN $3 THEN COALESCE(read_at, $4) END
+            WHERE user_id = $1 AND mail_id = $2",
             user_id,
             mail_id,
             read,
+            now,
         )
         .execute(&self.pool)
         .await {
@@ -148,12 +460,15 @@ impl MailRepository for MailRepositoryImpl {
         Ok(())
     }
 
-    async fn archive(&self, user_id: Uuid, mail_id: Uuid, archived: bool) -> Result<(), sqlx::Error> {
+    async fn archive(&self, user_id: Uuid, mail_id: Uuid, archived: bool, now: DateTime<Utc>) -> Result<(), sqlx::Error> {
         let result = match sqlx::query!(
-            "UPDATE mailbox SET archived = $3 WHERE user_id = $1 AND mail_id = $2",
+            "UPDATE mailbox
+            SET archived = $3, archived_at = CASE WHEN $3 THEN COALESCE(archived_at, $4) END
+            WHERE user_id = $1 AND mail_id = $2",
             user_id,
             mail_id,
             archived,
+            now,
         )
         .execute(&self.pool)
         .await {
@@ -170,4 +485,66 @@ impl MailRepository for MailRepositoryImpl {
 
         Ok(())
     }
+
+    async fn purge(&self, now: DateTime<Utc>, read_before: DateTime<Utc>) -> Result<MailRetentionReport, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // The mailbox does not cascade, so empty it first. Attachments and broadcasts go with the mail.
+        if let Err(e) = sqlx::query!(
+            "DELETE FROM mailbox
+            WHERE mail_id IN (SELECT mail_id FROM mail WHERE expires_at <= $1)",
+            now,
+        )
+        .execute(&mut *tx)
+        .await {
+            dbg!(&e);
+            return Err(e);
+        }
+        let expired_mail = match sqlx::query!("DELETE FROM mail WHERE expires_at <= $1", now)
+            .execute(&mut *tx)
+            .await {
+            Ok(result) => result.rows_affected(),
+            Err(e) => {
+                dbg!(&e);
+                return Err(e);
+            }
+        };
+
+        // Unclaimed attachments are kept, the player would lose them otherwise.
+        let old_entries = match sqlx::query!(
+            "DELETE FROM mailbox mb
+            USING mail m
+            WHERE mb.mail_id = m.mail_id
+                AND mb.read AND mb.archived
+                AND mb.read_at < $1 AND mb.archived_at < $1
+                AND (mb.claimed IS NOT NULL OR NOT EXISTS (SELECT 1 FROM mail_attachments a WHERE a.mail_id = m.mail_id))",
+            read_before,
+        )
+        .execute(&mut *tx)
+        .await {
+            Ok(result) => result.rows_affected(),
+            Err(e) => {
+                dbg!(&e);
+                return Err(e);
+            }
+        };
+
+        // Broadcasts stay until they expire, players who did not fetch their data yet still get them.
+        let orphaned_mail = match sqlx::query!(
+            "DELETE FROM mail m
+            WHERE NOT EXISTS (SELECT 1 FROM mailbox mb WHERE mb.mail_id = m.mail_id)
+                AND NOT EXISTS (SELECT 1 FROM mail_broadcasts b WHERE b.mail_id = m.mail_id)"
+        )
+        .execute(&mut *tx)
+        .await {
+            Ok(result) => result.rows_affected(),
+            Err(e) => {
+                dbg!(&e);
+                return Err(e);
+            }
+        };
+
+        tx.commit().await?;
+        Ok(MailRetentionReport { expired_mail, old_entries, orphaned_mail })
+    }
 }
\ No newline at end of file
@@ -1,8 +1,18 @@
 /// The repository layer is responseable for creating, reading, updating and deleting information from the database.
+pub mod achievements;
 pub mod data;
 pub mod effects;
 pub mod friends;
+pub mod idempotency;
 pub mod inventory;
+pub mod leaderboards;
+pub mod ledger;
 pub mod mail;
+pub mod quests;
+pub mod rewards;
+pub mod service_keys;
+pub mod shop;
+pub mod sessions;
 pub mod stats;
+pub mod trade;
 pub mod user;
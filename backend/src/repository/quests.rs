@@ -0,0 +1,261 @@
+use crate::domain::{GrantedReward, QuestPeriod, Reward};
+use crate::repository::rewards::grant_reward;
+use chrono::{DateTime, Utc};
+use rocket::async_trait;
+use sqlx::{Error, PgConnection, PgPool};
+use uuid::Uuid;
+
+/// The stored state of one quest of a player.
+#[derive(Debug)]
+pub struct QuestRecord {
+    pub quest_id: String,
+    pub progress: i32,
+    pub target: i32,
+    pub completed: Option<DateTime<Utc>>,
+    pub claimed: Option<DateTime<Utc>>,
+}
+
+/// The quests a catch counts for: the quests whose goal the fish matches, in the day and week it was caught in.
+#[derive(Debug)]
+pub struct QuestCatch {
+    pub quest_ids: Vec<String>,
+    pub daily_start: DateTime<Utc>,
+    pub weekly_start: DateTime<Utc>,
+}
+
+/// What happened when a player tried to claim the reward of a quest.
+#[derive(Debug)]
+pub enum QuestClaimOutcome {
+    Claimed(GrantedReward),
+    /// The player does not have the quest this period.
+    NotFound,
+    NotCompleted,
+    AlreadyClaimed,
+}
+
+#[async_trait]
+pub trait QuestRepository: Send + Sync {
+    /// The quests of the user for the period starting at `period_start`, None when none were picked yet.
+    async fn quests(
+        &self,
+        user_id: Uuid,
+        period: QuestPeriod,
+        period_start: DateTime<Utc>,
+    ) -> Result<Option<Vec<QuestRecord>>, sqlx::Error>;
+
+    /// Stores the picked quests with their targets, unless quests were picked for the period already.
+    /// Fails with RowNotFound when the user does not exist.
+    async fn assign(
+        &self,
+        user_id: Uuid,
+        period: QuestPeriod,
+        period_start: DateTime<Utc>,
+        quests: Vec<(String, i32)>,
+        now: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error>;
+
+    /// Marks a completed quest as claimed and grants its reward, all in one transaction.
+    async fn claim(
+        &self,
+        user_id: Uuid,
+        period: QuestPeriod,
+        period_start: DateTime<Utc>,
+        quest_id: &str,
+        reward: &Reward,
+        now: DateTime<Utc>,
+    ) -> Result<QuestClaimOutcome, sqlx::Error>;
+}
+
+#[derive(Debug, Clone)]
+pub struct QuestRepositoryImpl {
+    pool: PgPool,
+}
+
+impl QuestRepositoryImpl {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl QuestRepository for QuestRepositoryImpl {
+    async fn quests(
+        &self,
+        user_id: Uuid,
+        period: QuestPeriod,
+        period_start: DateTime<Utc>,
+    ) -> Result<Option<Vec<QuestRecord>>, sqlx::Error> {
+        let assigned = sqlx::query_scalar!(
+            r#"SELECT EXISTS (
+                SELECT 1 FROM quest_assignments
+                WHERE user_id = $1 AND period = $2 AND period_start = $3
+            ) AS "assigned!""#,
+            user_id,
+            period.as_str(),
+            period_start,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if !assigned {
+            return Ok(None);
+        }
+
+        let quests = sqlx::query_as!(
+            QuestRecord,
+            "SELECT quest_id, progress, target, completed, claimed
+            FROM player_quests
+            WHERE user_id = $1 AND period = $2 AND period_start = $3
+            ORDER BY quest_id",
+            user_id,
+            period.as_str(),
+            period_start,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(Some(quests))
+    }
+
+    async fn assign(
+        &self,
+        user_id: Uuid,
+        period: QuestPeriod,
+        period_start: DateTime<Utc>,
+        quests: Vec<(String, i32)>,
+        now: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let known = sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM users WHERE user_id = $1) AS "known!""#,
+            user_id,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if !known {
+            return Err(Error::RowNotFound);
+        }
+
+        // Whoever inserts the assignment first picks the quests, a concurrent pick is dropped.
+        let inserted = sqlx::query!(
+            "INSERT INTO quest_assignments (user_id, period, period_start, assigned)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id, period, period_start) DO NOTHING",
+            user_id,
+            period.as_str(),
+            period_start,
+            now,
+        )
+        .execute(&mut *tx)
+        .await?;
+        if inserted.rows_affected() == 0 {
+            return Ok(());
+        }
+
+        let (quest_ids, targets): (Vec<String>, Vec<i32>) = quests.into_iter().unzip();
+        if let Err(e) = sqlx::query!(
+            "INSERT INTO player_quests (user_id, period, period_start, quest_id, progress, target)
+            SELECT $1, $2, $3, quest_id, 0, target
+            FROM UNNEST($4::text[], $5::int[]) AS q (quest_id, target)",
+            user_id,
+            period.as_str(),
+            period_start,
+            &quest_ids,
+            &targets,
+        )
+        .execute(&mut *tx)
+        .await {
+            dbg!(&e);
+            return Err(e);
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn claim(
+        &self,
+        user_id: Uuid,
+        period: QuestPeriod,
+        period_start: DateTime<Utc>,
+        quest_id: &str,
+        reward: &Reward,
+        now: DateTime<Utc>,
+    ) -> Result<QuestClaimOutcome, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Locks the quest, so a concurrent claim waits and then sees it claimed.
+        let quest = sqlx::query!(
+            "SELECT completed, claimed
+            FROM player_quests
+            WHERE user_id = $1 AND period = $2 AND period_start = $3 AND quest_id = $4
+            FOR UPDATE",
+            user_id,
+            period.as_str(),
+            period_start,
+            quest_id,
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let quest = match quest {
+            Some(quest) => quest,
+            None => return Ok(QuestClaimOutcome::NotFound),
+        };
+        if quest.claimed.is_some() {
+            return Ok(QuestClaimOutcome::AlreadyClaimed);
+        }
+        if quest.completed.is_none() {
+            return Ok(QuestClaimOutcome::NotCompleted);
+        }
+
+        sqlx::query!(
+            "UPDATE player_quests
+            SET claimed = $5
+            WHERE user_id = $1 AND period = $2 AND period_start = $3 AND quest_id = $4",
+            user_id,
+            period.as_str(),
+            period_start,
+            quest_id,
+            now,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let granted = grant_reward(&mut tx, user_id, reward, "quest_reward", now).await?;
+        tx.commit().await?;
+        Ok(QuestClaimOutcome::Claimed(granted))
+    }
+}
+
+/// Counts a catch towards the quests of the player it matches, in the transaction that logs the catch.
+/// Only quests that were picked before the catch and are not completed yet count it.
+pub async fn advance_quests(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    caught: DateTime<Utc>,
+    quests: &QuestCatch,
+) -> Result<(), Error> {
+    if quests.quest_ids.is_empty() {
+        return Ok(());
+    }
+    // On the right side of SET, progress is the value before the update.
+    sqlx::query!(
+        "UPDATE player_quests q
+        SET progress = LEAST(q.progress + 1, q.target),
+            completed = CASE WHEN q.progress + 1 >= q.target THEN $2::timestamptz END
+        FROM quest_assignments a
+        WHERE a.user_id = q.user_id AND a.period = q.period AND a.period_start = q.period_start
+            AND q.user_id = $1 AND q.quest_id = ANY($3) AND q.completed IS NULL
+            AND a.assigned <= $2
+            AND ((q.period = $4 AND q.period_start = $5) OR (q.period = $6 AND q.period_start = $7))",
+        user_id,
+        caught,
+        &quests.quest_ids,
+        QuestPeriod::Daily.as_str(),
+        quests.daily_start,
+        QuestPeriod::Weekly.as_str(),
+        quests.weekly_start,
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
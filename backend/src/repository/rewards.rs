@@ -0,0 +1,90 @@
+use crate::domain::{
+    ClaimedAttachments, Currency, CurrencyChange, GrantedReward, InventoryItem, MailAttachment, NewMail, Reward,
+    TransactionSource,
+};
+use crate::repository::ledger::{apply_change, BalanceChange};
+use crate::repository::mail::send_mail;
+use chrono::{DateTime, Utc};
+use sqlx::{Error, PgConnection};
+use uuid::Uuid;
+
+/// Adds items to the inventory of a player and currency to their balances, in the transaction of the caller.
+/// The currency changes are recorded in the ledger with `reason`.
+pub async fn grant_attachments(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    attachments: &[MailAttachment],
+    reason: &str,
+    source: TransactionSource,
+    reference_id: Option<Uuid>,
+) -> Result<ClaimedAttachments, Error> {
+    let mut granted = ClaimedAttachments { items: Vec::new(), coins: 0, bucks: 0 };
+    for attachment in attachments {
+        match attachment {
+            MailAttachment::Item { definition_id, state_blob } => {
+                let item_uuid = Uuid::new_v4();
+                if let Err(e) = sqlx::query!(
+                    "INSERT INTO inventory_item (user_id, item_uuid, definition_id, state_blob)
+                    VALUES ($1, $2, $3, $4)",
+                    user_id,
+                    item_uuid,
+                    definition_id,
+                    state_blob,
+                )
+                .execute(&mut *conn)
+                .await {
+                    dbg!(&e);
+                    return Err(e);
+                }
+                granted.items.push(InventoryItem::new(item_uuid, *definition_id, state_blob.clone(), None));
+            }
+            MailAttachment::Currency { currency, amount } => {
+                let change = CurrencyChange {
+                    user_id,
+                    currency: *currency,
+                    delta: *amount,
+                    reason: reason.to_string(),
+                    source,
+                    reference_id,
+                };
+                // Only adds to the balance, so it can not be insufficient.
+                if apply_change(&mut *conn, &change).await? == BalanceChange::Insufficient {
+                    return Err(Error::Protocol(format!("a {} grant lowered a balance", reason)));
+                }
+                match currency {
+                    Currency::Coins => granted.coins += amount,
+                    Currency::Bucks => granted.bucks += amount,
+                }
+            }
+        }
+    }
+    Ok(granted)
+}
+
+/// Grants a reward in the transaction of the caller, or sends it as a mail when the reward says so.
+pub async fn grant_reward(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    reward: &Reward,
+    reason: &str,
+    now: DateTime<Utc>,
+) -> Result<GrantedReward, Error> {
+    match &reward.mail {
+        Some(mail) => {
+            let mail = NewMail {
+                mail_id: Uuid::new_v4(),
+                sender_id: None,
+                title: mail.title.clone(),
+                message: mail.message.clone(),
+                attachments: reward.attachments.clone(),
+                expires_at: None,
+            };
+            send_mail(conn, &mail, &[user_id], now).await?;
+            Ok(GrantedReward::Mailed { mail_id: mail.mail_id })
+        }
+        None => {
+            let granted = grant_attachments(conn, user_id, &reward.attachments, reason, TransactionSource::System, None).await?;
+            Ok(GrantedReward::Granted(granted))
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use crate::domain::{Scope, ServiceKey};
+use chrono::{DateTime, Utc};
+use rocket::async_trait;
+use sqlx::{Error, PgPool};
+use uuid::Uuid;
+
+#[async_trait]
+pub trait ServiceKeyRepository: Send + Sync {
+    async fn create(&self, key: ServiceKey, key_hash: String) -> Result<(), sqlx::Error>;
+
+    /// Returns the key together with the hash of its secret.
+    async fn get(&self, key_id: Uuid) -> Result<Option<(ServiceKey, String)>, sqlx::Error>;
+
+    async fn list(&self) -> Result<Vec<ServiceKey>, sqlx::Error>;
+
+    async fn revoke(&self, key_id: Uuid, revoked: DateTime<Utc>) -> Result<(), sqlx::Error>;
+}
+
+#[derive(Debug, Clone)]
+pub struct ServiceKeyRepositoryImpl {
+    pool: PgPool,
+}
+
+impl ServiceKeyRepositoryImpl {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+// Unknown scopes are dropped, so removing a scope from the code takes it away from every key.
+fn parse_scopes(scopes: Vec<String>) -> Vec<Scope> {
+    scopes.iter().filter_map(|s| Scope::parse(s)).collect()
+}
+
+#[async_trait]
+impl ServiceKeyRepository for ServiceKeyRepositoryImpl {
+    async fn create(&self, key: ServiceKey, key_hash: String) -> Result<(), sqlx::Error> {
+        let scopes: Vec<String> = key.scopes.iter().map(|s| s.as_str().to_string()).collect();
+        match sqlx::query!(
+            "INSERT INTO service_keys (key_id, name, key_hash, scopes, created)
+            VALUES ($1, $2, $3, $4, $5)",
+            key.key_id,
+            key.name,
+            key_hash,
+            &scopes,
+            key.created,
+        )
+        .execute(&self.pool)
+        .await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                dbg!(&e);
+                Err(e)
+            }
+        }
+    }
+
+    async fn get(&self, key_id: Uuid) -> Result<Option<(ServiceKey, String)>, sqlx::Error> {
+        let row = sqlx::query!(
+            "SELECT key_id, name, key_hash, scopes, created, revoked
+            FROM service_keys
+            WHERE key_id = $1",
+            key_id,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| {
+            (
+                ServiceKey {
+                    key_id: row.key_id,
+                    name: row.name,
+                    scopes: parse_scopes(row.scopes),
+                    created: row.created,
+                    revoked: row.revoked,
+                },
+                row.key_hash,
+            )
+        }))
+    }
+
+    async fn list(&self) -> Result<Vec<ServiceKey>, sqlx::Error> {
+        let rows = sqlx::query!(
+            "SELECT key_id, name, scopes, created, revoked
+            FROM service_keys
+            ORDER BY created ASC",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| ServiceKey {
+                key_id: row.key_id,
+                name: row.name,
+                scopes: parse_scopes(row.scopes),
+                created: row.created,
+                revoked: row.revoked,
+            })
+            .collect())
+    }
+
+    async fn revoke(&self, key_id: Uuid, revoked: DateTime<Utc>) -> Result<(), sqlx::Error> {
+        let result = sqlx::query!(
+            "UPDATE service_keys
+            SET revoked = $2
+            WHERE key_id = $1 AND revoked IS NULL",
+            key_id,
+            revoked,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(Error::RowNotFound);
+        }
+
+        Ok(())
+    }
+}
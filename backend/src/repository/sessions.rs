@@ -0,0 +1,217 @@
+use crate::domain::Session;
+use chrono::{DateTime, Utc};
+use rocket::async_trait;
+use sqlx::{Error, PgPool};
+use uuid::Uuid;
+
+/// What happened when a refresh token was used.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RefreshOutcome {
+    /// The token was valid and has been replaced by the new one.
+    Rotated { user_id: Uuid, session_id: Uuid },
+    /// The token was already used before. It might be stolen, so the session got revoked.
+    Reused,
+    /// The token is unknown, or its session is revoked or expired.
+    Invalid,
+}
+
+#[async_trait]
+pub trait SessionRepository: Send + Sync {
+    async fn create(&self, session: Session, refresh_token_hash: String) -> Result<(), sqlx::Error>;
+
+    async fn get(&self, session_id: Uuid) -> Result<Option<Session>, sqlx::Error>;
+
+    /// Returns the sessions of a user that are not revoked or expired.
+    async fn list_active(&self, user_id: Uuid, now: DateTime<Utc>) -> Result<Vec<Session>, sqlx::Error>;
+
+    async fn revoke(&self, user_id: Uuid, session_id: Uuid, now: DateTime<Utc>) -> Result<(), sqlx::Error>;
+
+    /// Replaces a refresh token by a new one and extends the session until `expires_at`.
+    async fn rotate(
+        &self,
+        old_token_hash: String,
+        new_token_hash: String,
+        now: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<RefreshOutcome, sqlx::Error>;
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionRepositoryImpl {
+    pool: PgPool,
+}
+
+impl SessionRepositoryImpl {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl SessionRepository for SessionRepositoryImpl {
+    async fn create(&self, session: Session, refresh_token_hash: String) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        if let Err(e) = sqlx::query!(
+            "INSERT INTO sessions (session_id, user_id, device, created, last_used, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+            session.session_id,
+            session.user_id,
+            session.device,
+            session.created,
+            session.last_used,
+            session.expires_at,
+        )
+        .execute(&mut *tx)
+        .await {
+            dbg!(&e);
+            return Err(e);
+        }
+
+        if let Err(e) = sqlx::query!(
+            "INSERT INTO refresh_tokens (token_hash, session_id, created)
+            VALUES ($1, $2, $3)",
+            refresh_token_hash,
+            session.session_id,
+            session.created,
+        )
+        .execute(&mut *tx)
+        .await {
+            dbg!(&e);
+            return Err(e);
+        }
+
+        match tx.commit().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                dbg!(&e);
+                Err(e)
+            }
+        }
+    }
+
+    async fn get(&self, session_id: Uuid) -> Result<Option<Session>, sqlx::Error> {
+        sqlx::query_as!(
+            Session,
+            "SELECT session_id, user_id, device, created, last_used, expires_at, revoked
+            FROM sessions
+            WHERE session_id = $1",
+            session_id,
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn list_active(&self, user_id: Uuid, now: DateTime<Utc>) -> Result<Vec<Session>, sqlx::Error> {
+        sqlx::query_as!(
+            Session,
+            "SELECT session_id, user_id, device, created, last_used, expires_at, revoked
+            FROM sessions
+            WHERE user_id = $1 AND revoked IS NULL AND expires_at > $2
+            ORDER BY last_used DESC",
+            user_id,
+            now,
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn revoke(&self, user_id: Uuid, session_id: Uuid, now: DateTime<Utc>) -> Result<(), sqlx::Error> {
+        let result = sqlx::query!(
+            "UPDATE sessions
+            SET revoked = $3
+            WHERE user_id = $1 AND session_id = $2 AND revoked IS NULL",
+            user_id,
+            session_id,
+            now,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(Error::RowNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn rotate(
+        &self,
+        old_token_hash: String,
+        new_token_hash: String,
+        now: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<RefreshOutcome, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Lock the token, so two refreshes with the same token can not both succeed.
+        let token = match sqlx::query!(
+            "SELECT r.session_id, r.used, s.user_id, s.revoked, s.expires_at
+            FROM refresh_tokens r
+            JOIN sessions s ON s.session_id = r.session_id
+            WHERE r.token_hash = $1
+            FOR UPDATE",
+            old_token_hash,
+        )
+        .fetch_optional(&mut *tx)
+        .await {
+            Ok(Some(token)) => token,
+            Ok(None) => return Ok(RefreshOutcome::Invalid),
+            Err(e) => {
+                dbg!(&e);
+                return Err(e);
+            }
+        };
+
+        if token.revoked.is_some() || token.expires_at <= now {
+            return Ok(RefreshOutcome::Invalid);
+        }
+
+        if token.used.is_some() {
+            // Somebody used an old token, either the client or an attacker has a copy.
+            // Revoke the session so neither of them can continue with it.
+            sqlx::query!(
+                "UPDATE sessions SET revoked = $2 WHERE session_id = $1",
+                token.session_id,
+                now,
+            )
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            return Ok(RefreshOutcome::Reused);
+        }
+
+        sqlx::query!(
+            "UPDATE refresh_tokens SET used = $2 WHERE token_hash = $1",
+            old_token_hash,
+            now,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "INSERT INTO refresh_tokens (token_hash, session_id, created)
+            VALUES ($1, $2, $3)",
+            new_token_hash,
+            token.session_id,
+            now,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "UPDATE sessions SET last_used = $2, expires_at = $3 WHERE session_id = $1",
+            token.session_id,
+            now,
+            expires_at,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(RefreshOutcome::Rotated {
+            user_id: token.user_id,
+            session_id: token.session_id,
+        })
+    }
+}
@@ -0,0 +1,222 @@
+use crate::domain::{Currency, CurrencyChange, InventoryItem, ShopOffer, TransactionSource};
+use crate::repository::ledger::{apply_change, BalanceChange};
+use chrono::{DateTime, Utc};
+use rocket::async_trait;
+use sqlx::{Error, PgPool};
+use uuid::Uuid;
+
+/// What happened when a player tried to buy an offer.
+#[derive(Debug)]
+pub enum PurchaseOutcome {
+    Purchased(InventoryItem),
+    OfferNotFound,
+    /// The offer is outside of its availability window.
+    NotAvailable,
+    /// The player already bought the offer as often as allowed.
+    LimitReached,
+    /// The player can not pay the price.
+    Insufficient(Currency),
+}
+
+#[async_trait]
+pub trait ShopRepository: Send + Sync {
+    async fn add_or_update_offer(&self, offer: ShopOffer, now: DateTime<Utc>) -> Result<(), sqlx::Error>;
+
+    /// Returns the offers that can be bought at `now`.
+    async fn list_available(&self, now: DateTime<Utc>) -> Result<Vec<ShopOffer>, sqlx::Error>;
+
+    /// Pays for an offer and gives the item, all in one transaction.
+    /// Returns `RowNotFound` when the user has no stats.
+    async fn purchase(
+        &self,
+        user_id: Uuid,
+        offer_id: Uuid,
+        purchase_id: Uuid,
+        item_uuid: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<PurchaseOutcome, sqlx::Error>;
+}
+
+#[derive(Debug, Clone)]
+pub struct ShopRepositoryImpl {
+    pool: PgPool,
+}
+
+impl ShopRepositoryImpl {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl ShopRepository for ShopRepositoryImpl {
+    async fn add_or_update_offer(&self, offer: ShopOffer, now: DateTime<Utc>) -> Result<(), sqlx::Error> {
+        if let Err(e) = sqlx::query!(
+            "INSERT INTO shop_offers
+                (offer_id, definition_id, state_template, currency, price, available_from, available_until, purchase_limit, created)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            ON CONFLICT (offer_id)
+            DO UPDATE SET
+                definition_id = EXCLUDED.definition_id,
+                state_template = EXCLUDED.state_template,
+                currency = EXCLUDED.currency,
+                price = EXCLUDED.price,
+                available_from = EXCLUDED.available_from,
+                available_until = EXCLUDED.available_until,
+                purchase_limit = EXCLUDED.purchase_limit",
+            offer.offer_id,
+            offer.definition_id,
+            offer.state_template,
+            offer.currency.as_str(),
+            offer.price,
+            offer.available_from,
+            offer.available_until,
+            offer.purchase_limit,
+            now,
+        )
+        .execute(&self.pool)
+        .await {
+            dbg!(&e);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn list_available(&self, now: DateTime<Utc>) -> Result<Vec<ShopOffer>, sqlx::Error> {
+        let rows = sqlx::query!(
+            "SELECT offer_id, definition_id, state_template, currency, price, available_from, available_until, purchase_limit
+            FROM shop_offers
+            WHERE (available_from IS NULL OR available_from <= $1)
+                AND (available_until IS NULL OR $1 < available_until)
+            ORDER BY created, offer_id",
+            now,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(ShopOffer {
+                    offer_id: row.offer_id,
+                    definition_id: row.definition_id,
+                    state_template: row.state_template,
+                    currency: Currency::parse(&row.currency)
+                        .ok_or_else(|| Error::Decode(format!("unknown currency {:?}", row.currency).into()))?,
+                    price: row.price,
+                    available_from: row.available_from,
+                    available_until: row.available_until,
+                    purchase_limit: row.purchase_limit,
+                })
+            })
+            .collect()
+    }
+
+    async fn purchase(
+        &self,
+        user_id: Uuid,
+        offer_id: Uuid,
+        purchase_id: Uuid,
+        item_uuid: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<PurchaseOutcome, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Locking the stats row makes concurrent purchases of one player wait for each other,
+        // so the purchase limit can not be exceeded by buying twice at once.
+        let exists = sqlx::query_scalar!(
+            "SELECT user_id FROM stats WHERE user_id = $1 FOR UPDATE",
+            user_id,
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if exists.is_none() {
+            return Err(Error::RowNotFound);
+        }
+
+        let offer = match sqlx::query!(
+            "SELECT offer_id, definition_id, state_template, currency, price, available_from, available_until, purchase_limit
+            FROM shop_offers
+            WHERE offer_id = $1
+            FOR SHARE",
+            offer_id,
+        )
+        .fetch_optional(&mut *tx)
+        .await? {
+            Some(row) => ShopOffer {
+                offer_id: row.offer_id,
+                definition_id: row.definition_id,
+                state_template: row.state_template,
+                currency: Currency::parse(&row.currency)
+                    .ok_or_else(|| Error::Decode(format!("unknown currency {:?}", row.currency).into()))?,
+                price: row.price,
+                available_from: row.available_from,
+                available_until: row.available_until,
+                purchase_limit: row.purchase_limit,
+            },
+            None => return Ok(PurchaseOutcome::OfferNotFound),
+        };
+
+        if !offer.is_available(now) {
+            return Ok(PurchaseOutcome::NotAvailable);
+        }
+
+        if let Some(limit) = offer.purchase_limit {
+            let purchased = sqlx::query_scalar!(
+                "SELECT COUNT(*) AS \"count!\" FROM shop_purchases WHERE user_id = $1 AND offer_id = $2",
+                user_id,
+                offer_id,
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            if purchased >= limit as i64 {
+                return Ok(PurchaseOutcome::LimitReached);
+            }
+        }
+
+        let payment = CurrencyChange {
+            user_id,
+            currency: offer.currency,
+            delta: -offer.price,
+            reason: "shop_purchase".to_string(),
+            source: TransactionSource::Player(user_id),
+            reference_id: Some(purchase_id),
+        };
+        if apply_change(&mut tx, &payment).await? == BalanceChange::Insufficient {
+            return Ok(PurchaseOutcome::Insufficient(offer.currency));
+        }
+
+        if let Err(e) = sqlx::query!(
+            "INSERT INTO inventory_item (user_id, item_uuid, definition_id, state_blob)
+            VALUES ($1, $2, $3, $4)",
+            user_id,
+            item_uuid,
+            offer.definition_id,
+            offer.state_template,
+        )
+        .execute(&mut *tx)
+        .await {
+            dbg!(&e);
+            return Err(e);
+        }
+
+        if let Err(e) = sqlx::query!(
+            "INSERT INTO shop_purchases (purchase_id, offer_id, user_id, item_uuid, currency, price, purchased)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            purchase_id,
+            offer_id,
+            user_id,
+            item_uuid,
+            offer.currency.as_str(),
+            offer.price,
+            now,
+        )
+        .execute(&mut *tx)
+        .await {
+            dbg!(&e);
+            return Err(e);
+        }
+
+        tx.commit().await?;
+        Ok(PurchaseOutcome::Purchased(InventoryItem::new(item_uuid, offer.definition_id, offer.state_template, None)))
+    }
+}
@@ -1,20 +1,45 @@
-use crate::domain::StatFish;
+use crate::domain::{CatchFilter, CatchRecord, CurrencyChange, StatFish, XpGain};
+use crate::repository::ledger::{apply_change, BalanceChange};
+use crate::repository::quests::{advance_quests, QuestCatch};
+use crate::repository::rewards::grant_reward;
+use crate::utils::levels::LevelCurve;
+use chrono::{DateTime, Utc};
 use rocket::async_trait;
 use sqlx::{Error, PgPool};
 use uuid::Uuid;
 
 #[async_trait]
 pub trait StatsRepository: Send + Sync {
-    async fn add_xp(&self, user_id: Uuid, amount: i32) -> Result<(), sqlx::Error>;
+    /// Adds xp and grants the rewards of every level reached on the way, all in one transaction.
+    async fn add_xp(&self, user_id: Uuid, amount: i32, curve: &LevelCurve, now: DateTime<Utc>) -> Result<XpGain, sqlx::Error>;
 
-    async fn change_bucks(&self, user_id: Uuid, amount: i32) -> Result<(), sqlx::Error>;
+    async fn xp(&self, user_id: Uuid) -> Result<Option<i32>, sqlx::Error>;
 
-    async fn change_coins(&self, user_id: Uuid, amount: i32) -> Result<(), sqlx::Error>;
+    /// Changes coins or bucks and records it in the ledger, see `ledger::apply_change`.
+    async fn change_currency(&self, change: CurrencyChange) -> Result<BalanceChange, sqlx::Error>;
 
     async fn add_playtime(&self, user_id: Uuid, amount: i32) -> Result<(), sqlx::Error>;
 
-    async fn add_fish(&self, fish: StatFish) -> Result<(), sqlx::Error>;
+    /// Records the catch in the catch log, with the selected rod and active effects, and updates the aggregates.
+    /// Logs the catch, adds it to the totals and counts it towards the matching quests, all in one transaction.
+    async fn add_fish(&self, fish: StatFish, caught: DateTime<Utc>, quests: QuestCatch) -> Result<(), sqlx::Error>;
+
+    /// Returns up to `limit` catches of the user, the latest first, starting after the `after` time and catch id.
+    async fn list_catches(
+        &self,
+        user_id: Uuid,
+        filter: CatchFilter,
+        after: Option<(DateTime<Utc>, i64)>,
+        limit: i64,
+    ) -> Result<Vec<CatchRecord>, sqlx::Error>;
+
+    /// Creates the missing catch log partitions up to `months_ahead` months from now, returns how many.
+    async fn create_catch_log_partitions(&self, months_ahead: i32) -> Result<i32, sqlx::Error>;
     
+    /// Returns the definition id of an item and whether it is locked in escrow,
+    /// None when the user does not have the item.
+    async fn item_definition(&self, user_id: Uuid, item_uid: Uuid) -> Result<Option<(i32, bool)>, sqlx::Error>;
+
     async fn select_rod(&self, user_id: Uuid, rod_uid: Uuid) -> Result<(), sqlx::Error>;
 
     async fn select_bait(&self, user_id: Uuid, bait_uid: Uuid) -> Result<(), sqlx::Error>;
@@ -33,46 +58,55 @@ impl StatsRepositoryImpl {
 
 #[async_trait]
 impl StatsRepository for StatsRepositoryImpl {
-    async fn add_xp(&self, user_id: Uuid, amount: i32) -> Result<(), sqlx::Error> {
-        let result = sqlx::query!(
+    async fn add_xp(&self, user_id: Uuid, amount: i32, curve: &LevelCurve, now: DateTime<Utc>) -> Result<XpGain, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let xp = sqlx::query_scalar!(
             "UPDATE stats
             SET xp = xp + $1
-            WHERE user_id = $2",
+            WHERE user_id = $2
+            RETURNING xp",
             amount,
             user_id,
         )
-        .execute(&self.pool)
-        .await?;
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(Error::RowNotFound)?;
 
-        if result.rows_affected() == 0 {
-            return Err(Error::RowNotFound);
+        let old_level = curve.level(xp - amount);
+        let progress = curve.progress(xp);
+        let mut rewards = Vec::new();
+        for (_, reward) in curve.rewards_between(old_level, progress.level) {
+            rewards.push(grant_reward(&mut tx, user_id, reward, "level_up", now).await?);
         }
 
-        Ok(())
+        tx.commit().await?;
+        Ok(XpGain {
+            old_level,
+            new_level: progress.level,
+            xp,
+            xp_to_next_level: progress.xp_to_next_level,
+            rewards,
+        })
     }
 
-    async fn change_bucks(&self, user_id: Uuid, amount: i32) -> Result<(), sqlx::Error> {
-        let result = sqlx::query!(
-            "UPDATE stats
-            SET bucks = bucks + $1
-            WHERE user_id = $2",
-            amount,
-            user_id,
-        )
-        .execute(&self.pool)
-        .await?;
-
-        if result.rows_affected() == 0 {
-            return Err(Error::RowNotFound);
-        }
+    async fn xp(&self, user_id: Uuid) -> Result<Option<i32>, sqlx::Error> {
+        sqlx::query_scalar!("SELECT xp FROM stats WHERE user_id = $1", user_id)
+            .fetch_optional(&self.pool)
+            .await
+    }
 
-        Ok(())
+    async fn change_currency(&self, change: CurrencyChange) -> Result<BalanceChange, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let outcome = apply_change(&mut tx, &change).await?;
+        tx.commit().await?;
+        Ok(outcome)
     }
 
-    async fn change_coins(&self, user_id: Uuid, amount: i32) -> Result<(), sqlx::Error> {
+    async fn add_playtime(&self, user_id: Uuid, amount: i32) -> Result<(), sqlx::Error> {
         let result = sqlx::query!(
             "UPDATE stats
-            SET coins = coins + $1
+            SET total_playtime = total_playtime + $1
             WHERE user_id = $2",
             amount,
             user_id,
@@ -87,27 +121,37 @@ impl StatsRepository for StatsRepositoryImpl {
         Ok(())
     }
 
-    async fn add_playtime(&self, user_id: Uuid, amount: i32) -> Result<(), sqlx::Error> {
-        let result = sqlx::query!(
-            "UPDATE stats
-            SET total_playtime = total_playtime + $1
-            WHERE user_id = $2",
-            amount,
-            user_id,
+    async fn add_fish(&self, fish: StatFish, caught: DateTime<Utc>, quests: QuestCatch) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Append to the catch log, the rod and effects as they are right now
+        let logged = sqlx::query!(
+            "INSERT INTO catch_log (user_id, caught, fish_id, length, area_id, bait_id, rod_uuid, rod_definition_id, effects)
+            SELECT s.user_id, $2, $3, $4, $5, $6, s.selected_rod, i.definition_id,
+                COALESCE(
+                    (
+                        SELECT array_agg(pe.item_id ORDER BY pe.item_id)
+                        FROM player_effects pe
+                        WHERE pe.user_id = s.user_id AND pe.expiry_time > $2
+                    ), '{}'
+                )
+            FROM stats s
+            LEFT JOIN inventory_item i ON i.user_id = s.user_id AND i.item_uuid = s.selected_rod
+            WHERE s.user_id = $1",
+            fish.user_id,
+            caught,
+            fish.fish_id,
+            fish.length,
+            fish.area_id,
+            fish.bait_id,
         )
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await?;
 
-        if result.rows_affected() == 0 {
+        if logged.rows_affected() == 0 {
             return Err(Error::RowNotFound);
         }
 
-        Ok(())
-    }
-
-    async fn add_fish(&self, fish: StatFish) -> Result<(), sqlx::Error> {
-        let mut tx = self.pool.begin().await?;
-
         // Insert or update fish
         sqlx::query!(
             "
@@ -151,10 +195,76 @@ impl StatsRepository for StatsRepositoryImpl {
         .execute(&mut *tx)
         .await?;
 
+        advance_quests(&mut tx, fish.user_id, caught, &quests).await?;
+
         tx.commit().await?;
         Ok(())
     }
 
+    async fn list_catches(
+        &self,
+        user_id: Uuid,
+        filter: CatchFilter,
+        after: Option<(DateTime<Utc>, i64)>,
+        limit: i64,
+    ) -> Result<Vec<CatchRecord>, sqlx::Error> {
+        let (after_time, after_id) = after.unzip();
+        match sqlx::query_as!(
+            CatchRecord,
+            "SELECT catch_id, caught, fish_id, length, area_id, bait_id, rod_uuid, rod_definition_id, effects
+            FROM catch_log
+            WHERE user_id = $1
+                AND ($2::INTEGER IS NULL OR fish_id = $2)
+                AND ($3::INTEGER IS NULL OR area_id = $3)
+                AND ($4::INTEGER IS NULL OR bait_id = $4)
+                AND ($5::TIMESTAMPTZ IS NULL OR caught >= $5)
+                AND ($6::TIMESTAMPTZ IS NULL OR caught < $6)
+                AND ($7::TIMESTAMPTZ IS NULL OR (caught, catch_id) < ($7, $8::BIGINT))
+            ORDER BY caught DESC, catch_id DESC
+            LIMIT $9",
+            user_id,
+            filter.fish_id,
+            filter.area_id,
+            filter.bait_id,
+            filter.since,
+            filter.until,
+            after_time,
+            after_id,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await {
+            Ok(catches) => Ok(catches),
+            Err(e) => {
+                dbg!(&e);
+                Err(e)
+            }
+        }
+    }
+
+    async fn create_catch_log_partitions(&self, months_ahead: i32) -> Result<i32, sqlx::Error> {
+        let created = sqlx::query_scalar!(
+            "SELECT create_catch_log_partitions($1) AS \"created!\"",
+            months_ahead,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(created)
+    }
+
+    async fn item_definition(&self, user_id: Uuid, item_uid: Uuid) -> Result<Option<(i32, bool)>, sqlx::Error> {
+        let row = sqlx::query!(
+            "SELECT definition_id, escrow_trade_id IS NOT NULL AS \"in_escrow!\" FROM inventory_item
+            WHERE user_id = $1 AND item_uuid = $2",
+            user_id,
+            item_uid,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| (row.definition_id, row.in_escrow)))
+    }
+
     async fn select_rod(&self, user_id: Uuid, rod_uid: Uuid) -> Result<(), sqlx::Error> {
         let result = match sqlx::query!(
             "UPDATE stats
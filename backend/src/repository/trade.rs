@@ -0,0 +1,495 @@
+use crate::domain::{Currency, CurrencyChange, Trade, TradeItem, TradeProposal, TradeStatus, TransactionSource};
+use crate::repository::ledger::{apply_change, BalanceChange};
+use chrono::{DateTime, Utc};
+use rocket::async_trait;
+use sqlx::{Error, PgConnection, PgPool};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// What happened when a player tried to propose, accept or close a trade.
+#[derive(Debug)]
+pub enum TradeOutcome {
+    Done(Trade),
+    /// The trade does not exist, or the player is not the one who may do this with it.
+    TradeNotFound,
+    /// The trade was already accepted, declined, countered or cancelled.
+    NotOpen,
+    /// An item is not owned by the player who should give it, or is locked in another trade.
+    ItemUnavailable(Uuid),
+    /// The player can not pay the coins of the trade.
+    InsufficientCoins(Uuid),
+}
+
+#[async_trait]
+pub trait TradeRepository: Send + Sync {
+    /// Creates an open trade and locks the offered items in escrow.
+    /// With `parent_trade_id` the trade counters that trade, which has to be open and proposed to the proposer.
+    /// Returns `RowNotFound` when one of the players has no stats.
+    async fn create(
+        &self,
+        proposal: TradeProposal,
+        trade_id: Uuid,
+        parent_trade_id: Option<Uuid>,
+        now: DateTime<Utc>,
+    ) -> Result<TradeOutcome, sqlx::Error>;
+
+    /// Swaps the items and coins of an open trade, all in one transaction. Only its recipient can accept it.
+    async fn accept(&self, trade_id: Uuid, recipient_id: Uuid, now: DateTime<Utc>) -> Result<TradeOutcome, sqlx::Error>;
+
+    /// Closes an open trade and releases its escrow.
+    /// The recipient can decline it and the proposer cancel it.
+    async fn close(
+        &self,
+        trade_id: Uuid,
+        user_id: Uuid,
+        status: TradeStatus,
+        now: DateTime<Utc>,
+    ) -> Result<TradeOutcome, sqlx::Error>;
+
+    async fn get(&self, trade_id: Uuid) -> Result<Option<Trade>, sqlx::Error>;
+
+    /// Returns the trades a user proposed or received, the newest first.
+    async fn list(&self, user_id: Uuid, limit: i64) -> Result<Vec<Trade>, sqlx::Error>;
+}
+
+#[derive(Debug, Clone)]
+pub struct TradeRepositoryImpl {
+    pool: PgPool,
+}
+
+impl TradeRepositoryImpl {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+struct TradeRow {
+    trade_id: Uuid,
+    proposer_id: Uuid,
+    recipient_id: Uuid,
+    offered_coins: i32,
+    requested_coins: i32,
+    status: String,
+    parent_trade_id: Option<Uuid>,
+    created: DateTime<Utc>,
+    resolved: Option<DateTime<Utc>>,
+}
+
+struct LockedItem {
+    item_uuid: Uuid,
+    user_id: Uuid,
+    escrow_trade_id: Option<Uuid>,
+}
+
+/// Locks the stats rows of both players, always in the same order so concurrent trades do not deadlock.
+/// Returns `RowNotFound` when one of them has no stats.
+async fn lock_players(conn: &mut PgConnection, a: Uuid, b: Uuid) -> Result<(), sqlx::Error> {
+    let locked = sqlx::query_scalar!(
+        "SELECT user_id FROM stats WHERE user_id = ANY($1) ORDER BY user_id FOR UPDATE",
+        &[a, b][..],
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    if locked.len() != 2 {
+        return Err(Error::RowNotFound);
+    }
+    Ok(())
+}
+
+async fn lock_trade(conn: &mut PgConnection, trade_id: Uuid) -> Result<Option<TradeRow>, sqlx::Error> {
+    sqlx::query_as!(
+        TradeRow,
+        "SELECT trade_id, proposer_id, recipient_id, offered_coins, requested_coins, status, parent_trade_id, created, resolved
+        FROM trades
+        WHERE trade_id = $1
+        FOR UPDATE",
+        trade_id,
+    )
+    .fetch_optional(&mut *conn)
+    .await
+}
+
+async fn lock_items(conn: &mut PgConnection, item_uuids: &[Uuid]) -> Result<Vec<LockedItem>, sqlx::Error> {
+    sqlx::query_as!(
+        LockedItem,
+        "SELECT item_uuid AS \"item_uuid!\", user_id, escrow_trade_id
+        FROM inventory_item
+        WHERE item_uuid = ANY($1)
+        ORDER BY item_uuid
+        FOR UPDATE",
+        item_uuids,
+    )
+    .fetch_all(&mut *conn)
+    .await
+}
+
+/// Returns the first item that is not owned by `owner` or whose escrow is not `escrow_trade_id`.
+fn unavailable_item(
+    locked: &[LockedItem],
+    item_uuids: &[Uuid],
+    owner: Uuid,
+    escrow_trade_id: Option<Uuid>,
+) -> Option<Uuid> {
+    item_uuids.iter().copied().find(|item_uuid| {
+        !locked.iter().any(|item| {
+            item.item_uuid == *item_uuid && item.user_id == owner && item.escrow_trade_id == escrow_trade_id
+        })
+    })
+}
+
+async fn release_escrow(conn: &mut PgConnection, trade_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE inventory_item SET escrow_trade_id = NULL WHERE escrow_trade_id = $1",
+        trade_id,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+async fn set_status(
+    conn: &mut PgConnection,
+    trade_id: Uuid,
+    status: TradeStatus,
+    now: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE trades SET status = $2, resolved = $3 WHERE trade_id = $1",
+        trade_id,
+        status.as_str(),
+        now,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Unselects the given items as rod or bait of the given players.
+async fn unselect(conn: &mut PgConnection, user_ids: &[Uuid], item_uuids: &[Uuid]) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE stats
+        SET selected_rod = CASE WHEN selected_rod = ANY($2) THEN NULL ELSE selected_rod END,
+            selected_bait = CASE WHEN selected_bait = ANY($2) THEN NULL ELSE selected_bait END
+        WHERE user_id = ANY($1) AND (selected_rod = ANY($2) OR selected_bait = ANY($2))",
+        user_ids,
+        item_uuids,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Adds the items to the trade rows.
+async fn with_items(conn: &mut PgConnection, rows: Vec<TradeRow>) -> Result<Vec<Trade>, sqlx::Error> {
+    let trade_ids: Vec<Uuid> = rows.iter().map(|row| row.trade_id).collect();
+    let items = sqlx::query!(
+        "SELECT trade_id, item_uuid, side, definition_id, state_blob
+        FROM trade_items
+        WHERE trade_id = ANY($1)
+        ORDER BY item_uuid",
+        &trade_ids,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut by_trade: HashMap<Uuid, (Vec<TradeItem>, Vec<TradeItem>)> = HashMap::new();
+    for item in items {
+        let (offered, requested) = by_trade.entry(item.trade_id).or_default();
+        let side = if item.side == "offered" { offered } else { requested };
+        side.push(TradeItem {
+            item_uuid: item.item_uuid,
+            definition_id: item.definition_id,
+            state_blob: item.state_blob,
+        });
+    }
+
+    rows.into_iter()
+        .map(|row| {
+            let (offered_items, requested_items) = by_trade.remove(&row.trade_id).unwrap_or_default();
+            Ok(Trade {
+                trade_id: row.trade_id,
+                proposer_id: row.proposer_id,
+                recipient_id: row.recipient_id,
+                offered_items,
+                offered_coins: row.offered_coins,
+                requested_items,
+                requested_coins: row.requested_coins,
+                status: TradeStatus::parse(&row.status)
+                    .ok_or_else(|| Error::Decode(format!("unknown trade status {:?}", row.status).into()))?,
+                parent_trade_id: row.parent_trade_id,
+                created: row.created,
+                resolved: row.resolved,
+            })
+        })
+        .collect()
+}
+
+async fn load_trade(conn: &mut PgConnection, trade_id: Uuid) -> Result<Trade, sqlx::Error> {
+    let row = sqlx::query_as!(
+        TradeRow,
+        "SELECT trade_id, proposer_id, recipient_id, offered_coins, requested_coins, status, parent_trade_id, created, resolved
+        FROM trades
+        WHERE trade_id = $1",
+        trade_id,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    with_items(conn, vec![row]).await?.pop().ok_or(Error::RowNotFound)
+}
+
+/// Moves `amount` coins from one player to the other. Returns false when the payer can not afford it.
+async fn pay_coins(
+    conn: &mut PgConnection,
+    from: Uuid,
+    to: Uuid,
+    amount: i32,
+    source: TransactionSource,
+    trade_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    if amount == 0 {
+        return Ok(true);
+    }
+    for (user_id, delta) in [(from, -amount), (to, amount)] {
+        let change = CurrencyChange {
+            user_id,
+            currency: Currency::Coins,
+            delta,
+            reason: "trade".to_string(),
+            source,
+            reference_id: Some(trade_id),
+        };
+        if apply_change(conn, &change).await? == BalanceChange::Insufficient {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[async_trait]
+impl TradeRepository for TradeRepositoryImpl {
+    async fn create(
+        &self,
+        proposal: TradeProposal,
+        trade_id: Uuid,
+        parent_trade_id: Option<Uuid>,
+        now: DateTime<Utc>,
+    ) -> Result<TradeOutcome, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        lock_players(&mut tx, proposal.proposer_id, proposal.recipient_id).await?;
+
+        if let Some(parent_trade_id) = parent_trade_id {
+            let parent = match lock_trade(&mut tx, parent_trade_id).await? {
+                Some(parent)
+                    if parent.recipient_id == proposal.proposer_id
+                        && parent.proposer_id == proposal.recipient_id => parent,
+                _ => return Ok(TradeOutcome::TradeNotFound),
+            };
+            if parent.status != TradeStatus::Open.as_str() {
+                return Ok(TradeOutcome::NotOpen);
+            }
+            set_status(&mut tx, parent_trade_id, TradeStatus::Countered, now).await?;
+            release_escrow(&mut tx, parent_trade_id).await?;
+        }
+
+        if let Err(e) = sqlx::query!(
+            "INSERT INTO trades
+                (trade_id, proposer_id, recipient_id, offered_coins, requested_coins, status, parent_trade_id, created)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            trade_id,
+            proposal.proposer_id,
+            proposal.recipient_id,
+            proposal.terms.offered_coins,
+            proposal.terms.requested_coins,
+            TradeStatus::Open.as_str(),
+            parent_trade_id,
+            now,
+        )
+        .execute(&mut *tx)
+        .await {
+            dbg!(&e);
+            return Err(e);
+        }
+
+        let all_items: Vec<Uuid> = proposal.terms.offered_items.iter().chain(&proposal.terms.requested_items).copied().collect();
+        let locked = lock_items(&mut tx, &all_items).await?;
+        if let Some(item_uuid) = unavailable_item(&locked, &proposal.terms.offered_items, proposal.proposer_id, None)
+            .or_else(|| unavailable_item(&locked, &proposal.terms.requested_items, proposal.recipient_id, None))
+        {
+            return Ok(TradeOutcome::ItemUnavailable(item_uuid));
+        }
+
+        sqlx::query!(
+            "UPDATE inventory_item SET escrow_trade_id = $1 WHERE item_uuid = ANY($2)",
+            trade_id,
+            &proposal.terms.offered_items,
+        )
+        .execute(&mut *tx)
+        .await?;
+        // Items in escrow can not be selected.
+        unselect(&mut tx, &[proposal.proposer_id], &proposal.terms.offered_items).await?;
+
+        let sides: Vec<&str> = proposal.terms.offered_items.iter().map(|_| "offered")
+            .chain(proposal.terms.requested_items.iter().map(|_| "requested"))
+            .collect();
+        if let Err(e) = sqlx::query!(
+            "INSERT INTO trade_items (trade_id, item_uuid, side, definition_id, state_blob)
+            SELECT $1, i.item_uuid, s.side, i.definition_id, i.state_blob
+            FROM UNNEST($2::UUID[], $3::TEXT[]) AS s(item_uuid, side)
+            JOIN inventory_item i ON i.item_uuid = s.item_uuid",
+            trade_id,
+            &all_items,
+            &sides as &[&str],
+        )
+        .execute(&mut *tx)
+        .await {
+            dbg!(&e);
+            return Err(e);
+        }
+
+        let trade = load_trade(&mut tx, trade_id).await?;
+        tx.commit().await?;
+        Ok(TradeOutcome::Done(trade))
+    }
+
+    async fn accept(&self, trade_id: Uuid, recipient_id: Uuid, now: DateTime<Utc>) -> Result<TradeOutcome, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // The players are locked before the trade, in the same order as when a trade is created.
+        let proposer_id = match sqlx::query_scalar!(
+            "SELECT proposer_id FROM trades WHERE trade_id = $1 AND recipient_id = $2",
+            trade_id,
+            recipient_id,
+        )
+        .fetch_optional(&mut *tx)
+        .await? {
+            Some(proposer_id) => proposer_id,
+            None => return Ok(TradeOutcome::TradeNotFound),
+        };
+        lock_players(&mut tx, proposer_id, recipient_id).await?;
+
+        let trade = match lock_trade(&mut tx, trade_id).await? {
+            Some(trade) => trade,
+            None => return Ok(TradeOutcome::TradeNotFound),
+        };
+        if trade.status != TradeStatus::Open.as_str() {
+            return Ok(TradeOutcome::NotOpen);
+        }
+
+        let items = sqlx::query!(
+            "SELECT item_uuid, side FROM trade_items WHERE trade_id = $1",
+            trade_id,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let (offered, requested): (Vec<_>, Vec<_>) = items.into_iter().partition(|item| item.side == "offered");
+        let offered: Vec<Uuid> = offered.into_iter().map(|item| item.item_uuid).collect();
+        let requested: Vec<Uuid> = requested.into_iter().map(|item| item.item_uuid).collect();
+        let all_items: Vec<Uuid> = offered.iter().chain(&requested).copied().collect();
+
+        let locked = lock_items(&mut tx, &all_items).await?;
+        if let Some(item_uuid) = unavailable_item(&locked, &offered, proposer_id, Some(trade_id))
+            .or_else(|| unavailable_item(&locked, &requested, recipient_id, None))
+        {
+            return Ok(TradeOutcome::ItemUnavailable(item_uuid));
+        }
+
+        let source = TransactionSource::Player(recipient_id);
+        if !pay_coins(&mut tx, proposer_id, recipient_id, trade.offered_coins, source, trade_id).await? {
+            return Ok(TradeOutcome::InsufficientCoins(proposer_id));
+        }
+        if !pay_coins(&mut tx, recipient_id, proposer_id, trade.requested_coins, source, trade_id).await? {
+            return Ok(TradeOutcome::InsufficientCoins(recipient_id));
+        }
+
+        // The items as they are handed over, for support.
+        sqlx::query!(
+            "UPDATE trade_items t
+            SET definition_id = i.definition_id, state_blob = i.state_blob
+            FROM inventory_item i
+            WHERE t.trade_id = $1 AND i.item_uuid = t.item_uuid",
+            trade_id,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if let Err(e) = sqlx::query!(
+            "UPDATE inventory_item
+            SET user_id = CASE WHEN user_id = $1 THEN $2 ELSE $1 END,
+                escrow_trade_id = NULL
+            WHERE item_uuid = ANY($3)",
+            proposer_id,
+            recipient_id,
+            &all_items,
+        )
+        .execute(&mut *tx)
+        .await {
+            dbg!(&e);
+            return Err(e);
+        }
+        unselect(&mut tx, &[proposer_id, recipient_id], &all_items).await?;
+
+        set_status(&mut tx, trade_id, TradeStatus::Accepted, now).await?;
+        let trade = load_trade(&mut tx, trade_id).await?;
+        tx.commit().await?;
+        Ok(TradeOutcome::Done(trade))
+    }
+
+    async fn close(
+        &self,
+        trade_id: Uuid,
+        user_id: Uuid,
+        status: TradeStatus,
+        now: DateTime<Utc>,
+    ) -> Result<TradeOutcome, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let trade = match lock_trade(&mut tx, trade_id).await? {
+            Some(trade) => trade,
+            None => return Ok(TradeOutcome::TradeNotFound),
+        };
+        let allowed = match status {
+            TradeStatus::Declined => trade.recipient_id == user_id,
+            TradeStatus::Cancelled => trade.proposer_id == user_id,
+            _ => false,
+        };
+        if !allowed {
+            return Ok(TradeOutcome::TradeNotFound);
+        }
+        if trade.status != TradeStatus::Open.as_str() {
+            return Ok(TradeOutcome::NotOpen);
+        }
+
+        set_status(&mut tx, trade_id, status, now).await?;
+        release_escrow(&mut tx, trade_id).await?;
+        let trade = load_trade(&mut tx, trade_id).await?;
+        tx.commit().await?;
+        Ok(TradeOutcome::Done(trade))
+    }
+
+    async fn get(&self, trade_id: Uuid) -> Result<Option<Trade>, sqlx::Error> {
+        let mut conn = self.pool.acquire().await?;
+        match load_trade(&mut conn, trade_id).await {
+            Ok(trade) => Ok(Some(trade)),
+            Err(Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn list(&self, user_id: Uuid, limit: i64) -> Result<Vec<Trade>, sqlx::Error> {
+        let mut conn = self.pool.acquire().await?;
+        let rows = sqlx::query_as!(
+            TradeRow,
+            "SELECT trade_id, proposer_id, recipient_id, offered_coins, requested_coins, status, parent_trade_id, created, resolved
+            FROM trades
+            WHERE proposer_id = $1 OR recipient_id = $1
+            ORDER BY created DESC, trade_id
+            LIMIT $2",
+            user_id,
+            limit,
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        with_items(&mut conn, rows).await
+    }
+}
@@ -1,19 +1,55 @@
-use crate::domain::User;
+use crate::domain::{Currency, CurrencyChange, ItemDefinition, TransactionSource, User};
+use crate::repository::ledger::apply_change;
+use chrono::{DateTime, Utc};
 use rocket::async_trait;
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
 use sqlx::types::Uuid;
 use sqlx::PgPool;
 
+/// The coins a new account starts with.
+pub const STARTING_COINS: i32 = 25;
+/// The bucks a new account starts with.
+pub const STARTING_BUCKS: i32 = 5000;
+
 #[async_trait]
 pub trait UserRepository: Send + Sync {
-    async fn create(&self, user: User) -> Result<(), sqlx::Error>;
+    /// Creates a user with its stats, starting balance and the given starter items.
+    async fn create(&self, user: User, starter_items: Vec<ItemDefinition>) -> Result<(), sqlx::Error>;
 
-    async fn from_uuid(&self, user_id: Uuid) -> Result<Option<User>, sqlx::Error>;
+    async fn get_by_uuid(&self, user_id: Uuid) -> Result<Option<User>, sqlx::Error>;
 
     async fn get_username_from_email(&self, email: String) -> Result<Option<Username>, sqlx::Error>;
 
-    async fn from_username(&self, email: String) -> Result<Option<User>, sqlx::Error>;
+    async fn get_by_username(&self, name: String) -> Result<Option<User>, sqlx::Error>;
+
+    async fn update_password(
+        &self,
+        user_id: Uuid,
+        password: String,
+        salt: String,
+        password_changed: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn get_by_email(&self, email: String) -> Result<Option<User>, sqlx::Error>;
+
+    async fn create_password_reset(
+        &self,
+        token_hash: String,
+        user_id: Uuid,
+        created: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error>;
+
+    /// Uses a reset token to set a new password.
+    /// Returns false when the token does not exist, is expired or was already used.
+    async fn reset_password(
+        &self,
+        token_hash: String,
+        password: String,
+        salt: String,
+        now: DateTime<Utc>,
+    ) -> Result<bool, sqlx::Error>;
 
     // add more functions such as update or delete.
 }
@@ -36,7 +72,7 @@ pub struct Username {
 
 #[async_trait]
 impl UserRepository for UserRepositoryImpl {
-    async fn create(&self, user: User) -> Result<(), sqlx::Error> {
+    async fn create(&self, user: User, starter_items: Vec<ItemDefinition>) -> Result<(), sqlx::Error> {
         let mut tx = self.pool.begin().await?;
 
         // Insert user
@@ -61,10 +97,10 @@ impl UserRepository for UserRepositoryImpl {
             "INSERT INTO stats (user_id, xp, coins, bucks, total_playtime)
             VALUES ($1, $2, $3, $4, $5);",
             user.user_id,
-            0,    // xp
-            25,   // coins
-            5000, // bucks
-            0     // total_playtime
+            0, // xp
+            0, // coins, the starting balance is added below
+            0, // bucks
+            0  // total_playtime
         )
         .execute(&mut *tx)
         .await {
@@ -79,48 +115,44 @@ impl UserRepository for UserRepositoryImpl {
             return Err(sqlx::Error::RowNotFound);
         }
 
-        // Insert bamboo rod
-        let result = match sqlx::query!(
-            "INSERT INTO inventory_item (user_id, item_uuid, definition_id, state_blob)
-            VALUES ($1, $2, $3, $4);",
-            user.user_id,     // user_id
-            Uuid::new_v4(),   // item_uid
-            1000,             // definition_id
-            "AQABAAX2////",   // state_blob
-        )
-        .execute(&mut *tx)
-        .await {
-            Ok(o) => o,
-            Err(e) => {
+        // Starting balance, through the ledger like every other change
+        for (currency, amount) in [(Currency::Coins, STARTING_COINS), (Currency::Bucks, STARTING_BUCKS)] {
+            let change = CurrencyChange {
+                user_id: user.user_id,
+                currency,
+                delta: amount,
+                reason: "account_created".to_string(),
+                source: TransactionSource::System,
+                reference_id: None,
+            };
+            if let Err(e) = apply_change(&mut tx, &change).await {
                 dbg!(&e);
                 return Err(e);
             }
-        };
-
-        if result.rows_affected() == 0 {
-            return Err(sqlx::Error::RowNotFound);
         }
 
-        // Insert hook
-        let result = match sqlx::query!(
-            "INSERT INTO inventory_item (user_id, item_uuid, definition_id, state_blob)
-            VALUES ($1, $2, $3, $4);",
-            user.user_id,     // user_id
-            Uuid::new_v4(),   // item_uid
-            0,                // definition_id
-            "AQABAAX2////",   // state_blob
-        )
-        .execute(&mut *tx)
-        .await {
-            Ok(o) => o,
-            Err(e) => {
-                dbg!(&e);
-                return Err(e);
-            }
-        };
+        // Insert starter items
+        for item in starter_items {
+            let result = match sqlx::query!(
+                "INSERT INTO inventory_item (user_id, item_uuid, definition_id, state_blob)
+                VALUES ($1, $2, $3, $4);",
+                user.user_id,
+                Uuid::new_v4(),
+                item.id,
+                item.default_state,
+            )
+            .execute(&mut *tx)
+            .await {
+                Ok(o) => o,
+                Err(e) => {
+                    dbg!(&e);
+                    return Err(e);
+                }
+            };
 
-        if result.rows_affected() == 0 {
-            return Err(sqlx::Error::RowNotFound);
+            if result.rows_affected() == 0 {
+                return Err(sqlx::Error::RowNotFound);
+            }
         }
 
         if let Err(e) = tx.commit().await {
@@ -130,10 +162,10 @@ impl UserRepository for UserRepositoryImpl {
         Ok(())
     }
 
-    async fn from_uuid(&self, user_id: Uuid) -> Result<Option<User>, sqlx::Error> {
+    async fn get_by_uuid(&self, user_id: Uuid) -> Result<Option<User>, sqlx::Error> {
         let user = sqlx::query_as!(
             User,
-            "SELECT user_id, name, email, password, salt, created
+            "SELECT user_id, name, email, password, salt, created, password_changed
              FROM users
              WHERE user_id = $1",
             user_id
@@ -163,16 +195,188 @@ impl UserRepository for UserRepositoryImpl {
         Ok(user)
     }
 
-    async fn from_username(&self, email: String) -> Result<Option<User>, sqlx::Error> {
+    async fn get_by_username(&self, name: String) -> Result<Option<User>, sqlx::Error> {
         let user = sqlx::query_as!(
             User,
-            "SELECT user_id, name, email, password, salt, created
+            "SELECT user_id, name, email, password, salt, created, password_changed
              FROM users
              WHERE name = $1",
+            name
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(user)
+    }
+
+    async fn update_password(
+        &self,
+        user_id: Uuid,
+        password: String,
+        salt: String,
+        password_changed: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let result = match sqlx::query!(
+            "UPDATE users
+            SET password = $2, salt = $3, password_changed = $4
+            WHERE user_id = $1",
+            user_id,
+            password,
+            salt,
+            password_changed,
+        )
+        .execute(&mut *tx)
+        .await {
+            Ok(o) => o,
+            Err(e) => {
+                dbg!(&e);
+                return Err(e);
+            }
+        };
+
+        if result.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+
+        // Log out every device, their refresh tokens should not outlive the old password.
+        if let Err(e) = sqlx::query!(
+            "UPDATE sessions
+            SET revoked = $2
+            WHERE user_id = $1 AND revoked IS NULL",
+            user_id,
+            password_changed,
+        )
+        .execute(&mut *tx)
+        .await {
+            dbg!(&e);
+            return Err(e);
+        }
+
+        match tx.commit().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                dbg!(&e);
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_by_email(&self, email: String) -> Result<Option<User>, sqlx::Error> {
+        let user = sqlx::query_as!(
+            User,
+            "SELECT user_id, name, email, password, salt, created, password_changed
+             FROM users
+             WHERE email = $1",
             email
         )
         .fetch_optional(&self.pool)
         .await?;
         Ok(user)
     }
+
+    async fn create_password_reset(
+        &self,
+        token_hash: String,
+        user_id: Uuid,
+        created: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        match sqlx::query!(
+            "INSERT INTO password_reset_tokens (token_hash, user_id, created, expires_at)
+            VALUES ($1, $2, $3, $4)",
+            token_hash,
+            user_id,
+            created,
+            expires_at,
+        )
+        .execute(&self.pool)
+        .await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                dbg!(&e);
+                Err(e)
+            }
+        }
+    }
+
+    async fn reset_password(
+        &self,
+        token_hash: String,
+        password: String,
+        salt: String,
+        now: DateTime<Utc>,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Claim the token, this fails when it is expired or already used.
+        let user_id = match sqlx::query_scalar!(
+            "UPDATE password_reset_tokens
+            SET used_at = $2
+            WHERE token_hash = $1 AND used_at IS NULL AND expires_at > $2
+            RETURNING user_id",
+            token_hash,
+            now,
+        )
+        .fetch_optional(&mut *tx)
+        .await {
+            Ok(Some(user_id)) => user_id,
+            Ok(None) => return Ok(false),
+            Err(e) => {
+                dbg!(&e);
+                return Err(e);
+            }
+        };
+
+        if let Err(e) = sqlx::query!(
+            "UPDATE users
+            SET password = $2, salt = $3, password_changed = $4
+            WHERE user_id = $1",
+            user_id,
+            password,
+            salt,
+            now,
+        )
+        .execute(&mut *tx)
+        .await {
+            dbg!(&e);
+            return Err(e);
+        }
+
+        // Other outstanding tokens of this user should not be usable anymore.
+        if let Err(e) = sqlx::query!(
+            "UPDATE password_reset_tokens
+            SET used_at = $2
+            WHERE user_id = $1 AND used_at IS NULL",
+            user_id,
+            now,
+        )
+        .execute(&mut *tx)
+        .await {
+            dbg!(&e);
+            return Err(e);
+        }
+
+        // And every device gets logged out.
+        if let Err(e) = sqlx::query!(
+            "UPDATE sessions
+            SET revoked = $2
+            WHERE user_id = $1 AND revoked IS NULL",
+            user_id,
+            now,
+        )
+        .execute(&mut *tx)
+        .await {
+            dbg!(&e);
+            return Err(e);
+        }
+
+        match tx.commit().await {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                dbg!(&e);
+                Err(e)
+            }
+        }
+    }
 }
@@ -0,0 +1,355 @@
+use chrono::Utc;
+use rocket::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedReceiver;
+use uuid::Uuid;
+
+use crate::domain::{Achievement, AchievementList, GrantedReward};
+use crate::error::AppError;
+use crate::repository::achievements::{AchievementRepository, PlayerTotals};
+use crate::utils::achievements::{AchievementCatalog, Goal};
+use crate::utils::catalog::ItemCatalog;
+use crate::utils::events::{EventBus, GameEvent};
+use crate::utils::levels::LevelCurve;
+
+#[async_trait]
+pub trait AchievementService: Send + Sync {
+    /// Updates the progress of the achievements the event can advance, and unlocks the ones that were reached.
+    async fn handle(&self, event: &GameEvent) -> Result<(), AppError>;
+
+    /// All achievements with the progress of the user, in the order of the achievements file.
+    async fn list(&self, user_id: Uuid) -> Result<AchievementList, AppError>;
+
+    /// The achievements the user unlocked, the latest first.
+    async fn unlocked(&self, user_id: Uuid) -> Result<AchievementList, AppError>;
+}
+
+/// Hands every event to `AchievementService::handle`, one after the other, for as long as the server runs.
+///
+/// Events are not stored, but progress is measured from the stats rather than counted,
+/// so an event that got lost is made up for by the next one of its kind.
+pub fn spawn_achievement_worker(achievement_service: Arc<dyn AchievementService>, mut events: UnboundedReceiver<GameEvent>) {
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            if let Err(e) = achievement_service.handle(&event).await {
+                eprintln!("Could not update the achievements for {:?}: {:?}", event, e);
+            }
+        }
+    });
+}
+
+pub struct AchievementServiceImpl<R: AchievementRepository> {
+    achievement_repository: R,
+    achievements: Arc<AchievementCatalog>,
+    catalog: Arc<ItemCatalog>,
+    levels: Arc<LevelCurve>,
+    events: Arc<EventBus>,
+}
+
+impl<R: AchievementRepository> AchievementServiceImpl<R> {
+    pub fn new(
+        achievement_repository: R,
+        achievements: Arc<AchievementCatalog>,
+        catalog: Arc<ItemCatalog>,
+        levels: Arc<LevelCurve>,
+        events: Arc<EventBus>,
+    ) -> Self {
+        Self { achievement_repository, achievements, catalog, levels, events }
+    }
+
+    /// The progress of the user towards the goal, not capped at its target.
+    async fn measure(&self, user_id: Uuid, goal: &Goal, totals: &PlayerTotals) -> Result<i64, AppError> {
+        Ok(match goal {
+            Goal::FishCaught { .. } => totals.fish_caught,
+            Goal::SpeciesCaught { .. } => totals.species_caught,
+            Goal::SpeciesInEveryArea { fish_id, areas } => {
+                self.achievement_repository.areas_with_species(user_id, *fish_id, areas).await?
+            }
+            Goal::Level { .. } => self.levels.level(totals.xp) as i64,
+            Goal::Playtime { .. } => totals.playtime as i64,
+            Goal::ItemsOwned { kind, .. } => {
+                let definition_ids: Vec<i32> = self.catalog
+                    .definitions()
+                    .iter()
+                    .filter(|definition| definition.kind == *kind)
+                    .map(|definition| definition.id)
+                    .collect();
+                self.achievement_repository.items_owned(user_id, &definition_ids).await?
+            }
+        })
+    }
+
+    async fn achievements_of(&self, user_id: Uuid) -> Result<Vec<Achievement>, AppError> {
+        self.achievement_repository
+            .totals(user_id)
+            .await?
+            .ok_or(AppError::NotFound("user_not_found"))?;
+        let mut records: HashMap<String, _> = self.achievement_repository
+            .list(user_id)
+            .await?
+            .into_iter()
+            .map(|record| (record.achievement_id.clone(), record))
+            .collect();
+
+        Ok(self.achievements
+            .all()
+            .iter()
+            .map(|definition| {
+                let record = records.remove(&definition.id);
+                Achievement {
+                    achievement_id: definition.id.clone(),
+                    name: definition.name.clone(),
+                    description: definition.description.clone(),
+                    progress: record.as_ref().map_or(0, |record| record.progress),
+                    target: definition.goal.target(),
+                    unlocked: record.and_then(|record| record.unlocked),
+                }
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl<R: AchievementRepository> AchievementService for AchievementServiceImpl<R> {
+    async fn handle(&self, event: &GameEvent) -> Result<(), AppError> {
+        let user_id = event.user_id();
+        let advanced: Vec<_> = self.achievements.advanced_by(event).collect();
+        if advanced.is_empty() {
+            return Ok(());
+        }
+
+        // Unlocked achievements stay unlocked, whatever happens to the numbers later.
+        let records = self.achievement_repository.list(user_id).await?;
+        let open: Vec<_> = advanced
+            .into_iter()
+            .filter(|definition| {
+                !records
+                    .iter()
+                    .any(|record| record.achievement_id == definition.id && record.unlocked.is_some())
+            })
+            .collect();
+        if open.is_empty() {
+            return Ok(());
+        }
+
+        let totals = self.achievement_repository
+            .totals(user_id)
+            .await?
+            .ok_or(AppError::NotFound("user_not_found"))?;
+        let mut progress = Vec::with_capacity(open.len());
+        for definition in &open {
+            let target = definition.goal.target();
+            let measured = self.measure(user_id, &definition.goal, &totals).await?;
+            progress.push((definition.id.clone(), measured.min(target as i64) as i32));
+        }
+        let now = Utc::now();
+        self.achievement_repository.save_progress(user_id, progress.clone(), now).await?;
+
+        for (definition, (_, value)) in open.into_iter().zip(progress) {
+            if value < definition.goal.target() {
+                continue;
+            }
+            let granted = self.achievement_repository
+                .unlock(user_id, &definition.id, &definition.reward, now)
+                .await?;
+            // Reward items count towards item goals too.
+            if let Some(GrantedReward::Granted(granted)) = granted {
+                if !granted.items.is_empty() {
+                    self.events.publish(GameEvent::InventoryChanged { user_id });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn list(&self, user_id: Uuid) -> Result<AchievementList, AppError> {
+        Ok(AchievementList { achievements: self.achievements_of(user_id).await? })
+    }
+
+    async fn unlocked(&self, user_id: Uuid) -> Result<AchievementList, AppError> {
+        let mut achievements: Vec<Achievement> = self.achievements_of(user_id)
+            .await?
+            .into_iter()
+            .filter(|achievement| achievement.unlocked.is_some())
+            .collect();
+        achievements.sort_by_key(|achievement| std::cmp::Reverse(achievement.unlocked));
+        Ok(AchievementList { achievements })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{ClaimedAttachments, Reward, StatFish};
+    use crate::repository::achievements::AchievementRecord;
+    use chrono::DateTime;
+    use std::sync::Mutex;
+
+    /// Achievement id, progress and unlock time.
+    type Record = (String, i32, Option<DateTime<Utc>>);
+
+    /// Keeps the progress of one player in memory.
+    struct FakeAchievementRepository {
+        totals: Option<(i32, i64)>,
+        records: Mutex<Vec<Record>>,
+        saved: Mutex<Vec<Vec<(String, i32)>>>,
+    }
+
+    #[async_trait]
+    impl AchievementRepository for FakeAchievementRepository {
+        async fn totals(&self, _user_id: Uuid) -> Result<Option<PlayerTotals>, sqlx::Error> {
+            Ok(self.totals.map(|(xp, fish_caught)| PlayerTotals { xp, playtime: 0, fish_caught, species_caught: 1 }))
+        }
+
+        async fn areas_with_species(&self, _user_id: Uuid, _fish_id: i32, _areas: &[i32]) -> Result<i64, sqlx::Error> {
+            Ok(0)
+        }
+
+        async fn items_owned(&self, _user_id: Uuid, _definition_ids: &[i32]) -> Result<i64, sqlx::Error> {
+            Ok(0)
+        }
+
+        async fn list(&self, _user_id: Uuid) -> Result<Vec<AchievementRecord>, sqlx::Error> {
+            Ok(self.records
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(achievement_id, progress, unlocked)| AchievementRecord {
+                    achievement_id: achievement_id.clone(),
+                    progress: *progress,
+                    unlocked: *unlocked,
+                })
+                .collect())
+        }
+
+        async fn save_progress(
+            &self,
+            _user_id: Uuid,
+            progress: Vec<(String, i32)>,
+            _now: DateTime<Utc>,
+        ) -> Result<(), sqlx::Error> {
+            let mut records = self.records.lock().unwrap();
+            for (achievement_id, value) in &progress {
+                match records.iter_mut().find(|(id, _, _)| id == achievement_id) {
+                    Some(record) => record.1 = *value,
+                    None => records.push((achievement_id.clone(), *value, None)),
+                }
+            }
+            self.saved.lock().unwrap().push(progress);
+            Ok(())
+        }
+
+        async fn unlock(
+            &self,
+            _user_id: Uuid,
+            achievement_id: &str,
+            _reward: &Reward,
+            now: DateTime<Utc>,
+        ) -> Result<Option<GrantedReward>, sqlx::Error> {
+            let mut records = self.records.lock().unwrap();
+            let record = records.iter_mut().find(|(id, _, _)| id == achievement_id).unwrap();
+            if record.2.is_some() {
+                return Ok(None);
+            }
+            record.2 = Some(now);
+            Ok(Some(GrantedReward::Granted(ClaimedAttachments { items: Vec::new(), coins: 0, bucks: 0 })))
+        }
+    }
+
+    const ACHIEVEMENTS: &str = r#"
+        [[achievement]]
+        id = "first_catch"
+        name = "First catch"
+        description = "Catch a fish."
+        goal = { type = "fish_caught", count = 1 }
+
+        [[achievement]]
+        id = "ten_catches"
+        name = "Ten catches"
+        description = "Catch 10 fish."
+        goal = { type = "fish_caught", count = 10 }
+
+        [[achievement]]
+        id = "level_two"
+        name = "Level 2"
+        description = "Reach level 2."
+        goal = { type = "level", level = 2 }
+    "#;
+
+    fn service(totals: Option<(i32, i64)>, records: Vec<Record>) -> AchievementServiceImpl<FakeAchievementRepository> {
+        let catalog = ItemCatalog::from_toml(include_str!("../../items.toml")).unwrap();
+        let levels = LevelCurve::from_toml(include_str!("../../levels.toml"), &catalog).unwrap();
+        let achievements = AchievementCatalog::from_toml(ACHIEVEMENTS, &catalog, &levels).unwrap();
+        let repository = FakeAchievementRepository { totals, records: Mutex::new(records), saved: Mutex::new(Vec::new()) };
+        AchievementServiceImpl::new(
+            repository,
+            Arc::new(achievements),
+            Arc::new(catalog),
+            Arc::new(levels),
+            Arc::new(EventBus::new()),
+        )
+    }
+
+    fn caught(user_id: Uuid) -> GameEvent {
+        let fish = StatFish { user_id, fish_id: 1, length: 10, bait_id: 0, area_id: 1 };
+        GameEvent::FishCaught { fish, caught: Utc::now() }
+    }
+
+    fn unlocked(achievements: &AchievementServiceImpl<FakeAchievementRepository>) -> Vec<String> {
+        let records = achievements.achievement_repository.records.lock().unwrap();
+        records.iter().filter(|record| record.2.is_some()).map(|record| record.0.clone()).collect()
+    }
+
+    #[tokio::test]
+    async fn progress_is_capped_and_only_reached_goals_unlock() {
+        let achievements = service(Some((0, 3)), Vec::new());
+        achievements.handle(&caught(Uuid::new_v4())).await.unwrap();
+        let saved = achievements.achievement_repository.saved.lock().unwrap().clone();
+        assert_eq!(saved, [vec![("first_catch".to_string(), 1), ("ten_catches".to_string(), 3)]]);
+        assert_eq!(unlocked(&achievements), ["first_catch"]);
+    }
+
+    #[tokio::test]
+    async fn unlocked_achievements_are_left_alone() {
+        let records = vec![("first_catch".to_string(), 1, Some(DateTime::UNIX_EPOCH))];
+        let achievements = service(Some((0, 12)), records);
+        achievements.handle(&caught(Uuid::new_v4())).await.unwrap();
+        let saved = achievements.achievement_repository.saved.lock().unwrap().clone();
+        assert_eq!(saved, [vec![("ten_catches".to_string(), 10)]]);
+        assert_eq!(unlocked(&achievements), ["first_catch", "ten_catches"]);
+    }
+
+    #[tokio::test]
+    async fn levels_are_measured_with_the_level_curve() {
+        let achievements = service(Some((99, 0)), Vec::new());
+        let level_up = GameEvent::LevelUp { user_id: Uuid::new_v4(), old_level: 1, new_level: 1 };
+        achievements.handle(&level_up).await.unwrap();
+        assert!(unlocked(&achievements).is_empty());
+
+        let achievements = service(Some((100, 0)), Vec::new());
+        achievements.handle(&level_up).await.unwrap();
+        assert_eq!(unlocked(&achievements), ["level_two"]);
+    }
+
+    #[tokio::test]
+    async fn events_without_achievements_change_nothing() {
+        let achievements = service(None, Vec::new());
+        achievements.handle(&GameEvent::PlaytimeAdded { user_id: Uuid::new_v4() }).await.unwrap();
+        assert!(achievements.achievement_repository.saved.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn list_shows_every_achievement_in_file_order() {
+        let achievements = service(Some((0, 3)), vec![("ten_catches".to_string(), 3, None)]);
+        let list = achievements.list(Uuid::new_v4()).await.unwrap().achievements;
+        let progress: Vec<_> = list
+            .iter()
+            .map(|achievement| (achievement.achievement_id.as_str(), achievement.progress, achievement.target))
+            .collect();
+        assert_eq!(progress, [("first_catch", 0, 1), ("ten_catches", 3, 10), ("level_two", 0, 2)]);
+
+        let error = service(None, Vec::new()).list(Uuid::new_v4()).await.unwrap_err();
+        assert_eq!(error.code(), "user_not_found");
+    }
+}
@@ -1,75 +1,191 @@
-use crate::domain::{LoginResponse, User};
+use crate::domain::{AuthenticatedUser, LoginResponse, Session};
+use crate::error::AppError;
+use crate::repository::sessions::{RefreshOutcome, SessionRepository};
 use crate::repository::user::UserRepository;
-use crate::utils::jwt::{generate_jwt, Claims};
+use crate::utils::jwt::{generate_jwt, Claims, SESSION_VALID_DAYS};
+use crate::utils::keys::KeySet;
+use crate::utils::token::{generate_token, hash_token};
 use bcrypt::verify;
-use jsonwebtoken::{decode, DecodingKey, Validation};
+use chrono::{Duration, Utc};
+use jsonwebtoken::jwk::JwkSet;
 use rocket::async_trait;
 use std::str::FromStr;
+use std::sync::Arc;
 use uuid::Uuid;
 
+/// Device name of sessions where the client did not name one.
+const UNKNOWN_DEVICE: &str = "unknown";
+
 /// business logic for authorisation.
 #[async_trait]
 pub trait AuthenticationService: Send + Sync {
-    /// Returns a JWT when credentials are valid.
-    async fn login(&self, username: String, password: String) -> Result<Option<LoginResponse>, sqlx::Error>;
+    /// Returns a JWT and refresh token when credentials are valid.
+    async fn login(
+        &self,
+        username: String,
+        password: String,
+        device: Option<String>,
+    ) -> Result<LoginResponse, AppError>;
+
+    /// Starts a new session for a user and returns its tokens.
+    /// The device name is shown in the session list, so users can tell their sessions apart.
+    async fn create_session(&self, user_id: Uuid, device: Option<String>) -> Result<LoginResponse, AppError>;
+
+    /// Exchanges a refresh token for a new JWT and refresh token.
+    /// Using a refresh token twice revokes its session.
+    async fn refresh(&self, refresh_token: String) -> Result<LoginResponse, AppError>;
+
+    /// Ends a session, its tokens can not be used anymore.
+    async fn logout(&self, user_id: Uuid, session_id: Uuid) -> Result<(), AppError>;
+
+    /// Returns the sessions of a user that are still active.
+    async fn list_sessions(&self, user_id: Uuid) -> Result<Vec<Session>, AppError>;
 
-    /// Checks if a JWT is valid given credentials.
-    async fn verify_jwt(&self, token: &str) -> Result<Option<User>, sqlx::Error>;
+    /// Checks if a JWT is valid and its session is still active.
+    async fn verify_jwt(&self, token: &str) -> Result<Option<AuthenticatedUser>, AppError>;
+
+    /// The public keys JWT's can be verified with.
+    fn jwks(&self) -> JwkSet;
 }
 
 /// AuthentcationServiceImpl requires:
 /// User repository for recieving information about users.
-/// The secret key for signing JWT's.
-pub struct AuthenticationServiceImpl<U: UserRepository> {
+/// Session repository for keeping track of logged in devices.
+/// The key set for signing and verifying JWT's.
+pub struct AuthenticationServiceImpl<U: UserRepository, S: SessionRepository> {
     user_repository: U,
-    secret_key: String,
+    session_repository: S,
+    keys: Arc<KeySet>,
 }
 
-impl<U: UserRepository> AuthenticationServiceImpl<U> {
-    pub fn new(user_repository: U, secret_key: String) -> Self {
+impl<U: UserRepository, S: SessionRepository> AuthenticationServiceImpl<U, S> {
+    pub fn new(user_repository: U, session_repository: S, keys: Arc<KeySet>) -> Self {
         Self {
             user_repository,
-            secret_key,
+            session_repository,
+            keys,
         }
     }
 }
 
 // Implement the authentication service trait for AuthenticationServiceImpl.
 #[async_trait]
-impl<U: UserRepository> AuthenticationService for AuthenticationServiceImpl<U> {
-    async fn login(&self, username: String, password: String) -> Result<Option<LoginResponse>, sqlx::Error> {
-        let user = match self.user_repository.from_username(username).await? {
+impl<U: UserRepository, S: SessionRepository> AuthenticationService for AuthenticationServiceImpl<U, S> {
+    async fn login(
+        &self,
+        username: String,
+        password: String,
+        device: Option<String>,
+    ) -> Result<LoginResponse, AppError> {
+        let user = match self.user_repository.get_by_username(username).await? {
             Some(user) => user,
             None => {
-                return Ok(None);
+                return Err(AppError::Unauthorized("invalid_credentials"));
             }
         };
         match verify_password(&password, &user.salt, &user.password) {
-            true => Ok(Some(
-                LoginResponse {
-                    code: 200,
-                    jwt: generate_jwt(user.user_id, &self.secret_key)?
-                }
-            )),
-            false => Ok(None),
+            true => self.create_session(user.user_id, device).await,
+            false => Err(AppError::Unauthorized("invalid_credentials")),
         }
     }
 
-    async fn verify_jwt(&self, token: &str) -> Result<Option<User>, sqlx::Error> {
-        let claims = decode::<Claims>(
-            token,
-            &DecodingKey::from_secret(self.secret_key.clone().as_bytes()),
-            &Validation::default(),
-        )
-        .map(|data| data.claims);
-
-        match claims {
-            Ok(claims) => Ok(self
-                .user_repository
-                .from_uuid(Uuid::from_str(&claims.user_id).expect("Failed to generate uuid."))
-                .await?),
-            Err(_) => Ok(None),
+    async fn create_session(&self, user_id: Uuid, device: Option<String>) -> Result<LoginResponse, AppError> {
+        let now = Utc::now();
+        let session = Session {
+            session_id: Uuid::new_v4(),
+            user_id,
+            device: device.unwrap_or_else(|| String::from(UNKNOWN_DEVICE)),
+            created: now,
+            last_used: now,
+            expires_at: now + Duration::days(SESSION_VALID_DAYS),
+            revoked: None,
+        };
+        let session_id = session.session_id;
+
+        let refresh_token = generate_token();
+        self.session_repository
+            .create(session, hash_token(&refresh_token))
+            .await?;
+
+        Ok(LoginResponse {
+            code: 200,
+            jwt: generate_jwt(user_id, session_id, &self.keys)?,
+            refresh_token,
+        })
+    }
+
+    async fn refresh(&self, refresh_token: String) -> Result<LoginResponse, AppError> {
+        let new_refresh_token = generate_token();
+        let now = Utc::now();
+        let outcome = self
+            .session_repository
+            .rotate(
+                hash_token(&refresh_token),
+                hash_token(&new_refresh_token),
+                now,
+                now + Duration::days(SESSION_VALID_DAYS),
+            )
+            .await?;
+
+        match outcome {
+            RefreshOutcome::Rotated { user_id, session_id } => Ok(LoginResponse {
+                code: 200,
+                jwt: generate_jwt(user_id, session_id, &self.keys)?,
+                refresh_token: new_refresh_token,
+            }),
+            RefreshOutcome::Reused => Err(AppError::Unauthorized("refresh_token_reused")),
+            RefreshOutcome::Invalid => Err(AppError::Unauthorized("invalid_refresh_token")),
+        }
+    }
+
+    async fn logout(&self, user_id: Uuid, session_id: Uuid) -> Result<(), AppError> {
+        self.session_repository
+            .revoke(user_id, session_id, Utc::now())
+            .await
+            .map_err(AppError::not_found("session_not_found"))
+    }
+
+    async fn list_sessions(&self, user_id: Uuid) -> Result<Vec<Session>, AppError> {
+        Ok(self.session_repository.list_active(user_id, Utc::now()).await?)
+    }
+
+    async fn verify_jwt(&self, token: &str) -> Result<Option<AuthenticatedUser>, AppError> {
+        let claims = match self.keys.decode::<Claims>(token) {
+            Some(claims) => claims,
+            None => return Ok(None),
+        };
+
+        let (user_id, session_id) = match (Uuid::from_str(&claims.user_id), Uuid::from_str(&claims.sid)) {
+            (Ok(user_id), Ok(session_id)) => (user_id, session_id),
+            _ => return Ok(None),
+        };
+
+        // The session should belong to this user and still be active.
+        match self.session_repository.get(session_id).await? {
+            Some(session)
+                if session.user_id == user_id
+                    && session.revoked.is_none()
+                    && session.expires_at > Utc::now() => {}
+            _ => return Ok(None),
         }
+
+        let user = match self.user_repository.get_by_uuid(user_id).await? {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+
+        // Tokens issued before the last password change are no longer valid.
+        if let Some(changed) = user.password_changed {
+            if (claims.iat as i64) < changed.timestamp() {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(AuthenticatedUser { user, session_id }))
+    }
+
+    fn jwks(&self) -> JwkSet {
+        self.keys.jwks()
     }
 }
 
@@ -1,12 +1,12 @@
 use rocket::async_trait;
 use uuid::Uuid;
 
-use crate::{domain::UserData, repository::data::DataRepository};
+use crate::{domain::UserData, error::AppError, repository::data::DataRepository};
 
 /// business logic for authorisation.
 #[async_trait]
 pub trait DataService: Send + Sync {
-    async fn retreive_all(&self, user_id: Uuid) -> Result<UserData, sqlx::Error>;
+    async fn retreive_all(&self, user_id: Uuid) -> Result<UserData, AppError>;
 }
 
 pub struct DataServiceImpl<U: DataRepository> {
@@ -24,11 +24,11 @@ impl<U: DataRepository> DataServiceImpl<U> {
 // Implement the data service trait for DataServiceImpl.
 #[async_trait]
 impl<U: DataRepository> DataService for DataServiceImpl<U> {
-    async fn retreive_all(&self, user_id: Uuid) -> Result<UserData, sqlx::Error> {
+    async fn retreive_all(&self, user_id: Uuid) -> Result<UserData, AppError> {
         let data = match self.data_repository.retreive_all(user_id).await? {
             Some(user) => user,
             None => {
-                return Err(sqlx::Error::WorkerCrashed);
+                return Err(AppError::NotFound("user_not_found"));
             }
         };
         Ok(data)
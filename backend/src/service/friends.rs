@@ -2,18 +2,22 @@ use rocket::async_trait;
 use chrono::Utc;
 use uuid::Uuid;
 
+use crate::error::AppError;
 use crate::repository::friends::FriendRepository;
 
 /// business logic for authorisation.
 #[async_trait]
 pub trait FriendService: Send + Sync {
-    async fn remove_friend(&self, user_one_id: Uuid, user_two_id: Uuid) -> Result<(), sqlx::Error>;
+    async fn remove_friend(&self, user_one_id: Uuid, user_two_id: Uuid) -> Result<(), AppError>;
 
-    async fn add_friend(&self, user_one_id: Uuid, user_two_id: Uuid) -> Result<(), sqlx::Error>;
+    async fn add_friend(&self, user_one_id: Uuid, user_two_id: Uuid) -> Result<(), AppError>;
 
-    async fn remove_friend_request(&self, user_one_id: Uuid, user_two_id: Uuid) -> Result<(), sqlx::Error>;
+    async fn remove_friend_request(&self, user_one_id: Uuid, user_two_id: Uuid) -> Result<(), AppError>;
 
-    async fn add_friend_request(&self, user_one_id: Uuid, user_two_id: Uuid, sender: Uuid) -> Result<(), sqlx::Error>;
+    async fn add_friend_request(&self, user_one_id: Uuid, user_two_id: Uuid, sender: Uuid) -> Result<(), AppError>;
+
+    /// Returns who send the pending friend request between two users, if there is one.
+    async fn get_request_sender(&self, user_one_id: Uuid, user_two_id: Uuid) -> Result<Option<Uuid>, AppError>;
 }
 
 pub struct FriendServiceImpl<U: FriendRepository> {
@@ -31,29 +35,51 @@ impl<U: FriendRepository> FriendServiceImpl<U> {
 // Implement the friend service trait for FriendServiceImpl.
 #[async_trait]
 impl<U: FriendRepository> FriendService for FriendServiceImpl<U> {
-    async fn remove_friend(&self, user_one_id: Uuid, user_two_id: Uuid) -> Result<(), sqlx::Error> {
-        self.friend_repository.remove_friend(user_one_id, user_two_id).await
+    async fn remove_friend(&self, user_one_id: Uuid, user_two_id: Uuid) -> Result<(), AppError> {
+        self.friend_repository
+            .remove_friend(user_one_id, user_two_id)
+            .await
+            .map_err(AppError::not_found("friend_not_found"))
     }
 
-    async fn add_friend(&self, user_one_id: Uuid, user_two_id: Uuid) -> Result<(), sqlx::Error> {
+    async fn add_friend(&self, user_one_id: Uuid, user_two_id: Uuid) -> Result<(), AppError> {
         let (user_one, user_two) = if user_one_id < user_two_id {
             (user_one_id, user_two_id)
         } else {
             (user_two_id, user_one_id)
         };
-        self.friend_repository.add_friend(user_one, user_two).await
+        match self.friend_repository.add_friend(user_one, user_two).await {
+            Ok(()) => Ok(()),
+            Err(e) => match AppError::from(e) {
+                AppError::Conflict(_) => Err(AppError::Conflict("already_friends")),
+                e => Err(e),
+            },
+        }
     }
 
-    async fn remove_friend_request(&self, user_one_id: Uuid, user_two_id: Uuid) -> Result<(), sqlx::Error> {
-        self.friend_repository.remove_friend_request(user_one_id, user_two_id).await
+    async fn remove_friend_request(&self, user_one_id: Uuid, user_two_id: Uuid) -> Result<(), AppError> {
+        self.friend_repository
+            .remove_friend_request(user_one_id, user_two_id)
+            .await
+            .map_err(AppError::not_found("friend_request_not_found"))
     }
 
-    async fn add_friend_request(&self, user_one_id: Uuid, user_two_id: Uuid, sender: Uuid) -> Result<(), sqlx::Error> {
+    async fn add_friend_request(&self, user_one_id: Uuid, user_two_id: Uuid, sender: Uuid) -> Result<(), AppError> {
         let (user_one, user_two) = if user_one_id < user_two_id {
             (user_one_id, user_two_id)
         } else {
             (user_two_id, user_one_id)
         };
-        self.friend_repository.add_friend_request(user_one, user_two, sender, Utc::now()).await
+        match self.friend_repository.add_friend_request(user_one, user_two, sender, Utc::now()).await {
+            Ok(()) => Ok(()),
+            Err(e) => match AppError::from(e) {
+                AppError::Conflict(_) => Err(AppError::Conflict("friend_request_exists")),
+                e => Err(e),
+            },
+        }
+    }
+
+    async fn get_request_sender(&self, user_one_id: Uuid, user_two_id: Uuid) -> Result<Option<Uuid>, AppError> {
+        Ok(self.friend_repository.get_request_sender(user_one_id, user_two_id).await?)
     }
 }
\ No newline at end of file
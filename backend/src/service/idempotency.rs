@@ -0,0 +1,95 @@
+use chrono::{Duration, Utc};
+use rocket::async_trait;
+
+use crate::domain::TransactionSource;
+use crate::error::AppError;
+use crate::repository::idempotency::IdempotencyRepository;
+
+/// How long the result of a request is kept when IDEMPOTENCY_TTL_HOURS is not set.
+pub const DEFAULT_IDEMPOTENCY_TTL_HOURS: i64 = 24;
+/// The longest idempotency key that is accepted.
+pub const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+/// The result of a request, as it is stored for its idempotency key.
+#[derive(Debug)]
+pub struct StoredResponse {
+    /// 200 for a result, the status of the error otherwise.
+    pub status: u16,
+    /// The JSON body, an `ErrorResponse` for an error.
+    pub body: String,
+}
+
+/// business logic for idempotency keys.
+#[async_trait]
+pub trait IdempotencyService: Send + Sync {
+    /// Starts a request with an idempotency key.
+    ///
+    /// Returns the stored result when the request was already done before, or `None` when it has to run now.
+    /// A key that was used for another request, or whose request is still running, is a conflict.
+    /// So is a key whose request stopped before its result was stored, until the key expires:
+    /// the request may have changed something, running it again could apply it twice.
+    async fn begin(
+        &self,
+        caller: TransactionSource,
+        key: &str,
+        request_hash: &str,
+    ) -> Result<Option<StoredResponse>, AppError>;
+
+    /// Stores the result of a request started with `begin`.
+    /// Without a result the key is freed, so a retry runs again.
+    async fn finish(&self, caller: TransactionSource, key: &str, response: Option<StoredResponse>) -> Result<(), AppError>;
+}
+
+pub struct IdempotencyServiceImpl<R: IdempotencyRepository> {
+    idempotency_repository: R,
+    ttl: Duration,
+}
+
+impl<R: IdempotencyRepository> IdempotencyServiceImpl<R> {
+    pub fn new(idempotency_repository: R, ttl: Duration) -> Self {
+        Self { idempotency_repository, ttl }
+    }
+}
+
+#[async_trait]
+impl<R: IdempotencyRepository> IdempotencyService for IdempotencyServiceImpl<R> {
+    async fn begin(
+        &self,
+        caller: TransactionSource,
+        key: &str,
+        request_hash: &str,
+    ) -> Result<Option<StoredResponse>, AppError> {
+        if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH {
+            return Err(AppError::BadRequest("invalid_idempotency_key"));
+        }
+
+        let now = Utc::now();
+        let stored = self.idempotency_repository
+            .claim(
+                caller,
+                key,
+                request_hash,
+                now,
+                now + self.ttl,
+            )
+            .await?;
+
+        match stored {
+            None => Ok(None),
+            Some(stored) if stored.request_hash != request_hash => Err(AppError::Conflict("idempotency_key_reused")),
+            Some(stored) => match (stored.status, stored.response) {
+                (Some(status), Some(body)) => Ok(Some(StoredResponse { status: status as u16, body })),
+                _ => Err(AppError::Conflict("request_in_progress")),
+            },
+        }
+    }
+
+    async fn finish(&self, caller: TransactionSource, key: &str, response: Option<StoredResponse>) -> Result<(), AppError> {
+        match response {
+            Some(response) => Ok(self.idempotency_repository
+                .complete(caller, key, response.status as i16, response.body)
+                .await?),
+            None => Ok(self.idempotency_repository.release(caller, key).await?),
+        }
+    }
+}
@@ -1,7 +1,27 @@
 use rocket::async_trait;
+use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::repository::inventory::InventoryRepository;
+use crate::domain::{ItemDefinition, ItemStateUpdate};
+use crate::error::AppError;
+use crate::repository::inventory::{InventoryRepository, UpsertOutcome};
+use crate::utils::catalog::ItemCatalog;
+use crate::utils::events::{EventBus, GameEvent};
+use crate::utils::item_state::ItemState;
+
+/// An item holds at least one and at most `max_stack` of its definition.
+pub fn check_quantity(definition: &ItemDefinition, state: &ItemState) -> Result<(), AppError> {
+    if state.quantity == 0 {
+        return Err(AppError::UnprocessableEntity("invalid_quantity"));
+    }
+    if state.quantity > definition.max_stack {
+        return Err(AppError::UnprocessableEntity("stack_limit_exceeded"));
+    }
+    Ok(())
+}
+
+/// How often a state change is tried again when the item changed between reading and writing it.
+const MAX_STATE_CHANGE_ATTEMPTS: usize = 5;
 
 // Here you add your business logic here.
 #[async_trait]
@@ -11,24 +31,78 @@ pub trait InventoryService: Send + Sync {
         user_id: Uuid,
         item_uuid: Uuid,
         definition_id: i32,
-        state_blob: String,
-    ) -> Result<(), sqlx::Error>;
+        state: ItemState,
+    ) -> Result<(), AppError>;
 
     async fn destroy(
         &self,
         user_id: Uuid,
         item_uid: Uuid,
-    ) -> Result<(), sqlx::Error>;
+    ) -> Result<(), AppError>;
+
+    /// Lowers the durability of an item, and destroys it when nothing is left.
+    /// Unbreakable items are left as they are.
+    async fn degrade(&self, user_id: Uuid, item_uid: Uuid, amount: i32) -> Result<ItemStateUpdate, AppError>;
+
+    /// Changes the quantity of an item, a negative amount uses some up.
+    /// The item is destroyed when nothing is left.
+    async fn increase(&self, user_id: Uuid, item_uid: Uuid, amount: i32) -> Result<ItemStateUpdate, AppError>;
 }
 
 pub struct InventoryServiceImpl<T: InventoryRepository> {
     inventory_repository: T,
+    catalog: Arc<ItemCatalog>,
+    events: Arc<EventBus>,
 }
 
 impl<R: InventoryRepository> InventoryServiceImpl<R> {
     // create a new function for InventoryServiceImpl.
-    pub fn new(inventory_repository: R) -> Self {
-        Self { inventory_repository }
+    pub fn new(inventory_repository: R, catalog: Arc<ItemCatalog>, events: Arc<EventBus>) -> Self {
+        Self { inventory_repository, catalog, events }
+    }
+
+    /// Applies `change` to the stored state of an item.
+    ///
+    /// `change` returns the new state and whether the item is used up, or `None` to leave it as it is.
+    /// The write only succeeds when nobody changed the item since it was read, otherwise it is done again
+    /// on the fresh state, so concurrent changes never overwrite each other.
+    async fn change_state<F>(&self, user_id: Uuid, item_uid: Uuid, change: F) -> Result<ItemStateUpdate, AppError>
+    where
+        F: Fn(&ItemDefinition, ItemState) -> Result<Option<(ItemState, bool)>, AppError> + Send + Sync,
+    {
+        for _ in 0..MAX_STATE_CHANGE_ATTEMPTS {
+            let item = self.inventory_repository
+                .get(user_id, item_uid)
+                .await?
+                .ok_or(AppError::NotFound("item_not_found"))?;
+            if item.escrow_trade_id.is_some() {
+                return Err(AppError::Conflict("item_in_escrow"));
+            }
+            let state = item.state.ok_or(AppError::UnprocessableEntity("invalid_state_blob"))?;
+            let definition = self.catalog
+                .get(item.definition_id)
+                .ok_or(AppError::UnprocessableEntity("unknown_definition"))?;
+
+            let (new_state, destroyed) = match change(definition, state)? {
+                Some(changed) => changed,
+                None => return Ok(ItemStateUpdate { item_uuid: item_uid, state, destroyed: false }),
+            };
+
+            let written = if destroyed {
+                self.inventory_repository.destroy_if_state(user_id, item_uid, &item.state_blob).await?
+            } else {
+                self.inventory_repository
+                    .replace_state(user_id, item_uid, &item.state_blob, new_state.encode())
+                    .await?
+            };
+            if written {
+                if destroyed {
+                    self.events.publish(GameEvent::InventoryChanged { user_id });
+                }
+                return Ok(ItemStateUpdate { item_uuid: item_uid, state: new_state, destroyed });
+            }
+        }
+        Err(AppError::Contention("item_changed_concurrently"))
     }
 }
 
@@ -40,16 +114,260 @@ impl<R: InventoryRepository> InventoryService for InventoryServiceImpl<R> {
         user_id: Uuid,
         item_uuid: Uuid,
         definition_id: i32,
-        state_blob: String,
-    ) -> Result<(), sqlx::Error> {
-        self.inventory_repository.add_or_update(user_id, item_uuid, definition_id, state_blob).await
+        state: ItemState,
+    ) -> Result<(), AppError> {
+        let definition = self.catalog
+            .get(definition_id)
+            .ok_or(AppError::UnprocessableEntity("unknown_definition"))?;
+        check_quantity(definition, &state)?;
+        let outcome = self.inventory_repository
+            .add_or_update(user_id, item_uuid, definition_id, state.encode())
+            .await?;
+        match outcome {
+            UpsertOutcome::Written => {
+                self.events.publish(GameEvent::InventoryChanged { user_id });
+                Ok(())
+            }
+            UpsertOutcome::OwnedByOtherUser => Err(AppError::Conflict("item_owned_by_other_user")),
+            UpsertOutcome::DefinitionMismatch => Err(AppError::Conflict("definition_mismatch")),
+            UpsertOutcome::InEscrow => Err(AppError::Conflict("item_in_escrow")),
+            UpsertOutcome::Removed => Err(AppError::Contention("item_changed_concurrently")),
+        }
     }
 
     async fn destroy(
         &self,
         user_id: Uuid,
         item_uid: Uuid,
-    ) -> Result<(), sqlx::Error> {
-        self.inventory_repository.destroy(user_id, item_uid).await
+    ) -> Result<(), AppError> {
+        let item = self.inventory_repository
+            .get(user_id, item_uid)
+            .await?
+            .ok_or(AppError::NotFound("item_not_found"))?;
+        if item.escrow_trade_id.is_some() {
+            return Err(AppError::Conflict("item_in_escrow"));
+        }
+        self.inventory_repository
+            .destroy(user_id, item_uid)
+            .await
+            .map_err(AppError::not_found("item_not_found"))?;
+        self.events.publish(GameEvent::InventoryChanged { user_id });
+        Ok(())
+    }
+
+    async fn degrade(&self, user_id: Uuid, item_uid: Uuid, amount: i32) -> Result<ItemStateUpdate, AppError> {
+        if amount <= 0 {
+            return Err(AppError::UnprocessableEntity("invalid_amount"));
+        }
+        self.change_state(user_id, item_uid, |_, state| {
+            if state.is_unbreakable() {
+                return Ok(None);
+            }
+            let durability = state.durability.saturating_sub(amount).max(0);
+            Ok(Some((ItemState { durability, ..state }, durability == 0)))
+        })
+        .await
+    }
+
+    async fn increase(&self, user_id: Uuid, item_uid: Uuid, amount: i32) -> Result<ItemStateUpdate, AppError> {
+        if amount == 0 {
+            return Err(AppError::UnprocessableEntity("invalid_amount"));
+        }
+        self.change_state(user_id, item_uid, |definition, state| {
+            let quantity = state.quantity as i64 + amount as i64;
+            if quantity < 0 {
+                return Err(AppError::UnprocessableEntity("insufficient_quantity"));
+            }
+            if quantity > definition.max_stack as i64 {
+                return Err(AppError::UnprocessableEntity("stack_limit_exceeded"));
+            }
+            Ok(Some((ItemState { quantity: quantity as u16, ..state }, quantity == 0)))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{InventoryItem, ItemKind};
+    use std::sync::Mutex;
+
+    /// Holds a single item. Another writer changes it `interference` times just before this service writes.
+    struct FakeInventoryRepository {
+        item: Mutex<Option<InventoryItem>>,
+        interference: Mutex<usize>,
+        writes: Mutex<usize>,
+    }
+
+    impl FakeInventoryRepository {
+        fn new(state: ItemState, interference: usize) -> Self {
+            Self {
+                item: Mutex::new(Some(InventoryItem::new(Uuid::nil(), 1, state.encode(), None))),
+                interference: Mutex::new(interference),
+                writes: Mutex::new(0),
+            }
+        }
+
+        /// Compares the stored blob with `expected` and lets `write` apply the change when it matches.
+        fn compare_and_set(&self, expected: &str, write: impl FnOnce(&mut Option<InventoryItem>)) -> bool {
+            *self.writes.lock().unwrap() += 1;
+            let mut item = self.item.lock().unwrap();
+            let mut interference = self.interference.lock().unwrap();
+            if *interference > 0 {
+                *interference -= 1;
+                let stored = item.as_mut().unwrap();
+                let mut state = stored.state.unwrap();
+                state.tier += 1;
+                *stored = InventoryItem::new(stored.item_uuid, stored.definition_id, state.encode(), None);
+            }
+            match item.as_ref() {
+                Some(stored) if stored.state_blob == expected && stored.escrow_trade_id.is_none() => {
+                    write(&mut item);
+                    true
+                }
+                _ => false,
+            }
+        }
+    }
+
+    #[async_trait]
+    impl InventoryRepository for FakeInventoryRepository {
+        async fn add_or_update(
+            &self,
+            _user_id: Uuid,
+            _item_uuid: Uuid,
+            _definition_id: i32,
+            _state_blob: String,
+        ) -> Result<UpsertOutcome, sqlx::Error> {
+            Ok(UpsertOutcome::Written)
+        }
+
+        async fn destroy(&self, _user_id: Uuid, _item_uid: Uuid) -> Result<(), sqlx::Error> {
+            Ok(())
+        }
+
+        async fn get(&self, _user_id: Uuid, _item_uid: Uuid) -> Result<Option<InventoryItem>, sqlx::Error> {
+            Ok(self.item.lock().unwrap().as_ref().map(|item| {
+                InventoryItem::new(item.item_uuid, item.definition_id, item.state_blob.clone(), item.escrow_trade_id)
+            }))
+        }
+
+        async fn replace_state(
+            &self,
+            _user_id: Uuid,
+            _item_uid: Uuid,
+            expected: &str,
+            state_blob: String,
+        ) -> Result<bool, sqlx::Error> {
+            Ok(self.compare_and_set(expected, |item| {
+                let stored = item.as_ref().unwrap();
+                *item = Some(InventoryItem::new(stored.item_uuid, stored.definition_id, state_blob, None));
+            }))
+        }
+
+        async fn destroy_if_state(&self, _user_id: Uuid, _item_uid: Uuid, expected: &str) -> Result<bool, sqlx::Error> {
+            Ok(self.compare_and_set(expected, |item| *item = None))
+        }
+    }
+
+    fn definition(max_stack: u16) -> ItemDefinition {
+        ItemDefinition {
+            id: 1,
+            name: "Worm".to_string(),
+            kind: ItemKind::Bait,
+            max_stack,
+            default_state: state(1, 10).encode(),
+            starter: false,
+        }
+    }
+
+    fn state(quantity: u16, durability: i32) -> ItemState {
+        ItemState { quantity, durability, ..ItemState::decode("AQABAAX2////").unwrap() }
+    }
+
+    fn service(state: ItemState, interference: usize) -> InventoryServiceImpl<FakeInventoryRepository> {
+        let catalog = ItemCatalog::new(vec![definition(10)]).unwrap();
+        InventoryServiceImpl::new(
+            FakeInventoryRepository::new(state, interference),
+            Arc::new(catalog),
+            Arc::new(EventBus::new()),
+        )
+    }
+
+    fn stored(inventory: &InventoryServiceImpl<FakeInventoryRepository>) -> Option<ItemState> {
+        inventory.inventory_repository.item.lock().unwrap().as_ref().and_then(|item| item.state)
+    }
+
+    #[test]
+    fn quantity_must_be_between_one_and_max_stack() {
+        assert_eq!(check_quantity(&definition(5), &state(0, 10)).unwrap_err().code(), "invalid_quantity");
+        assert!(check_quantity(&definition(5), &state(1, 10)).is_ok());
+        assert!(check_quantity(&definition(5), &state(5, 10)).is_ok());
+        assert_eq!(check_quantity(&definition(5), &state(6, 10)).unwrap_err().code(), "stack_limit_exceeded");
+    }
+
+    #[tokio::test]
+    async fn increase_retries_on_the_fresh_state_after_a_concurrent_change() {
+        let inventory = service(state(3, 10), 2);
+        let update = inventory.increase(Uuid::new_v4(), Uuid::nil(), 4).await.unwrap();
+        assert_eq!(update.state.quantity, 7);
+        assert!(!update.destroyed);
+        // The two changes of the other writer are kept, not overwritten.
+        assert_eq!(update.state.tier, state(3, 10).tier + 2);
+        assert_eq!(stored(&inventory), Some(update.state));
+        assert_eq!(*inventory.inventory_repository.writes.lock().unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_when_the_item_keeps_changing() {
+        let inventory = service(state(3, 10), MAX_STATE_CHANGE_ATTEMPTS);
+        let error = inventory.increase(Uuid::new_v4(), Uuid::nil(), 1).await.unwrap_err();
+        assert_eq!(error.code(), "item_changed_concurrently");
+        assert!(error.is_retryable());
+        assert_eq!(stored(&inventory).map(|state| state.quantity), Some(3));
+        assert_eq!(*inventory.inventory_repository.writes.lock().unwrap(), MAX_STATE_CHANGE_ATTEMPTS);
+    }
+
+    #[tokio::test]
+    async fn increase_checks_the_stack_limits() {
+        let inventory = service(state(3, 10), 0);
+        let error = inventory.increase(Uuid::new_v4(), Uuid::nil(), -4).await.unwrap_err();
+        assert_eq!(error.code(), "insufficient_quantity");
+        let error = inventory.increase(Uuid::new_v4(), Uuid::nil(), 8).await.unwrap_err();
+        assert_eq!(error.code(), "stack_limit_exceeded");
+        let error = inventory.increase(Uuid::new_v4(), Uuid::nil(), 0).await.unwrap_err();
+        assert_eq!(error.code(), "invalid_amount");
+        assert_eq!(*inventory.inventory_repository.writes.lock().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn using_up_the_last_one_destroys_the_item() {
+        let inventory = service(state(3, 10), 1);
+        let update = inventory.increase(Uuid::new_v4(), Uuid::nil(), -3).await.unwrap();
+        assert!(update.destroyed);
+        assert_eq!(stored(&inventory), None);
+    }
+
+    #[tokio::test]
+    async fn degrade_destroys_at_zero_durability_and_leaves_unbreakable_items() {
+        let inventory = service(state(1, 10), 0);
+        let update = inventory.degrade(Uuid::new_v4(), Uuid::nil(), 4).await.unwrap();
+        assert_eq!((update.state.durability, update.destroyed), (6, false));
+        let update = inventory.degrade(Uuid::new_v4(), Uuid::nil(), 20).await.unwrap();
+        assert_eq!((update.state.durability, update.destroyed), (0, true));
+
+        let unbreakable = service(state(1, -10), 0);
+        let update = unbreakable.degrade(Uuid::new_v4(), Uuid::nil(), 4).await.unwrap();
+        assert_eq!((update.state.durability, update.destroyed), (-10, false));
+        assert_eq!(*unbreakable.inventory_repository.writes.lock().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn items_in_escrow_are_not_changed() {
+        let inventory = service(state(3, 10), 0);
+        inventory.inventory_repository.item.lock().unwrap().as_mut().unwrap().escrow_trade_id = Some(Uuid::new_v4());
+        let error = inventory.increase(Uuid::new_v4(), Uuid::nil(), 1).await.unwrap_err();
+        assert_eq!(error.code(), "item_in_escrow");
     }
 }
@@ -0,0 +1,298 @@
+use chrono::{DateTime, Datelike, Duration, Utc};
+use rocket::async_trait;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::domain::{FriendLeaderboard, FriendLeaderboardEntry, Leaderboard, LeaderboardPage};
+use crate::error::AppError;
+use crate::repository::leaderboards::{FriendScore, LeaderboardRepository};
+
+/// How many entries are listed when no limit is given.
+pub const DEFAULT_LEADERBOARD_LIMIT: i64 = 50;
+/// The most entries that can be listed at once.
+pub const MAX_LEADERBOARD_LIMIT: i64 = 200;
+/// How many players above and below the user are listed when no number is given.
+pub const DEFAULT_NEIGHBOURS: i64 = 5;
+/// The most players above and below the user that can be listed.
+pub const MAX_NEIGHBOURS: i64 = 50;
+/// How often the boards are refreshed when LEADERBOARD_REFRESH_MINUTES is not set.
+pub const DEFAULT_LEADERBOARD_REFRESH_MINUTES: u64 = 5;
+
+#[async_trait]
+pub trait LeaderboardService: Send + Sync {
+    /// Returns `limit` entries starting at position `start`, 1 by default.
+    async fn page(&self, board: Leaderboard, start: Option<i64>, limit: Option<i64>) -> Result<LeaderboardPage, AppError>;
+
+    /// Returns the entry of the user with up to `neighbours` players above and below them.
+    async fn around(&self, board: Leaderboard, user_id: Uuid, neighbours: Option<i64>) -> Result<LeaderboardPage, AppError>;
+
+    async fn refresh(&self) -> Result<(), AppError>;
+
+    /// Ranks the user and their friends, with how their ranks changed since the weekly reset.
+    async fn friends(&self, board: Leaderboard, user_id: Uuid) -> Result<FriendLeaderboard, AppError>;
+
+    /// Takes the snapshot of the scores when the week changed, weeks start on Monday at midnight UTC.
+    async fn reset_week(&self) -> Result<(), AppError>;
+}
+
+/// Monday midnight UTC of the week `now` is in.
+fn week_start(now: DateTime<Utc>) -> DateTime<Utc> {
+    let monday = now.date_naive() - Duration::days(now.weekday().num_days_from_monday() as i64);
+    monday.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc()
+}
+
+/// Ranks the circle of a friends leaderboard by the current scores and by the scores at the weekly reset.
+/// Players with the same score share a rank, their positions follow the user id.
+fn rank_friends(mut scores: Vec<FriendScore>) -> Vec<FriendLeaderboardEntry> {
+    scores.sort_by(|a, b| b.score.cmp(&a.score).then(a.user_id.cmp(&b.user_id)));
+    let previous_scores: Vec<i64> = scores.iter().filter_map(|player| player.previous_score).collect();
+
+    let mut entries: Vec<FriendLeaderboardEntry> = Vec::with_capacity(scores.len());
+    for (index, player) in scores.into_iter().enumerate() {
+        let position = index as i64 + 1;
+        let rank = match entries.last() {
+            Some(above) if above.score == player.score => above.rank,
+            _ => position,
+        };
+        let previous_rank = player.previous_score
+            .map(|previous| previous_scores.iter().filter(|other| **other > previous).count() as i64 + 1);
+        entries.push(FriendLeaderboardEntry {
+            position,
+            rank,
+            user_id: player.user_id,
+            name: player.name,
+            score: player.score,
+            previous_rank,
+            rank_change: previous_rank.map(|previous_rank| previous_rank - rank),
+        });
+    }
+    entries
+}
+
+/// Refreshes the leaderboards every `interval` for as long as the server runs, starting right away.
+/// Also does the weekly reset once the week changed.
+pub fn spawn_refresh_job(leaderboard_service: Arc<dyn LeaderboardService>, interval: std::time::Duration) {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            if let Err(e) = leaderboard_service.reset_week().await {
+                eprintln!("Could not reset the weekly leaderboards: {:?}", e);
+            }
+            if let Err(e) = leaderboard_service.refresh().await {
+                eprintln!("Could not refresh the leaderboards: {:?}", e);
+            }
+        }
+    });
+}
+
+pub struct LeaderboardServiceImpl<R: LeaderboardRepository> {
+    leaderboard_repository: R,
+}
+
+impl<R: LeaderboardRepository> LeaderboardServiceImpl<R> {
+    pub fn new(leaderboard_repository: R) -> Self {
+        Self { leaderboard_repository }
+    }
+}
+
+#[async_trait]
+impl<R: LeaderboardRepository> LeaderboardService for LeaderboardServiceImpl<R> {
+    async fn page(&self, board: Leaderboard, start: Option<i64>, limit: Option<i64>) -> Result<LeaderboardPage, AppError> {
+        let start = start.unwrap_or(1);
+        if start < 1 {
+            return Err(AppError::UnprocessableEntity("invalid_start"));
+        }
+        let limit = limit.unwrap_or(DEFAULT_LEADERBOARD_LIMIT);
+        if !(1..=MAX_LEADERBOARD_LIMIT).contains(&limit) {
+            return Err(AppError::UnprocessableEntity("invalid_limit"));
+        }
+        let (entries, refreshed) = self.leaderboard_repository
+            .entries(board, start, start.saturating_add(limit - 1))
+            .await?;
+        Ok(LeaderboardPage { entries, refreshed })
+    }
+
+    async fn around(&self, board: Leaderboard, user_id: Uuid, neighbours: Option<i64>) -> Result<LeaderboardPage, AppError> {
+        let neighbours = neighbours.unwrap_or(DEFAULT_NEIGHBOURS);
+        if !(0..=MAX_NEIGHBOURS).contains(&neighbours) {
+            return Err(AppError::UnprocessableEntity("invalid_neighbours"));
+        }
+        let position = self.leaderboard_repository
+            .position(board, user_id)
+            .await?
+            .ok_or(AppError::NotFound("not_ranked"))?;
+        let (entries, refreshed) = self.leaderboard_repository
+            .entries(board, (position - neighbours).max(1), position + neighbours)
+            .await?;
+        Ok(LeaderboardPage { entries, refreshed })
+    }
+
+    async fn refresh(&self) -> Result<(), AppError> {
+        Ok(self.leaderboard_repository.refresh().await?)
+    }
+
+    async fn friends(&self, board: Leaderboard, user_id: Uuid) -> Result<FriendLeaderboard, AppError> {
+        let scores = self.leaderboard_repository.friends(board, user_id).await?;
+        if scores.is_empty() {
+            // The user is always part of their own circle, unless they do not exist.
+            return Err(AppError::NotFound("user_not_found"));
+        }
+        let week_start = self.leaderboard_repository.week_start().await?;
+        Ok(FriendLeaderboard { entries: rank_friends(scores), week_start })
+    }
+
+    async fn reset_week(&self) -> Result<(), AppError> {
+        let now = Utc::now();
+        if self.leaderboard_repository.reset_week(week_start(now), now).await? {
+            println!("Took the weekly leaderboard snapshot");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::LeaderboardEntry;
+    use std::sync::Mutex;
+
+    /// Ranks the user at `position` and remembers which positions were read.
+    struct FakeLeaderboardRepository {
+        position: Option<i64>,
+        ranges: Mutex<Vec<(i64, i64)>>,
+    }
+
+    #[async_trait]
+    impl LeaderboardRepository for FakeLeaderboardRepository {
+        async fn entries(
+            &self,
+            _board: Leaderboard,
+            start: i64,
+            end: i64,
+        ) -> Result<(Vec<LeaderboardEntry>, Option<DateTime<Utc>>), sqlx::Error> {
+            self.ranges.lock().unwrap().push((start, end));
+            Ok((Vec::new(), None))
+        }
+
+        async fn position(&self, _board: Leaderboard, _user_id: Uuid) -> Result<Option<i64>, sqlx::Error> {
+            Ok(self.position)
+        }
+
+        async fn refresh(&self) -> Result<(), sqlx::Error> {
+            Ok(())
+        }
+
+        async fn friends(&self, _board: Leaderboard, _user_id: Uuid) -> Result<Vec<FriendScore>, sqlx::Error> {
+            Ok(Vec::new())
+        }
+
+        async fn week_start(&self) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+            Ok(None)
+        }
+
+        async fn reset_week(&self, _week_start: DateTime<Utc>, _now: DateTime<Utc>) -> Result<bool, sqlx::Error> {
+            Ok(false)
+        }
+    }
+
+    fn service(position: Option<i64>) -> LeaderboardServiceImpl<FakeLeaderboardRepository> {
+        LeaderboardServiceImpl::new(FakeLeaderboardRepository { position, ranges: Mutex::new(Vec::new()) })
+    }
+
+    fn ranges(leaderboards: &LeaderboardServiceImpl<FakeLeaderboardRepository>) -> Vec<(i64, i64)> {
+        leaderboards.leaderboard_repository.ranges.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn parses_the_board_names() {
+        for board in [Leaderboard::Xp, Leaderboard::Catches, Leaderboard::Species] {
+            assert_eq!(Leaderboard::parse(board.as_str().unwrap()), Some(board));
+        }
+        assert_eq!(Leaderboard::FishLength(3).as_str(), None);
+        assert_eq!(Leaderboard::parse("gold"), None);
+    }
+
+    #[tokio::test]
+    async fn pages_start_at_the_top() {
+        let leaderboards = service(None);
+        leaderboards.page(Leaderboard::Xp, None, None).await.unwrap();
+        leaderboards.page(Leaderboard::Xp, Some(11), Some(10)).await.unwrap();
+        leaderboards.page(Leaderboard::Xp, Some(i64::MAX), Some(MAX_LEADERBOARD_LIMIT)).await.unwrap();
+        assert_eq!(ranges(&leaderboards), [(1, DEFAULT_LEADERBOARD_LIMIT), (11, 20), (i64::MAX, i64::MAX)]);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_pages() {
+        let leaderboards = service(None);
+        let cases = [
+            (Some(0), None, "invalid_start"),
+            (None, Some(0), "invalid_limit"),
+            (None, Some(MAX_LEADERBOARD_LIMIT + 1), "invalid_limit"),
+        ];
+        for (start, limit, code) in cases {
+            assert_eq!(leaderboards.page(Leaderboard::Xp, start, limit).await.unwrap_err().code(), code);
+        }
+        assert!(ranges(&leaderboards).is_empty());
+    }
+
+    #[tokio::test]
+    async fn around_lists_the_neighbours_without_going_above_the_top() {
+        let leaderboards = service(Some(10));
+        leaderboards.around(Leaderboard::Catches, Uuid::new_v4(), None).await.unwrap();
+        leaderboards.around(Leaderboard::Catches, Uuid::new_v4(), Some(0)).await.unwrap();
+        leaderboards.around(Leaderboard::Catches, Uuid::new_v4(), Some(20)).await.unwrap();
+        assert_eq!(ranges(&leaderboards), [(10 - DEFAULT_NEIGHBOURS, 10 + DEFAULT_NEIGHBOURS), (10, 10), (1, 30)]);
+
+        let error = leaderboards.around(Leaderboard::Catches, Uuid::new_v4(), Some(MAX_NEIGHBOURS + 1)).await.unwrap_err();
+        assert_eq!(error.code(), "invalid_neighbours");
+    }
+
+    #[tokio::test]
+    async fn around_needs_the_user_on_the_board() {
+        let error = service(None).around(Leaderboard::Species, Uuid::new_v4(), None).await.unwrap_err();
+        assert_eq!(error.code(), "not_ranked");
+    }
+
+    fn player(id: u128, score: i64, previous_score: Option<i64>) -> FriendScore {
+        FriendScore { user_id: Uuid::from_u128(id), name: format!("Player {}", id), score, previous_score }
+    }
+
+    /// User, position, rank, previous rank and rank change of an entry.
+    type Ranked = (u128, i64, i64, Option<i64>, Option<i64>);
+
+    fn ranks(entries: Vec<FriendLeaderboardEntry>) -> Vec<Ranked> {
+        entries
+            .into_iter()
+            .map(|entry| (entry.user_id.as_u128(), entry.position, entry.rank, entry.previous_rank, entry.rank_change))
+            .collect()
+    }
+
+    #[test]
+    fn rank_changes_count_the_places_moved_up() {
+        let entries = rank_friends(vec![player(1, 10, Some(30)), player(2, 20, Some(20)), player(3, 30, Some(10))]);
+        assert_eq!(ranks(entries), [(3, 1, 1, Some(3), Some(2)), (2, 2, 2, Some(2), Some(0)), (1, 3, 3, Some(1), Some(-2))]);
+    }
+
+    #[test]
+    fn equal_scores_share_a_rank_but_not_a_position() {
+        let entries = rank_friends(vec![player(3, 50, Some(5)), player(1, 50, Some(5)), player(2, 40, Some(9))]);
+        assert_eq!(ranks(entries), [(1, 1, 1, Some(2), Some(1)), (3, 2, 1, Some(2), Some(1)), (2, 3, 3, Some(1), Some(-2))]);
+    }
+
+    #[test]
+    fn players_who_joined_after_the_reset_have_no_previous_rank() {
+        // The newcomer is not counted when ranking the others at the reset.
+        let entries = rank_friends(vec![player(1, 5, Some(5)), player(2, 100, None), player(3, 0, Some(0))]);
+        assert_eq!(ranks(entries), [(2, 1, 1, None, None), (1, 2, 2, Some(1), Some(-1)), (3, 3, 3, Some(2), Some(-1))]);
+        assert!(rank_friends(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn weeks_start_on_monday_at_midnight() {
+        let monday = DateTime::parse_from_rfc3339("2026-10-12T00:00:00Z").unwrap().with_timezone(&Utc);
+        assert_eq!(week_start(monday), monday);
+        assert_eq!(week_start(monday + Duration::days(6) + Duration::hours(23)), monday);
+        assert_eq!(week_start(monday - Duration::seconds(1)), monday - Duration::days(7));
+    }
+}
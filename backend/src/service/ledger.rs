@@ -0,0 +1,65 @@
+use rocket::async_trait;
+use uuid::Uuid;
+
+use crate::domain::{Currency, CurrencyHistory, LedgerMismatch};
+use crate::error::AppError;
+use crate::repository::ledger::LedgerRepository;
+
+/// How many entries a page of the history has when no limit is given.
+pub const DEFAULT_HISTORY_LIMIT: i64 = 50;
+/// The largest page of the history that can be requested.
+pub const MAX_HISTORY_LIMIT: i64 = 200;
+
+/// business logic for the currency ledger.
+/// Balances are changed through the stats service, this only reads the ledger.
+#[async_trait]
+pub trait LedgerService: Send + Sync {
+    async fn history(
+        &self,
+        user_id: Uuid,
+        currency: Option<Currency>,
+        before: Option<i64>,
+        limit: Option<i64>,
+    ) -> Result<CurrencyHistory, AppError>;
+
+    /// Returns the balances that do not match the sum of their ledger entries, which should be none.
+    async fn reconcile(&self) -> Result<Vec<LedgerMismatch>, AppError>;
+}
+
+pub struct LedgerServiceImpl<R: LedgerRepository> {
+    ledger_repository: R,
+}
+
+impl<R: LedgerRepository> LedgerServiceImpl<R> {
+    pub fn new(ledger_repository: R) -> Self {
+        Self { ledger_repository }
+    }
+}
+
+#[async_trait]
+impl<R: LedgerRepository> LedgerService for LedgerServiceImpl<R> {
+    async fn history(
+        &self,
+        user_id: Uuid,
+        currency: Option<Currency>,
+        before: Option<i64>,
+        limit: Option<i64>,
+    ) -> Result<CurrencyHistory, AppError> {
+        let limit = limit.unwrap_or(DEFAULT_HISTORY_LIMIT);
+        if !(1..=MAX_HISTORY_LIMIT).contains(&limit) {
+            return Err(AppError::UnprocessableEntity("invalid_limit"));
+        }
+
+        let entries = self.ledger_repository.history(user_id, currency, before, limit).await?;
+        // A full page means there might be more.
+        let next_before = match entries.last() {
+            Some(last) if entries.len() as i64 == limit => Some(last.entry_id),
+            _ => None,
+        };
+        Ok(CurrencyHistory { entries, next_before })
+    }
+
+    async fn reconcile(&self) -> Result<Vec<LedgerMismatch>, AppError> {
+        Ok(self.ledger_repository.reconcile().await?)
+    }
+}
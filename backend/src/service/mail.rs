@@ -1,61 +1,370 @@
-use chrono::Utc;
+use chrono::{Duration, Utc};
 use rocket::async_trait;
+use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::repository::mail::MailRepository;
+use crate::domain::{
+    BroadcastFilter, ClaimedAttachments, MailAttachment, MailFilter, MailPage, MailRetentionReport, NewMail, UnreadCount,
+};
+use crate::error::AppError;
+use crate::repository::mail::{ClaimOutcome, MailRepository};
+use crate::service::inventory::check_quantity;
+use crate::utils::catalog::ItemCatalog;
+use crate::utils::cursor::{decode_cursor, encode_cursor};
+use crate::utils::events::{EventBus, GameEvent};
+use crate::utils::item_state::ItemState;
+
+/// The most attachments one mail can carry.
+pub const MAX_MAIL_ATTACHMENTS: usize = 10;
+/// How many mails are listed when no limit is given.
+pub const DEFAULT_MAIL_LIMIT: i64 = 50;
+/// The most mails that can be listed at once.
+pub const MAX_MAIL_LIMIT: i64 = 200;
+/// How long read and archived mail is kept when MAIL_RETENTION_DAYS is not set.
+pub const DEFAULT_MAIL_RETENTION_DAYS: i64 = 30;
+/// How often the retention job runs when MAIL_RETENTION_INTERVAL_MINUTES is not set.
+pub const DEFAULT_MAIL_RETENTION_INTERVAL_MINUTES: u64 = 60;
 
 #[async_trait]
 pub trait MailService: Send + Sync {
-    async fn create(
+    async fn create(&self, mail: NewMail, receiver_ids: Vec<Uuid>) -> Result<(), AppError>;
+
+    /// Sends a mail to every player matching the filter, including players who register later.
+    async fn broadcast(&self, mail: NewMail, filter: BroadcastFilter) -> Result<(), AppError>;
+
+    /// Returns a page of the mailbox, the newest mail first. `cursor` is the `next_cursor` of the previous page.
+    async fn list(
         &self,
-        mail_id: Uuid,
-        sender_id: Uuid,
-        receiver_ids: Vec<Uuid>,
-        title: String,
-        message: String,
-    ) -> Result<(), sqlx::Error>;
+        user_id: Uuid,
+        filter: MailFilter,
+        cursor: Option<String>,
+        limit: Option<i64>,
+        headers_only: bool,
+    ) -> Result<MailPage, AppError>;
+
+    async fn unread_count(&self, user_id: Uuid) -> Result<UnreadCount, AppError>;
+
+    /// Grants the attachments of a mail to the user, once per recipient.
+    async fn claim(&self, user_id: Uuid, mail_id: Uuid) -> Result<ClaimedAttachments, AppError>;
+
+    async fn delete(&self, user_id: Uuid, mail_id: Uuid) -> Result<(), AppError>;
+
+    async fn change_read_state(&self, user_id: Uuid, mail_id: Uuid, read: bool) -> Result<(), AppError>;
 
-    async fn delete(&self, user_id: Uuid, mail_id: Uuid) -> Result<(), sqlx::Error>;
+    async fn change_archive_state(&self, user_id: Uuid, mail_id: Uuid, archived: bool) -> Result<(), AppError>;
 
-    async fn change_read_state(&self, user_id: Uuid, mail_id: Uuid, read: bool) -> Result<(), sqlx::Error>;
+    /// Removes expired mail, old read and archived mail and mail nobody has anymore.
+    async fn purge(&self) -> Result<MailRetentionReport, AppError>;
+}
 
-    async fn change_archive_state(&self, user_id: Uuid, mail_id: Uuid, archived: bool) -> Result<(), sqlx::Error>;
+/// Runs `MailService::purge` every `interval` for as long as the server runs.
+pub fn spawn_retention_job(mail_service: Arc<dyn MailService>, interval: std::time::Duration) {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            match mail_service.purge().await {
+                Ok(report) => println!(
+                    "Mail retention removed {} expired mails, {} old mailbox entries and {} orphaned mails",
+                    report.expired_mail, report.old_entries, report.orphaned_mail
+                ),
+                Err(e) => eprintln!("Mail retention failed: {:?}", e),
+            }
+        }
+    });
 }
 
 pub struct MailServiceImpl<T: MailRepository> {
     mail_repository: T,
+    catalog: Arc<ItemCatalog>,
+    retention: Duration,
+    events: Arc<EventBus>,
 }
 
 impl<R: MailRepository> MailServiceImpl<R> {
     // create a new function for MailServiceImpl.
-    pub fn new(mail_repository: R) -> Self {
-        Self { mail_repository }
+    pub fn new(mail_repository: R, catalog: Arc<ItemCatalog>, retention: Duration, events: Arc<EventBus>) -> Self {
+        Self { mail_repository, catalog, retention, events }
+    }
+
+    /// Checks the expiry and attachments of a mail and puts the state blobs in the canonical encoding.
+    fn checked_mail(&self, mut mail: NewMail) -> Result<NewMail, AppError> {
+        if mail.expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+            return Err(AppError::UnprocessableEntity("invalid_expiry"));
+        }
+        if mail.attachments.len() > MAX_MAIL_ATTACHMENTS {
+            return Err(AppError::UnprocessableEntity("too_many_attachments"));
+        }
+        mail.attachments = mail.attachments
+            .into_iter()
+            .map(|attachment| self.checked_attachment(attachment))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(mail)
+    }
+
+    /// Checks an attachment and returns it with its state blob in the canonical encoding.
+    fn checked_attachment(&self, attachment: MailAttachment) -> Result<MailAttachment, AppError> {
+        match attachment {
+            MailAttachment::Item { definition_id, state_blob } => {
+                let definition = self.catalog
+                    .get(definition_id)
+                    .ok_or(AppError::UnprocessableEntity("unknown_definition"))?;
+                let state = ItemState::decode(&state_blob)
+                    .map_err(|_| AppError::UnprocessableEntity("invalid_state_blob"))?;
+                check_quantity(definition, &state)?;
+                Ok(MailAttachment::Item { definition_id, state_blob: state.encode() })
+            }
+            MailAttachment::Currency { currency, amount } => {
+                if amount <= 0 {
+                    return Err(AppError::UnprocessableEntity("invalid_amount"));
+                }
+                Ok(MailAttachment::Currency { currency, amount })
+            }
+        }
     }
 }
 
 // Implement MailService trait for MailServiceImpl.
 #[async_trait]
 impl<R: MailRepository> MailService for MailServiceImpl<R> {
-    async fn create(
+    async fn create(&self, mail: NewMail, receiver_ids: Vec<Uuid>) -> Result<(), AppError> {
+        let mail = self.checked_mail(mail)?;
+        Ok(self.mail_repository.create(mail, receiver_ids, Utc::now()).await?)
+    }
+
+    async fn broadcast(&self, mail: NewMail, filter: BroadcastFilter) -> Result<(), AppError> {
+        if filter.min_xp.is_some_and(|min_xp| min_xp < 0) {
+            return Err(AppError::UnprocessableEntity("invalid_min_xp"));
+        }
+        let mail = self.checked_mail(mail)?;
+        Ok(self.mail_repository.broadcast(mail, filter, Utc::now()).await?)
+    }
+
+    async fn list(
         &self,
-        mail_id: Uuid,
-        sender_id: Uuid,
-        receiver_ids: Vec<Uuid>,
-        title: String,
-        message: String,
-    ) -> Result<(), sqlx::Error> {
-        self.mail_repository.create(mail_id, sender_id, receiver_ids, title, message, Utc::now()).await
+        user_id: Uuid,
+        filter: MailFilter,
+        cursor: Option<String>,
+        limit: Option<i64>,
+        headers_only: bool,
+    ) -> Result<MailPage, AppError> {
+        let limit = limit.unwrap_or(DEFAULT_MAIL_LIMIT);
+        if !(1..=MAX_MAIL_LIMIT).contains(&limit) {
+            return Err(AppError::UnprocessableEntity("invalid_limit"));
+        }
+        let after = cursor.as_deref().map(decode_cursor).transpose()?;
+
+        // One more than asked tells whether there is a next page.
+        let mut mail = self.mail_repository
+            .list(user_id, filter, after, limit + 1, !headers_only, Utc::now())
+            .await?;
+        let next_cursor = if mail.len() as i64 > limit {
+            mail.truncate(limit as usize);
+            mail.last().map(|last| encode_cursor(last.send_time, last.mail_id))
+        } else {
+            None
+        };
+        Ok(MailPage { mail, next_cursor })
+    }
+
+    async fn unread_count(&self, user_id: Uuid) -> Result<UnreadCount, AppError> {
+        let unread = self.mail_repository.count_unread(user_id, Utc::now()).await?;
+        Ok(UnreadCount { unread })
+    }
+
+    async fn claim(&self, user_id: Uuid, mail_id: Uuid) -> Result<ClaimedAttachments, AppError> {
+        let outcome = self.mail_repository
+            .claim(user_id, mail_id, Utc::now())
+            .await?;
+        match outcome {
+            ClaimOutcome::Claimed(granted) => {
+                if !granted.items.is_empty() {
+                    self.events.publish(GameEvent::InventoryChanged { user_id });
+                }
+                Ok(granted)
+            }
+            ClaimOutcome::NotInMailbox => Err(AppError::NotFound("mail_not_found")),
+            ClaimOutcome::AlreadyClaimed => Err(AppError::Conflict("already_claimed")),
+            ClaimOutcome::NothingToClaim => Err(AppError::UnprocessableEntity("no_attachments")),
+        }
+    }
+
+    async fn delete(&self, user_id: Uuid, mail_id: Uuid) -> Result<(), AppError> {
+        self.mail_repository
+            .delete(user_id, mail_id)
+            .await
+            .map_err(AppError::not_found("mail_not_found"))
+    }
+
+    async fn change_read_state(&self, user_id: Uuid, mail_id: Uuid, read: bool) -> Result<(), AppError> {
+        self.mail_repository
+            .read(user_id, mail_id, read, Utc::now())
+            .await
+            .map_err(AppError::not_found("mail_not_found"))
+    }
+
+    async fn change_archive_state(&self, user_id: Uuid, mail_id: Uuid, archive: bool) -> Result<(), AppError> {
+        self.mail_repository
+            .archive(user_id, mail_id, archive, Utc::now())
+            .await
+            .map_err(AppError::not_found("mail_not_found"))
+    }
+
+    async fn purge(&self) -> Result<MailRetentionReport, AppError> {
+        let now = Utc::now();
+        Ok(self.mail_repository.purge(now, now - self.retention).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{Currency, MailEntry};
+    use chrono::DateTime;
+    use std::sync::Mutex;
+
+    type Claim = fn() -> ClaimOutcome;
+
+    /// Answers every claim with `claim` and remembers the mails that were sent.
+    struct FakeMailRepository {
+        claim: Claim,
+        sent: Mutex<Vec<NewMail>>,
+    }
+
+    #[async_trait]
+    impl MailRepository for FakeMailRepository {
+        async fn create(&self, mail: NewMail, _receiver_ids: Vec<Uuid>, _send_time: DateTime<Utc>) -> Result<(), sqlx::Error> {
+            self.sent.lock().unwrap().push(mail);
+            Ok(())
+        }
+
+        async fn broadcast(&self, mail: NewMail, _filter: BroadcastFilter, _send_time: DateTime<Utc>) -> Result<(), sqlx::Error> {
+            self.sent.lock().unwrap().push(mail);
+            Ok(())
+        }
+
+        async fn claim(&self, _user_id: Uuid, _mail_id: Uuid, _now: DateTime<Utc>) -> Result<ClaimOutcome, sqlx::Error> {
+            Ok((self.claim)())
+        }
+
+        async fn list(
+            &self,
+            _user_id: Uuid,
+            _filter: MailFilter,
+            _after: Option<(DateTime<Utc>, Uuid)>,
+            _limit: i64,
+            _with_message: bool,
+            _now: DateTime<Utc>,
+        ) -> Result<Vec<MailEntry>, sqlx::Error> {
+            Ok(Vec::new())
+        }
+
+        async fn count_unread(&self, _user_id: Uuid, _now: DateTime<Utc>) -> Result<i64, sqlx::Error> {
+            Ok(0)
+        }
+
+        async fn delete(&self, _user_id: Uuid, _mail_id: Uuid) -> Result<(), sqlx::Error> {
+            Ok(())
+        }
+
+        async fn read(&self, _user_id: Uuid, _mail_id: Uuid, _read: bool, _now: DateTime<Utc>) -> Result<(), sqlx::Error> {
+            Ok(())
+        }
+
+        async fn archive(&self, _user_id: Uuid, _mail_id: Uuid, _archived: bool, _now: DateTime<Utc>) -> Result<(), sqlx::Error> {
+            Ok(())
+        }
+
+        async fn purge(&self, _now: DateTime<Utc>, _read_before: DateTime<Utc>) -> Result<MailRetentionReport, sqlx::Error> {
+            Ok(MailRetentionReport::default())
+        }
+    }
+
+    fn service(claim: Claim) -> MailServiceImpl<FakeMailRepository> {
+        let repository = FakeMailRepository { claim, sent: Mutex::new(Vec::new()) };
+        let catalog = ItemCatalog::from_toml(include_str!("../../items.toml")).unwrap();
+        MailServiceImpl::new(repository, Arc::new(catalog), Duration::days(30), Arc::new(EventBus::new()))
+    }
+
+    fn mail(attachments: Vec<MailAttachment>) -> NewMail {
+        NewMail {
+            mail_id: Uuid::new_v4(),
+            sender_id: None,
+            title: "Welcome".to_string(),
+            message: "A gift for you".to_string(),
+            attachments,
+            expires_at: None,
+        }
+    }
+
+    fn hook(state_blob: &str) -> MailAttachment {
+        MailAttachment::Item { definition_id: 0, state_blob: state_blob.to_string() }
+    }
+
+    #[tokio::test]
+    async fn sends_valid_attachments() {
+        let mailer = service(|| ClaimOutcome::NothingToClaim);
+        let coins = MailAttachment::Currency { currency: Currency::Coins, amount: 100 };
+        mailer.create(mail(vec![hook("AQABAAX2////"), coins.clone()]), vec![Uuid::new_v4()]).await.unwrap();
+        assert_eq!(mailer.mail_repository.sent.lock().unwrap()[0].attachments, [hook("AQABAAX2////"), coins]);
     }
 
-    async fn delete(&self, user_id: Uuid, mail_id: Uuid) -> Result<(), sqlx::Error> {
-        self.mail_repository.delete(user_id, mail_id).await
+    #[tokio::test]
+    async fn rejects_invalid_attachments() {
+        let mailer = service(|| ClaimOutcome::NothingToClaim);
+        let mut two_hooks = ItemState::decode("AQABAAX2////").unwrap();
+        two_hooks.quantity = 2;
+        let cases = [
+            (vec![MailAttachment::Item { definition_id: 42, state_blob: "AQABAAX2////".to_string() }], "unknown_definition"),
+            (vec![hook("not base64!")], "invalid_state_blob"),
+            (vec![hook(&two_hooks.encode())], "stack_limit_exceeded"),
+            (vec![MailAttachment::Currency { currency: Currency::Bucks, amount: 0 }], "invalid_amount"),
+            (vec![hook("AQABAAX2////"); MAX_MAIL_ATTACHMENTS + 1], "too_many_attachments"),
+        ];
+        for (attachments, code) in cases {
+            let error = mailer.create(mail(attachments), vec![Uuid::new_v4()]).await.unwrap_err();
+            assert_eq!(error.code(), code);
+        }
+        assert!(mailer.mail_repository.sent.lock().unwrap().is_empty());
     }
 
-    async fn change_read_state(&self, user_id: Uuid, mail_id: Uuid, read: bool) -> Result<(), sqlx::Error> {
-        self.mail_repository.read(user_id, mail_id, read).await
+    #[tokio::test]
+    async fn claim_outcomes_become_error_codes() {
+        let cases: [(Claim, &str); 3] = [
+            (|| ClaimOutcome::NotInMailbox, "mail_not_found"),
+            (|| ClaimOutcome::AlreadyClaimed, "already_claimed"),
+            (|| ClaimOutcome::NothingToClaim, "no_attachments"),
+        ];
+        for (claim, code) in cases {
+            let error = service(claim).claim(Uuid::new_v4(), Uuid::new_v4()).await.unwrap_err();
+            assert_eq!(error.code(), code);
+        }
+
+        let claimed = service(|| ClaimOutcome::Claimed(ClaimedAttachments { items: Vec::new(), coins: 100, bucks: 0 }))
+            .claim(Uuid::new_v4(), Uuid::new_v4())
+            .await
+            .unwrap();
+        assert_eq!((claimed.coins, claimed.bucks), (100, 0));
     }
 
-    async fn change_archive_state(&self, user_id: Uuid, mail_id: Uuid, archive: bool) -> Result<(), sqlx::Error> {
-        self.mail_repository.archive(user_id, mail_id, archive).await
+    #[tokio::test]
+    async fn broadcasts_to_a_segment() {
+        let mailer = service(|| ClaimOutcome::NothingToClaim);
+        let filter = BroadcastFilter { registered_before: Some(Utc::now()), min_xp: Some(0) };
+        mailer.broadcast(mail(vec![hook("AQABAAX2////")]), filter).await.unwrap();
+        mailer.broadcast(mail(Vec::new()), BroadcastFilter::default()).await.unwrap();
+        assert_eq!(mailer.mail_repository.sent.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_broadcasts() {
+        let mailer = service(|| ClaimOutcome::NothingToClaim);
+        let filter = BroadcastFilter { registered_before: None, min_xp: Some(-1) };
+        let error = mailer.broadcast(mail(Vec::new()), filter).await.unwrap_err();
+        assert_eq!(error.code(), "invalid_min_xp");
+
+        let error = mailer.broadcast(mail(vec![hook("not base64!")]), BroadcastFilter::default()).await.unwrap_err();
+        assert_eq!(error.code(), "invalid_state_blob");
+        assert!(mailer.mail_repository.sent.lock().unwrap().is_empty());
     }
 }
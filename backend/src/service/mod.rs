@@ -1,9 +1,17 @@
 /// The service layer is responseble for applying computation to the data from an http request or from the database.
+pub mod achievements;
 pub mod authentication;
 pub mod data;
 pub mod effects;
 pub mod friends;
+pub mod idempotency;
 pub mod inventory;
+pub mod leaderboards;
+pub mod ledger;
 pub mod mail;
+pub mod quests;
+pub mod service_keys;
+pub mod shop;
 pub mod stats;
+pub mod trade;
 pub mod user;
@@ -0,0 +1,114 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use rocket::async_trait;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::domain::{GrantedReward, Quest, QuestList, QuestPeriod};
+use crate::error::AppError;
+use crate::repository::quests::{QuestClaimOutcome, QuestRecord, QuestRepository};
+use crate::utils::events::{EventBus, GameEvent};
+use crate::utils::quests::{next_reset, period_start, QuestPool};
+
+/// Days and weeks start at midnight UTC when QUEST_RESET_UTC_OFFSET is not set.
+pub const DEFAULT_QUEST_RESET_UTC_OFFSET: FixedOffset = match FixedOffset::east_opt(0) {
+    Some(offset) => offset,
+    None => panic!("UTC is a valid offset"),
+};
+
+#[async_trait]
+pub trait QuestService: Send + Sync {
+    /// The quests of the user for the current day and week, picked now when they have none yet.
+    async fn list(&self, user_id: Uuid) -> Result<QuestList, AppError>;
+
+    /// Grants the reward of a completed quest of the current day or week.
+    async fn claim(&self, user_id: Uuid, quest_id: &str) -> Result<GrantedReward, AppError>;
+}
+
+pub struct QuestServiceImpl<R: QuestRepository> {
+    quest_repository: R,
+    quests: Arc<QuestPool>,
+    /// Days and weeks start at midnight at this offset from UTC.
+    reset_offset: FixedOffset,
+    events: Arc<EventBus>,
+}
+
+impl<R: QuestRepository> QuestServiceImpl<R> {
+    pub fn new(quest_repository: R, quests: Arc<QuestPool>, reset_offset: FixedOffset, events: Arc<EventBus>) -> Self {
+        Self { quest_repository, quests, reset_offset, events }
+    }
+
+    /// The quests of the user for the period `now` is in, picks them when there are none yet.
+    /// Catches count for the quests from the moment they are picked, see `advance_quests`.
+    async fn current_quests(&self, user_id: Uuid, period: QuestPeriod, now: DateTime<Utc>) -> Result<Vec<QuestRecord>, AppError> {
+        let start = period_start(period, now, self.reset_offset);
+        if let Some(quests) = self.quest_repository.quests(user_id, period, start).await? {
+            return Ok(quests);
+        }
+
+        let picked = self.quests
+            .pick(period)
+            .into_iter()
+            .map(|quest| (quest.id.clone(), quest.goal.count))
+            .collect();
+        self.quest_repository
+            .assign(user_id, period, start, picked, now)
+            .await
+            .map_err(AppError::not_found("user_not_found"))?;
+        // Read back, a concurrent request may have picked them first.
+        Ok(self.quest_repository.quests(user_id, period, start).await?.unwrap_or_default())
+    }
+}
+
+#[async_trait]
+impl<R: QuestRepository> QuestService for QuestServiceImpl<R> {
+    async fn list(&self, user_id: Uuid) -> Result<QuestList, AppError> {
+        let now = Utc::now();
+        let mut quests = Vec::new();
+        for period in [QuestPeriod::Daily, QuestPeriod::Weekly] {
+            for record in self.current_quests(user_id, period, now).await? {
+                // Quests that were removed from the pool are left out until the period ends.
+                if let Some(definition) = self.quests.get(&record.quest_id) {
+                    quests.push(Quest {
+                        quest_id: record.quest_id,
+                        period,
+                        description: definition.description.clone(),
+                        progress: record.progress,
+                        target: record.target,
+                        completed: record.completed,
+                        claimed: record.claimed,
+                        reward: definition.reward.attachments.clone(),
+                    });
+                }
+            }
+        }
+        Ok(QuestList {
+            quests,
+            next_daily_reset: next_reset(QuestPeriod::Daily, now, self.reset_offset),
+            next_weekly_reset: next_reset(QuestPeriod::Weekly, now, self.reset_offset),
+        })
+    }
+
+    async fn claim(&self, user_id: Uuid, quest_id: &str) -> Result<GrantedReward, AppError> {
+        let definition = self.quests
+            .get(quest_id)
+            .ok_or(AppError::NotFound("quest_not_found"))?;
+        let now = Utc::now();
+        let start = period_start(definition.period, now, self.reset_offset);
+        let outcome = self.quest_repository
+            .claim(user_id, definition.period, start, quest_id, &definition.reward, now)
+            .await?;
+        match outcome {
+            QuestClaimOutcome::Claimed(granted) => {
+                if let GrantedReward::Granted(items) = &granted {
+                    if !items.items.is_empty() {
+                        self.events.publish(GameEvent::InventoryChanged { user_id });
+                    }
+                }
+                Ok(granted)
+            }
+            QuestClaimOutcome::NotFound => Err(AppError::NotFound("quest_not_found")),
+            QuestClaimOutcome::NotCompleted => Err(AppError::UnprocessableEntity("quest_not_completed")),
+            QuestClaimOutcome::AlreadyClaimed => Err(AppError::Conflict("already_claimed")),
+        }
+    }
+}
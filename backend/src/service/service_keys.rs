@@ -0,0 +1,85 @@
+use chrono::Utc;
+use rocket::async_trait;
+use uuid::Uuid;
+
+use crate::domain::{Scope, ServiceKey};
+use crate::error::AppError;
+use crate::repository::service_keys::ServiceKeyRepository;
+use crate::utils::token::{generate_token, hash_token};
+
+/// Every service key starts with this, so it can be told apart from a player JWT.
+pub const SERVICE_KEY_PREFIX: &str = "svc_";
+
+/// business logic for service keys.
+#[async_trait]
+pub trait ServiceKeyService: Send + Sync {
+    /// Creates a key and returns it together with the secret key string.
+    /// The secret is only stored hashed, so it can not be shown again later.
+    async fn create(&self, name: String, scopes: Vec<Scope>) -> Result<(ServiceKey, String), AppError>;
+
+    /// Returns the key when the given key string is valid and not revoked.
+    async fn verify(&self, key: &str) -> Result<Option<ServiceKey>, AppError>;
+
+    async fn list(&self) -> Result<Vec<ServiceKey>, AppError>;
+
+    async fn revoke(&self, key_id: Uuid) -> Result<(), AppError>;
+}
+
+pub struct ServiceKeyServiceImpl<R: ServiceKeyRepository> {
+    service_key_repository: R,
+}
+
+impl<R: ServiceKeyRepository> ServiceKeyServiceImpl<R> {
+    pub fn new(service_key_repository: R) -> Self {
+        Self { service_key_repository }
+    }
+}
+
+#[async_trait]
+impl<R: ServiceKeyRepository> ServiceKeyService for ServiceKeyServiceImpl<R> {
+    async fn create(&self, name: String, scopes: Vec<Scope>) -> Result<(ServiceKey, String), AppError> {
+        let secret = generate_token();
+        let key = ServiceKey {
+            key_id: Uuid::new_v4(),
+            name,
+            scopes,
+            created: Utc::now(),
+            revoked: None,
+        };
+        self.service_key_repository.create(key.clone(), hash_token(&secret)).await?;
+
+        let key_string = format!("{}{}_{}", SERVICE_KEY_PREFIX, key.key_id.simple(), secret);
+        Ok((key, key_string))
+    }
+
+    async fn verify(&self, key: &str) -> Result<Option<ServiceKey>, AppError> {
+        // A key looks like svc_<key id>_<secret>.
+        let (key_id, secret) = match key
+            .strip_prefix(SERVICE_KEY_PREFIX)
+            .and_then(|key| key.split_once('_'))
+        {
+            Some(parts) => parts,
+            None => return Ok(None),
+        };
+        let key_id = match Uuid::parse_str(key_id) {
+            Ok(key_id) => key_id,
+            Err(_) => return Ok(None),
+        };
+
+        match self.service_key_repository.get(key_id).await? {
+            Some((key, key_hash)) if key.revoked.is_none() && key_hash == hash_token(secret) => Ok(Some(key)),
+            _ => Ok(None),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<ServiceKey>, AppError> {
+        Ok(self.service_key_repository.list().await?)
+    }
+
+    async fn revoke(&self, key_id: Uuid) -> Result<(), AppError> {
+        self.service_key_repository
+            .revoke(key_id, Utc::now())
+            .await
+            .map_err(AppError::not_found("service_key_not_found"))
+    }
+}
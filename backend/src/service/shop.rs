@@ -0,0 +1,216 @@
+use chrono::Utc;
+use rocket::async_trait;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::domain::{InventoryItem, ShopOffer};
+use crate::error::AppError;
+use crate::repository::shop::{PurchaseOutcome, ShopRepository};
+use crate::service::inventory::check_quantity;
+use crate::service::stats::insufficient;
+use crate::utils::catalog::ItemCatalog;
+use crate::utils::events::{EventBus, GameEvent};
+use crate::utils::item_state::ItemState;
+
+/// business logic for the shop.
+#[async_trait]
+pub trait ShopService: Send + Sync {
+    async fn add_or_update_offer(&self, offer: ShopOffer) -> Result<(), AppError>;
+
+    /// Returns the offers that can be bought right now.
+    async fn list_available(&self) -> Result<Vec<ShopOffer>, AppError>;
+
+    /// Buys an offer and returns the new item.
+    async fn purchase(&self, user_id: Uuid, offer_id: Uuid) -> Result<InventoryItem, AppError>;
+}
+
+pub struct ShopServiceImpl<R: ShopRepository> {
+    shop_repository: R,
+    catalog: Arc<ItemCatalog>,
+    events: Arc<EventBus>,
+}
+
+impl<R: ShopRepository> ShopServiceImpl<R> {
+    pub fn new(shop_repository: R, catalog: Arc<ItemCatalog>, events: Arc<EventBus>) -> Self {
+        Self { shop_repository, catalog, events }
+    }
+}
+
+#[async_trait]
+impl<R: ShopRepository> ShopService for ShopServiceImpl<R> {
+    async fn add_or_update_offer(&self, offer: ShopOffer) -> Result<(), AppError> {
+        let definition = self.catalog
+            .get(offer.definition_id)
+            .ok_or(AppError::UnprocessableEntity("unknown_definition"))?;
+        let state = ItemState::decode(&offer.state_template)
+            .map_err(|_| AppError::UnprocessableEntity("invalid_state_blob"))?;
+        check_quantity(definition, &state)?;
+        if offer.price <= 0 {
+            return Err(AppError::UnprocessableEntity("invalid_price"));
+        }
+        if offer.purchase_limit.is_some_and(|limit| limit <= 0) {
+            return Err(AppError::UnprocessableEntity("invalid_purchase_limit"));
+        }
+        if let (Some(from), Some(until)) = (offer.available_from, offer.available_until) {
+            if from >= until {
+                return Err(AppError::UnprocessableEntity("invalid_availability_window"));
+            }
+        }
+        Ok(self.shop_repository.add_or_update_offer(offer, Utc::now()).await?)
+    }
+
+    async fn list_available(&self) -> Result<Vec<ShopOffer>, AppError> {
+        Ok(self.shop_repository.list_available(Utc::now()).await?)
+    }
+
+    async fn purchase(&self, user_id: Uuid, offer_id: Uuid) -> Result<InventoryItem, AppError> {
+        let outcome = self.shop_repository
+            .purchase(user_id, offer_id, Uuid::new_v4(), Uuid::new_v4(), Utc::now())
+            .await
+            .map_err(AppError::not_found("user_not_found"))?;
+
+        match outcome {
+            PurchaseOutcome::Purchased(item) => {
+                self.events.publish(GameEvent::InventoryChanged { user_id });
+                Ok(item)
+            }
+            PurchaseOutcome::OfferNotFound => Err(AppError::NotFound("offer_not_found")),
+            PurchaseOutcome::NotAvailable => Err(AppError::UnprocessableEntity("offer_not_available")),
+            PurchaseOutcome::LimitReached => Err(AppError::UnprocessableEntity("purchase_limit_reached")),
+            PurchaseOutcome::Insufficient(currency) => Err(insufficient(currency)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::Currency;
+    use chrono::{DateTime, Duration};
+    use std::sync::Mutex;
+
+    type Outcome = fn() -> Result<PurchaseOutcome, sqlx::Error>;
+
+    /// Answers every purchase with `outcome` and remembers the offers that were saved.
+    struct FakeShopRepository {
+        outcome: Outcome,
+        saved: Mutex<Vec<ShopOffer>>,
+    }
+
+    #[async_trait]
+    impl ShopRepository for FakeShopRepository {
+        async fn add_or_update_offer(&self, offer: ShopOffer, _now: DateTime<Utc>) -> Result<(), sqlx::Error> {
+            self.saved.lock().unwrap().push(offer);
+            Ok(())
+        }
+
+        async fn list_available(&self, _now: DateTime<Utc>) -> Result<Vec<ShopOffer>, sqlx::Error> {
+            Ok(Vec::new())
+        }
+
+        async fn purchase(
+            &self,
+            _user_id: Uuid,
+            _offer_id: Uuid,
+            _purchase_id: Uuid,
+            _item_uuid: Uuid,
+            _now: DateTime<Utc>,
+        ) -> Result<PurchaseOutcome, sqlx::Error> {
+            (self.outcome)()
+        }
+    }
+
+    fn service(outcome: Outcome) -> ShopServiceImpl<FakeShopRepository> {
+        let repository = FakeShopRepository { outcome, saved: Mutex::new(Vec::new()) };
+        let catalog = ItemCatalog::from_toml(include_str!("../../items.toml")).unwrap();
+        ShopServiceImpl::new(repository, Arc::new(catalog), Arc::new(EventBus::new()))
+    }
+
+    fn hook_offer() -> ShopOffer {
+        ShopOffer {
+            offer_id: Uuid::new_v4(),
+            definition_id: 0,
+            state_template: "AQABAAX2////".to_string(),
+            currency: Currency::Coins,
+            price: 100,
+            available_from: None,
+            available_until: None,
+            purchase_limit: Some(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn saves_a_valid_offer() {
+        let shop = service(|| Ok(PurchaseOutcome::OfferNotFound));
+        shop.add_or_update_offer(hook_offer()).await.unwrap();
+        assert_eq!(shop.shop_repository.saved.lock().unwrap().len(), 1);
+    }
+
+    type OfferChange = fn(&mut ShopOffer);
+
+    #[tokio::test]
+    async fn rejects_invalid_offers() {
+        let shop = service(|| Ok(PurchaseOutcome::OfferNotFound));
+        let cases: [(&str, OfferChange); 6] = [
+            ("unknown_definition", |offer| offer.definition_id = 42),
+            ("invalid_state_blob", |offer| offer.state_template = "not base64!".to_string()),
+            ("invalid_price", |offer| offer.price = 0),
+            ("invalid_purchase_limit", |offer| offer.purchase_limit = Some(0)),
+            // A hook stacks only once.
+            ("stack_limit_exceeded", |offer| {
+                let mut state = ItemState::decode(&offer.state_template).unwrap();
+                state.quantity = 2;
+                offer.state_template = state.encode();
+            }),
+            ("invalid_availability_window", |offer| {
+                offer.available_from = Some(DateTime::UNIX_EPOCH);
+                offer.available_until = Some(DateTime::UNIX_EPOCH);
+            }),
+        ];
+        for (code, change) in cases {
+            let mut offer = hook_offer();
+            change(&mut offer);
+            assert_eq!(shop.add_or_update_offer(offer).await.unwrap_err().code(), code);
+        }
+        assert!(shop.shop_repository.saved.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn purchase_outcomes_become_error_codes() {
+        let cases: [(Outcome, &str); 6] = [
+            (|| Err(sqlx::Error::RowNotFound), "user_not_found"),
+            (|| Ok(PurchaseOutcome::OfferNotFound), "offer_not_found"),
+            (|| Ok(PurchaseOutcome::NotAvailable), "offer_not_available"),
+            (|| Ok(PurchaseOutcome::LimitReached), "purchase_limit_reached"),
+            (|| Ok(PurchaseOutcome::Insufficient(Currency::Coins)), "insufficient_coins"),
+            (|| Ok(PurchaseOutcome::Insufficient(Currency::Bucks)), "insufficient_bucks"),
+        ];
+        for (outcome, code) in cases {
+            let result = service(outcome).purchase(Uuid::new_v4(), Uuid::new_v4()).await;
+            assert_eq!(result.unwrap_err().code(), code);
+        }
+    }
+
+    #[tokio::test]
+    async fn purchase_returns_the_bought_item() {
+        let shop = service(|| {
+            Ok(PurchaseOutcome::Purchased(InventoryItem::new(Uuid::nil(), 0, "AQABAAX2////".to_string(), None)))
+        });
+        let item = shop.purchase(Uuid::new_v4(), Uuid::new_v4()).await.unwrap();
+        assert_eq!(item.item_uuid, Uuid::nil());
+        assert_eq!(item.definition_id, 0);
+    }
+
+    #[test]
+    fn availability_window_includes_its_start_and_excludes_its_end() {
+        let now = Utc::now();
+        let mut offer = hook_offer();
+        assert!(offer.is_available(now));
+
+        offer.available_from = Some(now);
+        offer.available_until = Some(now + Duration::hours(1));
+        assert!(offer.is_available(now));
+        assert!(!offer.is_available(now - Duration::seconds(1)));
+        assert!(!offer.is_available(now + Duration::hours(1)));
+    }
+}
@@ -1,63 +1,256 @@
+use chrono::{FixedOffset, Utc};
 use rocket::async_trait;
+use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::{domain::{ItemType, SelectItemRequest, StatFish}, repository::stats::StatsRepository};
+use crate::{
+    domain::{
+        CatchFilter, CatchPage, Currency, CurrencyChange, GrantedReward, ItemKind, ItemType, LevelProgress, QuestPeriod, StatFish, XpGain,
+    },
+    error::AppError,
+    repository::{ledger::BalanceChange, quests::QuestCatch, stats::StatsRepository},
+    utils::catalog::ItemCatalog,
+    utils::events::{EventBus, GameEvent},
+    utils::levels::LevelCurve,
+    utils::quests::{period_start, QuestPool},
+    utils::cursor::{decode_cursor, encode_cursor},
+};
+
+/// How many catches are listed when no limit is given.
+pub const DEFAULT_CATCH_LIMIT: i64 = 50;
+/// The most catches that can be listed at once.
+pub const MAX_CATCH_LIMIT: i64 = 200;
+/// How many months of catch log partitions are created ahead of time.
+pub const CATCH_LOG_MONTHS_AHEAD: i32 = 2;
+
+/// Ledger reason codes are short snake case words, such as `shop_purchase`.
+pub fn valid_reason(reason: &str) -> bool {
+    !reason.is_empty()
+        && reason.len() <= 64
+        && reason.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// The error for a balance that is too low to pay with.
+pub fn insufficient(currency: Currency) -> AppError {
+    match currency {
+        Currency::Coins => AppError::UnprocessableEntity("insufficient_coins"),
+        Currency::Bucks => AppError::UnprocessableEntity("insufficient_bucks"),
+    }
+}
+
+/// Creates the catch log partitions at startup and then once a day, so catches rarely end up in the default partition.
+pub fn spawn_catch_log_partition_job(stats_service: Arc<dyn StatsService>) {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(std::time::Duration::from_secs(24 * 60 * 60));
+        loop {
+            ticks.tick().await;
+            match stats_service.prepare_catch_log().await {
+                Ok(0) => {}
+                Ok(created) => println!("Created {} catch log partitions", created),
+                Err(e) => eprintln!("Could not create catch log partitions: {:?}", e),
+            }
+        }
+    });
+}
 
 // Here you add your business logic here.
 #[async_trait]
 pub trait StatsService: Send + Sync {
-    async fn add_xp(&self, user_id: Uuid, amount: i32) -> Result<(), sqlx::Error>;
+    /// Adds xp, grants the rewards of the levels reached and publishes a `LevelUp` event when the level changed.
+    async fn add_xp(&self, user_id: Uuid, amount: i32) -> Result<XpGain, AppError>;
+
+    async fn level(&self, user_id: Uuid) -> Result<LevelProgress, AppError>;
 
-    async fn change_bucks(&self, user_id: Uuid, amount: i32) -> Result<(), sqlx::Error>;
+    /// Changes coins or bucks and returns the new balance.
+    /// A balance can not become negative, that fails with `insufficient_coins` or `insufficient_bucks`.
+    async fn change_currency(&self, change: CurrencyChange) -> Result<i32, AppError>;
 
-    async fn change_coins(&self, user_id: Uuid, amount: i32) -> Result<(), sqlx::Error>;
+    async fn add_playtime(&self, user_id: Uuid, amount: i32) -> Result<(), AppError>;
 
-    async fn add_playtime(&self, user_id: Uuid, amount: i32) -> Result<(), sqlx::Error>;
+    async fn add_fish(&self, fish: StatFish) -> Result<(), AppError>;
 
-    async fn add_fish(&self, fish: StatFish) -> Result<(), sqlx::Error>;
+    /// Returns a page of the catch log of a user, the latest catch first.
+    /// `cursor` is the `next_cursor` of the previous page.
+    async fn list_catches(
+        &self,
+        user_id: Uuid,
+        filter: CatchFilter,
+        cursor: Option<String>,
+        limit: Option<i64>,
+    ) -> Result<CatchPage, AppError>;
 
-    async fn select_item(&self, select_item: SelectItemRequest) -> Result<(), sqlx::Error>;
+    /// Creates the catch log partitions for the coming months, returns how many were missing.
+    async fn prepare_catch_log(&self) -> Result<i32, AppError>;
+
+    /// Selects an item of the user, `user_id` is the user the caller is allowed to act for.
+    async fn select_item(&self, user_id: Uuid, item_uid: Uuid, item_type: ItemType) -> Result<(), AppError>;
 }
 
 pub struct StatsServiceImpl<T: StatsRepository> {
     stats_repository: T,
+    catalog: Arc<ItemCatalog>,
+    levels: Arc<LevelCurve>,
+    quests: Arc<QuestPool>,
+    /// Days and weeks of quests start at midnight at this offset from UTC.
+    quest_reset_offset: FixedOffset,
+    events: Arc<EventBus>,
 }
 
 impl<R: StatsRepository> StatsServiceImpl<R> {
     // create a new function for StatsServiceImpl.
-    pub fn new(stats_repository: R) -> Self {
-        Self { stats_repository }
+    pub fn new(
+        stats_repository: R,
+        catalog: Arc<ItemCatalog>,
+        levels: Arc<LevelCurve>,
+        quests: Arc<QuestPool>,
+        quest_reset_offset: FixedOffset,
+        events: Arc<EventBus>,
+    ) -> Self {
+        Self { stats_repository, catalog, levels, quests, quest_reset_offset, events }
     }
 }
 
 // Implement StatsService trait for StatsServiceImpl.
 #[async_trait]
 impl<R: StatsRepository> StatsService for StatsServiceImpl<R> {
-    async fn add_xp(&self, user_id: Uuid, amount: i32) -> Result<(), sqlx::Error> {
-        self.stats_repository.add_xp(user_id, amount).await
+    async fn add_xp(&self, user_id: Uuid, amount: i32) -> Result<XpGain, AppError> {
+        if amount < 0 {
+            return Err(AppError::UnprocessableEntity("negative_amount"));
+        }
+        let gain = self.stats_repository
+            .add_xp(user_id, amount, &self.levels, Utc::now())
+            .await
+            .map_err(AppError::not_found("user_not_found"))?;
+        if gain.new_level > gain.old_level {
+            self.events.publish(GameEvent::LevelUp {
+                user_id,
+                old_level: gain.old_level,
+                new_level: gain.new_level,
+            });
+        }
+        let items_granted = gain.rewards.iter().any(|reward| {
+            matches!(reward, GrantedReward::Granted(granted) if !granted.items.is_empty())
+        });
+        if items_granted {
+            self.events.publish(GameEvent::InventoryChanged { user_id });
+        }
+        Ok(gain)
     }
 
-    async fn change_bucks(&self, user_id: Uuid, amount: i32) -> Result<(), sqlx::Error> {
-        self.stats_repository.change_bucks(user_id, amount).await
+    async fn level(&self, user_id: Uuid) -> Result<LevelProgress, AppError> {
+        let xp = self.stats_repository
+            .xp(user_id)
+            .await?
+            .ok_or(AppError::NotFound("user_not_found"))?;
+        Ok(self.levels.progress(xp))
     }
 
-    async fn change_coins(&self, user_id: Uuid, amount: i32) -> Result<(), sqlx::Error> {
-        self.stats_repository.change_coins(user_id, amount).await
+    async fn change_currency(&self, change: CurrencyChange) -> Result<i32, AppError> {
+        if !valid_reason(&change.reason) {
+            return Err(AppError::UnprocessableEntity("invalid_reason"));
+        }
+        let currency = change.currency;
+        let outcome = self.stats_repository
+            .change_currency(change)
+            .await
+            .map_err(AppError::not_found("user_not_found"))?;
+        match outcome {
+            BalanceChange::Applied { balance_after } => Ok(balance_after),
+            BalanceChange::Insufficient => Err(insufficient(currency)),
+        }
     }
 
-    async fn add_playtime(&self, user_id: Uuid, amount: i32) -> Result<(), sqlx::Error> {
-        self.stats_repository.add_playtime(user_id, amount).await
+    async fn add_playtime(&self, user_id: Uuid, amount: i32) -> Result<(), AppError> {
+        if amount < 0 {
+            return Err(AppError::UnprocessableEntity("negative_amount"));
+        }
+        self.stats_repository
+            .add_playtime(user_id, amount)
+            .await
+            .map_err(AppError::not_found("user_not_found"))?;
+        self.events.publish(GameEvent::PlaytimeAdded { user_id });
+        Ok(())
     }
 
-    async fn add_fish(&self, fish: StatFish) -> Result<(), sqlx::Error> {
-        self.stats_repository.add_fish(fish).await
+    async fn add_fish(&self, fish: StatFish) -> Result<(), AppError> {
+        let caught = Utc::now();
+        let quests = QuestCatch {
+            quest_ids: self.quests.matching(&fish),
+            daily_start: period_start(QuestPeriod::Daily, caught, self.quest_reset_offset),
+            weekly_start: period_start(QuestPeriod::Weekly, caught, self.quest_reset_offset),
+        };
+        // Unknown users answered like the foreign key violation they used to cause.
+        self.stats_repository
+            .add_fish(fish.clone(), caught, quests)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => AppError::UnprocessableEntity("unknown_reference"),
+                e => AppError::from(e),
+            })?;
+        self.events.publish(GameEvent::FishCaught { fish, caught });
+        Ok(())
     }
 
-    async fn select_item(&self, item_request: SelectItemRequest) -> Result<(), sqlx::Error> {
-        match item_request.item_type {
-            ItemType::Rod => self.stats_repository.select_rod(item_request.user_id, item_request.item_uid).await,
-            ItemType::Bait => self.stats_repository.select_bait(item_request.user_id, item_request.item_uid).await,
-            ItemType::Extra => unimplemented!(),
+    async fn list_catches(
+        &self,
+        user_id: Uuid,
+        filter: CatchFilter,
+        cursor: Option<String>,
+        limit: Option<i64>,
+    ) -> Result<CatchPage, AppError> {
+        let limit = limit.unwrap_or(DEFAULT_CATCH_LIMIT);
+        if !(1..=MAX_CATCH_LIMIT).contains(&limit) {
+            return Err(AppError::UnprocessableEntity("invalid_limit"));
         }
+        if let (Some(since), Some(until)) = (filter.since, filter.until) {
+            if since >= until {
+                return Err(AppError::UnprocessableEntity("invalid_time_range"));
+            }
+        }
+        let after = cursor.as_deref().map(decode_cursor).transpose()?;
+
+        // One more than asked tells whether there is a next page.
+        let mut catches = self.stats_repository
+            .list_catches(user_id, filter, after, limit + 1)
+            .await?;
+        let next_cursor = if catches.len() as i64 > limit {
+            catches.truncate(limit as usize);
+            catches.last().map(|last| encode_cursor(last.caught, last.catch_id))
+        } else {
+            None
+        };
+        Ok(CatchPage { catches, next_cursor })
+    }
+
+    async fn prepare_catch_log(&self) -> Result<i32, AppError> {
+        Ok(self.stats_repository.create_catch_log_partitions(CATCH_LOG_MONTHS_AHEAD).await?)
+    }
+
+    async fn select_item(&self, user_id: Uuid, item_uid: Uuid, item_type: ItemType) -> Result<(), AppError> {
+        let expected_kind = match item_type {
+            ItemType::Rod => ItemKind::Rod,
+            ItemType::Bait => ItemKind::Bait,
+            ItemType::Extra => return Err(AppError::UnprocessableEntity("item_type_not_selectable")),
+        };
+        let (definition_id, in_escrow) = self.stats_repository
+            .item_definition(user_id, item_uid)
+            .await?
+            .ok_or(AppError::NotFound("item_not_found"))?;
+        if in_escrow {
+            return Err(AppError::Conflict("item_in_escrow"));
+        }
+        let definition = self.catalog
+            .get(definition_id)
+            .ok_or(AppError::UnprocessableEntity("unknown_definition"))?;
+        if definition.kind != expected_kind {
+            return Err(AppError::UnprocessableEntity("wrong_item_kind"));
+        }
+
+        let selected = match item_type {
+            ItemType::Rod => self.stats_repository.select_rod(user_id, item_uid).await,
+            ItemType::Bait => self.stats_repository.select_bait(user_id, item_uid).await,
+            ItemType::Extra => return Err(AppError::UnprocessableEntity("item_type_not_selectable")),
+        };
+        selected.map_err(AppError::not_found("user_not_found"))
     }
 }
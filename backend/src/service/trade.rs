@@ -0,0 +1,324 @@
+use chrono::Utc;
+use rocket::async_trait;
+use std::collections::HashSet;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::domain::{Trade, TradeProposal, TradeStatus, TradeTerms};
+use crate::error::AppError;
+use crate::repository::trade::{TradeOutcome, TradeRepository};
+use crate::utils::events::{EventBus, GameEvent};
+
+/// The most items one side of a trade can have.
+pub const MAX_TRADE_ITEMS: usize = 20;
+/// How many trades are listed when no limit is given.
+pub const DEFAULT_TRADE_LIMIT: i64 = 50;
+/// The most trades that can be listed at once.
+pub const MAX_TRADE_LIMIT: i64 = 200;
+
+/// business logic for trades between players.
+#[async_trait]
+pub trait TradeService: Send + Sync {
+    async fn propose(&self, proposal: TradeProposal) -> Result<Trade, AppError>;
+
+    /// Answers an open trade the user received with one to its proposer, the original trade is closed as countered.
+    async fn counter(&self, trade_id: Uuid, user_id: Uuid, terms: TradeTerms) -> Result<Trade, AppError>;
+
+    async fn accept(&self, trade_id: Uuid, user_id: Uuid) -> Result<Trade, AppError>;
+
+    /// Closes a trade the user received.
+    async fn decline(&self, trade_id: Uuid, user_id: Uuid) -> Result<Trade, AppError>;
+
+    /// Closes a trade the user proposed.
+    async fn cancel(&self, trade_id: Uuid, user_id: Uuid) -> Result<Trade, AppError>;
+
+    /// Returns the open and resolved trades of a user, the newest first.
+    async fn list(&self, user_id: Uuid, limit: Option<i64>) -> Result<Vec<Trade>, AppError>;
+}
+
+pub struct TradeServiceImpl<R: TradeRepository> {
+    trade_repository: R,
+    events: Arc<EventBus>,
+}
+
+impl<R: TradeRepository> TradeServiceImpl<R> {
+    pub fn new(trade_repository: R, events: Arc<EventBus>) -> Self {
+        Self { trade_repository, events }
+    }
+
+    async fn create(&self, proposal: TradeProposal, parent_trade_id: Option<Uuid>) -> Result<Trade, AppError> {
+        validate(&proposal)?;
+        let proposer_id = proposal.proposer_id;
+        let outcome = self.trade_repository
+            .create(proposal, Uuid::new_v4(), parent_trade_id, Utc::now())
+            .await
+            .map_err(AppError::not_found("user_not_found"))?;
+        into_trade(outcome, proposer_id)
+    }
+}
+
+fn validate(proposal: &TradeProposal) -> Result<(), AppError> {
+    if proposal.proposer_id == proposal.recipient_id {
+        return Err(AppError::UnprocessableEntity("cannot_trade_with_self"));
+    }
+    if proposal.terms.offered_coins < 0 || proposal.terms.requested_coins < 0 {
+        return Err(AppError::UnprocessableEntity("invalid_coins"));
+    }
+    if proposal.terms.offered_items.len() > MAX_TRADE_ITEMS || proposal.terms.requested_items.len() > MAX_TRADE_ITEMS {
+        return Err(AppError::UnprocessableEntity("too_many_items"));
+    }
+    if proposal.terms.offered_items.is_empty()
+        && proposal.terms.requested_items.is_empty()
+        && proposal.terms.offered_coins == 0
+        && proposal.terms.requested_coins == 0
+    {
+        return Err(AppError::UnprocessableEntity("empty_trade"));
+    }
+    let mut seen = HashSet::new();
+    if !proposal.terms.offered_items.iter().chain(&proposal.terms.requested_items).all(|item| seen.insert(item)) {
+        return Err(AppError::UnprocessableEntity("duplicate_item"));
+    }
+    Ok(())
+}
+
+/// Turns the outcome into the trade or the error for `user_id`, the player who acted.
+fn into_trade(outcome: TradeOutcome, user_id: Uuid) -> Result<Trade, AppError> {
+    match outcome {
+        TradeOutcome::Done(trade) => Ok(trade),
+        TradeOutcome::TradeNotFound => Err(AppError::NotFound("trade_not_found")),
+        TradeOutcome::NotOpen => Err(AppError::Conflict("trade_not_open")),
+        TradeOutcome::ItemUnavailable(_) => Err(AppError::Conflict("item_unavailable")),
+        TradeOutcome::InsufficientCoins(payer) if payer == user_id => {
+            Err(AppError::UnprocessableEntity("insufficient_coins"))
+        }
+        TradeOutcome::InsufficientCoins(_) => Err(AppError::UnprocessableEntity("other_player_insufficient_coins")),
+    }
+}
+
+#[async_trait]
+impl<R: TradeRepository> TradeService for TradeServiceImpl<R> {
+    async fn propose(&self, proposal: TradeProposal) -> Result<Trade, AppError> {
+        self.create(proposal, None).await
+    }
+
+    async fn counter(&self, trade_id: Uuid, user_id: Uuid, terms: TradeTerms) -> Result<Trade, AppError> {
+        // The repository checks again while the trade is locked.
+        let original = match self.trade_repository.get(trade_id).await? {
+            Some(trade) if trade.recipient_id == user_id => trade,
+            _ => return Err(AppError::NotFound("trade_not_found")),
+        };
+        let proposal = TradeProposal {
+            proposer_id: user_id,
+            recipient_id: original.proposer_id,
+            terms,
+        };
+        self.create(proposal, Some(trade_id)).await
+    }
+
+    async fn accept(&self, trade_id: Uuid, user_id: Uuid) -> Result<Trade, AppError> {
+        let outcome = self.trade_repository
+            .accept(trade_id, user_id, Utc::now())
+            .await
+            .map_err(AppError::not_found("user_not_found"))?;
+        let trade = into_trade(outcome, user_id)?;
+        // Items changed hands when either side offered some.
+        if !trade.offered_items.is_empty() || !trade.requested_items.is_empty() {
+            self.events.publish(GameEvent::InventoryChanged { user_id: trade.proposer_id });
+            self.events.publish(GameEvent::InventoryChanged { user_id: trade.recipient_id });
+        }
+        Ok(trade)
+    }
+
+    async fn decline(&self, trade_id: Uuid, user_id: Uuid) -> Result<Trade, AppError> {
+        let outcome = self.trade_repository
+            .close(trade_id, user_id, TradeStatus::Declined, Utc::now())
+            .await?;
+        into_trade(outcome, user_id)
+    }
+
+    async fn cancel(&self, trade_id: Uuid, user_id: Uuid) -> Result<Trade, AppError> {
+        let outcome = self.trade_repository
+            .close(trade_id, user_id, TradeStatus::Cancelled, Utc::now())
+            .await?;
+        into_trade(outcome, user_id)
+    }
+
+    async fn list(&self, user_id: Uuid, limit: Option<i64>) -> Result<Vec<Trade>, AppError> {
+        let limit = limit.unwrap_or(DEFAULT_TRADE_LIMIT);
+        if !(1..=MAX_TRADE_LIMIT).contains(&limit) {
+            return Err(AppError::UnprocessableEntity("invalid_limit"));
+        }
+        Ok(self.trade_repository.list(user_id, limit).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+    use std::sync::Mutex;
+
+    /// Knows one trade and creates every proposal as an open trade.
+    struct FakeTradeRepository {
+        trade: Trade,
+        created: Mutex<Vec<(TradeProposal, Option<Uuid>)>>,
+    }
+
+    #[async_trait]
+    impl TradeRepository for FakeTradeRepository {
+        async fn create(
+            &self,
+            proposal: TradeProposal,
+            trade_id: Uuid,
+            parent_trade_id: Option<Uuid>,
+            now: DateTime<Utc>,
+        ) -> Result<TradeOutcome, sqlx::Error> {
+            let trade = Trade {
+                trade_id,
+                proposer_id: proposal.proposer_id,
+                recipient_id: proposal.recipient_id,
+                offered_items: Vec::new(),
+                offered_coins: proposal.terms.offered_coins,
+                requested_items: Vec::new(),
+                requested_coins: proposal.terms.requested_coins,
+                status: TradeStatus::Open,
+                parent_trade_id,
+                created: now,
+                resolved: None,
+            };
+            self.created.lock().unwrap().push((proposal, parent_trade_id));
+            Ok(TradeOutcome::Done(trade))
+        }
+
+        async fn accept(&self, _trade_id: Uuid, _recipient_id: Uuid, _now: DateTime<Utc>) -> Result<TradeOutcome, sqlx::Error> {
+            Ok(TradeOutcome::NotOpen)
+        }
+
+        async fn close(
+            &self,
+            _trade_id: Uuid,
+            _user_id: Uuid,
+            _status: TradeStatus,
+            _now: DateTime<Utc>,
+        ) -> Result<TradeOutcome, sqlx::Error> {
+            Ok(TradeOutcome::NotOpen)
+        }
+
+        async fn get(&self, trade_id: Uuid) -> Result<Option<Trade>, sqlx::Error> {
+            Ok(Some(self.trade.clone()).filter(|trade| trade.trade_id == trade_id))
+        }
+
+        async fn list(&self, _user_id: Uuid, _limit: i64) -> Result<Vec<Trade>, sqlx::Error> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn terms(offered_items: Vec<Uuid>, offered_coins: i32, requested_items: Vec<Uuid>, requested_coins: i32) -> TradeTerms {
+        TradeTerms { offered_items, offered_coins, requested_items, requested_coins }
+    }
+
+    fn proposal(terms: TradeTerms) -> TradeProposal {
+        TradeProposal { proposer_id: Uuid::from_u128(1), recipient_id: Uuid::from_u128(2), terms }
+    }
+
+    fn service() -> TradeServiceImpl<FakeTradeRepository> {
+        let trade = Trade {
+            trade_id: Uuid::from_u128(10),
+            proposer_id: Uuid::from_u128(1),
+            recipient_id: Uuid::from_u128(2),
+            offered_items: Vec::new(),
+            offered_coins: 100,
+            requested_items: Vec::new(),
+            requested_coins: 0,
+            status: TradeStatus::Open,
+            parent_trade_id: None,
+            created: DateTime::UNIX_EPOCH,
+            resolved: None,
+        };
+        let repository = FakeTradeRepository { trade, created: Mutex::new(Vec::new()) };
+        TradeServiceImpl::new(repository, Arc::new(EventBus::new()))
+    }
+
+    #[test]
+    fn accepts_valid_proposals() {
+        let item = Uuid::new_v4();
+        assert!(validate(&proposal(terms(vec![item], 0, Vec::new(), 0))).is_ok());
+        assert!(validate(&proposal(terms(Vec::new(), 0, Vec::new(), 5))).is_ok());
+        assert!(validate(&proposal(terms(vec![Uuid::new_v4()], 10, vec![item], 0))).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_proposals() {
+        let item = Uuid::new_v4();
+        let mut with_self = proposal(terms(Vec::new(), 10, Vec::new(), 0));
+        with_self.recipient_id = with_self.proposer_id;
+        let too_many: Vec<Uuid> = (0..=MAX_TRADE_ITEMS).map(|_| Uuid::new_v4()).collect();
+        let cases = [
+            (with_self, "cannot_trade_with_self"),
+            (proposal(terms(Vec::new(), -1, Vec::new(), 0)), "invalid_coins"),
+            (proposal(terms(Vec::new(), 0, Vec::new(), -1)), "invalid_coins"),
+            (proposal(terms(too_many.clone(), 0, Vec::new(), 0)), "too_many_items"),
+            (proposal(terms(Vec::new(), 0, too_many, 0)), "too_many_items"),
+            (proposal(terms(Vec::new(), 0, Vec::new(), 0)), "empty_trade"),
+            (proposal(terms(vec![item, item], 0, Vec::new(), 0)), "duplicate_item"),
+            (proposal(terms(vec![item], 0, vec![item], 0)), "duplicate_item"),
+        ];
+        for (proposal, code) in cases {
+            assert_eq!(validate(&proposal).unwrap_err().code(), code);
+        }
+    }
+
+    #[test]
+    fn insufficient_coins_names_who_can_not_pay() {
+        let user_id = Uuid::new_v4();
+        let error = into_trade(TradeOutcome::InsufficientCoins(user_id), user_id).unwrap_err();
+        assert_eq!(error.code(), "insufficient_coins");
+        let error = into_trade(TradeOutcome::InsufficientCoins(Uuid::new_v4()), user_id).unwrap_err();
+        assert_eq!(error.code(), "other_player_insufficient_coins");
+
+        let cases = [
+            (TradeOutcome::TradeNotFound, "trade_not_found"),
+            (TradeOutcome::NotOpen, "trade_not_open"),
+            (TradeOutcome::ItemUnavailable(Uuid::new_v4()), "item_unavailable"),
+        ];
+        for (outcome, code) in cases {
+            assert_eq!(into_trade(outcome, user_id).unwrap_err().code(), code);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_counter_goes_back_to_the_proposer() {
+        let trades = service();
+        let counter = trades
+            .counter(Uuid::from_u128(10), Uuid::from_u128(2), terms(Vec::new(), 0, Vec::new(), 150))
+            .await
+            .unwrap();
+        assert_eq!(counter.proposer_id, Uuid::from_u128(2));
+        assert_eq!(counter.recipient_id, Uuid::from_u128(1));
+        assert_eq!(counter.parent_trade_id, Some(Uuid::from_u128(10)));
+        assert_eq!(counter.requested_coins, 150);
+    }
+
+    #[tokio::test]
+    async fn only_the_recipient_can_counter() {
+        let trades = service();
+        for (trade_id, user_id) in [(10, 1), (10, 3), (11, 2)] {
+            let error = trades
+                .counter(Uuid::from_u128(trade_id), Uuid::from_u128(user_id), terms(Vec::new(), 5, Vec::new(), 0))
+                .await
+                .unwrap_err();
+            assert_eq!(error.code(), "trade_not_found");
+        }
+        assert!(trades.trade_repository.created.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn list_limit_must_be_in_range() {
+        let trades = service();
+        assert!(trades.list(Uuid::new_v4(), None).await.is_ok());
+        assert!(trades.list(Uuid::new_v4(), Some(MAX_TRADE_LIMIT)).await.is_ok());
+        for limit in [0, MAX_TRADE_LIMIT + 1] {
+            assert_eq!(trades.list(Uuid::new_v4(), Some(limit)).await.unwrap_err().code(), "invalid_limit");
+        }
+    }
+}
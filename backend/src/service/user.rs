@@ -1,59 +1,130 @@
-use crate::domain::{LoginResponse, User};
+use crate::domain::User;
+use crate::error::AppError;
 use crate::repository::user::*;
-use crate::utils::jwt::generate_jwt;
+use crate::service::authentication::verify_password;
+use crate::utils::catalog::ItemCatalog;
+use crate::utils::mailer::Mailer;
+use crate::utils::token::{generate_token, hash_token};
 use bcrypt::hash;
-use chrono::Utc;
+use chrono::{Duration, Utc};
 use rocket::async_trait;
+use std::sync::Arc;
 use uuid::Uuid;
 
 // Here you add your business logic here.
 #[async_trait]
 pub trait UserService: Send + Sync {
+    /// Creates a user and returns its id.
     async fn create(
         &self,
         name: String,
         email: String,
         password: String,
-    ) -> Result<LoginResponse, sqlx::Error>;
+    ) -> Result<Uuid, AppError>;
 
+    /// Mails the username to the given address if an account uses it.
+    /// Always returns true, so the response does not reveal if an account exists.
     async fn retreive_username(
         &self,
         email: String,
-    ) -> Result<bool, sqlx::Error>;
+    ) -> Result<bool, AppError>;
 
+    /// Mails a single use password reset code if an account uses the given address.
+    /// Always returns true, so the response does not reveal if an account exists.
+    async fn request_password_reset(
+        &self,
+        email: String,
+    ) -> Result<bool, AppError>;
+
+    /// Sets a new password when the reset code is valid.
+    async fn reset_password(
+        &self,
+        token: String,
+        new_password: String,
+    ) -> Result<(), AppError>;
+
+    /// Changes the password of the given user when the current password matches.
+    /// All sessions of the user are revoked, so the caller should start a new one.
     async fn change_password(
         &self,
-        name: String,
+        user: User,
+        current_password: String,
         new_password: String,
-    ) -> Result<bool, sqlx::Error>;
+    ) -> Result<(), AppError>;
 
-    async fn from_uuid(&self, user_id: Uuid) -> Result<Option<User>, sqlx::Error>;
+    async fn get_by_uuid(&self, user_id: Uuid) -> Result<Option<User>, AppError>;
 }
 
+/// How long a password reset code can be used.
+const PASSWORD_RESET_VALID_MINUTES: i64 = 30;
+
 pub struct UserServiceImpl<T: UserRepository> {
     user_repository: T,
-    secret_key: String,
+    mailer: Arc<dyn Mailer>,
+    catalog: Arc<ItemCatalog>,
 }
 
 impl<R: UserRepository> UserServiceImpl<R> {
     // create a new function for UserServiceImpl.
-    pub fn new(user_repository: R, secret_key: String) -> Self {
+    pub fn new(user_repository: R, mailer: Arc<dyn Mailer>, catalog: Arc<ItemCatalog>) -> Self {
         Self { 
             user_repository,
-            secret_key 
+            mailer,
+            catalog,
         }
     }
+
+    /// Sends the mail in a background task, so a request that mails takes as long as one that does not.
+    /// Otherwise the response time would reveal which addresses have an account.
+    fn send_mail_in_background(&self, to: String, subject: &'static str, body: String) {
+        let mailer = self.mailer.clone();
+        tokio::spawn(async move {
+            if let Err(e) = mailer.send(&to, subject, &body).await {
+                eprintln!("{}", e);
+            }
+        });
+    }
+}
+
+/// Stores a password reset code for the account that uses the address and mails it, if there is one.
+async fn send_password_reset<R: UserRepository>(
+    user_repository: &R,
+    mailer: &dyn Mailer,
+    email: String,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let user = match user_repository.get_by_email(email).await? {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+
+    let token = generate_token();
+    let now = Utc::now();
+    user_repository
+        .create_password_reset(
+            hash_token(&token),
+            user.user_id,
+            now,
+            now + Duration::minutes(PASSWORD_RESET_VALID_MINUTES),
+        )
+        .await?;
+
+    let body = format!(
+        "Hi {},\n\nUse this code to reset your password: {}\n\nThe code is valid for {} minutes. If you did not request a password reset you can ignore this mail.",
+        user.name, token, PASSWORD_RESET_VALID_MINUTES
+    );
+    mailer.send(&user.email, "Reset your password", &body).await?;
+    Ok(())
 }
 
 // Implement UserService trait for UserServiceImpl.
 #[async_trait]
-impl<R: UserRepository> UserService for UserServiceImpl<R> {
+impl<R: UserRepository + Clone + 'static> UserService for UserServiceImpl<R> {
     async fn create(
         &self,
         name: String,
         email: String,
         password: String,
-    ) -> Result<LoginResponse, sqlx::Error> {
+    ) -> Result<Uuid, AppError> {
         let salt = Uuid::new_v4().to_string();
         let user_id = Uuid::new_v4();
         let user = User {
@@ -63,16 +134,17 @@ impl<R: UserRepository> UserService for UserServiceImpl<R> {
             email,
             password: hash_password(&password, &salt),
             created: Utc::now(),
+            password_changed: None,
         };
 
-        match self.user_repository.create(user).await {
-            Ok(_) => Ok(LoginResponse {
-                code: 200,
-                jwt: generate_jwt(user_id, &self.secret_key)?,
-                }),
+        match self.user_repository.create(user, self.catalog.starter_items()).await {
+            Ok(_) => Ok(user_id),
             Err(e) => {
                 dbg!(&e); 
-                return Err(e);
+                match AppError::from(e) {
+                    AppError::Conflict(_) => Err(AppError::Conflict("name_or_email_taken")),
+                    e => Err(e),
+                }
             }
         }
     }
@@ -80,29 +152,87 @@ impl<R: UserRepository> UserService for UserServiceImpl<R> {
     async fn retreive_username(
         &self,
         email: String,
-    ) -> Result<bool, sqlx::Error> {
-        match self.user_repository.get_username_from_email(email).await {
-            Ok(_) => Ok(true),
-            Err(e) => {
-                dbg!(&e); 
-                return Ok(false);
+    ) -> Result<bool, AppError> {
+        if let Some(username) = self.user_repository.get_username_from_email(email.clone()).await? {
+            let body = format!("The username of your account is: {}", username.name);
+            self.send_mail_in_background(email, "Your username", body);
+        }
+        Ok(true)
+    }
+
+    async fn request_password_reset(
+        &self,
+        email: String,
+    ) -> Result<bool, AppError> {
+        // Looking up the account, storing the code and mailing it all happen in the background,
+        // so the response takes as long whether an account uses the address or not.
+        let user_repository = self.user_repository.clone();
+        let mailer = self.mailer.clone();
+        tokio::spawn(async move {
+            if let Err(e) = send_password_reset(&user_repository, &*mailer, email).await {
+                eprintln!("Could not send a password reset: {}", e);
             }
+        });
+        Ok(true)
+    }
+
+    async fn reset_password(
+        &self,
+        token: String,
+        new_password: String,
+    ) -> Result<(), AppError> {
+        if new_password.is_empty() {
+            return Err(AppError::UnprocessableEntity("empty_password"));
+        }
+
+        let salt = Uuid::new_v4().to_string();
+        let reset = self.user_repository
+            .reset_password(
+                hash_token(&token),
+                hash_password(&new_password, &salt),
+                salt,
+                Utc::now(),
+            )
+            .await?;
+
+        match reset {
+            true => Ok(()),
+            false => Err(AppError::BadRequest("invalid_reset_token")),
         }
     }
 
 
     async fn change_password(
         &self,
-        _name: String,
-        _new_password: String,
-    ) -> Result<bool, sqlx::Error> {
-        Ok(false)
+        user: User,
+        current_password: String,
+        new_password: String,
+    ) -> Result<(), AppError> {
+        if new_password.is_empty() {
+            return Err(AppError::UnprocessableEntity("empty_password"));
+        }
+        if !verify_password(&current_password, &user.salt, &user.password) {
+            return Err(AppError::Forbidden("wrong_password"));
+        }
+
+        // Every password gets its own fresh salt.
+        let salt = Uuid::new_v4().to_string();
+        self.user_repository
+            .update_password(
+                user.user_id,
+                hash_password(&new_password, &salt),
+                salt,
+                Utc::now(),
+            )
+            .await?;
+
+        Ok(())
     }
 
 
-    async fn from_uuid(&self, user_id: Uuid) -> Result<Option<User>, sqlx::Error> {
+    async fn get_by_uuid(&self, user_id: Uuid) -> Result<Option<User>, AppError> {
         // recieve the user from the database given a user_id.
-        self.user_repository.from_uuid(user_id).await
+        Ok(self.user_repository.get_by_uuid(user_id).await?)
     }
 }
 
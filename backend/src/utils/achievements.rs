@@ -0,0 +1,278 @@
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::env;
+use std::fmt;
+use std::fs;
+
+use crate::domain::{ItemKind, Reward};
+use crate::utils::catalog::ItemCatalog;
+use crate::utils::events::GameEvent;
+use crate::utils::levels::LevelCurve;
+use crate::utils::rewards::RewardConfig;
+
+/// The achievements that are used when ACHIEVEMENTS is not set, embedded in the binary.
+const DEFAULT_ACHIEVEMENTS: &str = include_str!("../../achievements.toml");
+
+#[derive(Debug)]
+pub struct AchievementError(pub String);
+
+impl fmt::Display for AchievementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid achievements: {}", self.0)
+    }
+}
+
+impl std::error::Error for AchievementError {}
+
+/// The layout of the achievements file, a list of `[[achievement]]` tables.
+#[derive(Deserialize)]
+struct AchievementFile {
+    #[serde(default)]
+    achievement: Vec<AchievementConfig>,
+}
+
+#[derive(Deserialize)]
+struct AchievementConfig {
+    id: String,
+    name: String,
+    description: String,
+    goal: Goal,
+    #[serde(default)]
+    reward: RewardConfig,
+}
+
+/// What a player has to do for an achievement.
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Goal {
+    FishCaught { count: i32 },
+    SpeciesCaught { count: i32 },
+    SpeciesInEveryArea { fish_id: i32, areas: Vec<i32> },
+    Level { level: i32 },
+    Playtime { amount: i32 },
+    /// Owning `count` items of a kind at the same time.
+    ItemsOwned { kind: ItemKind, count: i32 },
+}
+
+impl Goal {
+    /// The progress at which the achievement unlocks.
+    pub fn target(&self) -> i32 {
+        match self {
+            Goal::FishCaught { count } | Goal::SpeciesCaught { count } | Goal::ItemsOwned { count, .. } => *count,
+            Goal::SpeciesInEveryArea { areas, .. } => areas.len() as i32,
+            Goal::Level { level } => *level,
+            Goal::Playtime { amount } => *amount,
+        }
+    }
+
+    /// Whether the event can change the progress towards the goal.
+    pub fn advanced_by(&self, event: &GameEvent) -> bool {
+        match (self, event) {
+            (Goal::FishCaught { .. } | Goal::SpeciesCaught { .. }, GameEvent::FishCaught { .. }) => true,
+            (Goal::SpeciesInEveryArea { fish_id, areas }, GameEvent::FishCaught { fish, .. }) => {
+                fish.fish_id == *fish_id && areas.contains(&fish.area_id)
+            }
+            (Goal::Level { .. }, GameEvent::LevelUp { .. }) => true,
+            (Goal::Playtime { .. }, GameEvent::PlaytimeAdded { .. }) => true,
+            (Goal::ItemsOwned { .. }, GameEvent::InventoryChanged { .. }) => true,
+            _ => false,
+        }
+    }
+}
+
+pub struct AchievementDefinition {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub goal: Goal,
+    pub reward: Reward,
+}
+
+/// All achievements of the game, in the order of the file.
+pub struct AchievementCatalog {
+    achievements: Vec<AchievementDefinition>,
+}
+
+impl AchievementCatalog {
+    pub fn from_toml(source: &str, catalog: &ItemCatalog, levels: &LevelCurve) -> Result<Self, AchievementError> {
+        let file: AchievementFile = toml::from_str(source).map_err(|e| AchievementError(e.to_string()))?;
+        let mut ids = BTreeSet::new();
+        let mut achievements = Vec::new();
+        for config in file.achievement {
+            let id = config.id;
+            if id.is_empty() || !id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') {
+                return Err(AchievementError(format!("id {:?} is not snake case", id)));
+            }
+            if !ids.insert(id.clone()) {
+                return Err(AchievementError(format!("id {} is used twice", id)));
+            }
+            check_goal(&config.goal, catalog, levels).map_err(|e| AchievementError(format!("{} {}", id, e)))?;
+            let reward = config
+                .reward
+                .into_reward(catalog)
+                .map_err(|e| AchievementError(format!("reward of {} {}", id, e)))?;
+            achievements.push(AchievementDefinition {
+                id,
+                name: config.name,
+                description: config.description,
+                goal: config.goal,
+                reward,
+            });
+        }
+        Ok(Self { achievements })
+    }
+
+    pub fn all(&self) -> &[AchievementDefinition] {
+        &self.achievements
+    }
+
+    /// The achievements the event can bring a player closer to.
+    pub fn advanced_by<'a>(&'a self, event: &'a GameEvent) -> impl Iterator<Item = &'a AchievementDefinition> {
+        self.achievements.iter().filter(move |achievement| achievement.goal.advanced_by(event))
+    }
+}
+
+/// Goals have to be reachable, so they never show progress that can not end in an unlock.
+fn check_goal(goal: &Goal, catalog: &ItemCatalog, levels: &LevelCurve) -> Result<(), String> {
+    match goal {
+        Goal::SpeciesInEveryArea { areas, .. } => {
+            if areas.is_empty() {
+                return Err("has no areas".to_string());
+            }
+            if areas.iter().collect::<BTreeSet<_>>().len() != areas.len() {
+                return Err("lists an area twice".to_string());
+            }
+        }
+        Goal::Level { level } => {
+            if !(2..=levels.max_level()).contains(level) {
+                return Err(format!("needs level {}, outside of 2 to {}", level, levels.max_level()));
+            }
+        }
+        Goal::ItemsOwned { kind, .. } => {
+            if !catalog.definitions().iter().any(|definition| definition.kind == *kind) {
+                return Err(format!("needs {:?} items, the catalog has none", kind));
+            }
+        }
+        Goal::FishCaught { .. } | Goal::SpeciesCaught { .. } | Goal::Playtime { .. } => {}
+    }
+    if goal.target() <= 0 {
+        return Err("has a goal of 0 or less".to_string());
+    }
+    Ok(())
+}
+
+/// Loads the achievements from the TOML file ACHIEVEMENTS points to,
+/// or the achievements in backend/achievements.toml that are build into the binary.
+///
+/// Panics when an achievement is invalid, the backend can not run without them.
+pub fn achievements_from_env(catalog: &ItemCatalog, levels: &LevelCurve) -> AchievementCatalog {
+    match env::var("ACHIEVEMENTS") {
+        Ok(path) => {
+            let source = fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("could not read ACHIEVEMENTS file {:?}: {}", path, e));
+            AchievementCatalog::from_toml(&source, catalog, levels).unwrap_or_else(|e| panic!("{} in {:?}", e, path))
+        }
+        Err(_) => AchievementCatalog::from_toml(DEFAULT_ACHIEVEMENTS, catalog, levels).unwrap_or_else(|e| panic!("{}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::StatFish;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn catalogs() -> (ItemCatalog, LevelCurve) {
+        let catalog = ItemCatalog::from_toml(include_str!("../../items.toml")).unwrap();
+        let levels = LevelCurve::from_toml(include_str!("../../levels.toml"), &catalog).unwrap();
+        (catalog, levels)
+    }
+
+    fn load(source: &str) -> Result<AchievementCatalog, AchievementError> {
+        let (catalog, levels) = catalogs();
+        AchievementCatalog::from_toml(source, &catalog, &levels)
+    }
+
+    fn achievement(id: &str, goal: &str) -> String {
+        format!("[[achievement]]\nid = \"{}\"\nname = \"x\"\ndescription = \"x\"\ngoal = {}\n", id, goal)
+    }
+
+    fn caught(fish_id: i32, area_id: i32) -> GameEvent {
+        let fish = StatFish { user_id: Uuid::new_v4(), fish_id, length: 10, bait_id: 0, area_id };
+        GameEvent::FishCaught { fish, caught: Utc::now() }
+    }
+
+    #[test]
+    fn loads_the_default_achievements_in_file_order() {
+        let achievements = load(DEFAULT_ACHIEVEMENTS).unwrap();
+        let first = &achievements.all()[0];
+        assert_eq!(first.id, "first_catch");
+        assert_eq!(first.goal, Goal::FishCaught { count: 1 });
+        assert_eq!(first.reward.attachments.len(), 1);
+    }
+
+    #[test]
+    fn rejects_invalid_achievements() {
+        let cases = [
+            (achievement("First catch", "{ type = \"fish_caught\", count = 1 }"), "not snake case"),
+            (achievement("", "{ type = \"fish_caught\", count = 1 }"), "not snake case"),
+            (achievement("a", "{ type = \"fish_caught\", count = 1 }").repeat(2), "used twice"),
+            (achievement("a", "{ type = \"fish_caught\", count = 0 }"), "goal of 0 or less"),
+            (achievement("a", "{ type = \"species_in_every_area\", fish_id = 1, areas = [] }"), "has no areas"),
+            (achievement("a", "{ type = \"species_in_every_area\", fish_id = 1, areas = [2, 2] }"), "area twice"),
+            (achievement("a", "{ type = \"level\", level = 1 }"), "needs level 1"),
+            (achievement("a", "{ type = \"level\", level = 51 }"), "needs level 51"),
+            (achievement("a", "{ type = \"items_owned\", kind = \"extra\", count = 1 }"), "the catalog has none"),
+            (achievement("a", "{ type = \"fish_caught\", count = 1 }") + "reward = { items = [42] }\n", "unknown item 42"),
+            (achievement("a", "{ type = \"jump\", count = 1 }"), "unknown variant"),
+        ];
+        for (source, message) in cases {
+            let error = load(&source).err().unwrap();
+            assert!(error.0.contains(message), "{:?} does not contain {:?}", error.0, message);
+        }
+    }
+
+    #[test]
+    fn targets_follow_the_goal() {
+        assert_eq!(Goal::SpeciesInEveryArea { fish_id: 1, areas: vec![1, 2, 3] }.target(), 3);
+        assert_eq!(Goal::Level { level: 10 }.target(), 10);
+        assert_eq!(Goal::ItemsOwned { kind: ItemKind::Rod, count: 2 }.target(), 2);
+    }
+
+    #[test]
+    fn goals_are_only_advanced_by_their_events() {
+        let user_id = Uuid::new_v4();
+        let level_up = GameEvent::LevelUp { user_id, old_level: 1, new_level: 2 };
+        let playtime = GameEvent::PlaytimeAdded { user_id };
+        let inventory = GameEvent::InventoryChanged { user_id };
+
+        assert!(Goal::FishCaught { count: 1 }.advanced_by(&caught(5, 1)));
+        assert!(Goal::SpeciesCaught { count: 1 }.advanced_by(&caught(5, 1)));
+        assert!(!Goal::FishCaught { count: 1 }.advanced_by(&level_up));
+        assert!(Goal::Level { level: 5 }.advanced_by(&level_up));
+        assert!(!Goal::Level { level: 5 }.advanced_by(&playtime));
+        assert!(Goal::Playtime { amount: 5 }.advanced_by(&playtime));
+        assert!(Goal::ItemsOwned { kind: ItemKind::Rod, count: 2 }.advanced_by(&inventory));
+        assert!(!Goal::ItemsOwned { kind: ItemKind::Rod, count: 2 }.advanced_by(&caught(5, 1)));
+
+        let every_area = Goal::SpeciesInEveryArea { fish_id: 1, areas: vec![1, 2] };
+        assert!(every_area.advanced_by(&caught(1, 2)));
+        assert!(!every_area.advanced_by(&caught(1, 3)));
+        assert!(!every_area.advanced_by(&caught(2, 1)));
+    }
+
+    #[test]
+    fn lists_the_achievements_an_event_advances() {
+        let source = achievement("catch", "{ type = \"fish_caught\", count = 1 }")
+            + &achievement("level", "{ type = \"level\", level = 2 }")
+            + &achievement("pike_everywhere", "{ type = \"species_in_every_area\", fish_id = 7, areas = [1, 2] }");
+        let achievements = load(&source).unwrap();
+        let ids = |event| {
+            achievements.advanced_by(&event).map(|achievement| achievement.id.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(caught(7, 1)), ["catch", "pike_everywhere"]);
+        assert_eq!(ids(caught(3, 1)), ["catch"]);
+        assert!(ids(GameEvent::PlaytimeAdded { user_id: Uuid::new_v4() }).is_empty());
+    }
+}
@@ -0,0 +1,165 @@
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+
+use crate::domain::ItemDefinition;
+use crate::utils::item_state::ItemState;
+
+/// The catalog that is used when ITEM_CATALOG is not set, embedded in the binary.
+const DEFAULT_CATALOG: &str = include_str!("../../items.toml");
+
+#[derive(Debug)]
+pub struct CatalogError(pub String);
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid item catalog: {}", self.0)
+    }
+}
+
+impl std::error::Error for CatalogError {}
+
+/// The layout of the catalog file, a list of `[[item]]` tables.
+#[derive(Deserialize)]
+struct CatalogFile {
+    item: Vec<ItemDefinition>,
+}
+
+/// All item definitions of the game by id.
+pub struct ItemCatalog {
+    definitions: BTreeMap<i32, ItemDefinition>,
+    hash: String,
+}
+
+impl ItemCatalog {
+    pub fn new(definitions: Vec<ItemDefinition>) -> Result<Self, CatalogError> {
+        let mut by_id = BTreeMap::new();
+        for definition in definitions {
+            if definition.max_stack == 0 {
+                return Err(CatalogError(format!("item {} has a max_stack of 0", definition.id)));
+            }
+            let state = ItemState::decode(&definition.default_state)
+                .map_err(|e| CatalogError(format!("item {} has a bad default_state: {}", definition.id, e)))?;
+            if state.quantity == 0 || state.quantity > definition.max_stack {
+                return Err(CatalogError(format!(
+                    "item {} has a default_state quantity of {}, outside of 1 to {}",
+                    definition.id, state.quantity, definition.max_stack
+                )));
+            }
+            let id = definition.id;
+            if by_id.insert(id, definition).is_some() {
+                return Err(CatalogError(format!("item id {} is used twice", id)));
+            }
+        }
+
+        // Hashed in id order, so reordering the file does not make clients download it again.
+        let sorted: Vec<&ItemDefinition> = by_id.values().collect();
+        let json = serde_json::to_vec(&sorted).map_err(|e| CatalogError(e.to_string()))?;
+        let hash = format!("{:x}", Sha256::digest(json));
+
+        Ok(Self { definitions: by_id, hash })
+    }
+
+    pub fn from_toml(source: &str) -> Result<Self, CatalogError> {
+        let file: CatalogFile = toml::from_str(source).map_err(|e| CatalogError(e.to_string()))?;
+        Self::new(file.item)
+    }
+
+    pub fn get(&self, id: i32) -> Option<&ItemDefinition> {
+        self.definitions.get(&id)
+    }
+
+    /// All definitions, ordered by id.
+    pub fn definitions(&self) -> Vec<ItemDefinition> {
+        self.definitions.values().cloned().collect()
+    }
+
+    /// The items every new account starts with.
+    pub fn starter_items(&self) -> Vec<ItemDefinition> {
+        self.definitions.values().filter(|definition| definition.starter).cloned().collect()
+    }
+
+    /// sha256 of the definitions, changes whenever any definition changes.
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+}
+
+/// Loads the item catalog from the TOML file ITEM_CATALOG points to,
+/// or the catalog in backend/items.toml that is build into the binary.
+///
+/// Panics when the catalog is invalid, the backend can not run without it.
+pub fn catalog_from_env() -> ItemCatalog {
+    match env::var("ITEM_CATALOG") {
+        Ok(path) => {
+            let source = fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("could not read ITEM_CATALOG file {:?}: {}", path, e));
+            ItemCatalog::from_toml(&source).unwrap_or_else(|e| panic!("{} in {:?}", e, path))
+        }
+        Err(_) => ItemCatalog::from_toml(DEFAULT_CATALOG).unwrap_or_else(|e| panic!("{}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::ItemKind;
+
+    fn definition(id: i32, max_stack: u16, quantity: u16) -> ItemDefinition {
+        let mut state = ItemState::decode("AQABAAX2////").unwrap();
+        state.quantity = quantity;
+        ItemDefinition {
+            id,
+            name: format!("Item {}", id),
+            kind: ItemKind::Bait,
+            max_stack,
+            default_state: state.encode(),
+            starter: false,
+        }
+    }
+
+    #[test]
+    fn loads_the_default_catalog() {
+        let catalog = ItemCatalog::from_toml(DEFAULT_CATALOG).unwrap();
+        assert_eq!(catalog.get(0).map(|definition| definition.name.as_str()), Some("Hook"));
+        assert!(catalog.get(-1).is_none());
+        assert!(catalog.starter_items().iter().all(|definition| definition.starter));
+        assert!(!catalog.starter_items().is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_definitions() {
+        let mut bad_state = definition(1, 1, 1);
+        bad_state.default_state = "not base64!".to_string();
+        let cases = [
+            ("max_stack of 0", vec![definition(1, 0, 1)]),
+            ("bad default_state", vec![bad_state]),
+            ("quantity of 0", vec![definition(1, 5, 0)]),
+            ("quantity of 6", vec![definition(1, 5, 6)]),
+            ("used twice", vec![definition(1, 5, 1), definition(1, 5, 5)]),
+        ];
+        for (message, definitions) in cases {
+            let error = ItemCatalog::new(definitions).err().unwrap();
+            assert!(error.0.contains(message), "{:?} does not contain {:?}", error.0, message);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_kinds() {
+        let source = "[[item]]\nid = 1\nname = \"x\"\nkind = \"boat\"\nmax_stack = 1\ndefault_state = \"AQABAAX2////\"\n";
+        assert!(ItemCatalog::from_toml(source).is_err());
+    }
+
+    #[test]
+    fn hash_ignores_the_order_but_not_the_content() {
+        let catalog = ItemCatalog::new(vec![definition(1, 5, 1), definition(2, 5, 1)]).unwrap();
+        let reordered = ItemCatalog::new(vec![definition(2, 5, 1), definition(1, 5, 1)]).unwrap();
+        let changed = ItemCatalog::new(vec![definition(1, 5, 1), definition(2, 6, 1)]).unwrap();
+        assert_eq!(catalog.hash(), reordered.hash());
+        assert_ne!(catalog.hash(), changed.hash());
+        assert_eq!(catalog.definitions().iter().map(|definition| definition.id).collect::<Vec<_>>(), [1, 2]);
+    }
+}
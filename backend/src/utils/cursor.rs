@@ -0,0 +1,67 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::error::AppError;
+
+/// Encodes the position after the last row of a page, for lists ordered by a time and then a unique key.
+/// Clients pass it back as is, so it is opaque to them.
+pub fn encode_cursor<K: Display>(time: DateTime<Utc>, key: K) -> String {
+    URL_SAFE_NO_PAD.encode(format!("{}:{}", time.timestamp_micros(), key))
+}
+
+/// Reads a cursor made by `encode_cursor`, anything else is a bad request with `invalid_cursor`.
+pub fn decode_cursor<K: FromStr>(cursor: &str) -> Result<(DateTime<Utc>, K), AppError> {
+    let invalid = || AppError::BadRequest("invalid_cursor");
+    let decoded = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+    let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+    let (micros, key) = decoded.split_once(':').ok_or_else(invalid)?;
+    let time = micros
+        .parse()
+        .ok()
+        .and_then(DateTime::from_timestamp_micros)
+        .ok_or_else(invalid)?;
+    let key = key.parse().map_err(|_| invalid())?;
+    Ok((time, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let time = DateTime::from_timestamp_micros(1_792_000_000_123_456).unwrap();
+        let key = Uuid::new_v4();
+        let (decoded_time, decoded_key) = decode_cursor::<Uuid>(&encode_cursor(time, key)).unwrap();
+        assert_eq!((decoded_time, decoded_key), (time, key));
+
+        let (decoded_time, decoded_key) = decode_cursor::<i64>(&encode_cursor(time, -7)).unwrap();
+        assert_eq!((decoded_time, decoded_key), (time, -7));
+    }
+
+    #[test]
+    fn cursors_are_url_safe() {
+        let cursor = encode_cursor(Utc::now(), Uuid::new_v4());
+        assert!(cursor.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        let cursors = [
+            "not base64!".to_string(),
+            URL_SAFE_NO_PAD.encode([0xff, 0xfe]),
+            URL_SAFE_NO_PAD.encode("1792000000123456"),
+            URL_SAFE_NO_PAD.encode("soon:1"),
+            URL_SAFE_NO_PAD.encode(format!("{}:1", i64::MAX)),
+            URL_SAFE_NO_PAD.encode("1792000000123456:not a uuid"),
+            String::new(),
+        ];
+        for cursor in cursors {
+            assert_eq!(decode_cursor::<Uuid>(&cursor).unwrap_err().code(), "invalid_cursor", "{:?}", cursor);
+        }
+    }
+}
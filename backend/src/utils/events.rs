@@ -0,0 +1,55 @@
+use chrono::{DateTime, Utc};
+use std::sync::Mutex;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use uuid::Uuid;
+
+use crate::domain::StatFish;
+
+/// Something that happened to a player, published after it was committed.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum GameEvent {
+    FishCaught { fish: StatFish, caught: DateTime<Utc> },
+    LevelUp { user_id: Uuid, old_level: i32, new_level: i32 },
+    PlaytimeAdded { user_id: Uuid },
+    /// Items were added to or removed from the inventory.
+    InventoryChanged { user_id: Uuid },
+}
+
+impl GameEvent {
+    /// The player the event happened to.
+    pub fn user_id(&self) -> Uuid {
+        match self {
+            GameEvent::FishCaught { fish, .. } => fish.user_id,
+            GameEvent::LevelUp { user_id, .. }
+            | GameEvent::PlaytimeAdded { user_id }
+            | GameEvent::InventoryChanged { user_id } => *user_id,
+        }
+    }
+}
+
+/// Hands every published event to every subscriber, in order.
+///
+/// Events only live in memory, a subscriber that needs them to be durable has to record them itself.
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<UnboundedSender<GameEvent>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribe before the server starts, events published earlier are not replayed.
+    pub fn subscribe(&self) -> UnboundedReceiver<GameEvent> {
+        let (sender, receiver) = unbounded_channel();
+        self.subscribers.lock().unwrap_or_else(|e| e.into_inner()).push(sender);
+        receiver
+    }
+
+    pub fn publish(&self, event: GameEvent) {
+        let mut subscribers = self.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+        // Subscribers that dropped their receiver are forgotten.
+        subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
@@ -0,0 +1,151 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use utoipa::ToSchema;
+
+/// The only layout of a state blob there is so far.
+pub const STATE_VERSION: u8 = 1;
+
+/// Length of a version 1 state blob in bytes.
+const STATE_LENGTH: usize = 9;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct StateError(pub String);
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid state blob: {}", self.0)
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// The decoded `state_blob` of an inventory item.
+///
+/// The blob is the base64 of these bytes, multi byte numbers in little endian:
+///
+/// | byte | field      | type |
+/// |------|------------|------|
+/// | 0    | version    | u8   |
+/// | 1    | flags      | u8   |
+/// | 2-3  | quantity   | u16  |
+/// | 4    | tier       | u8   |
+/// | 5-8  | durability | i32  |
+///
+/// The only blob known to be written by the game client is the starter state `AQABAAX2////`:
+/// version 1, no flags, quantity 1, tier 5 and unbreakable. The layout is read from that blob, not from
+/// the client's serializer, so every state the server rewrites relies on it. Check blobs the client writes
+/// for other items against it and add them to `CLIENT_STATES` in the tests.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, ToSchema)]
+pub struct ItemState {
+    pub version: u8,
+    /// Bits the game client sets, the backend keeps them as they are.
+    pub flags: u8,
+    pub quantity: u16,
+    pub tier: u8,
+    /// Uses left before the item breaks, negative for items that never break.
+    pub durability: i32,
+}
+
+impl ItemState {
+    pub fn decode(blob: &str) -> Result<Self, StateError> {
+        let bytes = STANDARD
+            .decode(blob)
+            .map_err(|e| StateError(format!("not base64: {}", e)))?;
+
+        match bytes.first() {
+            None => return Err(StateError("empty".to_string())),
+            Some(&STATE_VERSION) => {}
+            Some(version) => return Err(StateError(format!("unknown version {}", version))),
+        }
+        if bytes.len() != STATE_LENGTH {
+            return Err(StateError(format!(
+                "expected {} bytes for version {}, got {}",
+                STATE_LENGTH,
+                STATE_VERSION,
+                bytes.len()
+            )));
+        }
+
+        Ok(Self {
+            version: bytes[0],
+            flags: bytes[1],
+            quantity: u16::from_le_bytes([bytes[2], bytes[3]]),
+            tier: bytes[4],
+            durability: i32::from_le_bytes([bytes[5], bytes[6], bytes[7], bytes[8]]),
+        })
+    }
+
+    pub fn encode(&self) -> String {
+        let mut bytes = Vec::with_capacity(STATE_LENGTH);
+        bytes.push(self.version);
+        bytes.push(self.flags);
+        bytes.extend_from_slice(&self.quantity.to_le_bytes());
+        bytes.push(self.tier);
+        bytes.extend_from_slice(&self.durability.to_le_bytes());
+        STANDARD.encode(bytes)
+    }
+
+    /// Checks what `decode` can not, for states that are build from JSON.
+    pub fn validate(&self) -> Result<(), StateError> {
+        if self.version != STATE_VERSION {
+            return Err(StateError(format!("unknown version {}", self.version)));
+        }
+        Ok(())
+    }
+
+    pub fn is_unbreakable(&self) -> bool {
+        self.durability < 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_then_decode_gives_the_same_state() {
+        let state = ItemState { version: STATE_VERSION, flags: 0b1010, quantity: 300, tier: 2, durability: 75 };
+        assert_eq!(ItemState::decode(&state.encode()), Ok(state));
+    }
+
+    /// Blobs written by the game client, with the state they hold.
+    const CLIENT_STATES: [(&str, ItemState); 1] = [
+        // The starter items in backend/items.toml.
+        ("AQABAAX2////", ItemState { version: 1, flags: 0, quantity: 1, tier: 5, durability: -10 }),
+    ];
+
+    #[test]
+    fn decodes_and_keeps_client_states() {
+        for (blob, expected) in CLIENT_STATES {
+            let state = ItemState::decode(blob).unwrap();
+            assert_eq!(state, expected, "{}", blob);
+            // The server writes back exactly what the client wrote.
+            assert_eq!(state.encode(), blob);
+        }
+        assert!(ItemState::decode("AQABAAX2////").unwrap().is_unbreakable());
+    }
+
+    #[test]
+    fn rejects_invalid_base64() {
+        assert!(ItemState::decode("not base64!").is_err());
+    }
+
+    #[test]
+    fn rejects_the_wrong_length() {
+        // Version 1 followed by 7 instead of 8 bytes.
+        let short = STANDARD.encode([1, 0, 1, 0, 5, 0, 0, 0]);
+        assert!(ItemState::decode(&short).is_err());
+        assert!(ItemState::decode("").is_err());
+    }
+
+    #[test]
+    fn rejects_an_unknown_version() {
+        let version_2 = STANDARD.encode([2, 0, 1, 0, 5, 0, 0, 0, 0]);
+        assert_eq!(ItemState::decode(&version_2), Err(StateError("unknown version 2".to_string())));
+
+        let state = ItemState { version: 2, flags: 0, quantity: 1, tier: 0, durability: 0 };
+        assert!(state.validate().is_err());
+    }
+}
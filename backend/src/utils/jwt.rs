@@ -1,35 +1,41 @@
+use crate::utils::keys::KeySet;
 use chrono::{Duration, Utc};
-use jsonwebtoken::{encode, EncodingKey, Header};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+/// How long an access token (JWT) is valid. Clients use their refresh token to get a new one.
+pub const ACCESS_TOKEN_MINUTES: i64 = 15;
+
+/// How long a session stays valid without being refreshed.
+pub const SESSION_VALID_DAYS: i64 = 30;
+
 /// Claims are encoded in the JWT.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub user_id: String,
+    pub sid: String, // The session this token belongs to
     pub exp: usize, // Expiration time (as a timestamp)
+    pub iat: usize, // Issued at (as a timestamp)
 }
 
 
-pub fn generate_jwt(user_id: Uuid, secret_key: &str) -> Result<String, sqlx::Error> {
+pub fn generate_jwt(user_id: Uuid, session_id: Uuid, keys: &KeySet) -> Result<String, sqlx::Error> {
     // calculate experation time.
-    let expiration = Utc::now()
-        .checked_add_signed(Duration::hours(24))
+    let now = Utc::now();
+    let expiration = now
+        .checked_add_signed(Duration::minutes(ACCESS_TOKEN_MINUTES))
         .expect("Invalid time")
         .timestamp() as usize;
 
     let claims = Claims {
         user_id: user_id.to_string(),
+        sid: session_id.to_string(),
         exp: expiration,
+        iat: now.timestamp() as usize,
     };
 
-    // generate jwt
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret_key.as_bytes()),
-    )
-    .expect("JWT creation failed");
+    // generate jwt, signed with the active key of the key set.
+    let token = keys.encode(&claims).expect("JWT creation failed");
 
     Ok(token)
-}
\ No newline at end of file
+}
@@ -0,0 +1,218 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+    OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use ring::signature::{Ed25519KeyPair, KeyPair, RsaKeyPair, RsaPublicKeyComponents};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::fs;
+
+/// Key id of the key made from `SECRET_KEY` when no key set is configured.
+pub const DEFAULT_KID: &str = "default";
+
+#[derive(Debug)]
+pub struct KeyError(pub String);
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid jwt key: {}", self.0)
+    }
+}
+
+impl std::error::Error for KeyError {}
+
+/// A key for signing and verifying JWT's, identified by its `kid`.
+pub struct JwtKey {
+    pub kid: String,
+    algorithm: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    /// The public part of the key, None for shared secrets since those can not be published.
+    jwk: Option<Jwk>,
+}
+
+impl JwtKey {
+    /// A shared secret, only this backend can verify tokens signed with it.
+    pub fn hs256(kid: &str, secret: &str) -> Self {
+        Self {
+            kid: kid.to_string(),
+            algorithm: Algorithm::HS256,
+            encoding: EncodingKey::from_secret(secret.as_bytes()),
+            decoding: DecodingKey::from_secret(secret.as_bytes()),
+            jwk: None,
+        }
+    }
+
+    /// An Ed25519 private key in PKCS#8 PEM format.
+    pub fn eddsa_from_pem(kid: &str, private_key: &[u8]) -> Result<Self, KeyError> {
+        let der = pem::parse(private_key).map_err(|e| KeyError(e.to_string()))?;
+        let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(der.contents())
+            .map_err(|e| KeyError(e.to_string()))?;
+        let x = URL_SAFE_NO_PAD.encode(key_pair.public_key().as_ref());
+
+        Ok(Self {
+            kid: kid.to_string(),
+            algorithm: Algorithm::EdDSA,
+            encoding: EncodingKey::from_ed_der(der.contents()),
+            decoding: DecodingKey::from_ed_components(&x).map_err(|e| KeyError(e.to_string()))?,
+            jwk: Some(public_jwk(
+                kid,
+                KeyAlgorithm::EdDSA,
+                AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x,
+                }),
+            )),
+        })
+    }
+
+    /// An RSA private key in PKCS#1 or PKCS#8 PEM format.
+    pub fn rs256_from_pem(kid: &str, private_key: &[u8]) -> Result<Self, KeyError> {
+        let der = pem::parse(private_key).map_err(|e| KeyError(e.to_string()))?;
+        let key_pair = match der.tag() {
+            "RSA PRIVATE KEY" => RsaKeyPair::from_der(der.contents()),
+            _ => RsaKeyPair::from_pkcs8(der.contents()),
+        }
+        .map_err(|e| KeyError(e.to_string()))?;
+        let public = RsaPublicKeyComponents::<Vec<u8>>::from(key_pair.public());
+
+        Ok(Self {
+            kid: kid.to_string(),
+            algorithm: Algorithm::RS256,
+            encoding: EncodingKey::from_rsa_pem(private_key).map_err(|e| KeyError(e.to_string()))?,
+            decoding: DecodingKey::from_rsa_raw_components(&public.n, &public.e),
+            jwk: Some(public_jwk(
+                kid,
+                KeyAlgorithm::RS256,
+                AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n: URL_SAFE_NO_PAD.encode(&public.n),
+                    e: URL_SAFE_NO_PAD.encode(&public.e),
+                }),
+            )),
+        })
+    }
+}
+
+fn public_jwk(kid: &str, algorithm: KeyAlgorithm, parameters: AlgorithmParameters) -> Jwk {
+    Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(algorithm),
+            key_id: Some(kid.to_string()),
+            ..Default::default()
+        },
+        algorithm: parameters,
+    }
+}
+
+/// All keys that JWT's are accepted from, one of them is used for signing new tokens.
+///
+/// To rotate, add a new key and make it active. Keep the old key in the set until
+/// the tokens signed with it have expired, then remove it.
+pub struct KeySet {
+    active: usize,
+    keys: Vec<JwtKey>,
+}
+
+impl KeySet {
+    pub fn new(active_kid: &str, keys: Vec<JwtKey>) -> Result<Self, KeyError> {
+        for (i, key) in keys.iter().enumerate() {
+            if keys[..i].iter().any(|other| other.kid == key.kid) {
+                return Err(KeyError(format!("kid {:?} is used more than once", key.kid)));
+            }
+        }
+        let active = keys
+            .iter()
+            .position(|key| key.kid == active_kid)
+            .ok_or_else(|| KeyError(format!("active kid {:?} is not in the key set", active_kid)))?;
+
+        Ok(Self { active, keys })
+    }
+
+    /// Signs the claims with the active key, its kid is set in the header.
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String, jsonwebtoken::errors::Error> {
+        let key = &self.keys[self.active];
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(key.kid.clone());
+        encode(&header, claims, &key.encoding)
+    }
+
+    /// Verifies a token with the key named in its header.
+    /// Returns None when the token is invalid, expired or signed by an unknown key.
+    pub fn decode<T: DeserializeOwned>(&self, token: &str) -> Option<T> {
+        let kid = decode_header(token).ok()?.kid?;
+        let key = self.keys.iter().find(|key| key.kid == kid)?;
+
+        // Only the algorithm of the key is accepted, the header can not pick another one.
+        decode::<T>(token, &key.decoding, &Validation::new(key.algorithm))
+            .map(|data| data.claims)
+            .ok()
+    }
+
+    /// The public keys, so other services can verify tokens without the secret.
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self.keys.iter().filter_map(|key| key.jwk.clone()).collect(),
+        }
+    }
+}
+
+/// The key set file, see `keys_from_env`.
+#[derive(Debug, Deserialize)]
+struct KeySetConfig {
+    active: String,
+    keys: Vec<KeyConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "algorithm")]
+enum KeyConfig {
+    HS256 { kid: String, secret: String },
+    EdDSA { kid: String, private_key_file: String },
+    RS256 { kid: String, private_key_file: String },
+}
+
+fn read_key_file(path: &str) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|e| panic!("could not read key file {:?}: {}", path, e))
+}
+
+/// Reads the key set from the JSON file in `JWT_KEYS`.
+/// Without it, `SECRET_KEY` is used as the only key (HS256).
+pub fn keys_from_env() -> KeySet {
+    let path = match env::var("JWT_KEYS") {
+        Ok(path) => path,
+        Err(_) => {
+            let secret = env::var("SECRET_KEY")
+                .expect("SECRET_KEY or JWT_KEYS must be set for generating JWT");
+            return KeySet::new(DEFAULT_KID, vec![JwtKey::hs256(DEFAULT_KID, &secret)])
+                .expect("Failed to create key set");
+        }
+    };
+
+    let config: KeySetConfig = serde_json::from_slice(&read_key_file(&path))
+        .unwrap_or_else(|e| panic!("could not parse JWT_KEYS file {:?}: {}", path, e));
+
+    let keys = config
+        .keys
+        .into_iter()
+        .map(|key| match key {
+            KeyConfig::HS256 { kid, secret } => Ok(JwtKey::hs256(&kid, &secret)),
+            KeyConfig::EdDSA { kid, private_key_file } => {
+                JwtKey::eddsa_from_pem(&kid, &read_key_file(&private_key_file))
+            }
+            KeyConfig::RS256 { kid, private_key_file } => {
+                JwtKey::rs256_from_pem(&kid, &read_key_file(&private_key_file))
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_else(|e| panic!("{}", e));
+
+    KeySet::new(&config.active, keys).unwrap_or_else(|e| panic!("{}", e))
+}
@@ -0,0 +1,208 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+
+use crate::domain::{LevelProgress, Reward};
+use crate::utils::catalog::ItemCatalog;
+use crate::utils::rewards::RewardConfig;
+
+/// The curve that is used when LEVEL_CURVE is not set, embedded in the binary.
+const DEFAULT_LEVELS: &str = include_str!("../../levels.toml");
+
+#[derive(Debug)]
+pub struct LevelError(pub String);
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid level curve: {}", self.0)
+    }
+}
+
+impl std::error::Error for LevelError {}
+
+/// The layout of the level file, a `[curve]` table and a list of `[[reward]]` tables.
+#[derive(Deserialize)]
+struct LevelFile {
+    curve: CurveConfig,
+    #[serde(default)]
+    reward: Vec<LevelRewardConfig>,
+}
+
+#[derive(Deserialize)]
+struct CurveConfig {
+    base: f64,
+    growth: f64,
+    max_level: i32,
+}
+
+#[derive(Deserialize)]
+struct LevelRewardConfig {
+    level: i32,
+    #[serde(flatten)]
+    reward: RewardConfig,
+}
+
+/// How much xp every level needs, and what players get for reaching it.
+pub struct LevelCurve {
+    /// The total xp needed for level `index + 1`, so the first entry is 0.
+    thresholds: Vec<i32>,
+    rewards: BTreeMap<i32, Reward>,
+}
+
+impl LevelCurve {
+    pub fn new(base: f64, growth: f64, max_level: i32, rewards: BTreeMap<i32, Reward>) -> Result<Self, LevelError> {
+        if !base.is_finite() || !growth.is_finite() || base < 1.0 || growth < 1.0 {
+            return Err(LevelError(format!("base {} and growth {} must be at least 1", base, growth)));
+        }
+        if max_level < 1 {
+            return Err(LevelError(format!("max_level {} is below 1", max_level)));
+        }
+        let mut thresholds = vec![0];
+        let mut total: i64 = 0;
+        for level in 1..max_level {
+            total += (base * growth.powi(level - 1)).round() as i64;
+            let total = i32::try_from(total)
+                .map_err(|_| LevelError(format!("level {} needs more xp than fits in the stats", level + 1)))?;
+            thresholds.push(total);
+        }
+        if let Some(level) = rewards.keys().find(|level| !(2..=max_level).contains(*level)) {
+            return Err(LevelError(format!("reward for level {} outside of 2 to {}", level, max_level)));
+        }
+        Ok(Self { thresholds, rewards })
+    }
+
+    pub fn from_toml(source: &str, catalog: &ItemCatalog) -> Result<Self, LevelError> {
+        let file: LevelFile = toml::from_str(source).map_err(|e| LevelError(e.to_string()))?;
+        let mut rewards = BTreeMap::new();
+        for config in file.reward {
+            let level = config.level;
+            let reward = config
+                .reward
+                .into_reward(catalog)
+                .map_err(|e| LevelError(format!("reward for level {} {}", level, e)))?;
+            if rewards.insert(level, reward).is_some() {
+                return Err(LevelError(format!("level {} has two rewards", level)));
+            }
+        }
+        Self::new(file.curve.base, file.curve.growth, file.curve.max_level, rewards)
+    }
+
+    pub fn max_level(&self) -> i32 {
+        self.thresholds.len() as i32
+    }
+
+    pub fn level(&self, xp: i32) -> i32 {
+        self.thresholds.partition_point(|threshold| *threshold <= xp) as i32
+    }
+
+    pub fn progress(&self, xp: i32) -> LevelProgress {
+        let level = self.level(xp);
+        LevelProgress {
+            level,
+            xp,
+            xp_to_next_level: self.thresholds.get(level as usize).map(|next| next - xp),
+        }
+    }
+
+    /// The rewards of the levels after `old_level` up to and including `new_level`, lowest level first.
+    pub fn rewards_between(&self, old_level: i32, new_level: i32) -> impl Iterator<Item = (i32, &Reward)> {
+        self.rewards
+            .range(old_level + 1..)
+            .take_while(move |(level, _)| **level <= new_level)
+            .map(|(level, reward)| (*level, reward))
+    }
+}
+
+/// Loads the level curve from the TOML file LEVEL_CURVE points to,
+/// or the curve in backend/levels.toml that is build into the binary.
+///
+/// Panics when the curve is invalid, the backend can not run without it.
+pub fn levels_from_env(catalog: &ItemCatalog) -> LevelCurve {
+    match env::var("LEVEL_CURVE") {
+        Ok(path) => {
+            let source = fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("could not read LEVEL_CURVE file {:?}: {}", path, e));
+            LevelCurve::from_toml(&source, catalog).unwrap_or_else(|e| panic!("{} in {:?}", e, path))
+        }
+        Err(_) => LevelCurve::from_toml(DEFAULT_LEVELS, catalog).unwrap_or_else(|e| panic!("{}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catalog() -> ItemCatalog {
+        ItemCatalog::from_toml(include_str!("../../items.toml")).unwrap()
+    }
+
+    fn flat_curve(max_level: i32, reward_levels: &[i32]) -> Result<LevelCurve, LevelError> {
+        let rewards = reward_levels.iter().map(|level| (*level, Reward::default())).collect();
+        LevelCurve::new(100.0, 1.0, max_level, rewards)
+    }
+
+    #[test]
+    fn every_level_needs_growth_times_the_one_before() {
+        let curve = LevelCurve::from_toml(DEFAULT_LEVELS, &catalog()).unwrap();
+        assert_eq!(curve.max_level(), 50);
+        // 100 xp to level 2, 115 more to level 3, 132 more to level 4.
+        let cases = [(0, 1), (99, 1), (100, 2), (214, 2), (215, 3), (346, 3), (347, 4), (i32::MAX, 50)];
+        for (xp, level) in cases {
+            assert_eq!(curve.level(xp), level, "xp {}", xp);
+        }
+    }
+
+    #[test]
+    fn progress_counts_down_to_the_next_level() {
+        let curve = flat_curve(3, &[]).unwrap();
+        assert_eq!(curve.progress(0), LevelProgress { level: 1, xp: 0, xp_to_next_level: Some(100) });
+        assert_eq!(curve.progress(150), LevelProgress { level: 2, xp: 150, xp_to_next_level: Some(50) });
+        assert_eq!(curve.progress(200), LevelProgress { level: 3, xp: 200, xp_to_next_level: None });
+        assert_eq!(curve.progress(5000), LevelProgress { level: 3, xp: 5000, xp_to_next_level: None });
+    }
+
+    #[test]
+    fn rewards_between_includes_every_level_crossed() {
+        let curve = flat_curve(10, &[2, 5, 10]).unwrap();
+        let levels = |old_level, new_level| {
+            curve.rewards_between(old_level, new_level).map(|(level, _)| level).collect::<Vec<_>>()
+        };
+        assert_eq!(levels(1, 10), [2, 5, 10]);
+        assert_eq!(levels(1, 4), [2]);
+        assert_eq!(levels(2, 5), [5]);
+        assert_eq!(levels(4, 9), [5]);
+        assert!(levels(5, 5).is_empty());
+        assert!(levels(10, 3).is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_curves() {
+        let cases = [
+            (LevelCurve::new(0.5, 1.1, 10, BTreeMap::new()), "must be at least 1"),
+            (LevelCurve::new(100.0, f64::NAN, 10, BTreeMap::new()), "must be at least 1"),
+            (LevelCurve::new(100.0, 1.1, 0, BTreeMap::new()), "below 1"),
+            (LevelCurve::new(100.0, 10.0, 50, BTreeMap::new()), "more xp than fits"),
+            (flat_curve(10, &[1]), "reward for level 1 outside"),
+            (flat_curve(10, &[11]), "reward for level 11 outside"),
+        ];
+        for (curve, message) in cases {
+            let error = curve.err().unwrap();
+            assert!(error.0.contains(message), "{:?} does not contain {:?}", error.0, message);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_rewards_in_the_file() {
+        let curve = "[curve]\nbase = 100\ngrowth = 1.1\nmax_level = 10\n";
+        let cases = [
+            ("[[reward]]\nlevel = 2\ncoins = 5\n[[reward]]\nlevel = 2\nbucks = 1\n", "two rewards"),
+            ("[[reward]]\nlevel = 3\nitems = [42]\n", "unknown item 42"),
+        ];
+        for (rewards, message) in cases {
+            let error = LevelCurve::from_toml(&format!("{}{}", curve, rewards), &catalog()).err().unwrap();
+            assert!(error.0.contains(message), "{:?} does not contain {:?}", error.0, message);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use rocket::async_trait;
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+
+#[derive(Debug)]
+pub struct MailerError(pub String);
+
+impl fmt::Display for MailerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "could not send mail: {}", self.0)
+    }
+}
+
+impl std::error::Error for MailerError {}
+
+/// Sends emails to players, for example their username or a password reset code.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), MailerError>;
+}
+
+/// Writes mails to a file, or to stdout when no file is given.
+/// Useful for local development where no mail server is running.
+pub struct LogMailer {
+    path: Option<PathBuf>,
+}
+
+impl LogMailer {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self { path }
+    }
+}
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), MailerError> {
+        let mail = format!("To: {}\nSubject: {}\n\n{}\n---\n", to, subject, body);
+        match &self.path {
+            None => {
+                println!("{}", mail);
+                Ok(())
+            }
+            Some(path) => {
+                let mut file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .map_err(|e| MailerError(e.to_string()))?;
+                file.write_all(mail.as_bytes())
+                    .await
+                    .map_err(|e| MailerError(e.to_string()))
+            }
+        }
+    }
+}
+
+/// Sends mails through an SMTP server.
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(transport: AsyncSmtpTransport<Tokio1Executor>, from: Mailbox) -> Self {
+        Self { transport, from }
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), MailerError> {
+        let to: Mailbox = to.parse().map_err(|e: lettre::address::AddressError| MailerError(e.to_string()))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(subject)
+            .body(body.to_string())
+            .map_err(|e| MailerError(e.to_string()))?;
+
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| MailerError(e.to_string()))
+    }
+}
+
+/// Builds the mailer from enviousment variables.
+///
+/// `MAILER=smtp` sends mails through `SMTP_HOST`. Optional are `SMTP_PORT`,
+/// `SMTP_USERNAME`, `SMTP_PASSWORD` and `SMTP_TLS` (`starttls`, `tls` or `none`).
+/// Use `SMTP_TLS=none` for a local test SMTP sink.
+/// `MAIL_FROM` is the sender address.
+///
+/// Any other value writes mails to `MAIL_LOG_FILE`, or stdout when that is not set.
+pub fn mailer_from_env() -> Arc<dyn Mailer> {
+    match env::var("MAILER").as_deref() {
+        Ok("smtp") => {
+            let host = env::var("SMTP_HOST").expect("SMTP_HOST must be set when MAILER=smtp");
+            let from: Mailbox = env::var("MAIL_FROM")
+                .expect("MAIL_FROM must be set when MAILER=smtp")
+                .parse()
+                .expect("MAIL_FROM is not a valid mail address");
+
+            let mut builder = match env::var("SMTP_TLS").as_deref() {
+                Ok("none") => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host),
+                Ok("tls") => AsyncSmtpTransport::<Tokio1Executor>::relay(&host)
+                    .expect("Could not create SMTP transport"),
+                _ => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host)
+                    .expect("Could not create SMTP transport"),
+            };
+            if let Ok(port) = env::var("SMTP_PORT") {
+                builder = builder.port(
+                    port.parse()
+                        .unwrap_or_else(|_| panic!("could not parse SMTP_PORT: {:?}", port)),
+                );
+            }
+            if let (Ok(username), Ok(password)) = (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+                builder = builder.credentials(Credentials::new(username, password));
+            }
+
+            Arc::new(SmtpMailer::new(builder.build(), from))
+        }
+        _ => Arc::new(LogMailer::new(env::var("MAIL_LOG_FILE").ok().map(PathBuf::from))),
+    }
+}
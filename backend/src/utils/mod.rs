@@ -1 +1,12 @@
-pub mod jwt;
\ No newline at end of file
+pub mod achievements;
+pub mod catalog;
+pub mod cursor;
+pub mod events;
+pub mod item_state;
+pub mod jwt;
+pub mod keys;
+pub mod levels;
+pub mod mailer;
+pub mod quests;
+pub mod rewards;
+pub mod token;
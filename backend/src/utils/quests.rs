@@ -0,0 +1,312 @@
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveTime, Utc};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::env;
+use std::fmt;
+use std::fs;
+use uuid::Uuid;
+
+use crate::domain::{QuestPeriod, Reward, StatFish};
+use crate::utils::catalog::ItemCatalog;
+use crate::utils::rewards::RewardConfig;
+
+/// The quests that are used when QUESTS is not set, embedded in the binary.
+const DEFAULT_QUESTS: &str = include_str!("../../quests.toml");
+
+#[derive(Debug)]
+pub struct QuestError(pub String);
+
+impl fmt::Display for QuestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid quests: {}", self.0)
+    }
+}
+
+impl std::error::Error for QuestError {}
+
+/// The layout of the quests file, how many quests a player gets and a list of `[[quest]]` tables.
+#[derive(Deserialize)]
+struct QuestFile {
+    daily_quests: usize,
+    weekly_quests: usize,
+    #[serde(default)]
+    quest: Vec<QuestConfig>,
+}
+
+#[derive(Deserialize)]
+struct QuestConfig {
+    id: String,
+    period: QuestPeriod,
+    weight: u32,
+    description: String,
+    goal: QuestGoal,
+    #[serde(default)]
+    reward: RewardConfig,
+}
+
+/// Catching `count` fish, only counting the fish that match all the given conditions.
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuestGoal {
+    pub count: i32,
+    pub fish_id: Option<i32>,
+    pub area_id: Option<i32>,
+    pub bait_id: Option<i32>,
+    /// Only fish longer than this count.
+    pub longer_than: Option<i32>,
+}
+
+impl QuestGoal {
+    pub fn matches(&self, fish: &StatFish) -> bool {
+        self.fish_id.is_none_or(|fish_id| fish.fish_id == fish_id)
+            && self.area_id.is_none_or(|area_id| fish.area_id == area_id)
+            && self.bait_id.is_none_or(|bait_id| fish.bait_id == bait_id)
+            && self.longer_than.is_none_or(|length| fish.length > length)
+    }
+}
+
+pub struct QuestDefinition {
+    pub id: String,
+    pub period: QuestPeriod,
+    pub weight: u32,
+    pub description: String,
+    pub goal: QuestGoal,
+    pub reward: Reward,
+}
+
+/// The quests players get theirs from, and how many they get per day and week.
+pub struct QuestPool {
+    quests: Vec<QuestDefinition>,
+    daily_quests: usize,
+    weekly_quests: usize,
+}
+
+impl QuestPool {
+    pub fn from_toml(source: &str, catalog: &ItemCatalog) -> Result<Self, QuestError> {
+        let file: QuestFile = toml::from_str(source).map_err(|e| QuestError(e.to_string()))?;
+        let mut ids = BTreeSet::new();
+        let mut quests = Vec::new();
+        for config in file.quest {
+            let id = config.id;
+            if id.is_empty() || !id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') {
+                return Err(QuestError(format!("id {:?} is not snake case", id)));
+            }
+            if !ids.insert(id.clone()) {
+                return Err(QuestError(format!("id {} is used twice", id)));
+            }
+            if config.weight == 0 {
+                return Err(QuestError(format!("{} has a weight of 0", id)));
+            }
+            if config.goal.count <= 0 {
+                return Err(QuestError(format!("{} has a count of 0 or less", id)));
+            }
+            let reward = config
+                .reward
+                .into_reward(catalog)
+                .map_err(|e| QuestError(format!("reward of {} {}", id, e)))?;
+            quests.push(QuestDefinition {
+                id,
+                period: config.period,
+                weight: config.weight,
+                description: config.description,
+                goal: config.goal,
+                reward,
+            });
+        }
+
+        let pool = Self { quests, daily_quests: file.daily_quests, weekly_quests: file.weekly_quests };
+        for period in [QuestPeriod::Daily, QuestPeriod::Weekly] {
+            let available = pool.quests.iter().filter(|quest| quest.period == period).count();
+            if available < pool.quests_per_period(period) {
+                return Err(QuestError(format!(
+                    "players get {} {} quests, there are only {}",
+                    pool.quests_per_period(period),
+                    period.as_str(),
+                    available
+                )));
+            }
+        }
+        Ok(pool)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&QuestDefinition> {
+        self.quests.iter().find(|quest| quest.id == id)
+    }
+
+    /// The ids of the quests, daily and weekly, whose goal the fish counts for.
+    pub fn matching(&self, fish: &StatFish) -> Vec<String> {
+        self.quests
+            .iter()
+            .filter(|quest| quest.goal.matches(fish))
+            .map(|quest| quest.id.clone())
+            .collect()
+    }
+
+    pub fn quests_per_period(&self, period: QuestPeriod) -> usize {
+        match period {
+            QuestPeriod::Daily => self.daily_quests,
+            QuestPeriod::Weekly => self.weekly_quests,
+        }
+    }
+
+    /// Picks the quests of one player for a period, a quest with twice the weight is picked twice as often.
+    /// A quest is never picked twice.
+    pub fn pick(&self, period: QuestPeriod) -> Vec<&QuestDefinition> {
+        let mut candidates: Vec<&QuestDefinition> = self.quests.iter().filter(|quest| quest.period == period).collect();
+        let mut picked = Vec::new();
+        while picked.len() < self.quests_per_period(period) && !candidates.is_empty() {
+            let total: u64 = candidates.iter().map(|quest| quest.weight as u64).sum();
+            // A v4 uuid is 122 random bits, plenty for a roll.
+            let mut roll = (Uuid::new_v4().as_u128() % total as u128) as u64;
+            let index = candidates
+                .iter()
+                .position(|quest| {
+                    if roll < quest.weight as u64 {
+                        return true;
+                    }
+                    roll -= quest.weight as u64;
+                    false
+                })
+                .unwrap_or(0);
+            picked.push(candidates.remove(index));
+        }
+        picked
+    }
+}
+
+/// The start of the day or week `now` is in, at midnight at the reset offset. Weeks start on Monday.
+pub fn period_start(period: QuestPeriod, now: DateTime<Utc>, offset: FixedOffset) -> DateTime<Utc> {
+    let today = now.with_timezone(&offset).date_naive();
+    let first_day = match period {
+        QuestPeriod::Daily => today,
+        QuestPeriod::Weekly => today - Duration::days(today.weekday().num_days_from_monday() as i64),
+    };
+    (first_day.and_time(NaiveTime::MIN) - Duration::seconds(offset.local_minus_utc() as i64)).and_utc()
+}
+
+/// When the quests of the period `now` is in are replaced.
+pub fn next_reset(period: QuestPeriod, now: DateTime<Utc>, offset: FixedOffset) -> DateTime<Utc> {
+    let length = match period {
+        QuestPeriod::Daily => Duration::days(1),
+        QuestPeriod::Weekly => Duration::days(7),
+    };
+    period_start(period, now, offset) + length
+}
+
+/// Loads the quest pool from the TOML file QUESTS points to,
+/// or the quests in backend/quests.toml that are build into the binary.
+///
+/// Panics when a quest is invalid, the backend can not run without them.
+pub fn quests_from_env(catalog: &ItemCatalog) -> QuestPool {
+    match env::var("QUESTS") {
+        Ok(path) => {
+            let source = fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("could not read QUESTS file {:?}: {}", path, e));
+            QuestPool::from_toml(&source, catalog).unwrap_or_else(|e| panic!("{} in {:?}", e, path))
+        }
+        Err(_) => QuestPool::from_toml(DEFAULT_QUESTS, catalog).unwrap_or_else(|e| panic!("{}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Weekday;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time).unwrap().with_timezone(&Utc)
+    }
+
+    fn offset(hours: i32) -> FixedOffset {
+        FixedOffset::east_opt(hours * 3600).unwrap()
+    }
+
+    #[test]
+    fn negative_offset_is_still_on_the_previous_day() {
+        // 22:00 on Saturday the 17th at -05:00.
+        let now = at("2026-10-18T03:00:00Z");
+        assert_eq!(period_start(QuestPeriod::Daily, now, offset(-5)), at("2026-10-17T05:00:00Z"));
+        assert_eq!(next_reset(QuestPeriod::Daily, now, offset(-5)), at("2026-10-18T05:00:00Z"));
+        assert_eq!(period_start(QuestPeriod::Weekly, now, offset(-5)), at("2026-10-12T05:00:00Z"));
+        assert_eq!(next_reset(QuestPeriod::Weekly, now, offset(-5)), at("2026-10-19T05:00:00Z"));
+    }
+
+    #[test]
+    fn positive_offset_is_already_in_the_next_day_and_week() {
+        // Sunday 23:00 in UTC is Monday 01:00 at +02:00.
+        let now = at("2026-10-18T23:00:00Z");
+        assert_eq!(now.weekday(), Weekday::Sun);
+        assert_eq!(period_start(QuestPeriod::Daily, now, offset(2)), at("2026-10-18T22:00:00Z"));
+        assert_eq!(next_reset(QuestPeriod::Daily, now, offset(2)), at("2026-10-19T22:00:00Z"));
+        assert_eq!(period_start(QuestPeriod::Weekly, now, offset(2)), at("2026-10-18T22:00:00Z"));
+        assert_eq!(next_reset(QuestPeriod::Weekly, now, offset(2)), at("2026-10-25T22:00:00Z"));
+
+        // In UTC it is still the week that started on Monday the 12th.
+        assert_eq!(period_start(QuestPeriod::Weekly, now, offset(0)), at("2026-10-12T00:00:00Z"));
+    }
+
+    #[test]
+    fn a_period_starts_at_its_reset() {
+        let reset = at("2026-10-18T22:00:00Z");
+        assert_eq!(period_start(QuestPeriod::Daily, reset, offset(2)), reset);
+        assert_eq!(period_start(QuestPeriod::Weekly, reset, offset(2)), reset);
+        assert_eq!(period_start(QuestPeriod::Daily, reset - Duration::seconds(1), offset(2)), at("2026-10-17T22:00:00Z"));
+    }
+
+    #[test]
+    fn pick_never_returns_a_quest_twice() {
+        let pool = QuestPool::from_toml(
+            r#"
+            daily_quests = 3
+            weekly_quests = 1
+
+            [[quest]]
+            id = "heavy"
+            period = "daily"
+            weight = 100
+            description = "Picked almost every time."
+            goal = { count = 1 }
+
+            [[quest]]
+            id = "light_1"
+            period = "daily"
+            weight = 1
+            description = "Rarely picked."
+            goal = { count = 1 }
+
+            [[quest]]
+            id = "light_2"
+            period = "daily"
+            weight = 1
+            description = "Rarely picked."
+            goal = { count = 1 }
+
+            [[quest]]
+            id = "light_3"
+            period = "daily"
+            weight = 1
+            description = "Rarely picked."
+            goal = { count = 1 }
+
+            [[quest]]
+            id = "weekly"
+            period = "weekly"
+            weight = 1
+            description = "The only weekly quest."
+            goal = { count = 1 }
+            "#,
+            &ItemCatalog::new(Vec::new()).unwrap(),
+        )
+        .unwrap();
+
+        for _ in 0..200 {
+            let picked = pool.pick(QuestPeriod::Daily);
+            let ids: BTreeSet<&str> = picked.iter().map(|quest| quest.id.as_str()).collect();
+            assert_eq!(picked.len(), 3);
+            assert_eq!(ids.len(), 3);
+            assert!(picked.iter().all(|quest| quest.period == QuestPeriod::Daily));
+        }
+        let weekly: Vec<&str> = pool.pick(QuestPeriod::Weekly).iter().map(|quest| quest.id.as_str()).collect();
+        assert_eq!(weekly, vec!["weekly"]);
+    }
+}
@@ -0,0 +1,95 @@
+use serde::Deserialize;
+
+use crate::domain::{Currency, MailAttachment, Reward, RewardMail};
+use crate::utils::catalog::ItemCatalog;
+
+/// A reward as written in the config files, for example:
+///
+/// ```toml
+/// coins = 100
+/// items = [1000]
+/// mail = { title = "Well done", message = "Here is a new rod." }
+/// ```
+///
+/// Items start with the default state of their definition. Without `mail` the reward is granted right away.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RewardConfig {
+    #[serde(default)]
+    pub coins: i32,
+    #[serde(default)]
+    pub bucks: i32,
+    #[serde(default)]
+    pub items: Vec<i32>,
+    pub mail: Option<RewardMail>,
+}
+
+impl RewardConfig {
+    /// Checks the reward against the catalog, the error describes what is wrong.
+    pub fn into_reward(self, catalog: &ItemCatalog) -> Result<Reward, String> {
+        if self.coins < 0 || self.bucks < 0 {
+            return Err("has negative coins or bucks".to_string());
+        }
+        let mut attachments = Vec::new();
+        for definition_id in self.items {
+            let definition = catalog
+                .get(definition_id)
+                .ok_or_else(|| format!("has unknown item {}", definition_id))?;
+            attachments.push(MailAttachment::Item {
+                definition_id,
+                state_blob: definition.default_state.clone(),
+            });
+        }
+        if self.coins > 0 {
+            attachments.push(MailAttachment::Currency { currency: Currency::Coins, amount: self.coins });
+        }
+        if self.bucks > 0 {
+            attachments.push(MailAttachment::Currency { currency: Currency::Bucks, amount: self.bucks });
+        }
+        Ok(Reward { attachments, mail: self.mail })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catalog() -> ItemCatalog {
+        ItemCatalog::from_toml(include_str!("../../items.toml")).unwrap()
+    }
+
+    #[test]
+    fn items_start_with_their_default_state_and_currency_follows() {
+        let config = RewardConfig { coins: 100, bucks: 5, items: vec![1000, 0], mail: None };
+        let reward = config.into_reward(&catalog()).unwrap();
+        assert_eq!(
+            reward.attachments,
+            [
+                MailAttachment::Item { definition_id: 1000, state_blob: "AQABAAX2////".to_string() },
+                MailAttachment::Item { definition_id: 0, state_blob: "AQABAAX2////".to_string() },
+                MailAttachment::Currency { currency: Currency::Coins, amount: 100 },
+                MailAttachment::Currency { currency: Currency::Bucks, amount: 5 },
+            ]
+        );
+        assert_eq!(reward.mail, None);
+    }
+
+    #[test]
+    fn leaves_out_empty_currency_and_keeps_the_mail() {
+        let mail = RewardMail { title: "Well done".to_string(), message: "Here is a hook.".to_string() };
+        let config = RewardConfig { items: vec![0], mail: Some(mail.clone()), ..RewardConfig::default() };
+        let reward = config.into_reward(&catalog()).unwrap();
+        assert_eq!(reward.attachments.len(), 1);
+        assert_eq!(reward.mail, Some(mail));
+        assert_eq!(RewardConfig::default().into_reward(&catalog()).unwrap(), Reward::default());
+    }
+
+    #[test]
+    fn rejects_negative_currency_and_unknown_items() {
+        let negative = RewardConfig { bucks: -1, ..RewardConfig::default() };
+        assert_eq!(negative.into_reward(&catalog()).unwrap_err(), "has negative coins or bucks");
+        let unknown = RewardConfig { items: vec![42], ..RewardConfig::default() };
+        assert_eq!(unknown.into_reward(&catalog()).unwrap_err(), "has unknown item 42");
+        assert!(toml::from_str::<RewardConfig>("gems = 5").is_err());
+    }
+}
@@ -0,0 +1,12 @@
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Generates a random opaque token, for example for password resets.
+pub fn generate_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// Hashes a token before it is stored, so a database leak does not leak usable tokens.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}